@@ -0,0 +1,430 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rebuilds an AXP image from a USB capture of a download, e.g. one made with USBPcap while the
+//! vendor tool flashed a board, for when no image file is available.
+//!
+//! Only the requests sent to the device are used: the flash downloaders are taken from the RAM
+//! downloads, the partitions from the partition table and the data written to each partition.
+
+use std::{io::Write as _, path::PathBuf};
+
+use anyhow::{bail, Context as _};
+use axdl::{
+    frame::{commands, AxdlFrameView},
+    partition::{Block, Image, ImageType, PartitionTable, Project},
+};
+
+#[derive(Debug, clap::Args)]
+pub struct CaptureArgs {
+    #[clap(long, help = "USB capture of a download in pcap format (not pcapng)")]
+    capture: PathBuf,
+    #[clap(long, help = "AXP image file to write")]
+    output: PathBuf,
+}
+
+/// USBPcap, as written by Wireshark on Windows.
+const LINKTYPE_USBPCAP: u32 = 249;
+/// Linux usbmon with the 48 byte header.
+const LINKTYPE_USB_LINUX: u32 = 189;
+/// Linux usbmon with the 64 byte header.
+const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+
+const TRANSFER_BULK: u8 = 3;
+
+/// Bus number and device address of a USB device.
+type Address = (u16, u16);
+/// Name and contents of a file in the AXP image.
+type ImageFile = (String, Vec<u8>);
+
+/// Returns the data of the bulk OUT transfers in the capture, with the bus and device address of
+/// the device they were sent to.
+fn bulk_out_packets(capture: &[u8]) -> anyhow::Result<Vec<(Address, &[u8])>> {
+    if capture.len() < 24 {
+        bail!("The capture is too short");
+    }
+    let little_endian = match capture[0..4] {
+        [0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1] => true,
+        [0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d] => false,
+        [0x0a, 0x0d, 0x0d, 0x0a] => bail!("pcapng is not supported, save the capture as pcap"),
+        _ => bail!("The capture is not a pcap file"),
+    };
+    let u32_at = |bytes: &[u8], offset: usize| {
+        let mut value = [0u8; 4];
+        value.copy_from_slice(&bytes[offset..offset + 4]);
+        if little_endian {
+            u32::from_le_bytes(value)
+        } else {
+            u32::from_be_bytes(value)
+        }
+    };
+    let link_type = u32_at(capture, 20);
+
+    let mut packets = Vec::new();
+    let mut offset = 24;
+    while offset + 16 <= capture.len() {
+        let length = u32_at(capture, offset + 8) as usize;
+        let record = capture
+            .get(offset + 16..offset + 16 + length)
+            .context("The capture is truncated")?;
+        offset += 16 + length;
+
+        let packet = match link_type {
+            LINKTYPE_USBPCAP => {
+                // USBPcap headers are always little endian.
+                let Some(header) = record.get(..27) else {
+                    continue;
+                };
+                let header_length = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let from_device = header[16] & 1 != 0;
+                let endpoint = header[21];
+                let address = (
+                    u16::from_le_bytes([header[17], header[18]]),
+                    u16::from_le_bytes([header[19], header[20]]),
+                );
+                (header[22] == TRANSFER_BULK && !from_device && endpoint & 0x80 == 0)
+                    .then(|| (address, record.get(header_length..)))
+            }
+            LINKTYPE_USB_LINUX | LINKTYPE_USB_LINUX_MMAPPED => {
+                let header_length = if link_type == LINKTYPE_USB_LINUX {
+                    48
+                } else {
+                    64
+                };
+                let Some(header) = record.get(..header_length) else {
+                    continue;
+                };
+                let address = (
+                    u16::from_le_bytes([header[12], header[13]]),
+                    header[11] as u16,
+                );
+                (header[8] == b'S' && header[9] == TRANSFER_BULK && header[10] & 0x80 == 0)
+                    .then(|| (address, record.get(header_length..)))
+            }
+            _ => bail!(
+                "Unsupported link type {}, expected a USB capture",
+                link_type
+            ),
+        };
+        if let Some((address, Some(data))) = packet {
+            if !data.is_empty() {
+                packets.push((address, data));
+            }
+        }
+    }
+    Ok(packets)
+}
+
+/// Where the data of a partition command goes.
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Ram(u64),
+    Partition(String),
+}
+
+/// Data written to one target.
+#[derive(Debug)]
+struct Stream {
+    target: Target,
+    length: u64,
+    data: Vec<u8>,
+}
+
+/// Data recovered from the requests of a download.
+#[derive(Debug, Default)]
+struct Download {
+    partition_table: Option<PartitionTable>,
+    /// RAM downloads and partitions in the order they were written.
+    streams: Vec<Stream>,
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(value)
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    let mut value = [0u8; 4];
+    value.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(value)
+}
+
+impl Download {
+    /// Follows the requests sent to the device, frames and data packets alike.
+    fn from_requests<'a>(requests: impl IntoIterator<Item = &'a [u8]>) -> anyhow::Result<Self> {
+        let mut download = Self::default();
+        let mut current: Option<Stream> = None;
+        // Bytes of the current block not received yet.
+        let mut block_remaining = 0usize;
+        for request in requests {
+            if block_remaining > 0 {
+                let length = request.len().min(block_remaining);
+                if let Some(stream) = &mut current {
+                    stream.data.extend_from_slice(&request[..length]);
+                }
+                block_remaining -= length;
+                continue;
+            }
+            let frame = AxdlFrameView::new(request);
+            let (Some(command), Some(payload)) = (frame.command_response(), frame.payload()) else {
+                if !request.iter().all(|&b| b == commands::HANDSHAKE) {
+                    tracing::warn!("Skipped {} bytes which are not a frame", request.len());
+                }
+                continue;
+            };
+            match command {
+                commands::START_PARTITION => {
+                    let (target, length) = match payload.len() {
+                        8 => (
+                            Target::Ram(u32_at(payload, 0) as u64),
+                            u32_at(payload, 4) as u64,
+                        ),
+                        16 => (Target::Ram(u64_at(payload, 0)), u64_at(payload, 8)),
+                        88 => {
+                            let name: Vec<u16> = payload[..72]
+                                .chunks_exact(2)
+                                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                                .take_while(|&c| c != 0)
+                                .collect();
+                            (
+                                Target::Partition(String::from_utf16_lossy(&name)),
+                                u64_at(payload, 72),
+                            )
+                        }
+                        length => bail!("Unexpected START_PARTITION payload of {} bytes", length),
+                    };
+                    current = Some(Stream {
+                        target,
+                        length,
+                        data: Vec::new(),
+                    });
+                }
+                commands::START_BLOCK if payload.len() >= 4 => {
+                    block_remaining = u32_at(payload, 0) as usize;
+                }
+                commands::END_PARTITION => {
+                    let Some(stream) = current.take() else {
+                        continue;
+                    };
+                    if stream.data.len() as u64 != stream.length {
+                        tracing::warn!(
+                            "{:?}: {} bytes captured, {} announced",
+                            stream.target,
+                            stream.data.len(),
+                            stream.length
+                        );
+                    }
+                    // A partition written again, e.g. by a retry, replaces the earlier data.
+                    download.streams.retain(|s| s.target != stream.target);
+                    download.streams.push(stream);
+                }
+                commands::SET_PARTITION_TABLE => {
+                    download.partition_table = Some(PartitionTable::from_bytes(payload)?);
+                }
+                _ => {}
+            }
+        }
+        Ok(download)
+    }
+
+    /// Builds the project and the files of the AXP image.
+    fn into_image(self, name: &str) -> anyhow::Result<(Project, Vec<ImageFile>)> {
+        let partition_table = self
+            .partition_table
+            .context("The capture does not contain the partition table")?;
+        let (ram, partitions): (Vec<_>, Vec<_>) = self
+            .streams
+            .into_iter()
+            .partition(|stream| matches!(stream.target, Target::Ram(_)));
+        let fdl_names: &[(&str, ImageType)] = match ram.len() {
+            1 => &[("FDL", ImageType::Fdl2)],
+            2 => &[("FDL1", ImageType::Fdl1), ("FDL2", ImageType::Fdl2)],
+            count => bail!("Expected one or two flash downloaders, found {}", count),
+        };
+        let mut project = Project::new(
+            name.to_uppercase(),
+            name.into(),
+            "1".into(),
+            ram.len() as u32,
+            partition_table,
+        );
+        let mut files = Vec::new();
+        for (stream, (name, r#type)) in ram.into_iter().zip(fdl_names) {
+            let Target::Ram(address) = stream.target else {
+                continue;
+            };
+            let file = format!("{}.bin", name.to_lowercase());
+            project.add_image(Image::new(
+                name.to_string(),
+                *r#type,
+                Block::Absolute(address),
+                Some(file.clone()),
+            ));
+            files.push((file, stream.data));
+        }
+        for stream in partitions {
+            let Target::Partition(partition) = stream.target else {
+                continue;
+            };
+            let file = format!("{}.img", partition);
+            project.add_image(Image::new(
+                partition.to_uppercase(),
+                ImageType::Code,
+                Block::Partition(partition),
+                Some(file.clone()),
+            ));
+            files.push((file, stream.data));
+        }
+        Ok((project, files))
+    }
+}
+
+/// Rebuilds an AXP image from a pcap capture of a download.
+fn rebuild(capture: &[u8], name: &str) -> anyhow::Result<Vec<u8>> {
+    let packets = bulk_out_packets(capture)?;
+    // The first frame tells which device the download went to.
+    let device = packets
+        .iter()
+        .find(|(_, data)| AxdlFrameView::new(data).is_valid())
+        .map(|(address, _)| *address)
+        .context("The capture does not contain a download")?;
+    let requests = packets
+        .into_iter()
+        .filter(|(address, _)| *address == device)
+        .map(|(_, data)| data);
+    let (project, files) = Download::from_requests(requests)?.into_image(name)?;
+
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+    writer.start_file(format!("{}.xml", name), options)?;
+    writer.write_all(axdl::partition::serialize::to_string(&project).as_bytes())?;
+    for (file, data) in files {
+        tracing::info!("{}: {} bytes", file, data.len());
+        writer.start_file(file, options)?;
+        writer.write_all(&data)?;
+    }
+    Ok(writer.finish()?.into_inner())
+}
+
+pub fn run(args: &CaptureArgs) -> anyhow::Result<()> {
+    let capture = std::fs::read(&args.capture)?;
+    let name = args
+        .output
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("capture");
+    let image = rebuild(&capture, name)?;
+    std::fs::write(&args.output, image)?;
+    println!("Wrote {}", args.output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axdl::progress::NoProgress;
+    use axdl::transport::record::{Direction, RecordingDevice};
+    use axdl_emulator::{
+        axp::{pattern, AxpBuilder},
+        Emulator,
+    };
+
+    /// Writes the packets as a USBPcap capture of device 1.3, as Wireshark on Windows does.
+    fn usbpcap(packets: &[(Direction, Vec<u8>)]) -> Vec<u8> {
+        let mut capture = Vec::new();
+        capture.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+        capture.extend_from_slice(&[2, 0, 4, 0]);
+        capture.extend_from_slice(&[0; 8]);
+        capture.extend_from_slice(&65535u32.to_le_bytes());
+        capture.extend_from_slice(&LINKTYPE_USBPCAP.to_le_bytes());
+        for (direction, data) in packets {
+            let (info, endpoint) = match direction {
+                Direction::Out => (0u8, 0x01u8),
+                Direction::In => (1, 0x81),
+            };
+            let mut record = Vec::new();
+            record.extend_from_slice(&27u16.to_le_bytes());
+            record.extend_from_slice(&[0; 12]);
+            record.extend_from_slice(&0x0009u16.to_le_bytes());
+            record.extend_from_slice(&[info]);
+            record.extend_from_slice(&1u16.to_le_bytes());
+            record.extend_from_slice(&3u16.to_le_bytes());
+            record.extend_from_slice(&[endpoint, TRANSFER_BULK]);
+            record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            record.extend_from_slice(data);
+            capture.extend_from_slice(&[0; 8]);
+            capture.extend_from_slice(&(record.len() as u32).to_le_bytes());
+            capture.extend_from_slice(&(record.len() as u32).to_le_bytes());
+            capture.extend_from_slice(&record);
+        }
+        capture
+    }
+
+    #[test]
+    fn test_rebuild_from_capture() {
+        let image = AxpBuilder::new(2)
+            .partition("spl", 0x40000)
+            .partition("rootfs", 0x400000)
+            .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+            .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+            .code("SPL", "spl", pattern(1000, 3))
+            .code("ROOTFS", "rootfs", pattern(200_000, 4));
+        let emulator = Emulator::new(2);
+        let recording_device = RecordingDevice::new(emulator.dyn_device());
+        let recording = recording_device.recording();
+        let mut device: axdl::transport::DynDevice = Box::new(recording_device);
+        let config = axdl::DownloadConfig::default();
+        axdl::download_image(
+            &mut std::io::Cursor::new(image.build()),
+            &mut device,
+            &config,
+            &mut NoProgress,
+        )
+        .unwrap();
+        let packets: Vec<_> = recording
+            .lock()
+            .unwrap()
+            .packets
+            .iter()
+            .map(|packet| (packet.direction, packet.data.clone()))
+            .collect();
+
+        let rebuilt = rebuild(&usbpcap(&packets), "rebuilt").unwrap();
+        let replayed = Emulator::new(2);
+        axdl::download_image(
+            &mut std::io::Cursor::new(rebuilt),
+            &mut replayed.dyn_device(),
+            &config,
+            &mut NoProgress,
+        )
+        .unwrap();
+        for name in ["spl", "rootfs"] {
+            assert_eq!(replayed.partition(name), emulator.partition(name));
+        }
+        assert_eq!(replayed.ram(0x3000), emulator.ram(0x3000));
+        assert_eq!(replayed.partition_table(), emulator.partition_table());
+    }
+
+    #[test]
+    fn test_rejects_pcapng() {
+        let capture = [0x0a, 0x0d, 0x0d, 0x0a].repeat(8);
+        assert!(rebuild(&capture, "x")
+            .unwrap_err()
+            .to_string()
+            .contains("pcapng"));
+    }
+}
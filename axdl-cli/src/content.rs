@@ -0,0 +1,388 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recognises what an image holds from its first bytes and the version strings in it, e.g. the
+//! kernel or U-Boot version, to tell releases apart without extracting them.
+
+use std::io::Read;
+
+/// Bytes read to recognise the content by its header.
+const HEAD_LENGTH: usize = 64 * 1024;
+/// Bytes searched for version strings, so that large raw images are not read completely.
+const SCAN_LIMIT: u64 = 64 * 1024 * 1024;
+/// Longest version string reported.
+const MAX_STRING_LENGTH: usize = 160;
+/// Largest device tree read.
+const MAX_DTB_SIZE: usize = 4 * 1024 * 1024;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const SQUASHFS_MAGIC: &[u8] = b"hsqs";
+const EXT_SUPERBLOCK: usize = 1024;
+const EXT_MAGIC: u16 = 0xef53;
+/// Magic of the arm64 kernel `Image` header, at offset 0x38.
+const ARM64_IMAGE_MAGIC: &[u8] = b"ARM\x64";
+const LINUX_BANNER: &[u8] = b"Linux version ";
+const UBOOT_BANNER: &[u8] = b"U-Boot ";
+
+/// Content of an image, as far as it was recognised.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Content {
+    /// Linux kernel and its release, if the banner was found.
+    Kernel(Option<String>),
+    DeviceTree {
+        model: Option<String>,
+    },
+    Squashfs {
+        version: (u16, u16),
+        compression: &'static str,
+        block_size: u32,
+        inodes: u32,
+    },
+    Ext {
+        label: String,
+    },
+    /// U-Boot or its SPL, with the version string.
+    UBoot(String),
+}
+
+impl std::fmt::Display for Content {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Content::Kernel(Some(release)) => write!(f, "Linux kernel {}", release),
+            Content::Kernel(None) => write!(f, "arm64 Linux kernel"),
+            Content::DeviceTree { model: Some(model) } => write!(f, "device tree, model {}", model),
+            Content::DeviceTree { model: None } => write!(f, "device tree"),
+            Content::Squashfs {
+                version,
+                compression,
+                block_size,
+                inodes,
+            } => write!(
+                f,
+                "squashfs {}.{}, {} compression, {} KiB blocks, {} inodes",
+                version.0,
+                version.1,
+                compression,
+                block_size / 1024,
+                inodes
+            ),
+            Content::Ext { label } if label.is_empty() => write!(f, "ext2/3/4 file system"),
+            Content::Ext { label } => write!(f, "ext2/3/4 file system, label {}", label),
+            Content::UBoot(version) => f.write_str(version),
+        }
+    }
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn le_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn le_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Returns the bytes up to the first NUL.
+fn c_str(data: &[u8]) -> &[u8] {
+    let length = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    &data[..length]
+}
+
+/// Returns the value of the string property `name` of the root node of a flattened device tree.
+fn fdt_root_property(fdt: &[u8], name: &str) -> Option<String> {
+    let align = |length: usize| length.div_ceil(4) * 4;
+    let strings = be_u32(fdt, 12)? as usize;
+    let mut offset = be_u32(fdt, 8)? as usize;
+    let mut depth = 0usize;
+    loop {
+        let token = be_u32(fdt, offset)?;
+        offset += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name_length = c_str(fdt.get(offset..)?).len();
+                offset += align(name_length + 1);
+                depth += 1;
+            }
+            FDT_END_NODE => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return None;
+                }
+            }
+            FDT_PROP => {
+                let length = be_u32(fdt, offset)? as usize;
+                let name_offset = be_u32(fdt, offset + 4)? as usize;
+                let value = fdt.get(offset + 8..(offset + 8).checked_add(length)?)?;
+                offset += 8 + align(length);
+                let property = c_str(fdt.get(strings.checked_add(name_offset)?..)?);
+                if depth == 1 && property == name.as_bytes() {
+                    return Some(String::from_utf8_lossy(c_str(value)).into_owned());
+                }
+            }
+            FDT_NOP => {}
+            _ => return None,
+        }
+    }
+}
+
+fn squashfs(head: &[u8]) -> Option<Content> {
+    let compression = match le_u16(head, 20)? {
+        1 => "gzip",
+        2 => "lzma",
+        3 => "lzo",
+        4 => "xz",
+        5 => "lz4",
+        6 => "zstd",
+        _ => "unknown",
+    };
+    Some(Content::Squashfs {
+        version: (le_u16(head, 28)?, le_u16(head, 30)?),
+        compression,
+        block_size: le_u32(head, 12)?,
+        inodes: le_u32(head, 4)?,
+    })
+}
+
+fn ext(head: &[u8]) -> Option<Content> {
+    if le_u16(head, EXT_SUPERBLOCK + 0x38)? != EXT_MAGIC {
+        return None;
+    }
+    let label = head.get(EXT_SUPERBLOCK + 0x78..EXT_SUPERBLOCK + 0x88)?;
+    Some(Content::Ext {
+        label: String::from_utf8_lossy(c_str(label)).into_owned(),
+    })
+}
+
+/// Returns the printable string at the start of `data`, or `None` if it may continue beyond.
+fn banner(data: &[u8]) -> Option<&[u8]> {
+    let length = data
+        .iter()
+        .take(MAX_STRING_LENGTH)
+        .position(|&b| !(0x20..0x7f).contains(&b));
+    match length {
+        Some(length) => Some(&data[..length]),
+        None if data.len() >= MAX_STRING_LENGTH => Some(&data[..MAX_STRING_LENGTH]),
+        None => None,
+    }
+}
+
+/// Finds the first complete kernel or, unless `kernel_only`, U-Boot banner in `window`.
+fn find_banner(window: &[u8], kernel_only: bool) -> Option<Content> {
+    let mut start = 0;
+    while start < window.len() {
+        let rest = &window[start..];
+        let linux = rest
+            .windows(LINUX_BANNER.len())
+            .position(|w| w == LINUX_BANNER);
+        let uboot = if kernel_only {
+            None
+        } else {
+            rest.windows(UBOOT_BANNER.len())
+                .position(|w| w == UBOOT_BANNER)
+        };
+        let (position, is_linux) = match (linux, uboot) {
+            (Some(linux), Some(uboot)) if uboot < linux => (uboot, false),
+            (Some(linux), _) => (linux, true),
+            (None, Some(uboot)) => (uboot, false),
+            (None, None) => return None,
+        };
+        let found = &rest[position..];
+        if is_linux {
+            // The banner goes on with the compiler, only the release is of interest.
+            let release = found[LINUX_BANNER.len()..]
+                .split(|&b| b == b' ' || !(0x20..0x7f).contains(&b))
+                .next()
+                .filter(|release| release.first().is_some_and(u8::is_ascii_digit));
+            if let (Some(release), Some(_)) = (release, banner(found)) {
+                return Some(Content::Kernel(Some(
+                    String::from_utf8_lossy(release).into_owned(),
+                )));
+            }
+        } else {
+            let version = &found[UBOOT_BANNER.len()..];
+            let is_version =
+                version.first().is_some_and(u8::is_ascii_digit) || version.starts_with(b"SPL 2");
+            if let (true, Some(banner)) = (is_version, banner(found)) {
+                return Some(Content::UBoot(String::from_utf8_lossy(banner).into_owned()));
+            }
+        }
+        start += position + 1;
+    }
+    None
+}
+
+/// Searches the image for a banner, reading at most [`SCAN_LIMIT`] bytes.
+fn scan(head: &[u8], rest: &mut dyn Read, kernel_only: bool) -> std::io::Result<Option<Content>> {
+    let mut window = head.to_vec();
+    let mut rest = rest.take(SCAN_LIMIT.saturating_sub(head.len() as u64));
+    let mut chunk = vec![0u8; 1024 * 1024];
+    loop {
+        if let Some(content) = find_banner(&window, kernel_only) {
+            return Ok(Some(content));
+        }
+        let length = rest.read(&mut chunk)?;
+        if length == 0 {
+            return Ok(None);
+        }
+        // Keep the end, where a banner may start which continues in the next chunk.
+        let keep = window.len().min(MAX_STRING_LENGTH + LINUX_BANNER.len());
+        window.drain(..window.len() - keep);
+        window.extend_from_slice(&chunk[..length]);
+    }
+}
+
+/// Recognises the content of an image read from `reader`, `None` if it is not known.
+pub fn peek(reader: &mut dyn Read) -> std::io::Result<Option<Content>> {
+    let mut head = Vec::new();
+    (&mut *reader)
+        .take(HEAD_LENGTH as u64)
+        .read_to_end(&mut head)?;
+    if be_u32(&head, 0) == Some(FDT_MAGIC) {
+        let total_size = be_u32(&head, 4).unwrap_or_default() as usize;
+        if total_size > head.len() && total_size <= MAX_DTB_SIZE {
+            (&mut *reader)
+                .take((total_size - head.len()) as u64)
+                .read_to_end(&mut head)?;
+        }
+        let model = fdt_root_property(&head, "model");
+        return Ok(Some(Content::DeviceTree { model }));
+    }
+    if head.starts_with(SQUASHFS_MAGIC) {
+        return Ok(squashfs(&head));
+    }
+    if let Some(content) = ext(&head) {
+        return Ok(Some(content));
+    }
+    let is_kernel = head.get(0x38..0x3c) == Some(ARM64_IMAGE_MAGIC);
+    match scan(&head, reader, is_kernel)? {
+        None if is_kernel => Ok(Some(Content::Kernel(None))),
+        content => Ok(content),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn peek_bytes(data: &[u8]) -> Option<Content> {
+        peek(&mut std::io::Cursor::new(data)).unwrap()
+    }
+
+    /// Device tree with the root node holding `model` and a child node.
+    fn device_tree(model: &str) -> Vec<u8> {
+        let word = |value: u32| value.to_be_bytes();
+        let mut structure = Vec::new();
+        structure.extend(word(FDT_BEGIN_NODE));
+        structure.extend([0u8; 4]);
+        let mut value = model.as_bytes().to_vec();
+        value.push(0);
+        structure.extend(word(FDT_PROP));
+        structure.extend(word(value.len() as u32));
+        structure.extend(word(0));
+        structure.extend(&value);
+        structure.resize(structure.len().div_ceil(4) * 4, 0);
+        structure.extend(word(FDT_BEGIN_NODE));
+        structure.extend(b"chosen\0\0");
+        structure.extend(word(FDT_END_NODE));
+        structure.extend(word(FDT_END_NODE));
+        structure.extend(word(9));
+        let strings = b"model\0";
+
+        let mut fdt = Vec::new();
+        let header_length = 40;
+        let total_size = header_length + structure.len() + strings.len();
+        for value in [
+            FDT_MAGIC,
+            total_size as u32,
+            header_length as u32,
+            (header_length + structure.len()) as u32,
+        ] {
+            fdt.extend(word(value));
+        }
+        fdt.resize(header_length, 0);
+        fdt.extend(structure);
+        fdt.extend(strings);
+        fdt
+    }
+
+    #[test]
+    fn test_device_tree_model() {
+        assert_eq!(
+            peek_bytes(&device_tree("Axera AX620E Demo Board")),
+            Some(Content::DeviceTree {
+                model: Some("Axera AX620E Demo Board".into())
+            })
+        );
+    }
+
+    #[test]
+    fn test_file_systems() {
+        let mut squashfs = vec![0u8; 96];
+        squashfs[..4].copy_from_slice(SQUASHFS_MAGIC);
+        squashfs[4..8].copy_from_slice(&1234u32.to_le_bytes());
+        squashfs[12..16].copy_from_slice(&131072u32.to_le_bytes());
+        squashfs[20..22].copy_from_slice(&4u16.to_le_bytes());
+        squashfs[28..30].copy_from_slice(&4u16.to_le_bytes());
+        assert_eq!(
+            peek_bytes(&squashfs).unwrap().to_string(),
+            "squashfs 4.0, xz compression, 128 KiB blocks, 1234 inodes"
+        );
+
+        let mut ext = vec![0u8; 4096];
+        ext[EXT_SUPERBLOCK + 0x38..EXT_SUPERBLOCK + 0x3a].copy_from_slice(&EXT_MAGIC.to_le_bytes());
+        ext[EXT_SUPERBLOCK + 0x78..EXT_SUPERBLOCK + 0x7e].copy_from_slice(b"rootfs");
+        assert_eq!(
+            peek_bytes(&ext).unwrap().to_string(),
+            "ext2/3/4 file system, label rootfs"
+        );
+    }
+
+    #[test]
+    fn test_version_banners() {
+        // The banner straddles the first read.
+        let mut kernel = vec![0u8; HEAD_LENGTH - 8];
+        kernel[0x38..0x3c].copy_from_slice(ARM64_IMAGE_MAGIC);
+        kernel.extend(b"Linux version 5.15.73 (builder@host) (gcc 9.2) #1 SMP PREEMPT\n\0");
+        kernel.extend(vec![0u8; 1000]);
+        assert_eq!(
+            peek_bytes(&kernel),
+            Some(Content::Kernel(Some("5.15.73".into())))
+        );
+        kernel.truncate(HEAD_LENGTH - 8);
+        assert_eq!(peek_bytes(&kernel), Some(Content::Kernel(None)));
+
+        let mut uboot =
+            b"U-Boot %s\0U-Boot SPL 2020.04-ax620e (Mar 01 2024 - 10:00:00 +0800)\0".to_vec();
+        uboot.extend(vec![0xffu8; 1000]);
+        assert_eq!(
+            peek_bytes(&uboot),
+            Some(Content::UBoot(
+                "U-Boot SPL 2020.04-ax620e (Mar 01 2024 - 10:00:00 +0800)".into()
+            ))
+        );
+
+        assert_eq!(peek_bytes(&[0u8; 10000]), None);
+    }
+}
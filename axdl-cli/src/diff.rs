@@ -0,0 +1,302 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compares two AXP images, e.g. two releases, to tell whether the partition table changed and
+//! which images did, and so whether `--no-repartition` and `--skip-same` are worth using.
+//!
+//! Images are compared by the sizes and digests of their files. The CRC-32 recorded in the
+//! archive is used by default, so nothing has to be decompressed. Other digests hash the
+//! decompressed files.
+
+use std::{fmt::Write as _, path::PathBuf};
+
+use axdl::{
+    hash::Digest,
+    partition::{Partition, PartitionTable, Project},
+};
+
+#[derive(Debug, clap::Args)]
+pub struct DiffArgs {
+    #[clap(help = "AXP image file to compare from, e.g. the release on the device")]
+    old: PathBuf,
+    #[clap(help = "AXP image file to compare to")]
+    new: PathBuf,
+    #[clap(
+        long,
+        default_value_t = Digest::Crc32,
+        help = "Digest to compare images by: crc32, sha256 or xxhash64. Others than crc32 decompress the images"
+    )]
+    digest: Digest,
+}
+
+/// Sizes and digests of the files of an image, `None` if the archive does not have them.
+type Fingerprint = Option<Vec<(u64, Vec<u8>)>>;
+
+/// Project of an AXP image and the fingerprints of its images.
+struct Release {
+    project: Project,
+    images: Vec<(String, Fingerprint)>,
+}
+
+impl Release {
+    fn read<R: std::io::Read + std::io::Seek>(
+        reader: &mut R,
+        digest: Digest,
+    ) -> anyhow::Result<Self> {
+        let project = axdl::read_project(reader)?;
+        reader.rewind()?;
+        let mut archive = zip::ZipArchive::new(reader)?;
+        let file_names = archive.file_names().map(str::to_string).collect::<Vec<_>>();
+        let mut images = Vec::new();
+        for image in project.images() {
+            let parts = image.parts(file_names.iter().map(String::as_str));
+            let fingerprint = if parts.is_empty() {
+                None
+            } else {
+                let mut fingerprint = Vec::new();
+                for part in &parts {
+                    let mut file = archive.by_name(part)?;
+                    let value = match digest {
+                        Digest::Crc32 => file.crc32().to_be_bytes().to_vec(),
+                        _ => digest.digest_reader(&mut file)?,
+                    };
+                    fingerprint.push((file.size(), value));
+                }
+                Some(fingerprint)
+            };
+            images.push((image.name().to_string(), fingerprint));
+        }
+        Ok(Self { project, images })
+    }
+
+    fn image(&self, name: &str) -> Option<&Fingerprint> {
+        self.images
+            .iter()
+            .find(|(image, _)| image == name)
+            .map(|(_, fingerprint)| fingerprint)
+    }
+}
+
+fn size_of(fingerprint: &Fingerprint) -> String {
+    match fingerprint {
+        Some(parts) => format!("{} bytes", parts.iter().map(|(size, _)| size).sum::<u64>()),
+        None => "missing".into(),
+    }
+}
+
+/// Formats a size or gap given in the units of `table`.
+pub(crate) fn units(table: &PartitionTable, value: u64) -> String {
+    match table.unit_size() {
+        Some(unit_size) => format!("{} bytes", value.saturating_mul(unit_size)),
+        None => format!("{} units", value),
+    }
+}
+
+/// Lists the changes from `old` to `new` in the partition table.
+fn diff_partitions(old: &PartitionTable, new: &PartitionTable) -> Vec<String> {
+    let find = |table: &PartitionTable, name: &str| -> Option<Partition> {
+        table
+            .partitions()
+            .iter()
+            .find(|partition| partition.name() == name)
+            .cloned()
+    };
+    let mut changes = Vec::new();
+    if (old.strategy(), old.unit()) != (new.strategy(), new.unit()) {
+        changes.push(format!(
+            "strategy {} unit {} -> strategy {} unit {}",
+            old.strategy(),
+            old.unit(),
+            new.strategy(),
+            new.unit()
+        ));
+    }
+    for partition in old.partitions() {
+        if find(new, partition.name()).is_none() {
+            changes.push(format!("- {}", partition.name()));
+        }
+    }
+    for partition in new.partitions() {
+        let Some(previous) = find(old, partition.name()) else {
+            changes.push(format!(
+                "+ {} ({})",
+                partition.name(),
+                units(new, partition.size())
+            ));
+            continue;
+        };
+        if previous.size() != partition.size() {
+            changes.push(format!(
+                "~ {}: size {} -> {}",
+                partition.name(),
+                units(old, previous.size()),
+                units(new, partition.size())
+            ));
+        }
+        if previous.gap() != partition.gap() {
+            changes.push(format!(
+                "~ {}: gap {} -> {}",
+                partition.name(),
+                units(old, previous.gap()),
+                units(new, partition.gap())
+            ));
+        }
+    }
+    if changes.is_empty() && old != new {
+        changes.push("partitions reordered".into());
+    }
+    changes
+}
+
+/// Describes the changes from `old` to `new`.
+fn diff(old: &Release, new: &Release) -> String {
+    let mut output = String::new();
+    for (label, old_value, new_value) in [
+        ("Name", old.project.name(), new.project.name()),
+        ("Version", old.project.version(), new.project.version()),
+    ] {
+        if old_value != new_value {
+            let _ = writeln!(output, "{}: {} -> {}", label, old_value, new_value);
+        }
+    }
+
+    let partitions = diff_partitions(old.project.partition_table(), new.project.partition_table());
+    if partitions.is_empty() {
+        let _ = writeln!(
+            output,
+            "Partition table: unchanged, --no-repartition is safe"
+        );
+    } else {
+        let _ = writeln!(
+            output,
+            "Partition table: changed, do not use --no-repartition"
+        );
+        for change in &partitions {
+            let _ = writeln!(output, "  {}", change);
+        }
+    }
+
+    let _ = writeln!(output, "Images:");
+    let mut unchanged = 0;
+    for (name, _) in &old.images {
+        if new.image(name).is_none() {
+            let _ = writeln!(output, "  - {}", name);
+        }
+    }
+    for (name, fingerprint) in &new.images {
+        match old.image(name) {
+            None => {
+                let _ = writeln!(output, "  + {} ({})", name, size_of(fingerprint));
+            }
+            Some(previous) if previous == fingerprint && fingerprint.is_some() => {
+                unchanged += 1;
+                let _ = writeln!(output, "  = {}", name);
+            }
+            Some(previous) => {
+                let _ = writeln!(
+                    output,
+                    "  ~ {} ({} -> {})",
+                    name,
+                    size_of(previous),
+                    size_of(fingerprint)
+                );
+            }
+        }
+    }
+    let _ = writeln!(
+        output,
+        "{} of {} images unchanged, --skip-same skips them on a device which has the old release",
+        unchanged,
+        new.images.len()
+    );
+    output
+}
+
+pub fn run(args: &DiffArgs) -> anyhow::Result<()> {
+    let read = |path: &PathBuf| {
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        Release::read(&mut reader, args.digest)
+    };
+    print!("{}", diff(&read(&args.old)?, &read(&args.new)?));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axdl::partition::ImageType;
+    use axdl_emulator::axp::{pattern, AxpBuilder};
+
+    fn release(builder: AxpBuilder) -> Release {
+        Release::read(&mut std::io::Cursor::new(builder.build()), Digest::Crc32).unwrap()
+    }
+
+    fn builder() -> AxpBuilder {
+        AxpBuilder::new(2)
+            .partition("spl", 0x100)
+            .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+            .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+            .code("SPL", "spl", pattern(1000, 3))
+    }
+
+    #[test]
+    fn test_diff_releases() {
+        let old = release(builder().partition("rootfs", 0x1000).code(
+            "ROOTFS",
+            "rootfs",
+            pattern(5000, 4),
+        ));
+        let new = release(
+            builder()
+                .partition("rootfs", 0x2000)
+                .partition("data", 0x800)
+                .code("ROOTFS", "rootfs", pattern(5000, 5))
+                .code("DATA", "data", pattern(300, 6)),
+        );
+        assert_eq!(
+            diff(&old, &new),
+            "Partition table: changed, do not use --no-repartition\n\
+             \x20 ~ rootfs: size 4194304 bytes -> 8388608 bytes\n\
+             \x20 + data (2097152 bytes)\n\
+             Images:\n\
+             \x20 = FDL1\n\
+             \x20 = FDL2\n\
+             \x20 = SPL\n\
+             \x20 ~ ROOTFS (5000 bytes -> 5000 bytes)\n\
+             \x20 + DATA (300 bytes)\n\
+             3 of 5 images unchanged, --skip-same skips them on a device which has the old release\n"
+        );
+        assert!(diff(&old, &old).starts_with("Partition table: unchanged"));
+    }
+
+    #[test]
+    fn test_digests_agree() {
+        let image = builder().build();
+        let read = |digest| Release::read(&mut std::io::Cursor::new(&image), digest).unwrap();
+        let crc32 = read(Digest::Crc32);
+        // The CRC-32 recorded in the archive is that of the decompressed file.
+        assert_eq!(
+            crc32.image("SPL"),
+            Some(&Some(vec![(1000, Digest::Crc32.digest(&pattern(1000, 3)))]))
+        );
+        assert_eq!(
+            read(Digest::Sha256).image("SPL"),
+            Some(&Some(vec![(
+                1000,
+                Digest::Sha256.digest(&pattern(1000, 3))
+            )]))
+        );
+    }
+}
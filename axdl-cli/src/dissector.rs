@@ -0,0 +1,271 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generates a Wireshark Lua dissector from [`axdl::frame::commands`], so that the dissector
+//! always knows the same codes and payload layouts as the implementation.
+
+use std::{fmt::Write as _, path::PathBuf};
+
+use axdl::{
+    frame::{
+        commands::{self, FieldType},
+        MINIMUM_LENGTH, SIGNATURE,
+    },
+    transport::ids,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct GenDissectorArgs {
+    #[clap(
+        long,
+        help = "Write the dissector to this file instead of the standard output"
+    )]
+    output: Option<PathBuf>,
+}
+
+/// Name of the Wireshark field of a payload field, e.g. `axdl.start_partition.length`.
+fn field_id(code: &commands::Code, field: &commands::Field) -> String {
+    format!("axdl.{}.{}", code.name.to_lowercase(), field.name)
+}
+
+/// Wireshark field declaration and the encoding passed to `add_packet_field`.
+fn proto_field(id: &str, name: &str, field_type: FieldType) -> (String, &'static str) {
+    match field_type {
+        // 64-bit fields also take 32-bit values, so START_PARTITION's address and length can be
+        // declared once for both of its address layouts.
+        FieldType::U16 | FieldType::U32 | FieldType::U64 => (
+            format!("ProtoField.uint64(\"{}\", \"{}\", base.DEC)", id, name),
+            "ENC_LITTLE_ENDIAN",
+        ),
+        FieldType::Utf16 { .. } => (
+            format!("ProtoField.string(\"{}\", \"{}\")", id, name),
+            "ENC_UTF_16 + ENC_LITTLE_ENDIAN",
+        ),
+        FieldType::Ascii => (
+            format!("ProtoField.string(\"{}\", \"{}\")", id, name),
+            "ENC_ASCII",
+        ),
+        FieldType::Bytes => (
+            format!("ProtoField.bytes(\"{}\", \"{}\")", id, name),
+            "ENC_NA",
+        ),
+    }
+}
+
+/// Size of the field in the Lua code, where -1 means up to the end of the payload.
+fn field_size(field_type: FieldType) -> i64 {
+    match field_type {
+        FieldType::U16 => 2,
+        FieldType::U32 => 4,
+        FieldType::U64 => 8,
+        FieldType::Utf16 { length } => length as i64,
+        FieldType::Ascii | FieldType::Bytes => -1,
+    }
+}
+
+/// Dissector function, which uses the tables generated in front of it.
+const DISSECTOR: &str = r#"
+local function is_handshake(buffer)
+    for i = 0, buffer:len() - 1 do
+        if buffer(i, 1):uint() ~= HANDSHAKE then
+            return false
+        end
+    end
+    return true
+end
+
+local function find_layout(code, payload_length)
+    for _, layout in ipairs(layouts[code] or {}) do
+        if layout.length == nil or layout.length == payload_length then
+            return layout
+        end
+    end
+    return nil
+end
+
+function axdl.dissector(buffer, pinfo, tree)
+    local length = buffer:len()
+    if length == 0 then
+        return 0
+    end
+    pinfo.cols.protocol = axdl.name
+    local subtree = tree:add(axdl, buffer(), "AXDL")
+    if length < HEADER_LENGTH + 2 or buffer(0, 4):le_uint() ~= SIGNATURE then
+        if is_handshake(buffer) then
+            subtree:add(f.handshake, buffer())
+            pinfo.cols.info = "HANDSHAKE"
+        else
+            subtree:add(f.data, buffer())
+            pinfo.cols.info = string.format("Data, %d bytes", length)
+        end
+        return length
+    end
+    local payload_length = buffer(4, 2):le_uint()
+    local code = buffer(6, 2):le_uint()
+    subtree:add_le(f.signature, buffer(0, 4))
+    subtree:add_le(f.length, buffer(4, 2))
+    subtree:add_le(f.code, buffer(6, 2))
+    pinfo.cols.info = codes[code] or string.format("Unknown 0x%04x", code)
+    if HEADER_LENGTH + payload_length + 2 > length then
+        return length
+    end
+    if payload_length > 0 then
+        local payload = buffer(HEADER_LENGTH, payload_length)
+        local layout = find_layout(code, payload_length)
+        if layout == nil then
+            subtree:add(f.payload, payload)
+        else
+            for _, field in ipairs(layout.fields) do
+                local size = field[3]
+                if size < 0 then
+                    size = payload_length - field[2]
+                end
+                subtree:add_packet_field(field[1], payload(field[2], size), field[4])
+            end
+        end
+    end
+    subtree:add_le(f.checksum, buffer(HEADER_LENGTH + payload_length, 2))
+    return length
+end
+"#;
+
+pub fn generate() -> String {
+    // Writing to a String does not fail, so the results of writeln! are ignored.
+    let mut lua = String::new();
+    let _ = writeln!(
+        lua,
+        "-- Wireshark dissector for the AXDL download protocol."
+    );
+    let _ = writeln!(
+        lua,
+        "-- Generated by axdl-cli {} gen-dissector, do not edit.",
+        env!("CARGO_PKG_VERSION")
+    );
+    let _ = writeln!(lua);
+    let _ = writeln!(
+        lua,
+        "local axdl = Proto(\"axdl\", \"Axera AXDL download protocol\")"
+    );
+    let _ = writeln!(lua, "local SIGNATURE = 0x{:08x}", SIGNATURE);
+    let _ = writeln!(lua, "local HEADER_LENGTH = {}", MINIMUM_LENGTH - 2);
+    let _ = writeln!(lua, "local HANDSHAKE = 0x{:02x}", commands::HANDSHAKE);
+    let _ = writeln!(lua);
+    let _ = writeln!(lua, "local codes = {{");
+    for code in commands::CODES {
+        let _ = writeln!(lua, "    [0x{:04x}] = \"{}\",", code.value, code.name);
+    }
+    let _ = writeln!(lua, "}}");
+    let _ = writeln!(lua);
+    lua.push_str(
+        r#"local f = axdl.fields
+f.signature = ProtoField.uint32("axdl.signature", "Signature", base.HEX)
+f.length = ProtoField.uint16("axdl.length", "Payload length", base.DEC)
+f.code = ProtoField.uint16("axdl.code", "Command/response", base.HEX, codes)
+f.checksum = ProtoField.uint16("axdl.checksum", "Checksum", base.HEX)
+f.payload = ProtoField.bytes("axdl.payload", "Payload")
+f.data = ProtoField.bytes("axdl.data", "Data")
+f.handshake = ProtoField.bytes("axdl.handshake", "Handshake")
+"#,
+    );
+
+    // Payload fields are declared once per code and name, as layouts may share them.
+    let mut layouts = String::new();
+    let mut declared = Vec::new();
+    for code in commands::CODES
+        .iter()
+        .filter(|code| !code.layouts.is_empty())
+    {
+        let _ = writeln!(layouts, "    [0x{:04x}] = {{", code.value);
+        for layout in code.layouts {
+            let length = layout
+                .length
+                .map(|length| length.to_string())
+                .unwrap_or_else(|| "nil".into());
+            let _ = writeln!(layouts, "        {{ length = {}, fields = {{", length);
+            for field in layout.fields {
+                let id = field_id(code, field);
+                let (declaration, encoding) = proto_field(&id, field.name, field.field_type);
+                if !declared.contains(&id) {
+                    let _ = writeln!(lua, "f[\"{}\"] = {}", id, declaration);
+                    declared.push(id.clone());
+                }
+                let _ = writeln!(
+                    layouts,
+                    "            {{ f[\"{}\"], {}, {}, {} }},",
+                    id,
+                    field.offset,
+                    field_size(field.field_type),
+                    encoding
+                );
+            }
+            let _ = writeln!(layouts, "        }} }},");
+        }
+        let _ = writeln!(layouts, "    }},");
+    }
+    let _ = writeln!(lua);
+    let _ = writeln!(
+        lua,
+        "-- Payload layouts per code: field, offset, size (-1 to the end), encoding."
+    );
+    let _ = writeln!(lua, "local layouts = {{");
+    lua.push_str(&layouts);
+    let _ = writeln!(lua, "}}");
+    lua.push_str(DISSECTOR);
+    let _ = writeln!(lua);
+    for id in ids::known() {
+        let _ = writeln!(
+            lua,
+            "DissectorTable.get(\"usb.product\"):add(0x{:04x}{:04x}, axdl)",
+            id.vendor_id, id.product_id
+        );
+    }
+    lua
+}
+
+pub fn run(args: &GenDissectorArgs) -> anyhow::Result<()> {
+    let lua = generate();
+    match &args.output {
+        Some(path) => std::fs::write(path, lua)?,
+        None => print!("{}", lua),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let lua = generate();
+        for code in commands::CODES {
+            assert!(lua.contains(&format!("[0x{:04x}] = \"{}\"", code.value, code.name)));
+        }
+        assert!(lua.contains(
+            "{ f[\"axdl.start_partition.partition\"], 0, 72, ENC_UTF_16 + ENC_LITTLE_ENDIAN },"
+        ));
+        assert!(lua.contains("{ f[\"axdl.read_block.offset\"], 4, 8, ENC_LITTLE_ENDIAN },"));
+        assert!(lua.contains("local SIGNATURE = 0x5c6d8e9f"));
+        assert!(lua.contains("add(0x32c91000, axdl)"));
+        // Fields shared by several layouts are declared once.
+        assert_eq!(
+            lua.matches("f[\"axdl.start_partition.length\"] = ").count(),
+            1
+        );
+        assert!(lua.contains(
+            "f[\"axdl.start_partition.length\"] = ProtoField.uint64(\"axdl.start_partition.length\", \"length\", base.DEC)"
+        ));
+    }
+}
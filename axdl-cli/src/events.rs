@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JSON events of a download, shared by `--notify-url` and `--ipc`.
+
+use axdl::{
+    progress::{ProgressEvent, StageEvent},
+    report::DownloadReport,
+    AxdlError,
+};
+use serde_json::json;
+
+/// Turns the progress of a download into JSON events, at most one per percent of each step.
+#[derive(Debug, Default)]
+pub struct JsonEvents {
+    /// Description and percentage of the last progress event, to skip unchanged ones.
+    last_progress: Option<(String, Option<u32>)>,
+}
+
+impl JsonEvents {
+    /// Returns the progress event, or `None` if the step and the percentage did not change.
+    pub fn progress(
+        &mut self,
+        event: Option<&ProgressEvent<'_>>,
+        description: &str,
+        progress: Option<f32>,
+    ) -> Option<serde_json::Value> {
+        let percent = progress.map(|progress| (progress * 100.0) as u32);
+        if self
+            .last_progress
+            .as_ref()
+            .is_some_and(|(last, last_percent)| last == description && *last_percent == percent)
+        {
+            return None;
+        }
+        self.last_progress = Some((description.to_string(), percent));
+        Some(json!({
+            "event": "progress",
+            "phase": event.map(|event| event.phase.id()),
+            "target": event.and_then(|event| event.target),
+            "timestamp_ms": event.map(|event| event.timestamp.as_millis() as u64),
+            "description": description,
+            "progress": progress,
+        }))
+    }
+}
+
+/// Returns the event of the device entering a boot stage.
+pub fn stage(event: &StageEvent<'_>) -> serde_json::Value {
+    json!({
+        "event": "stage",
+        "stage": event.stage.id(),
+        "handshake": event.handshake.map(|handshake| handshake.raw.as_str()),
+        "version": event.handshake.and_then(|handshake| handshake.version.as_deref()),
+        "timestamp_ms": event.timestamp.as_millis() as u64,
+    })
+}
+
+/// Returns the event of the final status of a download.
+pub fn finished(result: &Result<DownloadReport, AxdlError>) -> serde_json::Value {
+    match result {
+        Ok(report) => json!({
+            "event": "finished",
+            "success": report.is_success(),
+            "error": report.failure(),
+            "code": null,
+            "report": report.to_string(),
+        }),
+        Err(e) => json!({
+            "event": "finished",
+            "success": false,
+            "error": e.to_string(),
+            "code": e.code(),
+            "report": null,
+        }),
+    }
+}
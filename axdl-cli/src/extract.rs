@@ -0,0 +1,146 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Extracts one image from an AXP file by its name in the project, joining split images, so that
+//! it can be inspected or mounted without knowing the file names inside the archive.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Context as _};
+use axdl::partition::{Block, Image};
+
+#[derive(Debug, clap::Args)]
+pub struct ExtractArgs {
+    #[clap(long, help = "AXP image file")]
+    image: PathBuf,
+    #[clap(
+        long,
+        help = "Name of the image in the project or of its partition, e.g. ROOTFS"
+    )]
+    name: String,
+    #[clap(long, help = "File to write the image to")]
+    out: PathBuf,
+}
+
+fn matches(image: &Image, name: &str) -> bool {
+    image.name().eq_ignore_ascii_case(name)
+        || matches!(image.block(), Block::Partition(id) if id.eq_ignore_ascii_case(name))
+}
+
+/// Writes the image named `name` in the AXP file read from `reader` to `out`, returning its size.
+fn extract<R: std::io::Read + std::io::Seek>(
+    reader: &mut R,
+    name: &str,
+    out: impl FnOnce() -> std::io::Result<Box<dyn std::io::Write>>,
+) -> anyhow::Result<u64> {
+    let project = axdl::read_project(reader)?;
+    reader.rewind()?;
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let Some(image) = project.images().iter().find(|image| matches(image, name)) else {
+        let names = project
+            .images()
+            .iter()
+            .map(|image| image.name())
+            .collect::<Vec<_>>();
+        bail!(
+            "No image named {} in the project ({})",
+            name,
+            names.join(", ")
+        );
+    };
+    let parts = image.parts(archive.file_names());
+    if parts.is_empty() {
+        bail!("Image {} was not found in the archive", image.name());
+    }
+    let mut out = out()?;
+    let mut size = 0;
+    for part in &parts {
+        let mut file = archive
+            .by_name(part)
+            .with_context(|| format!("Failed to open {}", part))?;
+        size += std::io::copy(&mut file, &mut out)?;
+    }
+    out.flush()?;
+    Ok(size)
+}
+
+pub fn run(args: &ExtractArgs) -> anyhow::Result<()> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(&args.image)?);
+    let size = extract(&mut reader, &args.name, || {
+        let file = std::fs::File::create(&args.out)?;
+        Ok(Box::new(std::io::BufWriter::new(file)))
+    })?;
+    println!(
+        "Wrote {} bytes of {} to {}",
+        size,
+        args.name,
+        args.out.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use axdl::partition::ImageType;
+    use axdl_emulator::axp::{pattern, AxpBuilder};
+
+    /// Output which keeps what was written for the test to check.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_extract_split_image_by_name() {
+        let rootfs = pattern(250_000, 6);
+        let axp = AxpBuilder::new(2)
+            .partition("spl", 0x40000)
+            .partition("rootfs", 0x400000)
+            .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+            .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+            .code("SPL", "spl", pattern(1000, 3))
+            .split_code("ROOTFS", "rootfs", rootfs.clone(), 100_000)
+            .build();
+
+        let output = Output::default();
+        let out = output.clone();
+        let size = extract(&mut std::io::Cursor::new(&axp), "rootfs", || {
+            Ok(Box::new(out))
+        })
+        .unwrap();
+        assert_eq!(size, 250_000);
+        assert_eq!(*output.0.lock().unwrap(), rootfs);
+
+        let error = extract(&mut std::io::Cursor::new(&axp), "KERNEL", || {
+            Ok(Box::new(Output::default()))
+        })
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No image named KERNEL in the project (FDL1, FDL2, SPL, ROOTFS)"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Factory mode: flashes one unit after another, so that the operator only has to plug in a
+//! board in download mode and unplug it after the banner is shown.
+
+use std::path::PathBuf;
+
+use axdl::{download_image, AxdlError};
+
+#[cfg(feature = "stats")]
+use crate::stats::{sha256_file, SessionRecord, StatsDb};
+use crate::{check_boot, print_partial_failure, Args, CliProgress};
+
+#[derive(Debug, clap::Args)]
+pub struct FactoryArgs {
+    #[clap(long, help = "AXP image file")]
+    image: PathBuf,
+    #[clap(long, help = "Ring the terminal bell after each unit")]
+    beep: bool,
+    #[clap(long, help = "Do not read back the partitions after writing them")]
+    no_verify: bool,
+    #[clap(long, help = "Stop after this many units passed")]
+    count: Option<u64>,
+}
+
+const BANNER_WIDTH: usize = 60;
+
+/// Prints a full width colored banner which can be read from a distance.
+fn print_banner(passed: bool, title: &str) {
+    // Black on green for pass, white on red for fail.
+    let color = if passed {
+        "\x1b[1;30;42m"
+    } else {
+        "\x1b[1;37;41m"
+    };
+    let blank = " ".repeat(BANNER_WIDTH);
+    println!();
+    println!("{}{}\x1b[0m", color, blank);
+    println!("{}{:^width$}\x1b[0m", color, title, width = BANNER_WIDTH);
+    println!("{}{}\x1b[0m", color, blank);
+    println!();
+}
+
+/// Why a unit did not pass.
+enum Failure {
+    /// The device was unplugged, so there is nothing to wait for before the next unit.
+    Disconnected(String),
+    Other(String),
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Disconnected(message) | Failure::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<AxdlError> for Failure {
+    fn from(error: AxdlError) -> Self {
+        let disconnected = error.is_disconnected();
+        let message = match error {
+            AxdlError::PartialFailure {
+                completed,
+                failed,
+                remaining,
+                source,
+            } => {
+                print_partial_failure(&completed, &failed, &remaining);
+                format!("Failed to download {}: {}", failed, source)
+            }
+            e => e.to_string(),
+        };
+        if disconnected {
+            Failure::Disconnected(message)
+        } else {
+            Failure::Other(message)
+        }
+    }
+}
+
+/// Flashes and verifies one unit.
+fn flash_unit(args: &Args, factory: &FactoryArgs, provision_index: u64) -> Result<(), Failure> {
+    let mut config = crate::download_config(args, provision_index)
+        .map_err(|e| Failure::Other(format!("{:#}", e)))?;
+    config.verify = !factory.no_verify;
+    let device = args.native_transport().wait_for_device(None, || false)?;
+    let mut device = crate::inject_faults(args, device);
+    let mut file =
+        std::fs::File::open(&factory.image).map_err(|e| Failure::Other(e.to_string()))?;
+    let mut progress = CliProgress::for_download(args);
+    let outcome = download_image(&mut file, &mut device, &config, &mut progress)
+        .map(|report| check_boot(args, report));
+    progress.finished(&outcome);
+    let report = outcome?;
+    for line in report.to_string().lines() {
+        tracing::info!("{}", line);
+    }
+    match report.failure() {
+        Some(failure) => Err(Failure::Other(failure.into())),
+        None => Ok(()),
+    }
+}
+
+pub fn run(args: &Args, factory: &FactoryArgs) -> anyhow::Result<()> {
+    // Fail early on a bad configuration instead of on the first unit.
+    crate::download_config(args, args.provision_index)?;
+    std::fs::metadata(&factory.image)?;
+
+    #[cfg(feature = "stats")]
+    let stats = match &args.stats_db {
+        Some(db) => Some((StatsDb::open(db)?, sha256_file(&factory.image)?)),
+        None => None,
+    };
+
+    let transport = args.native_transport();
+    let mut passed = 0;
+    let mut failed = 0;
+    while factory.count.is_none_or(|count| passed < count) {
+        println!(
+            "Unit #{}: connect a device in download mode",
+            passed + failed + 1
+        );
+        // Serial numbers and MAC addresses are only used up by units which passed.
+        let provision_index = args.provision_index + passed;
+        #[cfg(feature = "stats")]
+        let started = std::time::Instant::now();
+        let result = flash_unit(args, factory, provision_index);
+        #[cfg(feature = "stats")]
+        if let Some((db, image_sha256)) = &stats {
+            let device_serial = crate::device_serial(args, provision_index);
+            let error = result.as_ref().err().map(Failure::to_string);
+            let record = SessionRecord {
+                device_serial: device_serial.as_deref(),
+                image: &factory.image,
+                image_sha256,
+                duration: started.elapsed(),
+                error: error.as_deref(),
+            };
+            if let Err(e) = db.record(&record) {
+                tracing::warn!("Failed to record the session: {}", e);
+            }
+        }
+        let disconnected = matches!(result, Err(Failure::Disconnected(_)));
+        match result {
+            Ok(()) => {
+                passed += 1;
+                print_banner(true, "PASS");
+            }
+            Err(e) => {
+                failed += 1;
+                print_banner(false, "FAIL");
+                println!("{}", e);
+            }
+        }
+        if factory.beep {
+            print!("\x07");
+        }
+        println!("Passed: {}, failed: {}", passed, failed);
+        if disconnected {
+            println!("The device was disconnected during the download");
+        } else {
+            println!("Remove the device");
+            transport.wait_for_removal(|| false)?;
+        }
+    }
+    Ok(())
+}
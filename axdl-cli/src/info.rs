@@ -0,0 +1,131 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lists the project, partition table and images of an AXP file. With `--deep` it also looks
+//! into the images, e.g. for the kernel and U-Boot versions, to confirm that a file is the
+//! intended release without extracting it.
+
+use std::{fmt::Write as _, path::PathBuf};
+
+use axdl::partition::Block;
+
+use crate::content;
+
+#[derive(Debug, clap::Args)]
+pub struct InfoArgs {
+    #[clap(long, help = "AXP image file")]
+    image: PathBuf,
+    #[clap(
+        long,
+        help = "Look into the images for kernel, U-Boot and device tree versions and file systems"
+    )]
+    deep: bool,
+}
+
+/// Describes the AXP file read from `reader`.
+fn info<R: std::io::Read + std::io::Seek>(reader: &mut R, deep: bool) -> anyhow::Result<String> {
+    let project = axdl::read_project(reader)?;
+    reader.rewind()?;
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let file_names = archive.file_names().map(str::to_string).collect::<Vec<_>>();
+
+    let mut output = String::new();
+    let _ = writeln!(output, "Project: {} {}", project.name(), project.version());
+    let table = project.partition_table();
+    let _ = writeln!(
+        output,
+        "Partitions (strategy {} unit {}):",
+        table.strategy(),
+        table.unit()
+    );
+    for partition in table.partitions() {
+        let _ = writeln!(
+            output,
+            "  {:<16} {}",
+            partition.name(),
+            crate::diff::units(table, partition.size())
+        );
+    }
+    let _ = writeln!(output, "Images:");
+    for image in project.images() {
+        let target = match image.block() {
+            Block::Partition(id) => id.clone(),
+            Block::Absolute(address) => format!("{:#x}", address),
+        };
+        let parts = image.parts(file_names.iter().map(String::as_str));
+        if parts.is_empty() {
+            let _ = writeln!(output, "  {:<16} {:<16} missing", image.name(), target);
+            continue;
+        }
+        let mut size = 0;
+        for part in &parts {
+            size += archive.by_name(part)?.size();
+        }
+        let mut line = format!("  {:<16} {:<16} {} bytes", image.name(), target, size);
+        if deep {
+            // The headers and banners are near the start, so a split image is only looked
+            // into by its first file.
+            if let Some(content) = content::peek(&mut archive.by_name(&parts[0])?)? {
+                line += &format!(", {}", content);
+            }
+        }
+        let _ = writeln!(output, "{}", line);
+    }
+    Ok(output)
+}
+
+pub fn run(args: &InfoArgs) -> anyhow::Result<()> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(&args.image)?);
+    print!("{}", info(&mut reader, args.deep)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axdl::partition::ImageType;
+    use axdl_emulator::axp::{pattern, AxpBuilder};
+
+    #[test]
+    fn test_deep_info() {
+        let mut kernel = vec![0u8; 0x1000];
+        kernel[0x38..0x3c].copy_from_slice(b"ARM\x64");
+        kernel.extend(b"Linux version 5.15.73 (builder@host) #1 SMP\n");
+        let axp = AxpBuilder::new(2)
+            .partition("spl", 0x100)
+            .partition("kernel", 0x1000)
+            .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+            .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+            .code(
+                "SPL",
+                "spl",
+                b"\0U-Boot SPL 2020.04 (Mar 01 2024)\0".to_vec(),
+            )
+            .code("KERNEL", "kernel", kernel)
+            .build();
+
+        let shallow = info(&mut std::io::Cursor::new(&axp), false).unwrap();
+        assert!(shallow.contains("  KERNEL           kernel           4140 bytes\n"));
+        let deep = info(&mut std::io::Cursor::new(&axp), true).unwrap();
+        assert!(deep.starts_with("Project: EMULATOR "));
+        assert!(deep.contains("  FDL1             0x3000           12345 bytes\n"));
+        assert!(deep.contains(
+            "  SPL              spl              34 bytes, U-Boot SPL 2020.04 (Mar 01 2024)\n"
+        ));
+        assert!(
+            deep.contains("  KERNEL           kernel           4140 bytes, Linux kernel 5.15.73\n")
+        );
+    }
+}
@@ -0,0 +1,300 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Local socket which streams the progress of a download as JSON lines and accepts a cancel
+//! command, so that IDEs and other desktop apps can run the CLI as a subprocess.
+//!
+//! Each event is one JSON object per line, the same as posted to `--notify-url`. Clients send
+//! `{"command":"cancel"}` to cancel the download. Events sent before a client connected are not
+//! replayed to it.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use axdl::{
+    cancel::CancellationToken,
+    progress::{ProgressEvent, StageEvent},
+    report::DownloadReport,
+    AxdlError,
+};
+
+use crate::events::{self, JsonEvents};
+
+/// Longest time a client may block an event, after which it is dropped so that it cannot stall
+/// the download.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+type Clients = Arc<Mutex<Vec<Box<dyn Write + Send>>>>;
+
+/// Server of `--ipc`, which accepts clients on a background thread.
+pub struct IpcServer {
+    /// Address clients connect to, with the port chosen by the OS if 0 was given.
+    address: String,
+    clients: Clients,
+    cancellation: CancellationToken,
+    events: JsonEvents,
+    /// Socket file, removed when the server is dropped.
+    #[cfg(unix)]
+    socket_path: Option<std::path::PathBuf>,
+}
+
+impl IpcServer {
+    /// Listens on `address`, which is `tcp:<host>:<port>` on the loopback interface or the path
+    /// of a Unix domain socket.
+    pub fn bind(address: &str) -> anyhow::Result<Self> {
+        let mut server = Self {
+            address: address.to_string(),
+            clients: Clients::default(),
+            cancellation: CancellationToken::new(),
+            events: JsonEvents::default(),
+            #[cfg(unix)]
+            socket_path: None,
+        };
+        if let Some(address) = address.strip_prefix("tcp:") {
+            let listener = std::net::TcpListener::bind(address)?;
+            let local_addr = listener.local_addr()?;
+            if !local_addr.ip().is_loopback() {
+                anyhow::bail!(
+                    "The IPC address must be on the loopback interface: {}",
+                    address
+                );
+            }
+            server.address = format!("tcp:{}", local_addr);
+            server.serve(
+                std::iter::from_fn(move || Some(listener.accept().map(|(stream, _)| stream))),
+                |stream| {
+                    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                    stream.try_clone()
+                },
+            );
+        } else {
+            server.bind_unix(address)?;
+        }
+        tracing::info!("Listening for IPC clients on {}", server.address);
+        Ok(server)
+    }
+
+    #[cfg(unix)]
+    fn bind_unix(&mut self, path: &str) -> anyhow::Result<()> {
+        use std::os::unix::{fs::FileTypeExt, net::UnixListener};
+
+        // A socket left over by a process which was killed; other files are not touched.
+        if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        self.socket_path = Some(path.into());
+        self.serve(
+            std::iter::from_fn(move || Some(listener.accept().map(|(stream, _)| stream))),
+            |stream| {
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                stream.try_clone()
+            },
+        );
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn bind_unix(&mut self, path: &str) -> anyhow::Result<()> {
+        anyhow::bail!(
+            "Unix domain sockets are not supported on this platform, use tcp:127.0.0.1:<port> instead of {}",
+            path
+        )
+    }
+
+    /// Accepts the clients connecting to `streams` on a background thread. `prepare` sets up a
+    /// new stream and returns a clone of it to read the commands from.
+    fn serve<S: Read + Write + Send + 'static>(
+        &self,
+        streams: impl Iterator<Item = std::io::Result<S>> + Send + 'static,
+        prepare: fn(&S) -> std::io::Result<S>,
+    ) {
+        let (clients, cancellation) = (self.clients.clone(), self.cancellation.clone());
+        std::thread::spawn(move || {
+            for stream in streams {
+                match stream.and_then(|stream| Ok((prepare(&stream)?, stream))) {
+                    Ok((reader, writer)) => add_client(&clients, &cancellation, reader, writer),
+                    Err(e) => tracing::warn!("Failed to accept an IPC client: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Token which is cancelled by the `cancel` command of a client.
+    pub fn cancellation(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// Writes `event` to every client, dropping the clients which are gone or too slow.
+    fn send(&self, event: &serde_json::Value) {
+        let line = format!("{}\n", event);
+        lock(&self.clients).retain_mut(|client| {
+            client
+                .write_all(line.as_bytes())
+                .and_then(|()| client.flush())
+                .is_ok()
+        });
+    }
+
+    /// Sends a progress event, at most once per percent of each step.
+    pub fn progress(&mut self, description: &str, progress: Option<f32>) {
+        if let Some(event) = self.events.progress(None, description, progress) {
+            self.send(&event);
+        }
+    }
+
+    /// Sends a progress event of the download with its phase and timestamp.
+    pub fn event(&mut self, event: &ProgressEvent<'_>) {
+        if let Some(json) = self
+            .events
+            .progress(Some(event), event.description, event.progress)
+        {
+            self.send(&json);
+        }
+    }
+
+    /// Sends the event of the device entering a boot stage.
+    pub fn stage(&mut self, event: &StageEvent<'_>) {
+        self.send(&events::stage(event));
+    }
+
+    /// Sends the final status of a download.
+    pub fn finished(&mut self, result: &Result<DownloadReport, AxdlError>) {
+        self.send(&events::finished(result));
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(path) = self.socket_path.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn lock(clients: &Clients) -> std::sync::MutexGuard<'_, Vec<Box<dyn Write + Send>>> {
+    clients.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Adds a client which receives the events on `writer`, and reads its commands from `reader` on a
+/// thread of its own.
+fn add_client(
+    clients: &Clients,
+    cancellation: &CancellationToken,
+    reader: impl Read + Send + 'static,
+    writer: impl Write + Send + 'static,
+) {
+    lock(clients).push(Box::new(writer));
+    let cancellation = cancellation.clone();
+    std::thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            match command(&line).as_deref() {
+                Some("cancel") => {
+                    tracing::warn!("Cancelled by an IPC client");
+                    cancellation.cancel();
+                }
+                _ if line.trim().is_empty() => {}
+                _ => tracing::warn!("Unknown IPC command: {}", line),
+            }
+        }
+    });
+}
+
+/// Returns the command of a line sent by a client, e.g. `cancel` for `{"command":"cancel"}`.
+fn command(line: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(line).ok()?;
+    value.get("command")?.as_str().map(str::to_string)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axdl::progress::Phase;
+
+    /// Polls `condition` for up to a few seconds, as clients are served on other threads.
+    fn eventually(condition: impl Fn() -> bool) -> bool {
+        (0..300).any(|_| {
+            std::thread::sleep(Duration::from_millis(10));
+            condition()
+        })
+    }
+
+    #[test]
+    fn test_ipc() {
+        assert!(IpcServer::bind("tcp:0.0.0.0:0").is_err());
+        let mut server = IpcServer::bind("tcp:127.0.0.1:0").unwrap();
+        let address = server.address.strip_prefix("tcp:").unwrap().to_string();
+        let client = std::net::TcpStream::connect(address).unwrap();
+        assert!(eventually(|| lock(&server.clients).len() == 1));
+
+        server.event(&ProgressEvent {
+            phase: Phase::Write,
+            target: Some("SPL"),
+            description: "Downloading image SPL",
+            progress: Some(0.5),
+            bytes: Some(5000),
+            timestamp: Duration::from_millis(1500),
+        });
+        // Same step and percentage, skipped.
+        server.progress("Downloading image SPL", Some(0.501));
+        server.finished(&Err(AxdlError::DeviceTimeout));
+        let mut lines = BufReader::new(client.try_clone().unwrap()).lines();
+        let mut next = || -> serde_json::Value {
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap()
+        };
+        let event = next();
+        assert_eq!(event["event"], "progress");
+        assert_eq!(event["target"], "SPL");
+        let event = next();
+        assert_eq!(event["event"], "finished");
+        assert_eq!(event["code"], "AXDL-DEV-002");
+
+        let cancellation = server.cancellation();
+        (&client).write_all(b"{\"command\":\"cancel\"}\n").unwrap();
+        assert!(eventually(|| cancellation.is_cancelled()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket() {
+        let path = std::env::temp_dir().join(format!("axdl-ipc-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let server = IpcServer::bind(path).unwrap();
+        // A stale socket is replaced.
+        std::mem::forget(server);
+        let server = IpcServer::bind(path).unwrap();
+        let _client = std::os::unix::net::UnixStream::connect(path).unwrap();
+        assert!(eventually(|| lock(&server.clients).len() == 1));
+        drop(server);
+        assert!(!std::path::Path::new(path).exists());
+    }
+
+    #[test]
+    fn test_command() {
+        assert_eq!(
+            command(r#"{"command":"cancel"}"#).as_deref(),
+            Some("cancel")
+        );
+        assert_eq!(command("cancel"), None);
+        assert_eq!(command(r#"{"cmd":"cancel"}"#), None);
+    }
+}
@@ -17,14 +17,49 @@ use std::time::Duration;
 
 use axdl::{
     download_image,
-    transport::{DynDevice, Transport as _},
-    DownloadConfig, DownloadProgress,
+    provision::{MacAddress, ProvisionData, Sequence, SerialNumber, Template},
+    report::DownloadReport,
+    transport::{
+        ids::UsbId,
+        inject::{FaultInjectingDevice, Injection},
+        record::RecordingDevice,
+        DynDevice, NativeTransport, TransportKind,
+    },
+    AxdlError, DownloadConfig, DownloadProgress, PartitionOptions,
 };
 
+mod capture;
+mod content;
+mod diff;
+mod dissector;
+#[cfg(any(feature = "notify", feature = "ipc"))]
+mod events;
+mod extract;
+mod factory;
+mod info;
+#[cfg(feature = "ipc")]
+mod ipc;
+#[cfg(feature = "manifest")]
+mod manifest;
+#[cfg(feature = "serial")]
+mod monitor;
+#[cfg(feature = "notify")]
+mod notify;
+mod overlay;
+mod replay;
+mod selftest;
+#[cfg(feature = "simulate")]
+mod simulate;
+#[cfg(feature = "stats")]
+mod stats;
+mod terminal;
+mod verify;
+
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum Transport {
     #[default]
     Usb,
+    #[cfg(feature = "serial")]
     Serial,
 }
 impl std::str::FromStr for Transport {
@@ -32,23 +67,54 @@ impl std::str::FromStr for Transport {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "usb" => Ok(Self::Usb),
+            #[cfg(feature = "serial")]
             "serial" => Ok(Self::Serial),
             _ => Err(format!("Unknown transport method: {}", s)),
         }
     }
 }
 
+/// Presets of the transfer settings for common flashing hosts.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum HostProfile {
+    /// Single-board computers such as the Raspberry Pi, where the time per block dominates.
+    Sbc,
+}
+
 /// command line arguments
 #[derive(Debug, clap::Parser)]
+#[command(subcommand_negates_reqs = true)]
 struct Args {
-    #[clap(short, long, help = "AXP image file")]
-    file: std::path::PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[clap(short, long, help = "AXP image file", required = true)]
+    file: Option<std::path::PathBuf>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "AXP image or directory whose images replace those of --file with the same name (repeatable, later ones win)"
+    )]
+    overlay: Vec<std::path::PathBuf>,
     #[clap(
         short,
         long,
         help = "Exclude root filesystem from the download operation"
     )]
     exclude_rootfs: bool,
+    #[clap(
+        long,
+        value_name = "NAMES",
+        value_delimiter = ',',
+        help = "Download only these partitions, by partition or image name (comma separated, repeatable)"
+    )]
+    include_partition: Vec<String>,
+    #[clap(
+        long,
+        value_name = "NAMES",
+        value_delimiter = ',',
+        help = "Skip these partitions, by partition or image name (comma separated, repeatable)"
+    )]
+    exclude_partition: Vec<String>,
     #[clap(short, long, help = "Wait for the device to be ready")]
     wait_for_device: bool,
     #[clap(long, help = "Timeout for waiting for the device to be ready")]
@@ -60,11 +126,594 @@ struct Args {
         default_value = "usb"
     )]
     transport: Transport,
+    #[clap(
+        long,
+        help = "Size of the receive buffer for response frames, also the largest partition table frame sent",
+        default_value_t = axdl::communication::DEFAULT_MAX_FRAME_SIZE
+    )]
+    max_frame_size: usize,
+    #[clap(
+        long,
+        help = "Block size used to download partition images (at most 65535 bytes)",
+        default_value_t = axdl::communication::DEFAULT_IMAGE_CHUNK_SIZE
+    )]
+    chunk_size: usize,
+    #[clap(
+        long,
+        help = "Use the chunk size as is instead of rounding it down to a multiple of the USB packet size"
+    )]
+    exact_chunk_size: bool,
+    #[clap(
+        long,
+        value_enum,
+        help = "Transfer settings preset for the host; sbc uses the largest blocks unless --chunk-size is given"
+    )]
+    profile: Option<HostProfile>,
+    #[clap(long, help = "Check every response frame and log protocol deviations")]
+    strict: bool,
+    #[clap(
+        long,
+        help = "Drop an acknowledge repeated within this many milliseconds, for firmware which sometimes sends it twice"
+    )]
+    duplicate_ack_window_ms: Option<u64>,
+    #[clap(
+        long,
+        help = "Read back every written partition and compare it with the image"
+    )]
+    verify: bool,
+    #[clap(
+        long,
+        default_value_t = axdl::hash::Digest::default(),
+        help = "Digest compared by --verify: crc32, sha256 or xxhash64"
+    )]
+    verify_digest: axdl::hash::Digest,
+    #[clap(
+        long,
+        help = "Skip partitions which already hold the image, found by reading them back first"
+    )]
+    skip_same: bool,
+    #[clap(
+        long,
+        help = "Number of times to retry the handshake",
+        default_value_t = 0
+    )]
+    handshake_retries: u32,
+    #[clap(
+        long,
+        help = "Also accept a handshake containing PATTERN at STAGE (romcode, fdl1 or fdl2), as STAGE=PATTERN"
+    )]
+    handshake: Vec<String>,
+    #[clap(
+        long,
+        help = "Bytes sent to request a handshake, in hexadecimal (default 3c3c3c)",
+        value_parser = parse_hex_bytes
+    )]
+    handshake_request: Option<Vec<u8>>,
+    #[clap(
+        long,
+        help = "Number of times to retry a failed block",
+        default_value_t = 0
+    )]
+    block_retries: u32,
+    #[clap(
+        long,
+        help = "Limit the image download speed to this many bytes per second"
+    )]
+    rate_limit: Option<u64>,
+    #[clap(
+        long,
+        help = "Pause between commands like the vendor tool, for firmware which fails at full speed"
+    )]
+    vendor_compat: bool,
+    #[clap(
+        long,
+        help = "Send the start block command and the block in separate writes on serial ports"
+    )]
+    no_coalesce_writes: bool,
+    #[clap(
+        long,
+        help = "Send a zero-length packet after every USB write which fills its last packet"
+    )]
+    zero_length_packets: bool,
+    #[clap(
+        long,
+        help = "Overwrite a partition image with the contents of FILE at OFFSET while writing it, as PARTITION@OFFSET=FILE"
+    )]
+    patch: Vec<String>,
+    #[clap(
+        long,
+        help = "Write only the first SIZE bytes of a partition image, as PARTITION=SIZE"
+    )]
+    truncate: Vec<String>,
+    #[clap(
+        long,
+        help = "Override options of one partition, as PARTITION:KEY=VALUE,... with the keys chunk-size, verify (on or off) and block-retries (repeatable)"
+    )]
+    partition_option: Vec<String>,
+    #[clap(
+        long,
+        value_parser = parse_number,
+        help = "Flash capacity of the device in bytes; fail before writing if the partition table does not fit"
+    )]
+    flash_size: Option<u64>,
+    #[clap(
+        long,
+        help = "Read images of up to this many MiB into memory while the device flushes the previous partition",
+        default_value_t = 0
+    )]
+    prefetch_mib: u64,
+    #[clap(
+        long,
+        value_parser = parse_duration,
+        help = "Time limit of the whole download, e.g. 90s, 15m or 1h, after which it stops with AXDL-DL-002"
+    )]
+    max_duration: Option<Duration>,
+    #[clap(long, help = "Timeout for commands and data blocks in seconds")]
+    timeout_secs: Option<u64>,
+    #[clap(
+        long,
+        help = "Timeout for finishing a partition image in seconds",
+        default_value_t = 60
+    )]
+    end_partition_timeout_secs: u64,
+    #[cfg(feature = "serial")]
+    #[clap(
+        long,
+        help = "RTS/DTR sequence run after opening the serial port, e.g. dtr=1,rts=1,wait=100,rts=0,wait=500,dtr=0"
+    )]
+    boot_sequence: Option<axdl::transport::serial::BootSequence>,
+    #[cfg(feature = "serial")]
+    #[clap(
+        long,
+        help = "USB interface of the download port for boards with several serial ports, by number (e.g. 0) or by a part of its name"
+    )]
+    serial_interface: Option<axdl::transport::serial::InterfaceFilter>,
+    #[cfg(feature = "serial")]
+    #[clap(
+        long,
+        help = "After the download, wait for the console to print PATTERN, e.g. a login prompt"
+    )]
+    boot_check: Option<String>,
+    #[cfg(feature = "serial")]
+    #[clap(
+        long,
+        help = "Seconds to wait for the --boot-check pattern",
+        default_value_t = 120
+    )]
+    boot_check_timeout_secs: u64,
+    #[cfg(feature = "serial")]
+    #[clap(
+        long,
+        help = "Serial port of the console for --boot-check, the first USB serial port found by default"
+    )]
+    console_port: Option<String>,
+    #[cfg(feature = "serial")]
+    #[clap(
+        long,
+        help = "Baud rate of the console for --boot-check",
+        default_value_t = 115_200
+    )]
+    console_baud: u32,
+    #[clap(
+        long,
+        help = "Partition to write per-device provisioning data to",
+        requires = "provision_template"
+    )]
+    provision_partition: Option<String>,
+    #[clap(
+        long,
+        help = "Template of the provisioning data with ${name} placeholders",
+        requires = "provision_partition"
+    )]
+    provision_template: Option<std::path::PathBuf>,
+    #[clap(
+        long,
+        help = "Serial number of the first device, available as ${serial}"
+    )]
+    provision_serial: Option<SerialNumber>,
+    #[clap(long, help = "MAC address of the first device, available as ${mac}")]
+    provision_mac: Option<MacAddress>,
+    #[clap(
+        long,
+        help = "Index of the device in the batch, added to the serial number and MAC address",
+        default_value_t = 0
+    )]
+    provision_index: u64,
+    #[clap(long, help = "Additional template value as name=value")]
+    provision_value: Vec<String>,
+    #[cfg(feature = "stats")]
+    #[clap(long, help = "SQLite database to record every download session in")]
+    stats_db: Option<std::path::PathBuf>,
+    #[cfg(feature = "notify")]
+    #[clap(
+        long,
+        help = "POST the progress and the final status of each download as JSON to this URL"
+    )]
+    notify_url: Option<String>,
+    #[cfg(feature = "ipc")]
+    #[clap(
+        long,
+        help = "Stream the progress as JSON lines to clients of this Unix socket, or of tcp:127.0.0.1:<port>, which may send {\"command\":\"cancel\"}"
+    )]
+    ipc: Option<String>,
+    #[clap(
+        long,
+        help = "Save every packet exchanged with the device to this file after a successful download"
+    )]
+    record: Option<std::path::PathBuf>,
+    #[cfg(feature = "simulate")]
+    #[clap(
+        long,
+        help = "Download to an emulated device instead of real hardware, to check an image or a script without a device"
+    )]
+    simulate: bool,
+    #[cfg(feature = "manifest")]
+    #[clap(
+        long,
+        help = "Write a CycloneDX manifest of the flashed images with their sizes and SHA-256 to this file after a successful download"
+    )]
+    manifest: Option<std::path::PathBuf>,
+    #[cfg(feature = "manifest")]
+    #[clap(
+        long,
+        default_value_t = axdl::hash::Digest::Sha256,
+        help = "Digest added to the SHA-256 in the manifest: crc32, sha256 or xxhash64"
+    )]
+    manifest_digest: axdl::hash::Digest,
+    #[clap(
+        long,
+        help = "Also look for devices with this USB id in download mode, e.g. 32c9:1001 (repeatable)"
+    )]
+    usb_id: Vec<UsbId>,
+    #[clap(
+        long,
+        help = "Inject faults into the responses of the device to reproduce field failures, e.g. drop-response:0.01 or corrupt-checksum:0.001 (repeatable)"
+    )]
+    inject: Vec<Injection>,
+    #[clap(
+        long,
+        help = "Seed of the injected faults, to inject the same ones again (random by default)"
+    )]
+    inject_seed: Option<u64>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Flash one unit after another, waiting for each device to be connected and removed
+    Factory(factory::FactoryArgs),
+    /// Show yield and throughput from the sessions recorded with --stats-db
+    #[cfg(feature = "stats")]
+    Stats(stats::StatsArgs),
+    /// Replay a session saved with --record and check that the same requests are sent
+    Replay(replay::ReplayArgs),
+    /// Generate a Wireshark Lua dissector for the AXDL protocol
+    GenDissector(dissector::GenDissectorArgs),
+    /// Rebuild an AXP image from a USB capture of a download
+    FromCapture(capture::CaptureArgs),
+    /// Write one image of an AXP file, found by its name in the project, to a file
+    Extract(extract::ExtractArgs),
+    /// List the project, partitions and images of an AXP file, with --deep also what the
+    /// images hold
+    Info(info::InfoArgs),
+    /// Compare the partition tables and images of two AXP files, e.g. two releases
+    Diff(diff::DiffArgs),
+    /// Check which protocol commands the romcode of a connected device supports, without
+    /// writing to its flash
+    Selftest(selftest::SelftestArgs),
+    /// Compare the partitions of a connected device with an AXP image without writing to its
+    /// flash
+    Verify(verify::VerifyArgs),
+    /// Stream the serial console of the board, e.g. to watch it boot after flashing
+    #[cfg(feature = "serial")]
+    Monitor(monitor::MonitorArgs),
+}
+
+impl Args {
+    fn native_transport(&self) -> NativeTransport {
+        match self.transport {
+            Transport::Usb => NativeTransport::Usb,
+            #[cfg(feature = "serial")]
+            Transport::Serial => NativeTransport::Serial,
+        }
+    }
+
+    fn transport_kind(&self) -> TransportKind {
+        match self.transport {
+            Transport::Usb => TransportKind::Usb,
+            #[cfg(feature = "serial")]
+            Transport::Serial => TransportKind::Serial,
+        }
+    }
+}
+
+/// Builds the download configuration, with provisioning data for the device at `provision_index`.
+fn download_config(args: &Args, provision_index: u64) -> anyhow::Result<DownloadConfig> {
+    let mut handshakes = axdl::communication::HandshakePatterns::default();
+    for handshake in &args.handshake {
+        let (stage, pattern) = handshake
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid handshake pattern: {}", handshake))?;
+        handshakes
+            .stage_mut(stage)
+            .ok_or_else(|| anyhow::anyhow!("Unknown boot stage: {}", stage))?
+            .push(pattern.to_string());
+    }
+    let config = DownloadConfig {
+        exclude_rootfs: args.exclude_rootfs,
+        include_partitions: args.include_partition.clone(),
+        exclude_partitions: args.exclude_partition.clone(),
+        max_frame_size: args.max_frame_size,
+        image_chunk_size: image_chunk_size(args),
+        auto_chunk_size: !args.exact_chunk_size,
+        strict: args.strict,
+        duplicate_ack_window: args
+            .duplicate_ack_window_ms
+            .map(std::time::Duration::from_millis),
+        verify: args.verify,
+        verify_digest: args.verify_digest,
+        skip_same: args.skip_same,
+        provision: provision_data(args, provision_index)?,
+        timeouts: {
+            let mut timeouts = axdl::communication::Timeouts {
+                end_partition: std::time::Duration::from_secs(args.end_partition_timeout_secs),
+                ..Default::default()
+            };
+            if let Some(timeout_secs) = args.timeout_secs {
+                timeouts.command = std::time::Duration::from_secs(timeout_secs);
+                timeouts.data = timeouts.command;
+            }
+            timeouts
+        },
+        retry: axdl::communication::RetryPolicy {
+            handshake: args.handshake_retries,
+            block: args.block_retries,
+        },
+        handshakes,
+        handshake_request: args
+            .handshake_request
+            .clone()
+            .unwrap_or_else(|| axdl::communication::DEFAULT_HANDSHAKE_REQUEST.to_vec()),
+        rate_limits: args
+            .rate_limit
+            .map(|rate| [(args.transport_kind(), rate)].into())
+            .unwrap_or_default(),
+        pacing: if args.vendor_compat {
+            axdl::communication::Pacing::VENDOR_COMPAT
+        } else {
+            axdl::communication::Pacing::default()
+        },
+        coalesce_writes: !args.no_coalesce_writes,
+        zero_length_packets: args.zero_length_packets,
+        filters: image_filters(args)?,
+        partition_options: partition_options(args)?,
+        max_duration: args.max_duration,
+        flash_capacity: args.flash_size,
+        prefetch_limit: args.prefetch_mib * 1024 * 1024,
+        sources: overlay_sources(args)?,
+        ..Default::default()
+    };
+    config.validate()?;
+    Ok(config)
+}
+
+/// Returns the sources of the images replaced by `--overlay`.
+fn overlay_sources(
+    args: &Args,
+) -> anyhow::Result<Vec<(String, std::sync::Arc<axdl::source::PartitionSource>)>> {
+    if args.overlay.is_empty() {
+        return Ok(Vec::new());
+    }
+    let file = args
+        .file
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("--overlay needs the base image given with --file"))?;
+    let project = axdl::read_project(&mut std::io::BufReader::new(std::fs::File::open(file)?))?;
+    overlay::sources(&project, &args.overlay)
+}
+
+/// Largest block which is a multiple of the high speed USB packet size and still fits in a
+/// read response of the default max frame size, so that --verify works with it.
+const SBC_CHUNK_SIZE: usize = 127 * 512;
+
+/// Returns the block size for partition images, from `--chunk-size` or the host profile.
+fn image_chunk_size(args: &Args) -> usize {
+    match args.profile {
+        // Fewer, larger blocks save a round trip per block, which costs the most on slow hosts.
+        Some(HostProfile::Sbc)
+            if args.chunk_size == axdl::communication::DEFAULT_IMAGE_CHUNK_SIZE =>
+        {
+            SBC_CHUNK_SIZE
+        }
+        _ => args.chunk_size,
+    }
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal number.
+fn parse_number(s: &str) -> Result<u64, std::num::ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+/// Parses a duration in seconds, or with an `s`, `m` or `h` suffix.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.strip_suffix(['s', 'm', 'h']) {
+        Some(number) => (number, &s[number.len()..]),
+        None => (s, "s"),
+    };
+    let value: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration: {}", s))?;
+    let seconds = match unit {
+        "m" => value * 60,
+        "h" => value * 3600,
+        _ => value,
+    };
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Parses bytes given in hexadecimal, e.g. `3c3c3c`.
+fn parse_hex_bytes(s: &str) -> Result<Vec<u8>, String> {
+    if s.is_empty() || !s.len().is_multiple_of(2) {
+        return Err("expected an even number of hexadecimal digits".into());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| format!("invalid hexadecimal byte at {}", i))
+        })
+        .collect()
+}
+
+/// Parses the `--patch` and `--truncate` options.
+fn image_filters(
+    args: &Args,
+) -> anyhow::Result<Vec<(String, std::sync::Arc<dyn axdl::filter::ImageFilter>)>> {
+    let mut filters: Vec<(String, std::sync::Arc<dyn axdl::filter::ImageFilter>)> = Vec::new();
+    for patch in &args.patch {
+        let invalid = || anyhow::anyhow!("Invalid patch: {}", patch);
+        let (target, file) = patch.split_once('=').ok_or_else(invalid)?;
+        let (partition, offset) = target.split_once('@').ok_or_else(invalid)?;
+        let offset = parse_number(offset).map_err(|_| invalid())?;
+        let data = std::fs::read(file)?;
+        filters.push((
+            partition.to_string(),
+            std::sync::Arc::new(axdl::filter::Patch { offset, data }),
+        ));
+    }
+    for truncate in &args.truncate {
+        let invalid = || anyhow::anyhow!("Invalid truncation: {}", truncate);
+        let (partition, size) = truncate.split_once('=').ok_or_else(invalid)?;
+        let size = parse_number(size).map_err(|_| invalid())?;
+        filters.push((
+            partition.to_string(),
+            std::sync::Arc::new(axdl::filter::Truncate { size }),
+        ));
+    }
+    Ok(filters)
+}
+
+/// Parses the `--partition-option` options, e.g. `spl:chunk-size=4096,block-retries=3`.
+fn partition_options(args: &Args) -> anyhow::Result<Vec<(String, PartitionOptions)>> {
+    let mut overrides = Vec::new();
+    for option in &args.partition_option {
+        let invalid = || anyhow::anyhow!("Invalid partition option: {}", option);
+        let (partition, settings) = option.split_once(':').ok_or_else(invalid)?;
+        let mut options = PartitionOptions::default();
+        for setting in settings.split(',') {
+            let (key, value) = setting.split_once('=').ok_or_else(invalid)?;
+            match key {
+                "chunk-size" => {
+                    options.chunk_size = Some(parse_number(value).map_err(|_| invalid())? as usize)
+                }
+                "verify" => {
+                    options.verify = Some(match value {
+                        "on" => true,
+                        "off" => false,
+                        _ => return Err(invalid()),
+                    })
+                }
+                "block-retries" => {
+                    options.block_retries = Some(value.parse().map_err(|_| invalid())?)
+                }
+                _ => anyhow::bail!("Unknown partition option {} in {}", key, option),
+            }
+        }
+        overrides.push((partition.to_string(), options));
+    }
+    Ok(overrides)
+}
+
+/// Returns the serial number provisioned to the device at `index`, if any.
+#[cfg(feature = "stats")]
+fn device_serial(args: &Args, index: u64) -> Option<String> {
+    args.provision_serial
+        .as_ref()
+        .and_then(|serial| serial.checked_add(index))
+        .map(|serial| serial.to_string())
+}
+
+/// Renders the provisioning template for the device at `index`.
+fn provision_data(args: &Args, index: u64) -> anyhow::Result<Option<ProvisionData>> {
+    let (Some(partition), Some(template)) = (&args.provision_partition, &args.provision_template)
+    else {
+        return Ok(None);
+    };
+    let template = Template::new(std::fs::read_to_string(template)?);
+    let mut values = Sequence {
+        serial: args.provision_serial.clone(),
+        mac: args.provision_mac,
+    }
+    .values(index)?;
+    for value in &args.provision_value {
+        let (name, value) = value
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid provisioning value: {}", value))?;
+        values.insert(name.to_string(), value.to_string());
+    }
+    let data = template.render(&values)?;
+    tracing::info!("Provisioning data for {}:\n{}", partition, data);
+    Ok(Some(ProvisionData {
+        partition: partition.clone(),
+        data: data.into_bytes(),
+    }))
+}
+
+/// Opens the first serial port and runs `--boot-sequence` on it, if one was given.
+#[cfg(feature = "serial")]
+fn open_serial(args: &Args) -> anyhow::Result<Option<DynDevice>> {
+    use axdl::transport::{serial::SerialTransport, Transport as _};
+
+    if args.transport != Transport::Serial {
+        return Ok(None);
+    }
+    let filter = args.serial_interface.as_ref();
+    if let Some(sequence) = &args.boot_sequence {
+        // The sequence puts the board into download mode, so the port must exist beforehand.
+        let paths = match filter {
+            Some(filter) => SerialTransport::list_devices_matching(filter)?,
+            None => SerialTransport::list_devices()?,
+        };
+        let path = paths
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Device not found"))?;
+        return Ok(Some(Box::new(
+            SerialTransport::open_device_with_boot_sequence(&path, sequence)?,
+        )));
+    }
+    let Some(filter) = filter else {
+        return Ok(None);
+    };
+    let device = if args.wait_for_device {
+        SerialTransport::wait_for_device_matching(
+            filter,
+            args.wait_for_device_timeout_secs.map(Duration::from_secs),
+            || false,
+        )?
+    } else {
+        SerialTransport::open_first_matching(filter)?.ok_or_else(|| {
+            anyhow::anyhow!("No serial port with the USB interface {} found", filter)
+        })?
+    };
+    Ok(Some(Box::new(device)))
+}
+
+#[cfg(not(feature = "serial"))]
+fn open_serial(_args: &Args) -> anyhow::Result<Option<DynDevice>> {
+    Ok(None)
 }
 
 struct CliProgress {
     pb: Option<indicatif::ProgressBar>,
     last_description: String,
+    #[cfg(feature = "notify")]
+    notifier: Option<notify::Notifier>,
+    #[cfg(feature = "ipc")]
+    ipc: Option<ipc::IpcServer>,
 }
 
 impl CliProgress {
@@ -72,18 +721,58 @@ impl CliProgress {
         Self {
             pb: None,
             last_description: String::new(),
+            #[cfg(feature = "notify")]
+            notifier: None,
+            #[cfg(feature = "ipc")]
+            ipc: None,
         }
     }
-}
 
-impl axdl::DownloadProgress for CliProgress {
-    fn is_cancelled(&self) -> bool {
-        false
+    /// Progress of a download run with `args`, also posted to `--notify-url` if given.
+    #[cfg_attr(not(feature = "notify"), allow(unused_variables))]
+    fn for_download(args: &Args) -> Self {
+        Self {
+            #[cfg(feature = "notify")]
+            notifier: args.notify_url.clone().map(notify::Notifier::new),
+            ..Self::new()
+        }
     }
-    fn report_progress(&mut self, description: &str, progress: Option<f32>) {
+
+    /// Starts the server of `--ipc`, if given, whose clients can cancel the download of `config`.
+    #[cfg(feature = "ipc")]
+    fn start_ipc(&mut self, args: &Args, config: &mut DownloadConfig) -> anyhow::Result<()> {
+        if let Some(address) = &args.ipc {
+            let server = ipc::IpcServer::bind(address)?;
+            config.cancellation = Some(server.cancellation());
+            self.ipc = Some(server);
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "ipc"))]
+    fn start_ipc(&mut self, _args: &Args, _config: &mut DownloadConfig) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Posts the final status of the download, if a notification URL was given, and sends it to
+    /// the IPC clients.
+    #[cfg_attr(not(any(feature = "notify", feature = "ipc")), allow(unused_variables))]
+    fn finished(&mut self, result: &Result<DownloadReport, AxdlError>) {
+        #[cfg(feature = "notify")]
+        if let Some(notifier) = &mut self.notifier {
+            notifier.finished(result);
+        }
+        #[cfg(feature = "ipc")]
+        if let Some(ipc) = &mut self.ipc {
+            ipc.finished(result);
+        }
+    }
+
+    /// Shows the progress on the console.
+    fn show(&mut self, description: &str, progress: Option<f32>) {
         if let Some(progress) = progress {
             if self.pb.is_none() {
-                let pb = indicatif::ProgressBar::new(100);
+                let pb = terminal::bars().add(indicatif::ProgressBar::new(100));
                 pb.set_style(
                     indicatif::ProgressStyle::with_template(
                         "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}]",
@@ -107,6 +796,182 @@ impl axdl::DownloadProgress for CliProgress {
     }
 }
 
+impl axdl::DownloadProgress for CliProgress {
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+    fn report_progress(&mut self, description: &str, progress: Option<f32>) {
+        #[cfg(feature = "notify")]
+        if let Some(notifier) = &mut self.notifier {
+            notifier.progress(description, progress);
+        }
+        #[cfg(feature = "ipc")]
+        if let Some(ipc) = &mut self.ipc {
+            ipc.progress(description, progress);
+        }
+        self.show(description, progress);
+    }
+    fn report_event(&mut self, event: &axdl::progress::ProgressEvent<'_>) {
+        #[cfg(feature = "notify")]
+        if let Some(notifier) = &mut self.notifier {
+            notifier.event(event);
+        }
+        #[cfg(feature = "ipc")]
+        if let Some(ipc) = &mut self.ipc {
+            ipc.event(event);
+        }
+        self.show(event.description, event.progress);
+    }
+    fn report_stage(&mut self, event: &axdl::progress::StageEvent<'_>) {
+        match event
+            .handshake
+            .and_then(|handshake| handshake.version.as_deref())
+        {
+            Some(version) => tracing::info!("Device entered {} (version {})", event.stage, version),
+            None => tracing::info!("Device entered {}", event.stage),
+        }
+        #[cfg(feature = "notify")]
+        if let Some(notifier) = &mut self.notifier {
+            notifier.stage(event);
+        }
+        #[cfg(feature = "ipc")]
+        if let Some(ipc) = &mut self.ipc {
+            ipc.stage(event);
+        }
+    }
+}
+
+/// Watches the console for `--boot-check`, if given, and adds the result to the report.
+#[cfg(feature = "serial")]
+fn check_boot(args: &Args, mut report: DownloadReport) -> DownloadReport {
+    if let Some(pattern) = &args.boot_check {
+        tracing::info!("Waiting for the device to boot");
+        report.boot = Some(monitor::check_boot(
+            args.console_port.clone(),
+            args.console_baud,
+            pattern,
+            Duration::from_secs(args.boot_check_timeout_secs),
+        ));
+    }
+    report
+}
+
+#[cfg(not(feature = "serial"))]
+fn check_boot(_args: &Args, report: DownloadReport) -> DownloadReport {
+    report
+}
+
+/// Prints which partitions are left in which state after a download stopped midway.
+/// Writes the manifest of a successful download to `--manifest`, if given.
+#[cfg(feature = "manifest")]
+fn write_manifest(
+    args: &Args,
+    image: &std::path::Path,
+    report: &DownloadReport,
+) -> anyhow::Result<()> {
+    match &args.manifest {
+        Some(path) => manifest::write(
+            path,
+            image,
+            report,
+            device_serial(args, args.provision_index).as_deref(),
+            args.manifest_digest,
+        ),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(feature = "manifest"))]
+fn write_manifest(
+    _args: &Args,
+    _image: &std::path::Path,
+    _report: &DownloadReport,
+) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// Opens the device with the boot sequence or the serial interface given, or the first one
+/// found, waiting for it with `--wait-for-device`.
+fn open_device(args: &Args) -> anyhow::Result<DynDevice> {
+    let device = if let Some(device) = simulated_device(args)? {
+        device
+    } else if let Some(device) = open_serial(args)? {
+        device
+    } else if args.wait_for_device {
+        args.native_transport()
+            .wait_for_device(
+                args.wait_for_device_timeout_secs.map(Duration::from_secs),
+                || false,
+            )
+            .map_err(|e| match e {
+                AxdlError::DeviceTimeout => anyhow::anyhow!("Timeout waiting for the device"),
+                e => anyhow::Error::from(e),
+            })?
+    } else {
+        args.native_transport()
+            .open_first()?
+            .ok_or_else(|| anyhow::anyhow!("Device not found"))?
+    };
+    Ok(inject_faults(args, device))
+}
+
+/// Returns the emulated device of `--simulate`, if given.
+#[cfg(feature = "simulate")]
+fn simulated_device(args: &Args) -> anyhow::Result<Option<DynDevice>> {
+    if !args.simulate {
+        return Ok(None);
+    }
+    let file = args
+        .file
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("--simulate needs --file"))?;
+    simulate::device(file, args.transport_kind()).map(Some)
+}
+
+#[cfg(not(feature = "simulate"))]
+fn simulated_device(_args: &Args) -> anyhow::Result<Option<DynDevice>> {
+    Ok(None)
+}
+
+/// Wraps the device to inject the faults given with `--inject`.
+fn inject_faults(args: &Args, device: DynDevice) -> DynDevice {
+    if args.inject.is_empty() {
+        return device;
+    }
+    let seed = args.inject_seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default()
+    });
+    tracing::warn!(
+        "Injecting faults with seed {}, rerun with --inject-seed {} to inject the same ones",
+        seed,
+        seed
+    );
+    Box::new(FaultInjectingDevice::new(device, args.inject.clone(), seed))
+}
+
+fn print_partial_failure(completed: &[String], failed: &str, remaining: &[String]) {
+    let list = |names: &[String]| {
+        if names.is_empty() {
+            "(none)".to_string()
+        } else {
+            names.join(", ")
+        }
+    };
+    terminal::bars().suspend(|| {
+        eprintln!();
+        eprintln!("==================== DOWNLOAD INCOMPLETE ====================");
+        eprintln!("The device now holds a mix of new and old partitions.");
+        eprintln!("  Written:     {}", list(completed));
+        eprintln!("  Failed:      {}", failed);
+        eprintln!("  Not written: {}", list(remaining));
+        eprintln!("=============================================================");
+        eprintln!();
+    });
+}
+
 fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt()
         .with_env_filter(
@@ -116,18 +981,49 @@ fn main() -> anyhow::Result<()> {
         )
         .with_file(true)
         .with_line_number(true)
+        .with_writer(|| terminal::LogWriter)
         .init();
 
     // Parse command line arguments.
     let args: Args = <Args as clap::Parser>::parse();
+    for id in &args.usb_id {
+        axdl::transport::ids::register(*id);
+    }
+
+    match &args.command {
+        Some(Command::Factory(factory)) => return factory::run(&args, factory),
+        #[cfg(feature = "stats")]
+        Some(Command::Stats(stats)) => {
+            let db = args
+                .stats_db
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("--stats-db is required"))?;
+            return stats::run(db, stats);
+        }
+        Some(Command::Replay(replay)) => return replay::run(&args, replay),
+        Some(Command::GenDissector(gen)) => return dissector::run(gen),
+        Some(Command::FromCapture(capture)) => return capture::run(capture),
+        Some(Command::Extract(extract)) => return extract::run(extract),
+        Some(Command::Info(info)) => return info::run(info),
+        Some(Command::Diff(diff)) => return diff::run(diff),
+        Some(Command::Selftest(selftest)) => return selftest::run(&args, selftest),
+        Some(Command::Verify(verify)) => return verify::run(&args, verify),
+        #[cfg(feature = "serial")]
+        Some(Command::Monitor(monitor)) => return monitor::run(monitor),
+        None => {}
+    }
 
     // Open the specified image file and find the configuration XML file.
-    let mut file = std::fs::File::open(&args.file)?;
-    let config = DownloadConfig {
-        exclude_rootfs: args.exclude_rootfs,
-    };
+    let file_path = args.file.as_deref().expect("--file is required");
+    let mut file = std::fs::File::open(file_path)?;
+    #[cfg(feature = "manifest")]
+    if args.manifest.is_some() && !args.overlay.is_empty() {
+        anyhow::bail!("--manifest cannot describe images replaced with --overlay");
+    }
+    let mut config = download_config(&args, args.provision_index)?;
 
-    let mut progress = CliProgress::new();
+    let mut progress = CliProgress::for_download(&args);
+    progress.start_ipc(&args, &mut config)?;
 
     if args.wait_for_device {
         if let Some(timeout) = args.wait_for_device_timeout_secs {
@@ -145,47 +1041,72 @@ fn main() -> anyhow::Result<()> {
         }
     }
 
-    let wait_start = std::time::Instant::now();
-    let mut device = loop {
-        let device: Option<DynDevice> = match args.transport {
-            Transport::Serial => axdl::transport::serial::SerialTransport::list_devices()?
-                .iter()
-                .next()
-                .map(|path| axdl::transport::serial::SerialTransport::open_device(path).ok())
-                .flatten()
-                .map(|device| {
-                    let device: DynDevice = Box::new(device);
-                    device
-                }),
-            Transport::Usb => axdl::transport::usb::UsbTransport::list_devices()?
-                .iter()
-                .next()
-                .map(|path| axdl::transport::usb::UsbTransport::open_device(path).ok())
-                .flatten()
-                .map(|device| {
-                    let device: DynDevice = Box::new(device);
-                    device
-                }),
-        };
+    let device = open_device(&args)?;
+    let (mut device, recording): (DynDevice, _) = if args.record.is_some() {
+        let recorder = RecordingDevice::new(device);
+        let recording = recorder.recording();
+        (Box::new(recorder), Some(recording))
+    } else {
+        (device, None)
+    };
 
-        if let Some(device) = device {
-            break device;
+    // Perform download
+    #[cfg(feature = "stats")]
+    let started = std::time::Instant::now();
+    let outcome = download_image(&mut file, &mut device, &config, &mut progress)
+        .map(|report| check_boot(&args, report));
+    progress.finished(&outcome);
+    let result = match outcome {
+        Err(AxdlError::PartialFailure {
+            completed,
+            failed,
+            remaining,
+            source,
+        }) => {
+            print_partial_failure(&completed, &failed, &remaining);
+            Err(anyhow::anyhow!("Failed to download {}: {}", failed, source))
         }
-
-        if args.wait_for_device {
-            if let Some(timeout) = args.wait_for_device_timeout_secs {
-                if wait_start.elapsed() > Duration::from_secs(timeout) {
-                    return Err(anyhow::anyhow!("Timeout waiting for the device"));
+        Err(e) => Err(e.into()),
+        Ok(report) => {
+            if config.verify || config.skip_same || report.boot.is_some() {
+                for line in report.to_string().lines() {
+                    tracing::info!("{}", line);
                 }
             }
-            std::thread::sleep(Duration::from_secs(1));
-        } else {
-            return Err(anyhow::anyhow!("Device not found"));
+            match report.failure() {
+                Some(failure) => Err(anyhow::anyhow!(failure)),
+                None => write_manifest(&args, file_path, &report),
+            }
         }
     };
-
-    // Perform download
-    download_image(&mut file, &mut device, &config, &mut progress)?;
-
-    Ok(())
+    if let (Some(path), Some(recording), Ok(())) = (&args.record, &recording, &result) {
+        let recording = recording.lock().unwrap_or_else(|e| e.into_inner());
+        recording.write_to(std::fs::File::create(path)?)?;
+        tracing::info!(
+            "Recorded {} packets to {}",
+            recording.packets.len(),
+            path.display()
+        );
+    }
+    #[cfg(feature = "stats")]
+    if let Some(db) = &args.stats_db {
+        let error = result.as_ref().err().map(|e| e.to_string());
+        let device_serial = device_serial(&args, args.provision_index);
+        let recorded = stats::StatsDb::open(db)
+            .map_err(anyhow::Error::from)
+            .and_then(|db| {
+                db.record(&stats::SessionRecord {
+                    device_serial: device_serial.as_deref(),
+                    image: file_path,
+                    image_sha256: &stats::sha256_file(file_path)?,
+                    duration: started.elapsed(),
+                    error: error.as_deref(),
+                })?;
+                Ok(())
+            });
+        if let Err(e) = recorded {
+            tracing::warn!("Failed to record the session: {}", e);
+        }
+    }
+    result
 }
@@ -0,0 +1,254 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Manifest of a download in the CycloneDX JSON format, listing the flashed images with their
+//! files, sizes and SHA-256, so that device provenance records can be fed to supply-chain tools.
+//!
+//! CycloneDX only knows cryptographic hashes, so another digest asked for, e.g. the CRC-32 of a
+//! factory specification, is added as an `axdl:<digest>` property next to the SHA-256.
+
+use std::{io::Read, path::Path};
+
+use axdl::{
+    hash::{self, Digest, Hasher},
+    report::DownloadReport,
+};
+use serde_json::json;
+
+/// SHA-256 and, unless it is SHA-256 too, the `extra` digest of data.
+struct Hashers {
+    sha256: Hasher,
+    extra: Option<(Digest, Hasher)>,
+}
+
+impl Hashers {
+    fn new(extra: Digest) -> Self {
+        Self {
+            sha256: Digest::Sha256.hasher(),
+            extra: (extra != Digest::Sha256).then(|| (extra, extra.hasher())),
+        }
+    }
+
+    /// Feeds everything read from `reader` to the hashers.
+    fn read(&mut self, reader: &mut dyn Read) -> std::io::Result<()> {
+        let mut buffer = vec![0u8; 1024 * 1024];
+        loop {
+            let length = reader.read(&mut buffer)?;
+            if length == 0 {
+                return Ok(());
+            }
+            self.sha256.update(&buffer[..length]);
+            if let Some((_, hasher)) = &mut self.extra {
+                hasher.update(&buffer[..length]);
+            }
+        }
+    }
+
+    /// Returns the `hashes` of a CycloneDX component, and the property of the extra digest.
+    fn finish(self) -> (serde_json::Value, Option<serde_json::Value>) {
+        let hashes = json!([{ "alg": "SHA-256", "content": hash::hex(&self.sha256.finalize()) }]);
+        let extra = self.extra.map(|(digest, hasher)| {
+            property(&format!("axdl:{}", digest), hash::hex(&hasher.finalize()))
+        });
+        (hashes, extra)
+    }
+}
+
+fn property(name: &str, value: impl ToString) -> serde_json::Value {
+    json!({ "name": name, "value": value.to_string() })
+}
+
+/// Builds the manifest of the partitions in `report`, written from the AXP image `file_name`
+/// read from `reader`, with the `digest` in addition to the SHA-256.
+fn manifest<R: Read + std::io::Seek>(
+    reader: &mut R,
+    file_name: &str,
+    report: &DownloadReport,
+    device_serial: Option<&str>,
+    digest: Digest,
+) -> anyhow::Result<serde_json::Value> {
+    let mut hashers = Hashers::new(digest);
+    hashers.read(reader)?;
+    let (image_hashes, image_digest) = hashers.finish();
+    reader.rewind()?;
+    let project = axdl::read_project(reader)?;
+    reader.rewind()?;
+    let mut archive = zip::ZipArchive::new(reader)?;
+
+    let mut components = Vec::new();
+    for partition in &report.partitions {
+        // Provisioning data is generated per device and has no file in the image.
+        let Some(image) = project.image(&partition.image) else {
+            continue;
+        };
+        let parts = image.parts(archive.file_names());
+        let mut hashers = Hashers::new(digest);
+        let mut size = 0;
+        for part in &parts {
+            let mut file = archive.by_name(part)?;
+            size += file.size();
+            hashers.read(&mut file)?;
+        }
+        let (hashes, extra) = hashers.finish();
+        let mut properties = vec![
+            property("axdl:partition", &partition.partition),
+            property("axdl:files", parts.join(",")),
+            property("axdl:size", size),
+            property("axdl:bytes-written", partition.bytes_written),
+            property("axdl:skipped", partition.skipped),
+        ];
+        properties.extend(extra);
+        components.push(json!({
+            "type": "firmware",
+            "bom-ref": partition.partition,
+            "name": partition.image,
+            "version": project.version(),
+            "hashes": hashes,
+            "properties": properties,
+        }));
+    }
+
+    let mut properties = vec![property("axdl:file", file_name)];
+    if let Some(serial) = device_serial {
+        properties.push(property("axdl:device-serial", serial));
+    }
+    properties.extend(image_digest);
+    Ok(json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": "axdl-cli",
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+            "component": {
+                "type": "firmware",
+                "name": project.name(),
+                "version": project.version(),
+                "hashes": image_hashes,
+                "properties": properties,
+            },
+        },
+        "components": components,
+    }))
+}
+
+/// Writes the manifest of a download of the AXP image `image` to `path`.
+pub fn write(
+    path: &Path,
+    image: &Path,
+    report: &DownloadReport,
+    device_serial: Option<&str>,
+    digest: Digest,
+) -> anyhow::Result<()> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(image)?);
+    let file_name = image
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let manifest = manifest(&mut reader, &file_name, report, device_serial, digest)?;
+    std::fs::write(path, serde_json::to_string_pretty(&manifest)?)?;
+    tracing::info!("Wrote the manifest to {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axdl::partition::ImageType;
+    use axdl::report::{PartitionReport, VerifyResult};
+    use axdl_emulator::axp::{pattern, AxpBuilder};
+
+    fn hash_of(data: &[u8]) -> String {
+        hash::hex(&Digest::Sha256.digest(data))
+    }
+
+    #[test]
+    fn test_manifest_of_download() {
+        let rootfs = pattern(250_000, 4);
+        let axp = AxpBuilder::new(2)
+            .partition("rootfs", 0x400000)
+            .partition("env", 0x1000)
+            .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+            .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+            .split_code("ROOTFS", "rootfs", rootfs.clone(), 100_000)
+            .build();
+        let partition = |image: &str, partition: &str| PartitionReport {
+            image: image.into(),
+            partition: partition.into(),
+            bytes_written: 250_000,
+            verify: VerifyResult::Skipped,
+            skipped: false,
+            duration: std::time::Duration::ZERO,
+        };
+        let report = DownloadReport {
+            partitions: vec![
+                partition("ROOTFS", "rootfs"),
+                partition(axdl::provision::IMAGE_NAME, "env"),
+            ],
+            ..Default::default()
+        };
+
+        let manifest = manifest(
+            &mut std::io::Cursor::new(&axp),
+            "image.axp",
+            &report,
+            Some("AX0100"),
+            Digest::Sha256,
+        )
+        .unwrap();
+        assert_eq!(manifest["bomFormat"], "CycloneDX");
+        let component = &manifest["metadata"]["component"];
+        assert_eq!(component["name"], "EMULATOR");
+        assert_eq!(component["hashes"][0]["content"], hash_of(&axp));
+        assert_eq!(component["properties"][1]["value"], "AX0100");
+        let components = manifest["components"].as_array().unwrap();
+        assert_eq!(components.len(), 1);
+        assert_eq!(components[0]["name"], "ROOTFS");
+        assert_eq!(components[0]["hashes"][0]["content"], hash_of(&rootfs));
+        assert_eq!(
+            components[0]["properties"][1]["value"],
+            "rootfs.img.000,rootfs.img.001,rootfs.img.002"
+        );
+        assert_eq!(components[0]["properties"][2]["value"], "250000");
+        assert_eq!(components[0]["properties"].as_array().unwrap().len(), 5);
+
+        let manifest = super::manifest(
+            &mut std::io::Cursor::new(&axp),
+            "image.axp",
+            &report,
+            None,
+            Digest::Crc32,
+        )
+        .unwrap();
+        let component = &manifest["metadata"]["component"];
+        assert_eq!(component["hashes"][0]["content"], hash_of(&axp));
+        assert_eq!(component["properties"][1]["name"], "axdl:crc32");
+        assert_eq!(
+            component["properties"][1]["value"],
+            hash::hex(&Digest::Crc32.digest(&axp))
+        );
+        let rootfs_property = &manifest["components"][0]["properties"][5];
+        assert_eq!(rootfs_property["name"], "axdl:crc32");
+        assert_eq!(
+            rootfs_property["value"],
+            hash::hex(&Digest::Crc32.digest(&rootfs))
+        );
+    }
+}
@@ -0,0 +1,258 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Monitor mode: streams the serial console of the board after flashing, so that users can see
+//! the new image boot without switching to a terminal program.
+
+use std::{
+    io::{Read, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use axdl::report::BootCheck;
+
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+const OPEN_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, clap::Args)]
+pub struct MonitorArgs {
+    #[clap(
+        long,
+        help = "Serial port of the console, the first USB serial port found by default"
+    )]
+    port: Option<String>,
+    #[clap(long, help = "Baud rate of the console", default_value_t = 115_200)]
+    baud: u32,
+    #[clap(
+        long,
+        help = "Prefix each line with the seconds since the monitor started"
+    )]
+    timestamps: bool,
+    #[clap(long, help = "Also write the console output to FILE")]
+    log: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Seconds to wait for the port to appear, e.g. while the board reboots",
+        default_value_t = 30
+    )]
+    wait_secs: u64,
+    #[clap(
+        long,
+        help = "Exit once the console prints PATTERN, e.g. a login prompt"
+    )]
+    until: Option<String>,
+    #[clap(
+        long,
+        help = "Stop after this many seconds; with --until, not seeing the pattern by then is an error"
+    )]
+    timeout_secs: Option<u64>,
+}
+
+/// Prefixes every line with a timestamp, keeping track of line starts across reads.
+struct Stamper {
+    timestamps: bool,
+    at_line_start: bool,
+}
+
+impl Stamper {
+    fn new(timestamps: bool) -> Self {
+        Self {
+            timestamps,
+            at_line_start: true,
+        }
+    }
+
+    /// Appends `data`, received `elapsed` after the start, to `output`.
+    fn stamp(&mut self, data: &[u8], elapsed: Duration, output: &mut Vec<u8>) {
+        for &byte in data {
+            if self.timestamps && self.at_line_start {
+                // Writing to a Vec cannot fail.
+                let _ = write!(output, "[{:>10.3}] ", elapsed.as_secs_f64());
+            }
+            output.push(byte);
+            self.at_line_start = byte == b'\n';
+        }
+    }
+}
+
+/// Looks for a pattern in a stream, also when it is split across reads.
+struct Matcher {
+    pattern: Vec<u8>,
+    tail: Vec<u8>,
+}
+
+impl Matcher {
+    fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.as_bytes().to_vec(),
+            tail: Vec::new(),
+        }
+    }
+
+    /// Returns whether the pattern has appeared once `data` is added.
+    fn feed(&mut self, data: &[u8]) -> bool {
+        self.tail.extend_from_slice(data);
+        let found = self
+            .tail
+            .windows(self.pattern.len().max(1))
+            .any(|window| window == self.pattern.as_slice());
+        let keep = self.pattern.len().saturating_sub(1);
+        let drop = self.tail.len().saturating_sub(keep);
+        self.tail.drain(..drop);
+        found
+    }
+}
+
+/// Returns the port given with `--port` or the first USB serial port.
+fn find_port(monitor: &MonitorArgs) -> Option<String> {
+    if let Some(port) = &monitor.port {
+        return Some(port.clone());
+    }
+    serialport::available_ports()
+        .ok()?
+        .into_iter()
+        .find(|port| matches!(port.port_type, serialport::SerialPortType::UsbPort(_)))
+        .map(|port| port.port_name)
+}
+
+/// Opens the console, waiting up to `--wait-secs` for it to appear.
+fn open(monitor: &MonitorArgs) -> anyhow::Result<Box<dyn serialport::SerialPort>> {
+    let deadline = Instant::now() + Duration::from_secs(monitor.wait_secs);
+    loop {
+        let result = find_port(monitor).map(|path| {
+            serialport::new(&path, monitor.baud)
+                .timeout(READ_TIMEOUT)
+                .open()
+                .map(|port| (path, port))
+        });
+        match result {
+            Some(Ok((path, port))) => {
+                tracing::info!("Monitoring {} at {} baud", path, monitor.baud);
+                return Ok(port);
+            }
+            Some(Err(e)) if Instant::now() >= deadline => {
+                anyhow::bail!("Failed to open the console: {}", e)
+            }
+            None if Instant::now() >= deadline => anyhow::bail!("Console port not found"),
+            _ => std::thread::sleep(OPEN_INTERVAL),
+        }
+    }
+}
+
+/// Streams the console until `--until` appears, returning when it did, or until `--timeout-secs`
+/// passes, returning `None`.
+fn watch(monitor: &MonitorArgs) -> anyhow::Result<Option<Duration>> {
+    let started = Instant::now();
+    let timeout = monitor.timeout_secs.map(Duration::from_secs);
+    let mut log = monitor
+        .log
+        .as_ref()
+        .map(std::fs::File::create)
+        .transpose()?;
+    let mut stamper = Stamper::new(monitor.timestamps);
+    let mut matcher = monitor.until.as_deref().map(Matcher::new);
+    let mut port = open(monitor)?;
+    let mut buffer = vec![0u8; 4096];
+    let mut output = Vec::new();
+    let stdout = std::io::stdout();
+    loop {
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            return Ok(None);
+        }
+        let length = match port.read(&mut buffer) {
+            Ok(length) => length,
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+            Err(e) => {
+                // The board may reset once more, e.g. after first boot setup.
+                tracing::warn!("Console disconnected ({}), waiting for it", e);
+                port = open(monitor)?;
+                continue;
+            }
+        };
+        let data = &buffer[..length];
+        output.clear();
+        stamper.stamp(data, started.elapsed(), &mut output);
+        let mut stdout = stdout.lock();
+        stdout.write_all(&output)?;
+        stdout.flush()?;
+        if let Some(log) = &mut log {
+            log.write_all(&output)?;
+        }
+        if matcher.as_mut().is_some_and(|matcher| matcher.feed(data)) {
+            println!();
+            return Ok(Some(started.elapsed()));
+        }
+    }
+}
+
+pub fn run(monitor: &MonitorArgs) -> anyhow::Result<()> {
+    match (watch(monitor)?, &monitor.until) {
+        (None, Some(pattern)) => anyhow::bail!("The console did not print {:?} in time", pattern),
+        (Some(_), Some(pattern)) => tracing::info!("Found {:?} on the console", pattern),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Waits for the console on `port` to print `pattern` after a download.
+///
+/// A console which cannot be opened counts as a failed boot, since a board which does not boot
+/// may not bring up its USB serial port either.
+pub fn check_boot(port: Option<String>, baud: u32, pattern: &str, timeout: Duration) -> BootCheck {
+    let monitor = MonitorArgs {
+        port,
+        baud,
+        timestamps: false,
+        log: None,
+        wait_secs: timeout.as_secs(),
+        until: Some(pattern.to_string()),
+        timeout_secs: Some(timeout.as_secs()),
+    };
+    let found_after = watch(&monitor).unwrap_or_else(|e| {
+        tracing::warn!("Boot check failed: {}", e);
+        None
+    });
+    BootCheck {
+        pattern: pattern.to_string(),
+        found_after,
+        timeout,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stamp_lines_across_reads() {
+        let mut stamper = Stamper::new(true);
+        let mut output = Vec::new();
+        stamper.stamp(b"U-Boot\nSta", Duration::from_millis(1500), &mut output);
+        stamper.stamp(b"rting kernel\n", Duration::from_millis(2250), &mut output);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[     1.500] U-Boot\n[     1.500] Starting kernel\n"
+        );
+    }
+
+    #[test]
+    fn test_match_across_reads() {
+        let mut matcher = Matcher::new("login:");
+        assert!(!matcher.feed(b"axera lo"));
+        assert!(matcher.feed(b"gin: "));
+        assert!(!Matcher::new("login:").feed(b"log in:"));
+    }
+}
@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Posts the progress and the final status of downloads as JSON to a URL, so that lab
+//! dashboards and chat integrations can follow long flash jobs.
+
+use std::{sync::mpsc, thread::JoinHandle, time::Duration};
+
+use axdl::{
+    progress::{ProgressEvent, StageEvent},
+    report::DownloadReport,
+    AxdlError,
+};
+
+use crate::events::{self, JsonEvents};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts events from a background thread, so that a slow server does not slow down the
+/// download. Events still queued are sent when the notifier is dropped.
+pub struct Notifier {
+    sender: Option<mpsc::Sender<serde_json::Value>>,
+    thread: Option<JoinHandle<()>>,
+    events: JsonEvents,
+}
+
+impl Notifier {
+    pub fn new(url: String) -> Self {
+        let (sender, receiver) = mpsc::channel::<serde_json::Value>();
+        let thread = std::thread::spawn(move || {
+            let agent: ureq::Agent = ureq::Agent::config_builder()
+                .timeout_global(Some(TIMEOUT))
+                .build()
+                .into();
+            for event in receiver {
+                if let Err(e) = agent.post(&url).send_json(&event) {
+                    tracing::warn!("Failed to notify {}: {}", url, e);
+                }
+            }
+        });
+        Self {
+            sender: Some(sender),
+            thread: Some(thread),
+            events: JsonEvents::default(),
+        }
+    }
+
+    fn send(&self, event: serde_json::Value) {
+        if let Some(sender) = &self.sender {
+            // The thread only stops once the sender is dropped.
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Sends a progress event, at most once per percent of each step.
+    pub fn progress(&mut self, description: &str, progress: Option<f32>) {
+        if let Some(event) = self.events.progress(None, description, progress) {
+            self.send(event);
+        }
+    }
+
+    /// Sends a progress event of the download with its phase and timestamp.
+    pub fn event(&mut self, event: &ProgressEvent<'_>) {
+        if let Some(json) = self
+            .events
+            .progress(Some(event), event.description, event.progress)
+        {
+            self.send(json);
+        }
+    }
+
+    /// Sends the event of the device entering a boot stage.
+    pub fn stage(&mut self, event: &StageEvent<'_>) {
+        self.send(events::stage(event));
+    }
+
+    /// Sends the final status of a download.
+    pub fn finished(&mut self, result: &Result<DownloadReport, AxdlError>) {
+        self.send(events::finished(result));
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    use axdl::progress::Phase;
+
+    use super::*;
+
+    /// Accepts POST requests on `listener` and returns their bodies once the client is gone.
+    fn serve(listener: std::net::TcpListener) -> JoinHandle<Vec<serde_json::Value>> {
+        std::thread::spawn(move || {
+            let mut bodies = Vec::new();
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            loop {
+                let mut length = 0;
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0u8; length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(serde_json::from_slice(&body).unwrap());
+                writer
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                    .unwrap();
+            }
+            bodies
+        })
+    }
+
+    #[test]
+    fn test_notify() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = serve(listener);
+
+        let mut notifier = Notifier::new(url);
+        notifier.event(&ProgressEvent {
+            phase: Phase::Write,
+            target: Some("SPL"),
+            description: "Downloading image SPL",
+            progress: Some(0.501),
+            bytes: Some(5010),
+            timestamp: Duration::from_millis(1500),
+        });
+        // Same step and percentage, skipped.
+        notifier.progress("Downloading image SPL", Some(0.505));
+        notifier.finished(&Err(AxdlError::DeviceTimeout));
+        drop(notifier);
+
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[0]["event"], "progress");
+        assert_eq!(bodies[0]["description"], "Downloading image SPL");
+        assert_eq!(bodies[0]["phase"], "write");
+        assert_eq!(bodies[0]["target"], "SPL");
+        assert_eq!(bodies[0]["timestamp_ms"], 1500);
+        assert_eq!(bodies[1]["event"], "finished");
+        assert_eq!(bodies[1]["success"], false);
+        assert_eq!(bodies[1]["error"], "[AXDL-DEV-002] Device timeout");
+        assert_eq!(bodies[1]["code"], "AXDL-DEV-002");
+    }
+}
@@ -0,0 +1,219 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Overlays of an AXP image: another AXP image or a directory whose files replace code images
+//! of the base image by name, e.g. a custom application partition on a vendor image, so that a
+//! large image need not be repacked for one changed partition.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use axdl::{
+    partition::{ImageType, Project},
+    source::PartitionSource,
+};
+
+/// Returns the sources replacing code images of the base `project` with those found in
+/// `overlays`. An image found in several overlays is taken from the last one.
+pub fn sources(
+    project: &Project,
+    overlays: &[PathBuf],
+) -> anyhow::Result<Vec<(String, Arc<PartitionSource>)>> {
+    let mut sources: Vec<(String, Arc<PartitionSource>)> = Vec::new();
+    for overlay in overlays {
+        let found = if overlay.is_dir() {
+            directory(project, overlay)?
+        } else {
+            archive(project, overlay)?
+        };
+        if found.is_empty() {
+            tracing::warn!("No image of the base image found in {}", overlay.display());
+        }
+        for (name, source) in found {
+            tracing::info!("Image {} is taken from {}", name, overlay.display());
+            sources.retain(|(other, _)| *other != name);
+            sources.push((name, Arc::new(source)));
+        }
+    }
+    Ok(sources)
+}
+
+/// Finds the files of the code images of `project` in the directory `path`, under the file
+/// names of the base image.
+fn directory(project: &Project, path: &Path) -> anyhow::Result<Vec<(String, PartitionSource)>> {
+    let mut file_names = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            file_names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    let mut sources = Vec::new();
+    for image in project.images_of_type(ImageType::Code) {
+        let parts = image.parts(file_names.iter().map(String::as_str));
+        if parts.is_empty() {
+            continue;
+        }
+        let mut size = 0;
+        for part in &parts {
+            size += std::fs::metadata(path.join(part))?.len();
+        }
+        let path = path.to_path_buf();
+        let source = PartitionSource::from_read(size, move || {
+            let mut reader: Box<dyn Read> = Box::new(std::io::empty());
+            for part in &parts {
+                reader = Box::new(reader.chain(File::open(path.join(part))?));
+            }
+            Ok(reader)
+        });
+        sources.push((image.name().to_string(), source));
+    }
+    Ok(sources)
+}
+
+/// Finds the code images of the AXP image `path` which are also in `project`.
+fn archive(project: &Project, path: &Path) -> anyhow::Result<Vec<(String, PartitionSource)>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let overlay = axdl::read_project(&mut reader)?;
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let mut sources = Vec::new();
+    for image in overlay.images_of_type(ImageType::Code) {
+        if project.image(image.name()).is_none() {
+            tracing::warn!(
+                "Image {} of {} is not in the base image, ignoring it",
+                image.name(),
+                path.display()
+            );
+            continue;
+        }
+        let parts = image.parts(archive.file_names());
+        if parts.is_empty() {
+            anyhow::bail!("Image {} was not found in {}", image.name(), path.display());
+        }
+        let mut size = 0;
+        for part in &parts {
+            size += archive.by_name(part)?.size();
+        }
+        let path = path.to_path_buf();
+        let source = PartitionSource::from_read(size, move || ArchiveReader::open(&path, &parts));
+        sources.push((image.name().to_string(), source));
+    }
+    Ok(sources)
+}
+
+/// Reads files of an AXP image one after another.
+///
+/// A file in the archive borrows the archive, so a thread which owns the archive decompresses
+/// the files into a pipe instead of reading the whole image into memory.
+struct ArchiveReader {
+    pipe: std::io::PipeReader,
+    copier: Option<std::thread::JoinHandle<std::io::Result<()>>>,
+}
+
+impl ArchiveReader {
+    fn open(path: &Path, parts: &[String]) -> std::io::Result<Self> {
+        let (pipe, mut writer) = std::io::pipe()?;
+        let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+        let parts = parts.to_vec();
+        let copier = std::thread::spawn(move || {
+            for part in &parts {
+                std::io::copy(&mut archive.by_name(part)?, &mut writer)?;
+            }
+            Ok(())
+        });
+        Ok(Self {
+            pipe,
+            copier: Some(copier),
+        })
+    }
+}
+
+impl Read for ArchiveReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let length = self.pipe.read(buf)?;
+        // The pipe also ends when the copier failed, so its result tells whether all was read.
+        if length == 0 && !buf.is_empty() {
+            if let Some(copier) = self.copier.take() {
+                copier
+                    .join()
+                    .map_err(|_| std::io::Error::other("overlay reader panicked"))??;
+            }
+        }
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axdl::{progress::NoProgress, DownloadConfig};
+    use axdl_emulator::{
+        axp::{pattern, AxpBuilder},
+        Emulator,
+    };
+
+    #[test]
+    fn test_overlay_download() {
+        let base = AxpBuilder::new(2)
+            .partition("spl", 0x40000)
+            .partition("rootfs", 0x400000)
+            .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+            .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+            .code("SPL", "spl", pattern(1000, 3))
+            .code("ROOTFS", "rootfs", pattern(5000, 4));
+        let dir = std::env::temp_dir().join(format!("axdl-overlay-{}", std::process::id()));
+        let files = dir.join("files");
+        std::fs::create_dir_all(&files).unwrap();
+        let archive = dir.join("overlay.axp");
+        let rootfs = pattern(300_000, 5);
+        let overlay = AxpBuilder::new(2)
+            .partition("rootfs", 0x400000)
+            .partition("app", 0x400000)
+            .split_code("ROOTFS", "rootfs", rootfs.clone(), 100_000)
+            .code("APP", "app", pattern(10, 6))
+            .compression(zip::CompressionMethod::Deflated)
+            .build();
+        std::fs::write(&archive, overlay).unwrap();
+        std::fs::write(files.join("spl.img"), pattern(2000, 7)).unwrap();
+        std::fs::write(files.join("rootfs.img"), pattern(10, 8)).unwrap();
+
+        // The directory replaces SPL, and the archive given after it ROOTFS.
+        let sources = sources(&base.project(), &[files, archive]).unwrap();
+        let names: Vec<_> = sources.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["SPL", "ROOTFS"]);
+        assert_eq!(sources[1].1.size(), 300_000);
+
+        let emulator = Emulator::new(2);
+        let config = DownloadConfig {
+            sources,
+            verify: true,
+            ..Default::default()
+        };
+        let result = axdl::download_image(
+            &mut std::io::Cursor::new(base.build()),
+            &mut emulator.dyn_device(),
+            &config,
+            &mut NoProgress,
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(result.unwrap().is_success());
+        assert_eq!(emulator.partition("spl"), Some(pattern(2000, 7)));
+        assert_eq!(emulator.partition("rootfs"), Some(rootfs));
+    }
+}
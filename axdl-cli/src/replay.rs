@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Replay mode: runs a download against a session recorded with `--record` and checks that the
+//! same requests are sent, without a device.
+
+use std::path::PathBuf;
+
+use axdl::{
+    download_image,
+    transport::{
+        record::{Recording, ReplayDevice},
+        DynDevice,
+    },
+};
+
+use crate::{Args, CliProgress};
+
+#[derive(Debug, clap::Args)]
+pub struct ReplayArgs {
+    #[clap(long, help = "Session recorded with --record")]
+    session: PathBuf,
+    #[clap(long, help = "AXP image file the session was recorded with")]
+    image: PathBuf,
+}
+
+pub fn run(args: &Args, replay: &ReplayArgs) -> anyhow::Result<()> {
+    let config = crate::download_config(args, args.provision_index)?;
+    let recording = Recording::read_from(std::fs::File::open(&replay.session)?)?;
+    let replay_device = ReplayDevice::new(recording);
+    let mut device: DynDevice = Box::new(replay_device.clone());
+    let mut file = std::fs::File::open(&replay.image)?;
+    let mut progress = CliProgress::new();
+    let result = download_image(&mut file, &mut device, &config, &mut progress);
+
+    let mismatches = replay_device.mismatches();
+    for mismatch in &mismatches {
+        tracing::error!("{}", mismatch);
+    }
+    if let Err(e) = result {
+        anyhow::bail!("Replay failed: {}", e);
+    }
+    if !mismatches.is_empty() {
+        anyhow::bail!(
+            "{} requests differ from the recorded session",
+            mismatches.len()
+        );
+    }
+    if !replay_device.is_complete() {
+        anyhow::bail!("The download ended before the end of the recorded session");
+    }
+    println!("Replay matched the recorded session");
+    Ok(())
+}
@@ -0,0 +1,280 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Self test: exercises the protocol commands against the romcode of a connected device and
+//! reports which ones it supports, to collect what real firmware does.
+//!
+//! Only RAM is written, to a scratch area given by the user, and the RAM download is never
+//! ended, since that would start the data written as code. The flash is not touched, so the
+//! device only has to be reset afterwards.
+
+use axdl::{
+    communication::Session,
+    frame::{commands, AxdlFrame, AxdlFrameView},
+    progress::NoProgress,
+    AxdlError,
+};
+
+use crate::Args;
+
+/// Command no known firmware implements, to see how unknown commands are answered.
+const UNUSED_COMMAND: u16 = 0x007f;
+
+#[derive(Debug, clap::Args)]
+pub struct SelftestArgs {
+    #[clap(
+        long,
+        help = "RAM address the test data may be written to, e.g. where FDL1 is loaded",
+        value_parser = crate::parse_number
+    )]
+    scratch_address: u64,
+    #[clap(
+        long,
+        help = "Bytes of test data to write to the scratch area",
+        default_value_t = 4096
+    )]
+    scratch_size: u16,
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Supported(String),
+    Unsupported(String),
+    Failed(String),
+    NotRun(&'static str),
+}
+
+#[derive(Debug)]
+struct Check {
+    name: &'static str,
+    outcome: Outcome,
+}
+
+fn code_name(code: u16) -> String {
+    commands::lookup(code)
+        .map(|code| code.name.to_string())
+        .unwrap_or_else(|| format!("{:#06X}", code))
+}
+
+/// Outcome of a command the session sent and checked for an acknowledge.
+fn acknowledged(result: Result<(), AxdlError>) -> Outcome {
+    match result {
+        Ok(()) => Outcome::Supported("acknowledged".into()),
+        Err(AxdlError::UnexpectedResponse(
+            code @ (commands::UNKNOWN_COMMAND | commands::INVALID_COMMAND),
+        )) => Outcome::Unsupported(format!("rejected with {}", code_name(code))),
+        Err(e) => Outcome::Failed(e.to_string()),
+    }
+}
+
+/// Sends a command which the session does not allow in its state and returns the response
+/// code and payload.
+fn probe(
+    session: &mut Session<'_>,
+    command: u16,
+    payload: Vec<u8>,
+) -> Result<(u16, Vec<u8>), AxdlError> {
+    let frame = AxdlFrame::new(command).with_payload(payload).build()?;
+    let timeout = session.timeouts().command;
+    session.device().write_timeout(&frame, timeout)?;
+    let response = AxdlFrameView::new(session.receive_response(timeout)?);
+    let code = response.command_response().ok_or(AxdlError::InvalidFrame)?;
+    Ok((code, response.payload().unwrap_or_default().to_vec()))
+}
+
+/// Writes `data` to RAM at `address` within a RAM download.
+fn write_ram(session: &mut Session<'_>, address: u64, data: &[u8]) -> Result<(), AxdlError> {
+    match u32::try_from(address) {
+        Ok(address) => session.start_partition_absolute_32(address, data.len() as u32)?,
+        Err(_) => session.start_partition_absolute(address, data.len() as u64)?,
+    }
+    session.write_image(
+        &mut &data[..],
+        data.len(),
+        "scratch",
+        data.len(),
+        None,
+        &mut NoProgress,
+    )?;
+    let timeout = session.timeouts().command;
+    session.end_partition(timeout)
+}
+
+/// Tries to read back `data` from RAM at `address` with the read partition commands.
+fn read_ram(session: &mut Session<'_>, address: u64, data: &[u8]) -> Outcome {
+    let mut start = address.to_le_bytes().to_vec();
+    start.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    match probe(session, commands::START_READ_PARTITION, start) {
+        Ok((commands::ACK, _)) => {}
+        Ok((code, _)) => return Outcome::Unsupported(format!("rejected with {}", code_name(code))),
+        Err(e) => return Outcome::Failed(e.to_string()),
+    }
+    let mut read = (data.len() as u32).to_le_bytes().to_vec();
+    read.extend_from_slice(&0u64.to_le_bytes());
+    let outcome = match probe(session, commands::READ_BLOCK, read) {
+        Ok((commands::READ_BLOCK_DATA, payload)) if payload == data => {
+            Outcome::Supported("read back matches".into())
+        }
+        Ok((commands::READ_BLOCK_DATA, payload)) => Outcome::Failed(format!(
+            "read back {} bytes which differ from the data written",
+            payload.len()
+        )),
+        Ok((code, _)) => Outcome::Failed(format!("read block answered {}", code_name(code))),
+        Err(e) => Outcome::Failed(e.to_string()),
+    };
+    if let Err(e) = probe(session, commands::END_READ_PARTITION, Vec::new()) {
+        tracing::warn!("Failed to end reading: {}", e);
+    }
+    outcome
+}
+
+/// Runs the checks in protocol order, stopping at the first one the later ones depend on.
+fn run_checks(session: &mut Session<'_>, address: u64, data: &[u8]) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let handshake = match session.wait_handshake_matching(&[""]) {
+        Ok(handshake) => Outcome::Supported(handshake.to_string()),
+        Err(e) => Outcome::Failed(e.to_string()),
+    };
+    let connected = matches!(handshake, Outcome::Supported(_));
+    checks.push(Check {
+        name: "Handshake",
+        outcome: handshake,
+    });
+    if !connected {
+        return checks;
+    }
+
+    let ram_download = acknowledged(session.start_ram_download());
+    let started = matches!(ram_download, Outcome::Supported(_));
+    checks.push(Check {
+        name: "Start RAM download",
+        outcome: ram_download,
+    });
+    let write = if started {
+        acknowledged(write_ram(session, address, data))
+    } else {
+        Outcome::NotRun("the RAM download was not started")
+    };
+    let written = matches!(write, Outcome::Supported(_));
+    checks.push(Check {
+        name: "RAM write",
+        outcome: write,
+    });
+    checks.push(Check {
+        name: "RAM read back",
+        outcome: if written {
+            read_ram(session, address, data)
+        } else {
+            Outcome::NotRun("nothing was written")
+        },
+    });
+
+    checks.push(Check {
+        name: "Unknown command",
+        outcome: match probe(session, UNUSED_COMMAND, Vec::new()) {
+            Ok((code @ (commands::UNKNOWN_COMMAND | commands::INVALID_COMMAND), _)) => {
+                Outcome::Supported(format!("rejected with {}", code_name(code)))
+            }
+            Ok((code, _)) => Outcome::Failed(format!("answered {}", code_name(code))),
+            Err(e) => Outcome::Failed(e.to_string()),
+        },
+    });
+    checks.push(Check {
+        name: "End RAM download",
+        outcome: Outcome::NotRun("it would start the test data as code"),
+    });
+    checks
+}
+
+pub fn run(args: &Args, selftest: &SelftestArgs) -> anyhow::Result<()> {
+    let mut device = crate::open_device(args)?;
+    let mut session = Session::new(&mut device).with_strict(true);
+    if let Some(request) = &args.handshake_request {
+        session = session.with_handshake_request(request.clone());
+    }
+    let data = (0..selftest.scratch_size)
+        .map(|i| (i as u8) ^ (i >> 8) as u8 ^ 0x5a)
+        .collect::<Vec<_>>();
+    let checks = run_checks(&mut session, selftest.scratch_address, &data);
+
+    let mut failed = 0;
+    for check in &checks {
+        let (status, detail) = match &check.outcome {
+            Outcome::Supported(detail) => ("supported", detail.as_str()),
+            Outcome::Unsupported(detail) => ("unsupported", detail.as_str()),
+            Outcome::Failed(detail) => {
+                failed += 1;
+                ("FAILED", detail.as_str())
+            }
+            Outcome::NotRun(reason) => ("not run", *reason),
+        };
+        println!("{:<20} {:<12} {}", check.name, status, detail);
+    }
+    for deviation in session.deviations() {
+        println!(
+            "Deviation on {:?}: {}",
+            deviation.request, deviation.description
+        );
+    }
+    println!("Reset the device before downloading to it");
+    if failed > 0 {
+        anyhow::bail!("{} checks failed", failed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axdl_emulator::Emulator;
+
+    #[test]
+    fn test_selftest_against_romcode() {
+        let emulator = Emulator::new(2);
+        let mut device = emulator.dyn_device();
+        let mut session = Session::new(&mut device).with_strict(true);
+        let data = axdl_emulator::axp::pattern(1000, 1);
+        let checks = run_checks(&mut session, 0x3000, &data);
+        let outcomes = checks
+            .iter()
+            .map(|check| (check.name, &check.outcome))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            [
+                ("Handshake", &Outcome::Supported("romcode v1.0;raw".into())),
+                (
+                    "Start RAM download",
+                    &Outcome::Supported("acknowledged".into())
+                ),
+                ("RAM write", &Outcome::Supported("acknowledged".into())),
+                (
+                    "RAM read back",
+                    &Outcome::Unsupported("rejected with INVALID_COMMAND".into())
+                ),
+                (
+                    "Unknown command",
+                    &Outcome::Supported("rejected with UNKNOWN_COMMAND".into())
+                ),
+                (
+                    "End RAM download",
+                    &Outcome::NotRun("it would start the test data as code")
+                ),
+            ]
+        );
+        assert_eq!(emulator.ram(0x3000), Some(data));
+        assert!(session.deviations().is_empty());
+    }
+}
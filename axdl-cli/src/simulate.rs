@@ -0,0 +1,67 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Simulated downloads: runs the whole download against the emulator instead of a device, so
+//! that images and automation scripts can be checked on machines without hardware.
+
+use std::path::Path;
+
+use axdl::transport::{DynDevice, TransportKind};
+
+/// Returns an emulated device for the AXP image `file`, which loads as many flash downloaders
+/// as the image has and reports `kind` as its transport, so that the options for it apply.
+pub fn device(file: &Path, kind: TransportKind) -> anyhow::Result<DynDevice> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(file)?);
+    let project = axdl::read_project(&mut reader)?;
+    tracing::warn!("Simulating the download with an emulated device, nothing is flashed");
+    let emulator = axdl_emulator::Emulator::new(project.fdl_level());
+    Ok(Box::new(emulator.device().with_transport_kind(kind)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axdl::{partition::ImageType, progress::NoProgress, DownloadConfig};
+    use axdl_emulator::axp::{pattern, AxpBuilder};
+
+    #[test]
+    fn test_simulated_download() {
+        let image = AxpBuilder::new(2)
+            .partition("rootfs", 0x100000)
+            .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+            .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+            .code("ROOTFS", "rootfs", pattern(5000, 3))
+            .build();
+        let path = std::env::temp_dir().join(format!("axdl-simulate-{}.axp", std::process::id()));
+        std::fs::write(&path, &image).unwrap();
+
+        let mut device = device(&path, TransportKind::Serial).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(device.transport_kind(), TransportKind::Serial);
+        let config = DownloadConfig {
+            verify: true,
+            ..Default::default()
+        };
+        let report = axdl::download_image(
+            &mut std::io::Cursor::new(image),
+            &mut device,
+            &config,
+            &mut NoProgress,
+        )
+        .unwrap();
+        assert!(report.is_success());
+        assert_eq!(report.partitions[0].bytes_written, 5000);
+    }
+}
@@ -0,0 +1,235 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Local SQLite database of download sessions, for yield and throughput reports on a station.
+
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axdl::hash::{self, Digest};
+use rusqlite::{params, Connection};
+
+#[derive(Debug, clap::Args)]
+pub struct StatsArgs {
+    #[clap(long, help = "Only count sessions from the last N hours")]
+    since_hours: Option<u64>,
+    #[clap(long, help = "Also list the last N sessions", default_value_t = 0)]
+    recent: usize,
+}
+
+/// One download session to record.
+pub struct SessionRecord<'a> {
+    pub device_serial: Option<&'a str>,
+    pub image: &'a Path,
+    pub image_sha256: &'a str,
+    pub duration: Duration,
+    /// Why the session failed, or `None` if it passed.
+    pub error: Option<&'a str>,
+}
+
+/// Sessions of one image, aggregated.
+#[derive(Debug, PartialEq)]
+pub struct ImageSummary {
+    pub image: String,
+    pub image_sha256: String,
+    pub total: u64,
+    pub passed: u64,
+    pub average_duration: Duration,
+    /// Time between the first and the last session.
+    pub span: Duration,
+}
+
+impl std::fmt::Display for ImageSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} (sha256 {})", self.image, &self.image_sha256[..16])?;
+        writeln!(
+            f,
+            "  sessions: {}, passed: {}, failed: {}, yield: {:.1} %",
+            self.total,
+            self.passed,
+            self.total - self.passed,
+            self.passed as f64 * 100.0 / self.total as f64
+        )?;
+        write!(
+            f,
+            "  average duration: {:.1} s",
+            self.average_duration.as_secs_f64()
+        )?;
+        if !self.span.is_zero() {
+            write!(
+                f,
+                ", throughput: {:.1} units/hour",
+                self.total as f64 * 3600.0 / self.span.as_secs_f64()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub struct StatsDb {
+    connection: Connection,
+}
+
+fn unix_time(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+impl StatsDb {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    fn with_connection(connection: Connection) -> rusqlite::Result<Self> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS sessions (
+                id INTEGER PRIMARY KEY,
+                finished_at INTEGER NOT NULL,
+                device_serial TEXT,
+                image TEXT NOT NULL,
+                image_sha256 TEXT NOT NULL,
+                duration_ms INTEGER NOT NULL,
+                passed INTEGER NOT NULL,
+                error TEXT
+            )",
+        )?;
+        Ok(Self { connection })
+    }
+
+    pub fn record(&self, record: &SessionRecord) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT INTO sessions
+                (finished_at, device_serial, image, image_sha256, duration_ms, passed, error)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                unix_time(SystemTime::now()),
+                record.device_serial,
+                record.image.display().to_string(),
+                record.image_sha256,
+                record.duration.as_millis() as i64,
+                record.error.is_none(),
+                record.error,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Aggregates the sessions finished at or after `since`, per image.
+    pub fn summary(&self, since: SystemTime) -> rusqlite::Result<Vec<ImageSummary>> {
+        let mut statement = self.connection.prepare(
+            "SELECT MAX(image), image_sha256, COUNT(*), SUM(passed), AVG(duration_ms),
+                MAX(finished_at) - MIN(finished_at)
+                FROM sessions WHERE finished_at >= ?1
+                GROUP BY image_sha256 ORDER BY MAX(finished_at)",
+        )?;
+        let rows = statement.query_map([unix_time(since)], |row| {
+            Ok(ImageSummary {
+                image: row.get(0)?,
+                image_sha256: row.get(1)?,
+                total: row.get(2)?,
+                passed: row.get(3)?,
+                average_duration: Duration::from_millis(row.get::<_, f64>(4)? as u64),
+                span: Duration::from_secs(row.get(5)?),
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Returns the last `count` sessions as printable lines, newest first.
+    pub fn recent(&self, count: usize) -> rusqlite::Result<Vec<String>> {
+        let mut statement = self.connection.prepare(
+            "SELECT finished_at, device_serial, image, duration_ms, passed, error
+                FROM sessions ORDER BY id DESC LIMIT ?1",
+        )?;
+        let rows = statement.query_map([count as i64], |row| {
+            let passed: bool = row.get(4)?;
+            let error: Option<String> = row.get(5)?;
+            Ok(format!(
+                "{} {} {} {:.1} s {}{}",
+                row.get::<_, i64>(0)?,
+                row.get::<_, Option<String>>(1)?
+                    .unwrap_or_else(|| "-".into()),
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)? as f64 / 1000.0,
+                if passed { "PASS" } else { "FAIL" },
+                error.map(|e| format!(": {}", e)).unwrap_or_default(),
+            ))
+        })?;
+        rows.collect()
+    }
+}
+
+/// Returns the SHA-256 of the file as a hex string.
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+    Ok(hash::hex(&Digest::Sha256.digest_reader(&mut file)?))
+}
+
+pub fn run(db: &Path, args: &StatsArgs) -> anyhow::Result<()> {
+    let db = StatsDb::open(db)?;
+    let since = args
+        .since_hours
+        .map(|hours| SystemTime::now() - Duration::from_secs(hours * 3600))
+        .unwrap_or(UNIX_EPOCH);
+    let summary = db.summary(since)?;
+    if summary.is_empty() {
+        println!("No sessions recorded");
+    }
+    for image in summary {
+        println!("{}", image);
+    }
+    if args.recent > 0 {
+        println!();
+        for line in db.recent(args.recent)? {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let db = StatsDb::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let record = |serial, error| SessionRecord {
+            device_serial: Some(serial),
+            image: Path::new("image.axp"),
+            image_sha256: "0123456789abcdef0123",
+            duration: Duration::from_secs(10),
+            error,
+        };
+        db.record(&record("AX01", None)).unwrap();
+        db.record(&record("AX02", Some("Verification failed")))
+            .unwrap();
+        db.record(&record("AX02", None)).unwrap();
+
+        let summary = db.summary(UNIX_EPOCH).unwrap();
+        assert_eq!(summary.len(), 1);
+        assert_eq!(summary[0].total, 3);
+        assert_eq!(summary[0].passed, 2);
+        assert_eq!(summary[0].average_duration, Duration::from_secs(10));
+
+        let recent = db.recent(2).unwrap();
+        assert_eq!(recent.len(), 2);
+        assert!(recent[0].contains("AX02") && recent[0].contains("PASS"));
+        assert!(recent[1].ends_with("FAIL: Verification failed"));
+    }
+}
@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Verify-only mode: compares the partitions of a connected device with an AXP image without
+//! writing to its flash, e.g. to audit units returned from the field.
+
+use std::path::PathBuf;
+
+use axdl::{
+    report::{DownloadReport, VerifyResult},
+    verify_image,
+};
+
+use crate::{Args, CliProgress};
+
+#[derive(Debug, clap::Args)]
+pub struct VerifyArgs {
+    #[clap(short, long, help = "AXP image file to compare the device with")]
+    file: PathBuf,
+}
+
+/// Lines of the outcome of each partition, and the names of the partitions which differ.
+fn summary(report: &DownloadReport) -> (Vec<String>, Vec<&str>) {
+    let mut lines = Vec::new();
+    let mut differing = Vec::new();
+    for partition in &report.partitions {
+        let outcome = match partition.verify {
+            VerifyResult::Failed { offset } => {
+                differing.push(partition.partition.as_str());
+                format!("DIFFERS at {:#X}", offset)
+            }
+            VerifyResult::Passed => "matches".to_string(),
+            VerifyResult::Skipped => "not compared".to_string(),
+        };
+        lines.push(format!(
+            "{:<16} {:<16} {}",
+            partition.partition, partition.image, outcome
+        ));
+    }
+    (lines, differing)
+}
+
+pub fn run(args: &Args, verify: &VerifyArgs) -> anyhow::Result<()> {
+    let config = crate::download_config(args, args.provision_index)?;
+    let mut file = std::fs::File::open(&verify.file)?;
+    let mut device = crate::open_device(args)?;
+    let mut progress = CliProgress::new();
+    let report = verify_image(&mut file, &mut device, &config, &mut progress)?;
+
+    let (lines, differing) = summary(&report);
+    for line in lines {
+        println!("{}", line);
+    }
+    if !differing.is_empty() {
+        anyhow::bail!(
+            "{} of {} partitions differ from the image: {}",
+            differing.len(),
+            report.partitions.len(),
+            differing.join(", ")
+        );
+    }
+    println!("All {} partitions match the image", report.partitions.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axdl::report::PartitionReport;
+
+    #[test]
+    fn test_summary() {
+        let partition = |partition: &str, verify| PartitionReport {
+            image: partition.to_uppercase(),
+            partition: partition.into(),
+            bytes_written: 0,
+            verify,
+            skipped: false,
+            duration: std::time::Duration::ZERO,
+        };
+        let report = DownloadReport {
+            partitions: vec![
+                partition("spl", VerifyResult::Passed),
+                partition("rootfs", VerifyResult::Failed { offset: 0x1000 }),
+            ],
+            ..Default::default()
+        };
+        let (lines, differing) = summary(&report);
+        assert_eq!(
+            lines,
+            [
+                "spl              SPL              matches",
+                "rootfs           ROOTFS           DIFFERS at 0x1000",
+            ]
+        );
+        assert_eq!(differing, ["rootfs"]);
+    }
+}
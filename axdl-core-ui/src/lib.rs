@@ -0,0 +1,395 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! View-model shared by the axdl frontends, independent of the UI toolkit.
+//!
+//! A frontend feeds [`Command`]s from the user and from the download it drives into a
+//! [`Flasher`], and renders the [`Event`]s it returns. Other observers, such as a log or an
+//! automation API, can [`Flasher::subscribe`] to the same events.
+
+use std::{sync::mpsc, time::Duration};
+
+use axdl::{cancel::CancellationToken, report::DownloadReport};
+
+pub mod settings;
+pub mod timing;
+
+pub use settings::{report_rows, result_text, ReportRow, Settings};
+pub use timing::Timing;
+
+/// Progress of a running download.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Progress {
+    pub description: String,
+    /// Completed fraction of the current step from 0 to 1, `None` if unknown.
+    pub fraction: Option<f32>,
+    /// Time since the download started.
+    pub elapsed: Duration,
+    /// Smoothed speed while image data is transferred.
+    pub bytes_per_second: Option<f64>,
+    /// Estimated time until the current image is transferred.
+    pub remaining: Option<Duration>,
+}
+
+impl Progress {
+    /// Elapsed time, speed and remaining time in one line, e.g. `0:42, 12.3 MiB/s, 0:10 left`.
+    pub fn timing_text(&self) -> String {
+        let mut text = timing::format_duration(self.elapsed);
+        if let Some(speed) = self.bytes_per_second {
+            text += &format!(", {}", timing::format_speed(speed));
+        }
+        if let Some(remaining) = self.remaining {
+            text += &format!(", {} left", timing::format_duration(remaining));
+        }
+        text
+    }
+}
+
+/// Image file selected for download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub name: String,
+    pub size: u64,
+}
+
+/// State of the flasher.
+///
+/// It moves from `Idle` to `DeviceSelected` and `ImageLoaded` as the user selects a device and
+/// an image, in either order, then to `Flashing` and finally `Done` or `Error`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum State {
+    /// No device is selected.
+    Idle,
+    /// A device is selected but no image.
+    DeviceSelected,
+    /// A device and an image are selected, ready to flash.
+    ImageLoaded,
+    Flashing(Progress),
+    Done(DownloadReport),
+    Error(String),
+}
+
+impl State {
+    fn name(&self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::DeviceSelected => "device selected",
+            State::ImageLoaded => "image loaded",
+            State::Flashing(_) => "flashing",
+            State::Done(_) => "done",
+            State::Error(_) => "error",
+        }
+    }
+}
+
+/// Input of the flasher, from the user or from the download it drives.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// A device was opened, named for display.
+    SelectDevice(String),
+    /// The device was closed or disconnected.
+    ReleaseDevice,
+    LoadImage(Image),
+    UnloadImage,
+    StartFlash,
+    /// Progress reported by the download. Ignored once the download finished.
+    ReportProgress(Progress),
+    /// The download finished, with its report or what went wrong.
+    Finish(Result<DownloadReport, String>),
+    /// Cancels the running download, which then finishes with an error.
+    Cancel,
+    /// Leaves `Done` or `Error` for the state of the current selection.
+    Dismiss,
+}
+
+/// Change of the flasher, for the frontend to render.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    StateChanged(State),
+    DeviceChanged(Option<String>),
+    ImageChanged(Option<Image>),
+}
+
+/// Command which is not allowed in the current state.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("cannot {command} while {state}")]
+pub struct Rejected {
+    pub command: &'static str,
+    pub state: &'static str,
+}
+
+/// State machine of a flashing frontend.
+#[derive(Debug)]
+pub struct Flasher {
+    state: State,
+    device: Option<String>,
+    image: Option<Image>,
+    cancellation: Option<CancellationToken>,
+    elapsed: Option<Duration>,
+    subscribers: Vec<mpsc::Sender<Event>>,
+}
+
+impl Default for Flasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Flasher {
+    pub fn new() -> Self {
+        Self {
+            state: State::Idle,
+            device: None,
+            image: None,
+            cancellation: None,
+            elapsed: None,
+            subscribers: Vec::new(),
+        }
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn device(&self) -> Option<&str> {
+        self.device.as_deref()
+    }
+
+    pub fn image(&self) -> Option<&Image> {
+        self.image.as_ref()
+    }
+
+    pub fn is_flashing(&self) -> bool {
+        matches!(self.state, State::Flashing(_))
+    }
+
+    /// Returns true if [`Command::StartFlash`] is allowed.
+    pub fn can_flash(&self) -> bool {
+        !self.is_flashing() && self.device.is_some() && self.image.is_some()
+    }
+
+    /// Elapsed time of the running or the last download as last reported, kept after it
+    /// finished to compare the throughput of hosts.
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.elapsed
+    }
+
+    /// Token of the running download, to put into its [`axdl::DownloadConfig`] so that
+    /// [`Command::Cancel`] stops it.
+    pub fn cancellation(&self) -> Option<CancellationToken> {
+        self.cancellation.clone()
+    }
+
+    /// Returns a stream of the events of all later commands.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<Event> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// State the selection leads to when nothing is running.
+    fn resting_state(&self) -> State {
+        match (&self.device, &self.image) {
+            (None, _) => State::Idle,
+            (Some(_), None) => State::DeviceSelected,
+            (Some(_), Some(_)) => State::ImageLoaded,
+        }
+    }
+
+    fn reject(&self, command: &'static str) -> Rejected {
+        Rejected {
+            command,
+            state: self.state.name(),
+        }
+    }
+
+    /// Applies `command` and returns the resulting events, which are also sent to the
+    /// subscribers.
+    pub fn apply(&mut self, command: Command) -> Result<Vec<Event>, Rejected> {
+        let mut events = Vec::new();
+        let mut state = None;
+        match command {
+            Command::SelectDevice(device) => {
+                if self.is_flashing() {
+                    return Err(self.reject("select a device"));
+                }
+                self.device = Some(device);
+                events.push(Event::DeviceChanged(self.device.clone()));
+                state = Some(self.resting_state());
+            }
+            Command::ReleaseDevice => {
+                self.device = None;
+                events.push(Event::DeviceChanged(None));
+                // A running download fails on its own and finishes with the error.
+                if !self.is_flashing() {
+                    state = Some(self.resting_state());
+                }
+            }
+            Command::LoadImage(image) => {
+                if self.is_flashing() {
+                    return Err(self.reject("load an image"));
+                }
+                self.image = Some(image);
+                events.push(Event::ImageChanged(self.image.clone()));
+                state = Some(self.resting_state());
+            }
+            Command::UnloadImage => {
+                if self.is_flashing() {
+                    return Err(self.reject("unload the image"));
+                }
+                self.image = None;
+                events.push(Event::ImageChanged(None));
+                state = Some(self.resting_state());
+            }
+            Command::StartFlash => {
+                if !self.can_flash() {
+                    return Err(self.reject("start flashing"));
+                }
+                self.cancellation = Some(CancellationToken::new());
+                self.elapsed = Some(Duration::ZERO);
+                state = Some(State::Flashing(Progress::default()));
+            }
+            Command::ReportProgress(progress) => {
+                if self.is_flashing() {
+                    self.elapsed = Some(progress.elapsed);
+                    state = Some(State::Flashing(progress));
+                }
+            }
+            Command::Finish(result) => {
+                if !self.is_flashing() {
+                    return Err(self.reject("finish"));
+                }
+                self.cancellation = None;
+                state = Some(match result {
+                    Ok(report) => State::Done(report),
+                    Err(e) => State::Error(e),
+                });
+            }
+            Command::Cancel => match &self.cancellation {
+                Some(cancellation) => cancellation.cancel(),
+                None => return Err(self.reject("cancel")),
+            },
+            Command::Dismiss => {
+                if matches!(self.state, State::Done(_) | State::Error(_)) {
+                    state = Some(self.resting_state());
+                }
+            }
+        }
+        if let Some(state) = state.filter(|state| *state != self.state) {
+            self.state = state.clone();
+            events.push(Event::StateChanged(state));
+        }
+        self.subscribers.retain(|subscriber| {
+            events
+                .iter()
+                .all(|event| subscriber.send(event.clone()).is_ok())
+        });
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn image() -> Image {
+        Image {
+            name: "image.axp".into(),
+            size: 1000,
+        }
+    }
+
+    #[test]
+    fn test_flash_cycle() {
+        let mut flasher = Flasher::new();
+        let events = flasher.subscribe();
+        assert_eq!(
+            flasher.apply(Command::StartFlash),
+            Err(Rejected {
+                command: "start flashing",
+                state: "idle"
+            })
+        );
+
+        // The image may be loaded before the device.
+        flasher.apply(Command::LoadImage(image())).unwrap();
+        assert_eq!(flasher.state(), &State::Idle);
+        assert_eq!(
+            flasher
+                .apply(Command::SelectDevice("AX620E".into()))
+                .unwrap(),
+            [
+                Event::DeviceChanged(Some("AX620E".into())),
+                Event::StateChanged(State::ImageLoaded),
+            ]
+        );
+
+        flasher.apply(Command::StartFlash).unwrap();
+        let cancellation = flasher.cancellation().unwrap();
+        assert!(flasher.apply(Command::LoadImage(image())).is_err());
+        let progress = Progress {
+            description: "Writing rootfs".into(),
+            fraction: Some(0.5),
+            elapsed: Duration::from_secs(42),
+            bytes_per_second: Some(12.5 * 1024.0 * 1024.0),
+            remaining: Some(Duration::from_secs(10)),
+        };
+        flasher
+            .apply(Command::ReportProgress(progress.clone()))
+            .unwrap();
+        assert_eq!(flasher.state(), &State::Flashing(progress.clone()));
+        assert_eq!(progress.timing_text(), "0:42, 12.5 MiB/s, 0:10 left");
+        flasher.apply(Command::Cancel).unwrap();
+        assert!(cancellation.is_cancelled());
+        flasher
+            .apply(Command::Finish(Err("cancelled".into())))
+            .unwrap();
+        assert_eq!(flasher.state(), &State::Error("cancelled".into()));
+        assert!(flasher.cancellation().is_none());
+        assert_eq!(flasher.elapsed(), Some(Duration::from_secs(42)));
+
+        // Late progress does not leave the final state.
+        flasher
+            .apply(Command::ReportProgress(Progress::default()))
+            .unwrap();
+        flasher.apply(Command::StartFlash).unwrap();
+        flasher
+            .apply(Command::Finish(Ok(DownloadReport::default())))
+            .unwrap();
+        assert_eq!(flasher.state(), &State::Done(DownloadReport::default()));
+        flasher.apply(Command::ReleaseDevice).unwrap();
+        assert_eq!(flasher.state(), &State::Idle);
+
+        let states = events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::StateChanged(state) => Some(state.name()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            states,
+            [
+                "image loaded",
+                "flashing",
+                "flashing",
+                "error",
+                "flashing",
+                "done",
+                "idle"
+            ]
+        );
+    }
+}
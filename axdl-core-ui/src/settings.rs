@@ -0,0 +1,161 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Download settings as entered in a UI and the rows the report is shown as.
+
+use std::time::Duration;
+
+use axdl::{
+    report::{DownloadReport, VerifyResult},
+    AxdlError, DownloadConfig,
+};
+
+/// Download settings as entered in the UI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub exclude_rootfs: bool,
+    pub verify: bool,
+    pub chunk_size: String,
+    pub handshake_retries: String,
+    pub block_retries: String,
+    pub timeout_secs: String,
+    pub end_partition_timeout_secs: String,
+    /// Comma separated partition names.
+    pub include_partitions: String,
+    pub exclude_partitions: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            exclude_rootfs: false,
+            verify: false,
+            chunk_size: "48000".into(),
+            handshake_retries: "0".into(),
+            block_retries: "0".into(),
+            timeout_secs: "600".into(),
+            end_partition_timeout_secs: "60".into(),
+            include_partitions: String::new(),
+            exclude_partitions: String::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Builds the download configuration from the settings.
+    pub fn download_config(&self) -> Result<DownloadConfig, AxdlError> {
+        fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, AxdlError> {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| AxdlError::InvalidConfig(format!("invalid {}: {}", name, value)))
+        }
+        fn names(value: &str) -> Vec<String> {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect()
+        }
+
+        let timeout = Duration::from_secs(number("timeout", &self.timeout_secs)?);
+        Ok(DownloadConfig {
+            exclude_rootfs: self.exclude_rootfs,
+            verify: self.verify,
+            image_chunk_size: number("chunk size", &self.chunk_size)?,
+            timeouts: axdl::communication::Timeouts {
+                command: timeout,
+                data: timeout,
+                end_partition: Duration::from_secs(number(
+                    "end partition timeout",
+                    &self.end_partition_timeout_secs,
+                )?),
+            },
+            retry: axdl::communication::RetryPolicy {
+                handshake: number("handshake retries", &self.handshake_retries)?,
+                block: number("block retries", &self.block_retries)?,
+            },
+            include_partitions: names(&self.include_partitions),
+            exclude_partitions: names(&self.exclude_partitions),
+            ..Default::default()
+        })
+    }
+}
+
+/// Row of the download report.
+#[derive(Debug, Clone)]
+pub struct ReportRow {
+    pub partition: String,
+    pub image: String,
+    pub bytes_written: String,
+    pub verify: String,
+    pub duration: String,
+    pub ok: bool,
+}
+
+pub fn report_rows(report: &DownloadReport) -> Vec<ReportRow> {
+    report
+        .partitions
+        .iter()
+        .map(|partition| ReportRow {
+            partition: partition.partition.clone(),
+            image: partition.image.clone(),
+            bytes_written: format!("{} bytes", partition.bytes_written),
+            verify: partition.verify.to_string(),
+            duration: format!("{:.1} s", partition.duration.as_secs_f64()),
+            ok: !matches!(partition.verify, VerifyResult::Failed { .. }),
+        })
+        .collect()
+}
+
+/// Result of a download as recorded in the recent images.
+pub fn result_text<E: std::fmt::Display>(result: &Result<DownloadReport, E>) -> String {
+    match result {
+        Ok(report) if report.is_success() => "OK".to_string(),
+        Ok(_) => "Verify failed".to_string(),
+        Err(e) => format!("Failed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_download_config() {
+        let settings = Settings {
+            verify: true,
+            timeout_secs: " 30 ".into(),
+            include_partitions: "rootfs, ,boot".into(),
+            ..Default::default()
+        };
+        let config = settings.download_config().unwrap();
+        assert!(config.verify);
+        assert_eq!(config.image_chunk_size, 48000);
+        assert_eq!(config.timeouts.command, Duration::from_secs(30));
+        assert_eq!(config.timeouts.end_partition, Duration::from_secs(60));
+        assert_eq!(config.include_partitions, ["rootfs", "boot"]);
+
+        let settings = Settings {
+            block_retries: "many".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            settings.download_config().unwrap_err().to_string(),
+            AxdlError::InvalidConfig("invalid block retries: many".into()).to_string()
+        );
+    }
+}
@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Elapsed time, speed and remaining time of a download, derived from its progress events.
+
+use std::time::Duration;
+
+use axdl::progress::ProgressEvent;
+
+use crate::Progress;
+
+/// Weight of the latest sample in the smoothed speed.
+const SMOOTHING: f64 = 0.3;
+
+/// Bytes of a target transferred at a point in time.
+#[derive(Debug, Clone, PartialEq)]
+struct Sample {
+    target: String,
+    bytes: u64,
+    timestamp: Duration,
+}
+
+/// Turns the progress events of one download into [`Progress`] with timings.
+///
+/// Timestamps are those of [`ProgressEvent::timestamp`], so that it works the same in the
+/// browser, where the standard clock is not available.
+#[derive(Debug, Clone)]
+pub struct Timing {
+    started: Duration,
+    last: Option<Sample>,
+    bytes_per_second: Option<f64>,
+}
+
+impl Timing {
+    /// Starts timing a download at `now`, e.g. [`axdl::progress::timestamp`].
+    pub fn start(now: Duration) -> Self {
+        Self {
+            started: now,
+            last: None,
+            bytes_per_second: None,
+        }
+    }
+
+    /// Time from the start of the download until `now`.
+    pub fn elapsed(&self, now: Duration) -> Duration {
+        now.saturating_sub(self.started)
+    }
+
+    /// Returns the progress shown once the download returned at `now`, which keeps the final
+    /// elapsed time.
+    pub fn finished(&self, now: Duration) -> Progress {
+        Progress {
+            description: "Finished".to_string(),
+            fraction: Some(1.0),
+            elapsed: self.elapsed(now),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the progress shown for `event`.
+    ///
+    /// The speed and the remaining time are only known while image data is transferred. The
+    /// remaining time is that of the current target.
+    pub fn record(&mut self, event: &ProgressEvent<'_>) -> Progress {
+        let mut progress = Progress {
+            description: event.description.to_string(),
+            fraction: event.progress,
+            elapsed: self.elapsed(event.timestamp),
+            ..Default::default()
+        };
+        let (Some(target), Some(bytes)) = (event.target, event.bytes) else {
+            self.last = None;
+            return progress;
+        };
+        if let Some(last) = self
+            .last
+            .as_ref()
+            .filter(|last| last.target == target && last.bytes < bytes)
+        {
+            let seconds = event.timestamp.saturating_sub(last.timestamp).as_secs_f64();
+            if seconds > 0.0 {
+                let speed = (bytes - last.bytes) as f64 / seconds;
+                self.bytes_per_second = Some(match self.bytes_per_second {
+                    Some(smoothed) => smoothed + SMOOTHING * (speed - smoothed),
+                    None => speed,
+                });
+            }
+        }
+        self.last = Some(Sample {
+            target: target.to_string(),
+            bytes,
+            timestamp: event.timestamp,
+        });
+        progress.bytes_per_second = self.bytes_per_second;
+        progress.remaining = self
+            .bytes_per_second
+            .zip(event.progress.filter(|&fraction| fraction > 0.0))
+            .map(|(speed, fraction)| {
+                let total = bytes as f64 / fraction as f64;
+                Duration::from_secs_f64(((total - bytes as f64) / speed).max(0.0))
+            });
+        progress
+    }
+}
+
+/// Formats a duration as minutes and seconds, e.g. `1:05`, with hours if needed.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// Formats a speed in MiB/s, or KiB/s below 1 MiB/s.
+pub fn format_speed(bytes_per_second: f64) -> String {
+    if bytes_per_second >= 1024.0 * 1024.0 {
+        format!("{:.1} MiB/s", bytes_per_second / (1024.0 * 1024.0))
+    } else {
+        format!("{:.0} KiB/s", bytes_per_second / 1024.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axdl::progress::Phase;
+
+    fn write(bytes: u64, millis: u64) -> ProgressEvent<'static> {
+        ProgressEvent {
+            phase: Phase::Write,
+            target: Some("ROOTFS"),
+            description: "Downloading image ROOTFS",
+            progress: Some(bytes as f32 / 8_000_000.0),
+            bytes: Some(bytes),
+            timestamp: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn test_timing() {
+        let mut timing = Timing::start(Duration::from_millis(1000));
+        let progress = timing.record(&write(1_000_000, 2000));
+        assert_eq!(progress.elapsed, Duration::from_secs(1));
+        assert_eq!(progress.bytes_per_second, None);
+
+        // 1 MB in 0.5 s, with 6 MB left.
+        let progress = timing.record(&write(2_000_000, 2500));
+        assert_eq!(progress.bytes_per_second, Some(2_000_000.0));
+        assert_eq!(progress.remaining, Some(Duration::from_secs(3)));
+        let progress = timing.record(&write(3_000_000, 3500));
+        let speed = progress.bytes_per_second.unwrap();
+        assert!((speed - 1_700_000.0).abs() < 1.0);
+
+        let progress = timing.record(&ProgressEvent {
+            phase: Phase::Flush,
+            target: Some("ROOTFS"),
+            description: "Waiting for the device to finish writing ROOTFS",
+            progress: None,
+            bytes: None,
+            timestamp: Duration::from_millis(4000),
+        });
+        assert_eq!(progress.elapsed, Duration::from_secs(3));
+        assert_eq!(progress.bytes_per_second, None);
+        assert_eq!(progress.remaining, None);
+        assert_eq!(
+            timing.finished(Duration::from_millis(6500)).elapsed,
+            Duration::from_millis(5500)
+        );
+
+        assert_eq!(format_duration(Duration::from_secs(65)), "1:05");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1:02:05");
+        assert_eq!(format_speed(1_700_000.0), "1.6 MiB/s");
+        assert_eq!(format_speed(51_200.0), "50 KiB/s");
+    }
+}
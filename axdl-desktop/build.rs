@@ -0,0 +1,3 @@
+fn main() {
+    slint_build::compile("ui/desktop-window.slint").expect("Slint build failed");
+}
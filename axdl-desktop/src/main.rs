@@ -0,0 +1,279 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+
+use std::{
+    cell::RefCell,
+    path::PathBuf,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axdl::{
+    download_image, report::DownloadReport, transport::NativeTransport, AxdlError, DownloadConfig,
+    DownloadProgress,
+};
+use axdl_core_ui::{timing, Command, Event, Flasher, Image, Progress, Settings, State, Timing};
+
+mod update;
+
+slint::include_modules!();
+
+/// How long to wait for a device in download mode after Download is clicked.
+const WAIT_FOR_DEVICE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Applies `command` to the flasher and shows the changes. Returns false if it was rejected.
+fn dispatch(ui: &DesktopWindow, flasher: &Mutex<Flasher>, command: Command) -> bool {
+    let mut flasher = flasher.lock().unwrap_or_else(|e| e.into_inner());
+    let events = match flasher.apply(command) {
+        Ok(events) => events,
+        Err(e) => {
+            tracing::warn!("{}", e);
+            return false;
+        }
+    };
+    for event in events {
+        match event {
+            Event::DeviceChanged(_) => {}
+            Event::ImageChanged(image) => {
+                ui.set_image_file_opened(image.is_some());
+                ui.set_image_file(image.map(|image| image.name).unwrap_or_default().into());
+            }
+            Event::StateChanged(state) => {
+                ui.set_downloading(matches!(state, State::Flashing(_)));
+                match state {
+                    State::Flashing(progress) => {
+                        ui.invoke_set_progress(
+                            progress.description.clone().into(),
+                            progress.fraction.unwrap_or(-1.0),
+                        );
+                        ui.set_timing_text(progress.timing_text().into());
+                    }
+                    State::Done(report) => {
+                        let message = if report.is_success() {
+                            "Download completed".to_string()
+                        } else {
+                            format!("Verification failed\n{}", report)
+                        };
+                        tracing::info!("{}", message);
+                        ui.invoke_set_progress(message.into(), -1.0);
+                        let elapsed = flasher.elapsed().unwrap_or_default();
+                        ui.set_timing_text(
+                            format!("Finished in {}", timing::format_duration(elapsed)).into(),
+                        );
+                    }
+                    State::Error(e) => {
+                        tracing::error!("Download failed: {}", e);
+                        ui.invoke_set_progress(format!("Download failed: {}", e).into(), -1.0);
+                    }
+                    State::Idle | State::DeviceSelected | State::ImageLoaded => {}
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Applies `command` on the UI thread, for the thread running the download.
+fn dispatch_later(
+    ui: &slint::Weak<DesktopWindow>,
+    flasher: &Arc<Mutex<Flasher>>,
+    command: Command,
+) {
+    let flasher = flasher.clone();
+    let _ = ui.upgrade_in_event_loop(move |ui| {
+        dispatch(&ui, &flasher, command);
+    });
+}
+
+/// Feeds the progress of a download running on a background thread to the flasher.
+struct DesktopProgress {
+    ui: slint::Weak<DesktopWindow>,
+    flasher: Arc<Mutex<Flasher>>,
+    timing: Timing,
+}
+
+impl DownloadProgress for DesktopProgress {
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+    fn report_progress(&mut self, description: &str, progress: Option<f32>) {
+        let progress = Progress {
+            description: description.to_string(),
+            fraction: progress,
+            elapsed: self.timing.elapsed(axdl::progress::timestamp()),
+            ..Default::default()
+        };
+        dispatch_later(&self.ui, &self.flasher, Command::ReportProgress(progress));
+    }
+    fn report_event(&mut self, event: &axdl::progress::ProgressEvent<'_>) {
+        let progress = self.timing.record(event);
+        dispatch_later(&self.ui, &self.flasher, Command::ReportProgress(progress));
+    }
+}
+
+fn download(
+    transport: NativeTransport,
+    path: PathBuf,
+    config: DownloadConfig,
+    progress: &mut DesktopProgress,
+) -> Result<DownloadReport, AxdlError> {
+    config.validate()?;
+    progress.report_progress("Waiting for the device", None);
+    let cancellation = config.cancellation.clone().unwrap_or_default();
+    let mut device = transport.wait_for_device(Some(WAIT_FOR_DEVICE_TIMEOUT), || {
+        cancellation.is_cancelled()
+    })?;
+    let mut file = std::fs::File::open(&path)
+        .map_err(|e| AxdlError::IoError(format!("failed to open {}", path.display()), e))?;
+    download_image(&mut file, &mut device, &config, progress)
+}
+
+fn transport(name: &str) -> NativeTransport {
+    match name {
+        "Serial" => NativeTransport::Serial,
+        _ => NativeTransport::Usb,
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::builder()
+                .with_default_directive(tracing::level_filters::LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .init();
+
+    let ui = DesktopWindow::new()?;
+    let image_file: Rc<RefCell<Option<PathBuf>>> = Rc::new(RefCell::new(None));
+    let flasher = Arc::new(Mutex::new(Flasher::new()));
+
+    if update::is_enabled() {
+        update::check_in_background(ui.as_weak());
+    }
+
+    // The device is only opened once the download starts, so the chosen transport stands for it.
+    dispatch(
+        &ui,
+        &flasher,
+        Command::SelectDevice(ui.get_transport().into()),
+    );
+    {
+        let ui_handle = ui.as_weak();
+        let flasher = flasher.clone();
+        ui.on_transport_changed(move |name| {
+            let ui = ui_handle.unwrap();
+            dispatch(&ui, &flasher, Command::SelectDevice(name.into()));
+        });
+    }
+
+    {
+        let ui_handle = ui.as_weak();
+        let image_file = image_file.clone();
+        let flasher = flasher.clone();
+        ui.on_open_image(move || {
+            let ui = ui_handle.unwrap();
+            let Some(path) = rfd::FileDialog::new()
+                .set_title("Open AXP image")
+                .add_filter("AXP image", &["axp"])
+                .pick_file()
+            else {
+                return;
+            };
+            let size = match std::fs::metadata(&path) {
+                Ok(metadata) => metadata.len(),
+                Err(e) => {
+                    tracing::error!("Failed to open {}: {}", path.display(), e);
+                    return;
+                }
+            };
+            let image = Image {
+                name: path.display().to_string(),
+                size,
+            };
+            if dispatch(&ui, &flasher, Command::LoadImage(image)) {
+                image_file.replace(Some(path));
+            }
+        });
+    }
+
+    {
+        let ui_handle = ui.as_weak();
+        let flasher = flasher.clone();
+        ui.on_cancel(move || {
+            let ui = ui_handle.unwrap();
+            dispatch(&ui, &flasher, Command::Cancel);
+        });
+    }
+
+    {
+        let ui_handle = ui.as_weak();
+        let image_file = image_file.clone();
+        let flasher = flasher.clone();
+        ui.on_download(move || {
+            let ui = ui_handle.unwrap();
+            let Some(path) = image_file.borrow().clone() else {
+                tracing::error!("Image file is not selected");
+                return;
+            };
+            let settings = Settings {
+                exclude_rootfs: ui.get_exclude_rootfs(),
+                verify: ui.get_verify(),
+                ..Default::default()
+            };
+            let mut config = match settings.download_config() {
+                Ok(config) => config,
+                Err(e) => {
+                    tracing::error!("{}", e);
+                    return;
+                }
+            };
+            let transport = transport(ui.get_transport().as_str());
+
+            if !dispatch(&ui, &flasher, Command::StartFlash) {
+                return;
+            }
+            config.cancellation = flasher
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .cancellation();
+            let timing = Timing::start(axdl::progress::timestamp());
+            let mut progress = DesktopProgress {
+                ui: ui_handle.clone(),
+                flasher: flasher.clone(),
+                timing: timing.clone(),
+            };
+            std::thread::spawn(move || {
+                let result = download(transport, path, config, &mut progress);
+                dispatch_later(
+                    &progress.ui,
+                    &progress.flasher,
+                    Command::ReportProgress(timing.finished(axdl::progress::timestamp())),
+                );
+                dispatch_later(
+                    &progress.ui,
+                    &progress.flasher,
+                    Command::Finish(result.map_err(|e| e.to_string())),
+                );
+            });
+        });
+    }
+
+    ui.run()?;
+    Ok(())
+}
@@ -0,0 +1,88 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks GitHub releases for a newer version of the desktop flasher.
+//!
+//! Installing the update is left to the platform package, so this only tells the user
+//! where to download it from. The check is skipped when the app is started with
+//! `--no-update-check` or with `AXDL_NO_UPDATE_CHECK` set, e.g. on machines without internet
+//! access.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::DesktopWindow;
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/ciniml/axdl-rs/releases/latest";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    html_url: String,
+}
+
+/// Parses a version such as `v0.1.2` into its numeric components.
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version
+        .trim_start_matches('v')
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect()
+}
+
+fn latest_release() -> Result<Option<Release>, Box<dyn std::error::Error>> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+    let release: Release = agent
+        .get(LATEST_RELEASE_URL)
+        .header(
+            "User-Agent",
+            concat!("axdl-desktop/", env!("CARGO_PKG_VERSION")),
+        )
+        .call()?
+        .body_mut()
+        .read_json()?;
+    let current = parse_version(env!("CARGO_PKG_VERSION"));
+    let newer = match (parse_version(&release.tag_name), current) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    };
+    Ok(newer.then_some(release))
+}
+
+/// Returns whether the update check is enabled, see the module documentation.
+pub fn is_enabled() -> bool {
+    std::env::var_os("AXDL_NO_UPDATE_CHECK").is_none()
+        && !std::env::args().any(|arg| arg == "--no-update-check")
+}
+
+/// Checks for a newer release without blocking the UI and shows it if there is one.
+pub fn check_in_background(ui: slint::Weak<DesktopWindow>) {
+    std::thread::spawn(move || match latest_release() {
+        Ok(Some(release)) => {
+            tracing::info!("axdl {} is available", release.tag_name);
+            let _ = ui.upgrade_in_event_loop(move |ui| {
+                ui.set_update_version(release.tag_name.into());
+                ui.set_update_url(release.html_url.into());
+            });
+        }
+        Ok(None) => {}
+        Err(e) => tracing::debug!("Failed to check for updates: {}", e),
+    });
+}
@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Builder for AXP images used as emulator test inputs.
+
+use std::io::Write;
+
+use axdl::partition::{Block, Image, ImageType, Partition, PartitionTable, Project};
+
+/// Builds an in-memory AXP image from a partition layout and image contents.
+#[derive(Debug)]
+pub struct AxpBuilder {
+    fdl_level: u32,
+    partition_table: PartitionTable,
+    images: Vec<Image>,
+    files: Vec<(String, Vec<u8>)>,
+    compression: zip::CompressionMethod,
+}
+
+impl AxpBuilder {
+    pub fn new(fdl_level: u32) -> Self {
+        Self {
+            fdl_level,
+            partition_table: PartitionTable::new(1, 2),
+            images: Vec::new(),
+            files: Vec::new(),
+            compression: zip::CompressionMethod::Stored,
+        }
+    }
+
+    pub fn compression(mut self, compression: zip::CompressionMethod) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn partition(mut self, name: &str, size: u64) -> Self {
+        self.partition_table
+            .add_partition(Partition::new(name.into(), 0, size));
+        self
+    }
+
+    /// Adds a flash downloader loaded to RAM at `address`.
+    pub fn fdl(mut self, name: &str, r#type: ImageType, address: u64, data: Vec<u8>) -> Self {
+        let file = format!("{}.bin", name.to_lowercase());
+        self.images.push(Image::new(
+            name.into(),
+            r#type,
+            Block::Absolute(address),
+            Some(file.clone()),
+        ));
+        self.files.push((file, data));
+        self
+    }
+
+    /// Adds a code image written to `partition`.
+    pub fn code(mut self, name: &str, partition: &str, data: Vec<u8>) -> Self {
+        let file = format!("{}.img", name.to_lowercase());
+        self.images.push(Image::new(
+            name.into(),
+            ImageType::Code,
+            Block::Partition(partition.into()),
+            Some(file.clone()),
+        ));
+        self.files.push((file, data));
+        self
+    }
+
+    /// Adds a code image written to `partition`, stored as numbered files of `chunk_size` bytes.
+    pub fn split_code(
+        mut self,
+        name: &str,
+        partition: &str,
+        data: Vec<u8>,
+        chunk_size: usize,
+    ) -> Self {
+        let file = format!("{}.img", name.to_lowercase());
+        self.images.push(Image::new(
+            name.into(),
+            ImageType::Code,
+            Block::Partition(partition.into()),
+            Some(file.clone()),
+        ));
+        for (index, chunk) in data.chunks(chunk_size).enumerate() {
+            self.files
+                .push((format!("{}.{:03}", file, index), chunk.to_vec()));
+        }
+        self
+    }
+
+    pub fn project(&self) -> Project {
+        let mut project = Project::new(
+            "EMULATOR".into(),
+            "EMULATOR".into(),
+            "1".into(),
+            self.fdl_level,
+            self.partition_table.clone(),
+        );
+        for image in &self.images {
+            project.add_image(image.clone());
+        }
+        project
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default().compression_method(self.compression);
+        writer
+            .start_file("emulator.xml", options)
+            .expect("failed to add the project file");
+        writer
+            .write_all(axdl::partition::serialize::to_string(&self.project()).as_bytes())
+            .expect("failed to write the project file");
+        for (name, data) in &self.files {
+            writer
+                .start_file(name.as_str(), options)
+                .expect("failed to add an image file");
+            writer
+                .write_all(data)
+                .expect("failed to write an image file");
+        }
+        writer
+            .finish()
+            .expect("failed to finish the image")
+            .into_inner()
+    }
+}
+
+/// Returns `size` bytes of data which differs between blocks.
+pub fn pattern(size: usize, seed: u8) -> Vec<u8> {
+    (0..size)
+        .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed) ^ (i >> 12) as u8)
+        .collect()
+}
@@ -0,0 +1,495 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Virtual device emulating the romcode, FDL1 and FDL2 stages of the download protocol.
+//!
+//! The emulator runs behind [`axdl::transport::mock::MockDevice`], so the whole
+//! [`axdl::download_image`] sequence can be exercised without hardware.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use axdl::frame::{commands, AxdlFrame, AxdlFrameView};
+use axdl::transport::{mock::MockDevice, DynDevice};
+
+pub mod axp;
+
+/// Responses sent by the emulator, re-exported from [`axdl::frame::commands`].
+pub mod response {
+    pub use axdl::frame::commands::{
+        ACK, DESTINATION_ERROR, DOWNLOAD_NOT_STARTED, INVALID_COMMAND, SIZE_ERROR, UNKNOWN_COMMAND,
+        VERIFY_ERROR, VERSION,
+    };
+    pub const READ_FLASH: u16 = axdl::frame::commands::READ_BLOCK_DATA;
+}
+
+const HANDSHAKE_REQUEST: [u8; 3] = [commands::HANDSHAKE; 3];
+
+/// Program running on the emulated device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Romcode,
+    Fdl1,
+    Fdl2,
+}
+
+impl Stage {
+    /// Version string reported on handshake.
+    pub fn version(&self) -> &'static str {
+        match self {
+            Self::Romcode => "romcode v1.0;raw",
+            Self::Fdl1 => "fdl1 v1.0;raw",
+            Self::Fdl2 => "fdl2 v1.0;raw",
+        }
+    }
+}
+
+/// Packet that triggers a fault. Occurrences are counted from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    Handshake(usize),
+    Command(u16, usize),
+    Data(usize),
+}
+
+/// What the emulator does instead of handling the triggering packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FaultAction {
+    /// Responds with the given response code.
+    Respond(u16),
+    /// Sends nothing, so the host times out.
+    Drop,
+    /// Sends an acknowledge with a broken checksum.
+    CorruptChecksum,
+    /// Reports the given version string on handshake.
+    Version(String),
+    /// Sends the given bytes as they are.
+    Raw(Vec<u8>),
+    /// Handles the packet as usual and sends the response twice, like firmware which sometimes
+    /// repeats an acknowledge.
+    Repeat,
+}
+
+/// Fault injected once when its trigger matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fault {
+    pub trigger: Trigger,
+    pub action: FaultAction,
+}
+
+impl Fault {
+    pub fn new(trigger: Trigger, action: FaultAction) -> Self {
+        Self { trigger, action }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    Ram(u64),
+    Partition(String),
+}
+
+#[derive(Debug)]
+struct Transfer {
+    target: Target,
+    length: u64,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct State {
+    fdl_level: u32,
+    stage: Stage,
+    faults: Vec<Fault>,
+    handshakes: usize,
+    last_handshake_request: Option<Vec<u8>>,
+    data_blocks: usize,
+    zero_length_packets: usize,
+    commands: HashMap<u16, usize>,
+    ram_download: bool,
+    transfer: Option<Transfer>,
+    pending_block: Option<usize>,
+    /// Whether to send the response to the current packet twice.
+    repeat: bool,
+    reading: Option<String>,
+    ram: BTreeMap<u64, Vec<u8>>,
+    partitions: BTreeMap<String, Vec<u8>>,
+    partition_table: Option<Vec<u8>>,
+}
+
+/// Emulated device. Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct Emulator {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new(2)
+    }
+}
+
+impl Emulator {
+    /// Creates an emulator in the romcode stage, loading `fdl_level` flash downloaders before FDL2 runs.
+    pub fn new(fdl_level: u32) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                fdl_level,
+                stage: Stage::Romcode,
+                faults: Vec::new(),
+                handshakes: 0,
+                last_handshake_request: None,
+                data_blocks: 0,
+                zero_length_packets: 0,
+                commands: HashMap::new(),
+                ram_download: false,
+                transfer: None,
+                pending_block: None,
+                repeat: false,
+                reading: None,
+                ram: BTreeMap::new(),
+                partitions: BTreeMap::new(),
+                partition_table: None,
+            })),
+        }
+    }
+
+    pub fn with_fault(self, fault: Fault) -> Self {
+        self.state().faults.push(fault);
+        self
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns a device connected to this emulator.
+    pub fn device(&self) -> MockDevice {
+        let emulator = self.clone();
+        MockDevice::new(move |packet| emulator.state().handle(packet))
+    }
+
+    pub fn dyn_device(&self) -> DynDevice {
+        Box::new(self.device())
+    }
+
+    /// Reboots into the romcode stage. Partitions and the command counters are kept.
+    pub fn reset(&self) {
+        let mut state = self.state();
+        state.stage = Stage::Romcode;
+        state.ram_download = false;
+        state.transfer = None;
+        state.pending_block = None;
+        state.reading = None;
+        state.ram.clear();
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.state().stage
+    }
+
+    /// Returns the image downloaded to RAM at `address`.
+    pub fn ram(&self, address: u64) -> Option<Vec<u8>> {
+        self.state().ram.get(&address).cloned()
+    }
+
+    /// Returns the data written to the partition `name`.
+    pub fn partition(&self, name: &str) -> Option<Vec<u8>> {
+        self.state().partitions.get(name).cloned()
+    }
+
+    /// Returns the names of the written partitions in order.
+    pub fn partition_names(&self) -> Vec<String> {
+        self.state().partitions.keys().cloned().collect()
+    }
+
+    /// Returns the payload of the last set partition table command.
+    pub fn partition_table(&self) -> Option<Vec<u8>> {
+        self.state().partition_table.clone()
+    }
+
+    /// Returns the bytes of the last handshake request received.
+    pub fn last_handshake_request(&self) -> Option<Vec<u8>> {
+        self.state().last_handshake_request.clone()
+    }
+
+    /// Returns how many times `command` has been received.
+    pub fn command_count(&self, command: u16) -> usize {
+        self.state().commands.get(&command).copied().unwrap_or(0)
+    }
+
+    /// Returns how many zero-length packets have been received.
+    pub fn zero_length_packets(&self) -> usize {
+        self.state().zero_length_packets
+    }
+}
+
+fn respond(code: u16) -> Vec<Vec<u8>> {
+    respond_with(code, Vec::new())
+}
+
+fn respond_with(code: u16, payload: impl Into<Vec<u8>>) -> Vec<Vec<u8>> {
+    match AxdlFrame::new(code).with_payload(payload).build() {
+        Ok(frame) => vec![frame],
+        // The payload does not fit in a frame.
+        Err(_) => respond(response::SIZE_ERROR),
+    }
+}
+
+fn utf16_name(bytes: &[u8]) -> String {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0)
+        .collect::<Vec<_>>();
+    String::from_utf16_lossy(&units)
+}
+
+impl State {
+    /// Takes the fault triggered by `trigger`, unless it only repeats the usual response.
+    fn take_fault(&mut self, trigger: &Trigger) -> Option<FaultAction> {
+        let index = self.faults.iter().position(|f| &f.trigger == trigger)?;
+        match self.faults.remove(index).action {
+            FaultAction::Repeat => {
+                self.repeat = true;
+                None
+            }
+            action => Some(action),
+        }
+    }
+
+    fn apply_fault(&self, action: FaultAction) -> Vec<Vec<u8>> {
+        match action {
+            FaultAction::Respond(code) => respond(code),
+            FaultAction::Drop => Vec::new(),
+            FaultAction::CorruptChecksum => {
+                let mut frames = respond(response::ACK);
+                for frame in &mut frames {
+                    if let Some(last) = frame.last_mut() {
+                        *last ^= 0xff;
+                    }
+                }
+                frames
+            }
+            FaultAction::Version(version) => respond_with(response::VERSION, version),
+            FaultAction::Raw(packet) => vec![packet],
+            // Taken by `take_fault`, which leaves the packet to the usual handling.
+            FaultAction::Repeat => Vec::new(),
+        }
+    }
+
+    fn handle(&mut self, packet: &[u8]) -> Vec<Vec<u8>> {
+        // A zero-length packet only ends a USB transfer.
+        if packet.is_empty() {
+            self.zero_length_packets += 1;
+            return Vec::new();
+        }
+        // A host may send a command and the data it announces in one write, as over a serial
+        // port where the device reads a stream.
+        let frame_length = AxdlFrameView::new(packet).frame_length();
+        let mut responses = match frame_length {
+            Some(length) if self.pending_block.is_none() && length < packet.len() => {
+                let (frame, data) = packet.split_at(length);
+                let mut responses = self.handle_packet(frame);
+                responses.extend(self.handle_packet(data));
+                responses
+            }
+            _ => self.handle_packet(packet),
+        };
+        if std::mem::take(&mut self.repeat) {
+            responses.extend(responses.clone());
+        }
+        responses
+    }
+
+    fn handle_packet(&mut self, packet: &[u8]) -> Vec<Vec<u8>> {
+        if let Some(block_size) = self.pending_block.take() {
+            return self.handle_data(packet, block_size);
+        }
+        // Hosts may append version negotiation bytes, which the emulator ignores.
+        if packet.starts_with(&HANDSHAKE_REQUEST) {
+            self.handshakes += 1;
+            self.last_handshake_request = Some(packet.to_vec());
+            if let Some(action) = self.take_fault(&Trigger::Handshake(self.handshakes)) {
+                return self.apply_fault(action);
+            }
+            return respond_with(response::VERSION, self.stage.version());
+        }
+
+        let view = AxdlFrameView::new(packet);
+        if !view.is_valid() {
+            return respond(response::VERIFY_ERROR);
+        }
+        let (Some(command), Some(payload)) = (view.command_response(), view.payload()) else {
+            return respond(response::VERIFY_ERROR);
+        };
+        let count = self.commands.entry(command).or_insert(0);
+        *count += 1;
+        let count = *count;
+        if let Some(action) = self.take_fault(&Trigger::Command(command, count)) {
+            return self.apply_fault(action);
+        }
+        if command == commands::READ_BLOCK {
+            return self.handle_read_block(payload);
+        }
+        respond(self.handle_command(command, payload))
+    }
+
+    fn handle_read_block(&mut self, payload: &[u8]) -> Vec<Vec<u8>> {
+        let Some(name) = self.reading.as_ref() else {
+            return respond(response::DOWNLOAD_NOT_STARTED);
+        };
+        if payload.len() < 12 {
+            return respond(response::INVALID_COMMAND);
+        }
+        let size = u32::from_le_bytes(payload[0..4].try_into().unwrap_or_default()) as usize;
+        let offset = u64::from_le_bytes(payload[4..12].try_into().unwrap_or_default()) as usize;
+        let data = self
+            .partitions
+            .get(name)
+            .and_then(|data| data.get(offset..))
+            .map(|data| &data[..size.min(data.len())])
+            .unwrap_or_default();
+        if data.is_empty() {
+            return respond(response::SIZE_ERROR);
+        }
+        respond_with(response::READ_FLASH, data)
+    }
+
+    fn handle_data(&mut self, packet: &[u8], block_size: usize) -> Vec<Vec<u8>> {
+        self.data_blocks += 1;
+        if let Some(action) = self.take_fault(&Trigger::Data(self.data_blocks)) {
+            return self.apply_fault(action);
+        }
+        if packet.len() != block_size {
+            return respond(response::SIZE_ERROR);
+        }
+        match self.transfer.as_mut() {
+            Some(transfer) => {
+                transfer.data.extend_from_slice(packet);
+                respond(response::ACK)
+            }
+            None => respond(response::DOWNLOAD_NOT_STARTED),
+        }
+    }
+
+    fn handle_command(&mut self, command: u16, payload: &[u8]) -> u16 {
+        let in_loader = matches!(self.stage, Stage::Romcode | Stage::Fdl1);
+        match command {
+            // Start RAM download
+            commands::START_RAM_DOWNLOAD if in_loader => {
+                self.ram_download = true;
+                response::ACK
+            }
+            // Start partition
+            commands::START_PARTITION => {
+                if self.transfer.is_some() {
+                    return response::INVALID_COMMAND;
+                }
+                let (target, length) = match (self.stage, payload.len()) {
+                    (Stage::Fdl2, 88) => (
+                        Target::Partition(utf16_name(&payload[..72])),
+                        u64::from_le_bytes(payload[72..80].try_into().unwrap_or_default()),
+                    ),
+                    (_, 8) if in_loader && self.ram_download => (
+                        Target::Ram(
+                            u32::from_le_bytes(payload[0..4].try_into().unwrap_or_default()) as u64,
+                        ),
+                        u32::from_le_bytes(payload[4..8].try_into().unwrap_or_default()) as u64,
+                    ),
+                    (_, 16) if in_loader && self.ram_download => (
+                        Target::Ram(u64::from_le_bytes(
+                            payload[0..8].try_into().unwrap_or_default(),
+                        )),
+                        u64::from_le_bytes(payload[8..16].try_into().unwrap_or_default()),
+                    ),
+                    _ => return response::INVALID_COMMAND,
+                };
+                self.transfer = Some(Transfer {
+                    target,
+                    length,
+                    data: Vec::new(),
+                });
+                response::ACK
+            }
+            // Start block
+            commands::START_BLOCK => {
+                if self.transfer.is_none() {
+                    return response::DOWNLOAD_NOT_STARTED;
+                }
+                if payload.len() < 2 {
+                    return response::INVALID_COMMAND;
+                }
+                self.pending_block = Some(u16::from_le_bytes([payload[0], payload[1]]) as usize);
+                response::ACK
+            }
+            // End partition
+            commands::END_PARTITION => {
+                let Some(transfer) = self.transfer.take() else {
+                    return response::DOWNLOAD_NOT_STARTED;
+                };
+                if transfer.data.len() as u64 != transfer.length {
+                    return response::SIZE_ERROR;
+                }
+                match transfer.target {
+                    Target::Ram(address) => {
+                        self.ram.insert(address, transfer.data);
+                    }
+                    Target::Partition(name) => {
+                        self.partitions.insert(name, transfer.data);
+                    }
+                }
+                response::ACK
+            }
+            // End RAM download
+            commands::END_RAM_DOWNLOAD if in_loader && self.ram_download => {
+                if self.transfer.is_some() {
+                    return response::INVALID_COMMAND;
+                }
+                self.ram_download = false;
+                self.stage = match (self.stage, self.fdl_level) {
+                    (Stage::Romcode, 2) => Stage::Fdl1,
+                    _ => Stage::Fdl2,
+                };
+                response::ACK
+            }
+            // Set partition table
+            commands::SET_PARTITION_TABLE if self.stage == Stage::Fdl2 => {
+                self.partition_table = Some(payload.to_vec());
+                response::ACK
+            }
+            // Start read partition
+            commands::START_READ_PARTITION if self.stage == Stage::Fdl2 && payload.len() == 88 => {
+                let name = utf16_name(&payload[..72]);
+                if !self.partitions.contains_key(&name) {
+                    return response::DESTINATION_ERROR;
+                }
+                self.reading = Some(name);
+                response::ACK
+            }
+            // End read partition
+            commands::END_READ_PARTITION if self.reading.is_some() => {
+                self.reading = None;
+                response::ACK
+            }
+            commands::START_RAM_DOWNLOAD
+            | commands::END_RAM_DOWNLOAD
+            | commands::SET_PARTITION_TABLE
+            | commands::START_READ_PARTITION
+            | commands::END_READ_PARTITION => response::INVALID_COMMAND,
+            _ => response::UNKNOWN_COMMAND,
+        }
+    }
+}
@@ -0,0 +1,1470 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axdl::cancel::CancellationToken;
+use axdl::communication::vendor::VendorCommand;
+use axdl::communication::{
+    self, BlockWriter, HandshakeInfo, Pacing, Request, RetryPolicy, Session, SessionState,
+};
+use axdl::hash::Digest;
+use axdl::partition::{ImageType, PartitionTable};
+use axdl::progress::{BootStage, FnProgress, NoProgress, Phase, StageEvent};
+use axdl::provision::{ProvisionData, Sequence, Template};
+use axdl::report::{DownloadReport, VerifyResult};
+use axdl::source::PartitionSource;
+use axdl::transport::record::{Direction, Recording, RecordingDevice, ReplayDevice};
+use axdl::transport::{Device, DeviceInfo, DynDevice, TransportKind};
+use axdl::{filter, AxdlError, DownloadConfig, PartitionOptions};
+use axdl_emulator::axp::{pattern, AxpBuilder};
+use axdl_emulator::{response, Emulator, Fault, FaultAction, Stage, Trigger};
+
+fn two_level_image() -> AxpBuilder {
+    AxpBuilder::new(2)
+        .partition("spl", 0x40000)
+        .partition("rootfs", 0x400000)
+        .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+        .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+        .code("SPL", "spl", pattern(1000, 3))
+        .code("ROOTFS", "rootfs", pattern(200_000, 4))
+}
+
+fn download(
+    emulator: &Emulator,
+    image: &AxpBuilder,
+    config: &DownloadConfig,
+) -> Result<DownloadReport, AxdlError> {
+    let mut reader = std::io::Cursor::new(image.build());
+    let mut device = emulator.dyn_device();
+    axdl::download_image(&mut reader, &mut device, config, &mut NoProgress)
+}
+
+#[test]
+fn download_two_level_fdl() {
+    let emulator = Emulator::new(2);
+    download(&emulator, &two_level_image(), &DownloadConfig::default()).unwrap();
+
+    assert_eq!(emulator.stage(), Stage::Fdl2);
+    assert_eq!(emulator.ram(0x3000), Some(pattern(12345, 1)));
+    assert_eq!(emulator.ram(0x5c00_0000), Some(pattern(70000, 2)));
+    assert_eq!(emulator.partition("spl"), Some(pattern(1000, 3)));
+    assert_eq!(emulator.partition("rootfs"), Some(pattern(200_000, 4)));
+    let image = two_level_image();
+    assert_eq!(
+        emulator.partition_table(),
+        Some(image.project().partition_table().to_bytes().unwrap())
+    );
+}
+
+#[test]
+fn download_single_level_fdl() {
+    let image = AxpBuilder::new(1)
+        .partition("rootfs", 0x10000)
+        .fdl("FDL", ImageType::Fdl2, 0x3000, pattern(4000, 1))
+        .code("ROOTFS", "rootfs", pattern(5000, 2))
+        .compression(zip::CompressionMethod::Deflated);
+    let emulator = Emulator::new(1);
+    download(&emulator, &image, &DownloadConfig::default()).unwrap();
+
+    assert_eq!(emulator.ram(0x3000), Some(pattern(4000, 1)));
+    assert_eq!(emulator.partition("rootfs"), Some(pattern(5000, 2)));
+}
+
+/// Records the stages the device entered with the versions they reported.
+#[derive(Default)]
+struct RecordedStages(Vec<(BootStage, Option<String>)>);
+
+impl axdl::DownloadProgress for RecordedStages {
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+    fn report_progress(&mut self, _description: &str, _progress: Option<f32>) {}
+    fn report_stage(&mut self, event: &StageEvent<'_>) {
+        self.0.push((
+            event.stage,
+            event
+                .handshake
+                .and_then(|handshake| handshake.version.clone()),
+        ));
+    }
+}
+
+#[test]
+fn stage_events() {
+    let version = |stage, version: Option<&str>| (stage, version.map(str::to_string));
+
+    let emulator = Emulator::new(2);
+    let mut reader = std::io::Cursor::new(two_level_image().build());
+    let mut stages = RecordedStages::default();
+    axdl::download_image(
+        &mut reader,
+        &mut emulator.dyn_device(),
+        &DownloadConfig::default(),
+        &mut stages,
+    )
+    .unwrap();
+    assert_eq!(
+        stages.0,
+        [
+            version(BootStage::Romcode, Some("1.0")),
+            version(BootStage::Fdl1, Some("1.0")),
+            version(BootStage::Fdl2, None),
+        ]
+    );
+
+    let image = AxpBuilder::new(1)
+        .partition("rootfs", 0x10000)
+        .fdl("FDL", ImageType::Fdl2, 0x3000, pattern(4000, 1))
+        .code("ROOTFS", "rootfs", pattern(5000, 2));
+    let emulator = Emulator::new(1);
+    let mut reader = std::io::Cursor::new(image.build());
+    let mut stages = RecordedStages::default();
+    axdl::download_image(
+        &mut reader,
+        &mut emulator.dyn_device(),
+        &DownloadConfig::default(),
+        &mut stages,
+    )
+    .unwrap();
+    assert_eq!(
+        stages.0,
+        [
+            version(BootStage::Romcode, Some("1.0")),
+            version(BootStage::Fdl2, Some("1.0")),
+        ]
+    );
+}
+
+#[test]
+fn free_functions_in_sequence() {
+    let image = AxpBuilder::new(1).partition("rootfs", 0x10000);
+    let fdl = pattern(4000, 1);
+    let rootfs = pattern(5000, 2);
+    let emulator = Emulator::new(1);
+    let mut device = emulator.dyn_device();
+
+    communication::wait_handshake(&mut device, "romcode").unwrap();
+    communication::start_ram_download(&mut device).unwrap();
+    communication::start_partition_absolute_32(&mut device, 0x3000, fdl.len() as u32).unwrap();
+    communication::write_image(
+        &mut device,
+        &mut fdl.as_slice(),
+        1000,
+        "FDL",
+        fdl.len(),
+        None,
+        &mut NoProgress,
+    )
+    .unwrap();
+    communication::end_partition(&mut device, Duration::from_secs(1)).unwrap();
+    communication::end_ram_download(&mut device).unwrap();
+    communication::wait_handshake(&mut device, "fdl2").unwrap();
+    communication::set_partition_table(&mut device, image.project().partition_table()).unwrap();
+    communication::start_partition_id(&mut device, "rootfs", rootfs.len() as u64).unwrap();
+    for block in rootfs.chunks(2000) {
+        communication::start_block(&mut device, block.len() as u16).unwrap();
+        let written = device.write_timeout(block, Duration::from_secs(1)).unwrap();
+        assert_eq!(written, block.len());
+        communication::receive_response(&mut device, Duration::from_secs(1)).unwrap();
+    }
+    communication::end_partition(&mut device, Duration::from_secs(1)).unwrap();
+
+    assert_eq!(emulator.ram(0x3000), Some(fdl));
+    assert_eq!(emulator.partition("rootfs"), Some(rootfs));
+}
+
+#[test]
+fn exclude_rootfs() {
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        exclude_rootfs: true,
+        ..Default::default()
+    };
+    download(&emulator, &two_level_image(), &config).unwrap();
+
+    assert_eq!(emulator.partition_names(), vec!["spl".to_string()]);
+}
+
+#[test]
+fn include_and_exclude_partitions() {
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        include_partitions: vec!["spl".into(), "ROOTFS".into()],
+        exclude_partitions: vec!["rootfs".into()],
+        ..Default::default()
+    };
+    download(&emulator, &two_level_image(), &config).unwrap();
+
+    assert_eq!(emulator.partition_names(), vec!["spl".to_string()]);
+}
+
+#[test]
+fn unknown_included_partition() {
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        include_partitions: vec!["boot".into()],
+        ..Default::default()
+    };
+    let result = download(&emulator, &two_level_image(), &config);
+
+    assert!(matches!(result, Err(AxdlError::InvalidConfig(_))));
+    assert_eq!(emulator.command_count(0x0000), 0);
+}
+
+#[test]
+fn provisioning_data() {
+    let image = two_level_image().partition("env", 0x1000);
+    let values = Sequence {
+        serial: Some("AX0100".parse().unwrap()),
+        mac: Some("02:00:00:00:00:ff".parse().unwrap()),
+    }
+    .values(1)
+    .unwrap();
+    let data = Template::new("serial=${serial}\nethaddr=${mac}\n")
+        .render(&values)
+        .unwrap();
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        verify: true,
+        provision: Some(ProvisionData {
+            partition: "env".into(),
+            data: data.into_bytes(),
+        }),
+        ..Default::default()
+    };
+    let report = download(&emulator, &image, &config).unwrap();
+
+    assert_eq!(
+        emulator.partition("env"),
+        Some(b"serial=AX0101\nethaddr=02:00:00:00:01:00\n".to_vec())
+    );
+    let last = report.partitions.last().unwrap();
+    assert_eq!(last.partition, "env");
+    assert_eq!(last.verify, VerifyResult::Passed);
+}
+
+#[test]
+fn provisioning_unknown_partition() {
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        provision: Some(ProvisionData {
+            partition: "vendor".into(),
+            data: b"serial=AX0001".to_vec(),
+        }),
+        ..Default::default()
+    };
+    let result = download(&emulator, &two_level_image(), &config);
+
+    assert!(matches!(result, Err(AxdlError::InvalidConfig(_))));
+    assert_eq!(emulator.command_count(0x0000), 0);
+}
+
+#[test]
+fn small_chunk_size() {
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        image_chunk_size: 4096,
+        ..Default::default()
+    };
+    download(&emulator, &two_level_image(), &config).unwrap();
+
+    assert_eq!(emulator.partition("rootfs"), Some(pattern(200_000, 4)));
+}
+
+#[test]
+fn verify_after_write() {
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        verify: true,
+        ..Default::default()
+    };
+    let report = download(&emulator, &two_level_image(), &config).unwrap();
+
+    assert!(report.is_success());
+    assert_eq!(report.partitions.len(), 2);
+    assert_eq!(report.partitions[1].partition, "rootfs");
+    assert_eq!(report.partitions[1].bytes_written, 200_000);
+    assert_eq!(report.partitions[1].verify, VerifyResult::Passed);
+}
+
+#[test]
+fn verify_joins_responses_split_across_transfers() {
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        verify: true,
+        ..Default::default()
+    };
+    let mut reader = std::io::Cursor::new(two_level_image().build());
+    let mut device: DynDevice = Box::new(emulator.device().with_transfer_size(512));
+    let report = axdl::download_image(&mut reader, &mut device, &config, &mut NoProgress).unwrap();
+    assert_eq!(report.partitions[1].verify, VerifyResult::Passed);
+
+    // The rest of a frame which never arrives is not taken for a complete one.
+    let mut device = emulator.device();
+    let frame = axdl::frame::AxdlFrame::new(response::READ_FLASH)
+        .with_payload([0u8; 100])
+        .build()
+        .unwrap();
+    device.push_response(frame[..64].to_vec());
+    let mut device: DynDevice = Box::new(device);
+    let mut session = Session::new(&mut device);
+    assert!(matches!(
+        session.receive_response(Duration::from_millis(10)),
+        Err(AxdlError::InvalidFrame)
+    ));
+}
+
+#[test]
+fn skip_same_partitions() {
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        skip_same: true,
+        ..Default::default()
+    };
+    let report = download(&emulator, &two_level_image(), &config).unwrap();
+    assert!(report.partitions.iter().all(|partition| !partition.skipped));
+    assert_eq!(emulator.command_count(0x0001), 4);
+
+    // Only ROOTFS differs on the second run.
+    emulator.reset();
+    let image = AxpBuilder::new(2)
+        .partition("spl", 0x40000)
+        .partition("rootfs", 0x400000)
+        .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+        .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+        .code("SPL", "spl", pattern(1000, 3))
+        .code("ROOTFS", "rootfs", pattern(200_000, 5));
+    let report = download(&emulator, &image, &config).unwrap();
+
+    assert!(report.partitions[0].skipped);
+    assert_eq!(report.partitions[0].bytes_written, 0);
+    assert!(!report.partitions[1].skipped);
+    assert_eq!(emulator.command_count(0x0001), 4 + 3);
+    assert_eq!(emulator.partition("rootfs"), Some(pattern(200_000, 5)));
+}
+
+#[test]
+fn skip_same_writes_partition_refused_partway() {
+    use axdl::frame::commands;
+
+    // The first run reads nothing back, the second one reads SPL and then ROOTFS, whose
+    // second block is refused.
+    let emulator = Emulator::new(2).with_fault(Fault::new(
+        Trigger::Command(commands::READ_BLOCK, 3),
+        FaultAction::Respond(response::INVALID_COMMAND),
+    ));
+    download(&emulator, &two_level_image(), &DownloadConfig::default()).unwrap();
+    let written = emulator.command_count(commands::START_PARTITION);
+
+    emulator.reset();
+    let config = DownloadConfig {
+        skip_same: true,
+        ..Default::default()
+    };
+    let report = download(&emulator, &two_level_image(), &config).unwrap();
+
+    assert!(report.partitions[0].skipped);
+    assert!(!report.partitions[1].skipped);
+    assert_eq!(report.partitions[1].bytes_written, 200_000);
+    assert_eq!(emulator.command_count(commands::READ_BLOCK), 3);
+    assert_eq!(emulator.command_count(commands::END_READ_PARTITION), 2);
+    assert_eq!(
+        emulator.command_count(commands::START_PARTITION),
+        written + 3
+    );
+    assert_eq!(emulator.partition("rootfs"), Some(pattern(200_000, 4)));
+}
+
+#[test]
+fn verify_image_without_writing() {
+    use axdl::frame::commands;
+
+    let emulator = Emulator::new(2);
+    download(&emulator, &two_level_image(), &DownloadConfig::default()).unwrap();
+    let written = emulator.command_count(commands::START_PARTITION);
+
+    // The image differs in ROOTFS from what the device holds.
+    emulator.reset();
+    let image = AxpBuilder::new(2)
+        .partition("spl", 0x40000)
+        .partition("rootfs", 0x400000)
+        .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+        .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+        .code("SPL", "spl", pattern(1000, 3))
+        .code("ROOTFS", "rootfs", pattern(200_000, 5));
+    let mut reader = std::io::Cursor::new(image.build());
+    let mut device = emulator.dyn_device();
+    let report = axdl::verify_image(
+        &mut reader,
+        &mut device,
+        &DownloadConfig::default(),
+        &mut NoProgress,
+    )
+    .unwrap();
+
+    assert_eq!(report.failure(), Some("Verification failed"));
+    assert_eq!(report.partitions[0].verify, VerifyResult::Passed);
+    assert!(matches!(
+        report.partitions[1].verify,
+        VerifyResult::Failed { .. }
+    ));
+    assert!(report.partitions.iter().all(|p| p.bytes_written == 0));
+    // Only the flash downloaders were loaded.
+    assert_eq!(
+        emulator.command_count(commands::START_PARTITION),
+        written + 2
+    );
+    assert_eq!(emulator.command_count(commands::SET_PARTITION_TABLE), 1);
+    assert_eq!(emulator.partition("rootfs"), Some(pattern(200_000, 4)));
+}
+
+#[test]
+fn verify_detects_mismatch() {
+    for digest in Digest::ALL {
+        let mut data = pattern(1000, 3);
+        data[10] ^= 0xff;
+        let emulator = Emulator::new(2).with_fault(Fault::new(
+            Trigger::Command(0x0011, 1),
+            FaultAction::Raw(
+                axdl::frame::AxdlFrame::new(response::READ_FLASH)
+                    .with_payload(data)
+                    .build()
+                    .unwrap(),
+            ),
+        ));
+        let config = DownloadConfig {
+            verify: true,
+            verify_digest: digest,
+            ..Default::default()
+        };
+        let report = download(&emulator, &two_level_image(), &config).unwrap();
+
+        assert!(!report.is_success(), "{}", digest);
+        assert_eq!(
+            report.partitions[0].verify,
+            VerifyResult::Failed { offset: 10 }
+        );
+        assert_eq!(report.partitions[1].verify, VerifyResult::Passed);
+    }
+}
+
+#[test]
+fn serial_blocks_are_sent_with_their_command() {
+    let download_over_serial = |coalesce_writes| {
+        let emulator = Emulator::new(2);
+        let recorder = RecordingDevice::new(Box::new(
+            emulator.device().with_transport_kind(TransportKind::Serial),
+        ));
+        let recording = recorder.recording();
+        let mut device: DynDevice = Box::new(recorder);
+        let config = DownloadConfig {
+            verify: true,
+            coalesce_writes,
+            ..Default::default()
+        };
+        let mut reader = std::io::Cursor::new(two_level_image().build());
+        let report =
+            axdl::download_image(&mut reader, &mut device, &config, &mut NoProgress).unwrap();
+        assert!(report.is_success());
+        assert_eq!(report.partitions[1].verify, VerifyResult::Passed);
+        assert_eq!(emulator.partition("rootfs"), Some(pattern(200_000, 4)));
+        let writes = recording
+            .lock()
+            .unwrap()
+            .packets
+            .iter()
+            .filter(|packet| packet.direction == Direction::Out)
+            .count();
+        (
+            writes,
+            emulator.command_count(axdl::frame::commands::START_BLOCK),
+        )
+    };
+    let (separate_writes, blocks) = download_over_serial(false);
+    let (coalesced_writes, coalesced_blocks) = download_over_serial(true);
+    assert_eq!(coalesced_blocks, blocks);
+    assert_eq!(coalesced_writes, separate_writes - blocks);
+}
+
+#[test]
+fn zero_length_packets_are_opt_in() {
+    let download_over_usb = |zero_length_packets| {
+        let emulator = Emulator::new(2);
+        let mut device: DynDevice = Box::new(emulator.device().with_max_packet_size(512));
+        let config = DownloadConfig {
+            zero_length_packets,
+            ..Default::default()
+        };
+        let mut reader = std::io::Cursor::new(two_level_image().build());
+        let report =
+            axdl::download_image(&mut reader, &mut device, &config, &mut NoProgress).unwrap();
+        assert!(report.is_success());
+        assert_eq!(emulator.partition("rootfs"), Some(pattern(200_000, 4)));
+        emulator.zero_length_packets()
+    };
+    // The default block size is rounded down to whole packets, so every full block would end
+    // on a packet boundary.
+    assert_eq!(download_over_usb(false), 0);
+    assert!(download_over_usb(true) > 0);
+}
+
+#[test]
+fn handshake_request_and_versions() {
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        handshake_request: vec![0x3c, 0x3c, 0x3c, 0x02, 0x00],
+        ..Default::default()
+    };
+    let report = download(&emulator, &two_level_image(), &config).unwrap();
+
+    assert_eq!(
+        emulator.last_handshake_request(),
+        Some(vec![0x3c, 0x3c, 0x3c, 0x02, 0x00])
+    );
+    let stages = report
+        .handshakes
+        .iter()
+        .map(|handshake| handshake.stage.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(stages, ["romcode", "fdl1"]);
+    let romcode = &report.handshakes[0];
+    assert_eq!(romcode.version.as_deref(), Some("1.0"));
+    assert!(romcode.has_option("raw"));
+    assert!(romcode.version_at_least(&[1]));
+    assert!(!romcode.version_at_least(&[1, 1]));
+    assert!(report
+        .to_string()
+        .contains("Device: romcode v1.0;raw, fdl1 v1.0;raw\n"));
+
+    let info = HandshakeInfo::parse("bootrom");
+    assert_eq!(info.version, None);
+    assert!(!info.version_at_least(&[0]));
+}
+
+#[test]
+fn nack_on_block_fails() {
+    let emulator = Emulator::new(2).with_fault(Fault::new(
+        Trigger::Data(3),
+        FaultAction::Respond(response::VERIFY_ERROR),
+    ));
+    let error = download(&emulator, &two_level_image(), &DownloadConfig::default()).unwrap_err();
+
+    assert!(matches!(
+        error.root_cause(),
+        AxdlError::UnexpectedResponse(response::VERIFY_ERROR)
+    ));
+    assert!(error
+        .to_string()
+        .ends_with("(writing block 2 of FDL1 at offset 0x7d0 over mock)"));
+}
+
+#[test]
+fn errors_start_with_their_code() {
+    let emulator = Emulator::new(2).with_fault(Fault::new(
+        Trigger::Command(0x0001, 3),
+        FaultAction::Respond(response::DESTINATION_ERROR),
+    ));
+    let error = download(&emulator, &two_level_image(), &DownloadConfig::default()).unwrap_err();
+
+    assert_eq!(error.code(), "AXDL-DL-001");
+    assert!(error
+        .to_string()
+        .starts_with("[AXDL-DL-001] Download of SPL failed: [AXDL-PROTO-006] "));
+}
+
+#[test]
+fn failure_reports_partition_outcomes() {
+    // The third start command is the one for SPL, the first code image.
+    let emulator = Emulator::new(2).with_fault(Fault::new(
+        Trigger::Command(0x0001, 3),
+        FaultAction::Respond(response::DESTINATION_ERROR),
+    ));
+    let result = download(&emulator, &two_level_image(), &DownloadConfig::default());
+
+    match result {
+        Err(AxdlError::PartialFailure {
+            completed,
+            failed,
+            remaining,
+            source,
+        }) => {
+            assert!(completed.is_empty());
+            assert_eq!(failed, "SPL");
+            assert_eq!(remaining, vec!["ROOTFS".to_string()]);
+            assert!(matches!(
+                *source,
+                AxdlError::UnexpectedResponse(response::DESTINATION_ERROR)
+            ));
+        }
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+    assert!(emulator.partition("rootfs").is_none());
+}
+
+#[test]
+fn dropped_response_times_out() {
+    let emulator =
+        Emulator::new(2).with_fault(Fault::new(Trigger::Command(0x000b, 1), FaultAction::Drop));
+    let result = download(&emulator, &two_level_image(), &DownloadConfig::default());
+
+    assert!(matches!(result, Err(AxdlError::DeviceTimeout)));
+    assert!(emulator.partition_table().is_none());
+}
+
+/// Waits for the whole timeout when the device has nothing to send, like real hardware.
+struct SlowDevice(DynDevice);
+
+impl DeviceInfo for SlowDevice {
+    fn transport_kind(&self) -> TransportKind {
+        self.0.transport_kind()
+    }
+    fn display_name(&self) -> String {
+        self.0.display_name()
+    }
+    fn unique_id(&self) -> String {
+        self.0.unique_id()
+    }
+}
+
+impl Device for SlowDevice {
+    fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, AxdlError> {
+        let result = self.0.read_timeout(buf, timeout);
+        if matches!(result, Err(AxdlError::DeviceTimeout)) {
+            std::thread::sleep(timeout);
+        }
+        result
+    }
+    fn write_timeout(&mut self, buf: &[u8], timeout: Duration) -> Result<usize, AxdlError> {
+        self.0.write_timeout(buf, timeout)
+    }
+}
+
+#[test]
+fn cancellation_interrupts_device_wait() {
+    // The device never finishes the partition, and waiting for it would take a minute.
+    let emulator =
+        Emulator::new(2).with_fault(Fault::new(Trigger::Command(0x0003, 1), FaultAction::Drop));
+    let token = CancellationToken::new();
+    let config = DownloadConfig {
+        cancellation: Some(token.clone()),
+        ..Default::default()
+    };
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(300));
+        token.cancel();
+    });
+    let started = std::time::Instant::now();
+    let mut reader = std::io::Cursor::new(two_level_image().build());
+    let mut device: DynDevice = Box::new(SlowDevice(emulator.dyn_device()));
+    let result = axdl::download_image(&mut reader, &mut device, &config, &mut NoProgress);
+    canceller.join().unwrap();
+
+    assert!(matches!(
+        result.unwrap_err().root_cause(),
+        AxdlError::UserCancelled
+    ));
+    assert!(started.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn retries_recover_from_dropped_responses() {
+    let emulator = Emulator::new(2)
+        .with_fault(Fault::new(Trigger::Handshake(1), FaultAction::Drop))
+        .with_fault(Fault::new(Trigger::Data(3), FaultAction::Drop));
+    let config = DownloadConfig {
+        retry: RetryPolicy {
+            handshake: 1,
+            block: 1,
+        },
+        ..Default::default()
+    };
+    download(&emulator, &two_level_image(), &config).unwrap();
+
+    assert_eq!(emulator.ram(0x5c00_0000), Some(pattern(70000, 2)));
+    assert_eq!(emulator.partition("rootfs"), Some(pattern(200_000, 4)));
+}
+
+#[test]
+fn per_partition_options() {
+    use axdl::frame::commands;
+
+    let config = DownloadConfig {
+        partition_options: vec![
+            (
+                "spl".into(),
+                PartitionOptions {
+                    chunk_size: Some(256),
+                    verify: Some(true),
+                    ..Default::default()
+                },
+            ),
+            (
+                "ROOTFS".into(),
+                PartitionOptions {
+                    block_retries: Some(1),
+                    ..Default::default()
+                },
+            ),
+        ],
+        ..Default::default()
+    };
+    // Find the first ROOTFS block by writing everything before it.
+    let probe = Emulator::new(2);
+    let spl_only = DownloadConfig {
+        include_partitions: vec!["spl".into()],
+        ..config.clone()
+    };
+    download(&probe, &two_level_image(), &spl_only).unwrap();
+    let first_rootfs_block = probe.command_count(commands::START_BLOCK) + 1;
+
+    let emulator = Emulator::new(2).with_fault(Fault::new(
+        Trigger::Data(first_rootfs_block),
+        FaultAction::Drop,
+    ));
+    let report = download(&emulator, &two_level_image(), &config).unwrap();
+
+    assert_eq!(report.partitions[0].verify, VerifyResult::Passed);
+    assert_eq!(report.partitions[1].verify, VerifyResult::Skipped);
+    assert_eq!(emulator.command_count(commands::READ_BLOCK), 4);
+    assert_eq!(emulator.partition("rootfs"), Some(pattern(200_000, 4)));
+
+    // Options of partitions which do not exist are rejected before anything is sent.
+    let config = DownloadConfig {
+        partition_options: vec![("boot".into(), PartitionOptions::default())],
+        ..Default::default()
+    };
+    let emulator = Emulator::new(2);
+    let result = download(&emulator, &two_level_image(), &config);
+    assert!(matches!(result, Err(AxdlError::InvalidConfig(_))));
+    assert_eq!(emulator.command_count(commands::START_RAM_DOWNLOAD), 0);
+}
+
+#[test]
+fn corrupted_response_is_rejected() {
+    let emulator = Emulator::new(2).with_fault(Fault::new(
+        Trigger::Command(0x0003, 1),
+        FaultAction::CorruptChecksum,
+    ));
+    let result = download(&emulator, &two_level_image(), &DownloadConfig::default());
+
+    assert!(matches!(result, Err(AxdlError::InvalidFrame)));
+}
+
+#[test]
+fn unexpected_handshake() {
+    let emulator = Emulator::new(2).with_fault(Fault::new(
+        Trigger::Handshake(2),
+        FaultAction::Version("romcode v1.0;raw".into()),
+    ));
+    let result = download(&emulator, &two_level_image(), &DownloadConfig::default());
+
+    assert!(matches!(result, Err(AxdlError::UnexpectedHandshake(_))));
+}
+
+#[test]
+fn strict_mode_records_deviations() {
+    let ack_with_payload = axdl::frame::AxdlFrame::new(response::ACK)
+        .with_payload([0x01, 0x02])
+        .build()
+        .unwrap();
+    let emulator = Emulator::new(2).with_fault(Fault::new(
+        Trigger::Command(0x0000, 1),
+        FaultAction::Raw(ack_with_payload),
+    ));
+    let mut device = emulator.dyn_device();
+    let mut session = Session::new(&mut device).with_strict(true);
+    session.wait_handshake("romcode").unwrap();
+    session.start_ram_download().unwrap();
+
+    let deviations = session.deviations();
+    assert_eq!(deviations.len(), 1);
+    assert_eq!(deviations[0].request, Request::Command(0x0000));
+}
+
+#[test]
+fn vendor_command() {
+    let rtc = VendorCommand::new("rtc", 0x0030);
+    let ack_with_payload = axdl::frame::AxdlFrame::new(response::ACK)
+        .with_payload([0x01, 0x02])
+        .build()
+        .unwrap();
+    let emulator = Emulator::new(2).with_fault(Fault::new(
+        Trigger::Command(rtc.command, 1),
+        FaultAction::Raw(ack_with_payload),
+    ));
+    let mut device = emulator.dyn_device();
+    let mut session = Session::new(&mut device);
+    let handshake = session.wait_handshake("romcode").unwrap();
+    assert!(matches!(
+        session.vendor_command(&handshake, &rtc, &[]),
+        Err(AxdlError::Unsupported(_))
+    ));
+    let handshake = HandshakeInfo::parse("fdl2 v1.0;raw;rtc");
+    assert!(matches!(
+        session.vendor_command(&handshake, &rtc, &[]),
+        Err(AxdlError::InvalidState(_))
+    ));
+    assert_eq!(emulator.command_count(rtc.command), 0);
+
+    let mut session = session.with_state(SessionState::Fdl);
+    let answer = session
+        .vendor_command(&handshake, &rtc, &1_700_000_000u64.to_le_bytes())
+        .unwrap();
+    assert_eq!(answer, [0x01, 0x02]);
+    // The emulated FDL2 does not know the command.
+    assert!(matches!(
+        session.vendor_command(&handshake, &rtc, &[]),
+        Err(AxdlError::UnexpectedResponse(response::UNKNOWN_COMMAND))
+    ));
+    assert_eq!(emulator.command_count(rtc.command), 2);
+}
+
+#[test]
+fn duplicate_acks_are_dropped() {
+    let data = pattern(3000, 5);
+    let write = |window: Option<Duration>| {
+        let emulator =
+            Emulator::new(2).with_fault(Fault::new(Trigger::Data(1), FaultAction::Repeat));
+        let mut device = emulator.dyn_device();
+        let mut session = Session::new(&mut device)
+            .with_strict(true)
+            .with_duplicate_ack_window(window);
+        session.wait_handshake("romcode").unwrap();
+        session.start_ram_download().unwrap();
+        session
+            .start_partition_absolute_32(0x3000, data.len() as u32)
+            .unwrap();
+        session
+            .write_image(
+                &mut data.as_slice(),
+                1000,
+                "FDL1",
+                data.len(),
+                None,
+                &mut NoProgress,
+            )
+            .unwrap();
+        session.end_partition(Duration::from_secs(1)).unwrap();
+        let out_of_step = session.receive_response(Duration::from_millis(10)).is_ok();
+        let deviations = session.deviations().to_vec();
+        drop(session);
+        assert_eq!(emulator.ram(0x3000).as_ref(), Some(&data));
+        (deviations, out_of_step)
+    };
+
+    // The copy is taken for the next response, and every later one is read one step late.
+    let (deviations, out_of_step) = write(None);
+    assert!(deviations.is_empty());
+    assert!(out_of_step);
+
+    let (deviations, out_of_step) = write(Some(Duration::from_millis(20)));
+    assert_eq!(deviations.len(), 1);
+    assert_eq!(deviations[0].request, Request::Data);
+    assert!(!out_of_step);
+
+    let config = DownloadConfig {
+        duplicate_ack_window: Some(Duration::from_millis(20)),
+        ..Default::default()
+    };
+    let emulator = Emulator::new(2).with_fault(Fault::new(Trigger::Data(3), FaultAction::Repeat));
+    download(&emulator, &two_level_image(), &config).unwrap();
+    assert_eq!(emulator.partition("rootfs"), Some(pattern(200_000, 4)));
+}
+
+#[test]
+fn duplicate_acks_split_across_transfers() {
+    let data = pattern(3000, 5);
+    let emulator = Emulator::new(2).with_fault(Fault::new(Trigger::Data(1), FaultAction::Repeat));
+    // Every frame arrives in transfers of 6 bytes, so the copy is read in parts too.
+    let mut device: DynDevice = Box::new(emulator.device().with_transfer_size(6));
+    let mut session = Session::new(&mut device)
+        .with_strict(true)
+        .with_duplicate_ack_window(Some(Duration::from_millis(20)));
+    session.wait_handshake("romcode").unwrap();
+    session.start_ram_download().unwrap();
+    session
+        .start_partition_absolute_32(0x3000, data.len() as u32)
+        .unwrap();
+    session
+        .write_image(
+            &mut data.as_slice(),
+            1000,
+            "FDL1",
+            data.len(),
+            None,
+            &mut NoProgress,
+        )
+        .unwrap();
+    session.end_partition(Duration::from_secs(1)).unwrap();
+    assert_eq!(session.deviations().len(), 1);
+    assert!(session.receive_response(Duration::from_millis(10)).is_err());
+    drop(session);
+    assert_eq!(emulator.ram(0x3000), Some(data));
+}
+
+#[test]
+fn lenient_mode_ignores_deviations() {
+    let emulator = Emulator::new(2).with_fault(Fault::new(
+        Trigger::Handshake(1),
+        FaultAction::Raw(
+            axdl::frame::AxdlFrame::new(response::ACK)
+                .with_payload("romcode v1.0;raw")
+                .build()
+                .unwrap(),
+        ),
+    ));
+    let mut device = emulator.dyn_device();
+    let mut session = Session::new(&mut device);
+    session.wait_handshake("romcode").unwrap();
+
+    assert!(session.deviations().is_empty());
+}
+
+#[test]
+fn configurable_handshake_patterns() {
+    let bootrom = || {
+        Emulator::new(2).with_fault(Fault::new(
+            Trigger::Handshake(1),
+            FaultAction::Version("bootrom v2.0;raw".into()),
+        ))
+    };
+    assert!(matches!(
+        download(&bootrom(), &two_level_image(), &DownloadConfig::default()),
+        Err(AxdlError::UnexpectedHandshake(handshake)) if handshake == "bootrom v2.0;raw"
+    ));
+
+    let mut config = DownloadConfig::default();
+    config.handshakes.romcode.push("bootrom".into());
+    let emulator = bootrom();
+    download(&emulator, &two_level_image(), &config).unwrap();
+    assert_eq!(emulator.stage(), Stage::Fdl2);
+}
+
+#[test]
+fn recorded_session_replays() {
+    let image = two_level_image().build();
+    let config = DownloadConfig::default();
+    let emulator = Emulator::new(2);
+    let recorder = RecordingDevice::new(emulator.dyn_device());
+    let recording = recorder.recording();
+    let mut device: DynDevice = Box::new(recorder);
+    let mut reader = std::io::Cursor::new(image.clone());
+    axdl::download_image(&mut reader, &mut device, &config, &mut NoProgress).unwrap();
+
+    let mut session = Vec::new();
+    recording.lock().unwrap().write_to(&mut session).unwrap();
+    let recording = Recording::read_from(session.as_slice()).unwrap();
+
+    let replay = |config: &DownloadConfig| {
+        let replay = ReplayDevice::new(recording.clone());
+        let mut device: DynDevice = Box::new(replay.clone());
+        let mut reader = std::io::Cursor::new(image.clone());
+        let result = axdl::download_image(&mut reader, &mut device, config, &mut NoProgress);
+        (result, replay)
+    };
+    let (result, replay_device) = replay(&config);
+    result.unwrap();
+    assert!(replay_device.mismatches().is_empty());
+    assert!(replay_device.is_complete());
+
+    let (_, replay_device) = replay(&DownloadConfig {
+        image_chunk_size: 0x4000,
+        ..Default::default()
+    });
+    assert!(!replay_device.mismatches().is_empty());
+}
+
+#[derive(Default)]
+struct RecordedProgress(Vec<Option<f32>>);
+
+impl axdl::DownloadProgress for RecordedProgress {
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+    fn report_progress(&mut self, _description: &str, progress: Option<f32>) {
+        self.0.push(progress);
+    }
+}
+
+#[test]
+fn download_split_image() {
+    let rootfs = pattern(250_000, 6);
+    let image = AxpBuilder::new(2)
+        .partition("spl", 0x40000)
+        .partition("rootfs", 0x400000)
+        .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+        .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+        .split_code("ROOTFS", "rootfs", rootfs.clone(), 100_000);
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        verify: true,
+        ..Default::default()
+    };
+    let report = download(&emulator, &image, &config).unwrap();
+    assert!(report.is_success());
+    assert_eq!(report.partitions[0].bytes_written, rootfs.len() as u64);
+    assert_eq!(emulator.partition("rootfs"), Some(rootfs));
+}
+
+#[test]
+fn progress_events_have_phases() {
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        verify: true,
+        ..Default::default()
+    };
+    let mut reader = std::io::Cursor::new(two_level_image().build());
+    let mut device = emulator.dyn_device();
+    let mut events = Vec::new();
+    let mut progress = FnProgress(|event: axdl::progress::ProgressEvent<'_>| {
+        assert_eq!(event.description, event.phase.describe(event.target));
+        events.push((
+            event.phase,
+            event.target.map(str::to_string),
+            event.timestamp,
+        ));
+    });
+    axdl::download_image(&mut reader, &mut device, &config, &mut progress).unwrap();
+
+    let phases = events
+        .iter()
+        .map(|(phase, _, _)| *phase)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        phases[..4],
+        [
+            Phase::LoadImage,
+            Phase::Start,
+            Phase::Handshake,
+            Phase::FlashDownloader
+        ]
+    );
+    assert!(events.windows(2).all(|pair| pair[0].2 <= pair[1].2));
+    let position = |phase: Phase, target: &str| {
+        events
+            .iter()
+            .position(|(p, t, _)| *p == phase && t.as_deref() == Some(target))
+            .unwrap()
+    };
+    assert!(position(Phase::Write, "ROOTFS") < position(Phase::Flush, "ROOTFS"));
+    assert!(position(Phase::Flush, "ROOTFS") < position(Phase::Verify, "rootfs"));
+}
+
+#[test]
+fn download_filtered_image() {
+    let rootfs = pattern(250_000, 6);
+    let image = AxpBuilder::new(2)
+        .partition("spl", 0x40000)
+        .partition("rootfs", 0x400000)
+        .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+        .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+        .split_code("ROOTFS", "rootfs", rootfs.clone(), 100_000);
+    let emulator = Emulator::new(2);
+    // The patch crosses both a block and a file boundary.
+    let patch = filter::Patch {
+        offset: 99_990,
+        data: vec![0xa5; 20],
+    };
+    let config = DownloadConfig {
+        verify: true,
+        filters: vec![
+            ("rootfs".into(), Arc::new(patch)),
+            (
+                "ROOTFS".into(),
+                Arc::new(filter::Truncate { size: 200_100 }),
+            ),
+        ],
+        ..Default::default()
+    };
+    let report = download(&emulator, &image, &config).unwrap();
+    assert!(report.is_success());
+    assert_eq!(report.partitions[0].bytes_written, 200_100);
+
+    let mut expected = rootfs[..200_100].to_vec();
+    expected[99_990..100_010].fill(0xa5);
+    assert_eq!(emulator.partition("rootfs"), Some(expected));
+}
+
+#[test]
+fn download_from_custom_source() {
+    let emulator = Emulator::new(2);
+    // Generated per device, so it is not in the archive.
+    let spl = pattern(3000, 9);
+    let data = spl.clone();
+    let source = PartitionSource::from_read(spl.len() as u64, move || {
+        Ok(std::io::Cursor::new(data.clone()))
+    });
+    let config = DownloadConfig {
+        verify: true,
+        exclude_rootfs: true,
+        sources: vec![("spl".into(), Arc::new(source))],
+        ..Default::default()
+    };
+    let report = download(&emulator, &two_level_image(), &config).unwrap();
+    assert!(report.is_success());
+    assert_eq!(report.partitions[0].bytes_written, 3000);
+    assert_eq!(report.partitions[0].verify, VerifyResult::Passed);
+    assert_eq!(emulator.partition("spl"), Some(spl));
+
+    emulator.reset();
+    let config = DownloadConfig {
+        skip_same: true,
+        ..config
+    };
+    let report = download(&emulator, &two_level_image(), &config).unwrap();
+    assert!(report.partitions[0].skipped);
+}
+
+#[test]
+fn verify_locates_mismatch_in_split_image() {
+    let rootfs = pattern(30_000, 8);
+    let image = AxpBuilder::new(2)
+        .partition("spl", 0x40000)
+        .partition("rootfs", 0x400000)
+        .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+        .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+        .split_code("ROOTFS", "rootfs", rootfs.clone(), 10_000);
+    let mut written = rootfs;
+    written[9_990..10_010].fill(0x5a);
+    // The third block crosses the file boundary and reads back with a changed byte.
+    let mut block = written[8192..12_288].to_vec();
+    block[10_005 - 8192] ^= 0xff;
+    let emulator = Emulator::new(2).with_fault(Fault::new(
+        Trigger::Command(0x0011, 3),
+        FaultAction::Raw(
+            axdl::frame::AxdlFrame::new(response::READ_FLASH)
+                .with_payload(block)
+                .build()
+                .unwrap(),
+        ),
+    ));
+    let patch = filter::Patch {
+        offset: 9_990,
+        data: vec![0x5a; 20],
+    };
+    let config = DownloadConfig {
+        verify: true,
+        image_chunk_size: 4096,
+        filters: vec![("rootfs".into(), Arc::new(patch))],
+        ..Default::default()
+    };
+    let report = download(&emulator, &image, &config).unwrap();
+
+    assert_eq!(
+        report.partitions[0].verify,
+        VerifyResult::Failed { offset: 10_005 }
+    );
+    assert_eq!(emulator.partition("rootfs"), Some(written));
+}
+
+#[test]
+fn download_warns_about_unexpected_content() {
+    let mut squashfs = pattern(5000, 7);
+    squashfs[..4].copy_from_slice(b"hsqs");
+    let image = AxpBuilder::new(2)
+        .partition("kernel", 0x40000)
+        .partition("rootfs", 0x400000)
+        .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+        .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+        .code("KERNEL", "kernel", squashfs.clone())
+        .code("ROOTFS", "rootfs", squashfs);
+    let emulator = Emulator::new(2);
+    let report = download(&emulator, &image, &DownloadConfig::default()).unwrap();
+    assert_eq!(
+        report.warnings,
+        ["image for partition kernel looks like squashfs, expected FIT or uImage or arm64 kernel"]
+    );
+    assert!(report
+        .to_string()
+        .contains("Warning: image for partition kernel looks like squashfs"));
+    assert_eq!(report.partitions.len(), 2);
+}
+
+#[test]
+fn download_checks_flash_capacity() {
+    let image = AxpBuilder::new(2)
+        .partition("spl", 0x40000)
+        .partition("rootfs", 0x400000)
+        .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+        .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+        .code("SPL", "spl", pattern(1000, 3));
+    // The partitions take 0x440000 KiB.
+    let required = 0x440000 * 1024;
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        flash_capacity: Some(required - 1),
+        ..Default::default()
+    };
+    let result = download(&emulator, &image, &config);
+    assert!(matches!(
+        result,
+        Err(AxdlError::FlashTooSmall { required: r, capacity: c }) if r == required && c == required - 1
+    ));
+    assert_eq!(emulator.partition_table(), None);
+
+    let config = DownloadConfig {
+        flash_capacity: Some(required),
+        ..Default::default()
+    };
+    download(&emulator, &image, &config).unwrap();
+}
+
+#[test]
+fn partition_table_larger_than_a_frame() {
+    let image = two_level_image();
+    let table = image.project().partition_table().to_bytes().unwrap();
+    let length = axdl::frame::MINIMUM_LENGTH + table.len();
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        max_frame_size: length - 1,
+        ..Default::default()
+    };
+    let result = download(&emulator, &image, &config);
+    assert!(matches!(
+        result,
+        Err(AxdlError::FrameTooLarge { length: l, max }) if l == length && max == length - 1
+    ));
+    assert_eq!(emulator.ram(0x3000), None);
+
+    let config = DownloadConfig {
+        max_frame_size: length,
+        ..Default::default()
+    };
+    download(&emulator, &image, &config).unwrap();
+}
+
+#[test]
+fn block_writer_retries_and_reports() {
+    let emulator = Emulator::new(2).with_fault(Fault::new(Trigger::Data(2), FaultAction::Drop));
+    let mut device = emulator.dyn_device();
+    let mut session = Session::new(&mut device);
+    session.wait_handshake("romcode").unwrap();
+    session.start_ram_download().unwrap();
+    let data = pattern(10000, 5);
+    session
+        .start_partition_absolute_32(0x3000, data.len() as u32)
+        .unwrap();
+
+    let mut progress = RecordedProgress::default();
+    let mut writer = BlockWriter::new(&mut session, 1000, &mut progress)
+        .with_retry(RetryPolicy {
+            block: 1,
+            ..Default::default()
+        })
+        .with_progress_report("FDL1", data.len(), 5);
+    writer.write_all(&mut data.as_slice()).unwrap();
+    assert_eq!(writer.bytes_transferred(), data.len());
+    session.end_partition(Duration::from_secs(1)).unwrap();
+
+    // The last entry is the flushing phase, reported once every block was acknowledged.
+    assert_eq!(progress.0, [Some(0.5), Some(1.0), None]);
+    assert_eq!(emulator.ram(0x3000), Some(data));
+}
+
+#[test]
+fn block_reader_retries_and_reports() {
+    let emulator = Emulator::new(2).with_fault(Fault::new(
+        Trigger::Command(axdl::frame::commands::READ_BLOCK, 2),
+        FaultAction::Drop,
+    ));
+    download(&emulator, &two_level_image(), &DownloadConfig::default()).unwrap();
+    let mut device = emulator.dyn_device();
+    let mut session = Session::new(&mut device)
+        .with_state(SessionState::Fdl)
+        .with_retry(RetryPolicy {
+            block: 1,
+            ..Default::default()
+        });
+
+    let mut progress = RecordedProgress::default();
+    let mut data = Vec::new();
+    let mut reader = session
+        .block_reader("rootfs", 200_000, 48000, &mut progress)
+        .with_progress_report(2);
+    reader.read_all(&mut data).unwrap();
+    assert_eq!(reader.bytes_read(), 200_000);
+    assert_eq!(data, pattern(200_000, 4));
+    // Five blocks, reported after every second one and at the end.
+    assert_eq!(progress.0, [Some(0.48), Some(0.96), Some(1.0)]);
+    assert_eq!(session.state(), SessionState::Fdl);
+
+    // A dump stops at the next block once it is cancelled.
+    let mut progress = CancelAfterReport(false);
+    let mut data = Vec::new();
+    let result = session
+        .block_reader("rootfs", 200_000, 48000, &mut progress)
+        .with_progress_report(2)
+        .read_all(&mut data);
+    assert!(matches!(result, Err(AxdlError::UserCancelled)));
+    assert_eq!(data.len(), 96000);
+}
+
+#[test]
+fn write_partition_bytes() {
+    let emulator = Emulator::new(2);
+    download(&emulator, &two_level_image(), &DownloadConfig::default()).unwrap();
+    let data = pattern(3000, 7);
+    let mut device = emulator.dyn_device();
+    let options = PartitionOptions {
+        chunk_size: Some(1000),
+        verify: Some(true),
+        ..Default::default()
+    };
+    let result =
+        communication::write_partition_bytes(&mut device, "spl", &data, &options, &mut NoProgress)
+            .unwrap();
+    assert_eq!(result, VerifyResult::Passed);
+    assert_eq!(emulator.partition("spl"), Some(data));
+
+    let result = communication::write_partition_bytes(
+        &mut device,
+        "spl",
+        &[0; 16],
+        &PartitionOptions::default(),
+        &mut NoProgress,
+    )
+    .unwrap();
+    assert_eq!(result, VerifyResult::Skipped);
+}
+
+#[test]
+fn read_partition_to_file() {
+    let emulator = Emulator::new(2);
+    download(&emulator, &two_level_image(), &DownloadConfig::default()).unwrap();
+    let mut device = emulator.dyn_device();
+    let mut backup = Vec::new();
+    communication::read_partition_id(&mut device, "rootfs", 200_000, 48000, &mut backup).unwrap();
+    assert_eq!(backup, pattern(200_000, 4));
+}
+
+/// Cancels once any progress was reported.
+struct CancelAfterReport(bool);
+
+impl axdl::DownloadProgress for CancelAfterReport {
+    fn is_cancelled(&self) -> bool {
+        self.0
+    }
+    fn report_progress(&mut self, _description: &str, _progress: Option<f32>) {
+        self.0 = true;
+    }
+}
+
+#[test]
+fn rate_limit_slows_down_the_download() {
+    let image = two_level_image();
+    let mut config = DownloadConfig::default();
+    config.rate_limits.insert(TransportKind::Mock, 2_000_000);
+    let started = std::time::Instant::now();
+    download(&Emulator::new(2), &image, &config).unwrap();
+    // 283345 bytes of images at 2 MB/s.
+    assert!(started.elapsed() >= Duration::from_millis(140));
+
+    config.rate_limits.insert(TransportKind::Mock, 0);
+    assert!(matches!(
+        download(&Emulator::new(2), &image, &config),
+        Err(AxdlError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn max_duration_stops_the_session() {
+    let mut config = DownloadConfig {
+        max_duration: Some(Duration::from_millis(150)),
+        ..Default::default()
+    };
+    // ROOTFS alone takes 200 ms at 1 MB/s.
+    config.rate_limits.insert(TransportKind::Mock, 1_000_000);
+    let result = download(&Emulator::new(2), &two_level_image(), &config);
+    match result {
+        Err(AxdlError::PartialFailure {
+            completed,
+            failed,
+            source,
+            ..
+        }) => {
+            assert_eq!(completed, ["SPL"]);
+            assert_eq!(failed, "ROOTFS");
+            assert!(matches!(
+                *source,
+                AxdlError::DeadlineExceeded(limit) if limit == Duration::from_millis(150)
+            ));
+            assert_eq!(source.code(), "AXDL-DL-002");
+        }
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn pacing_pauses_between_commands() {
+    let emulator = Emulator::new(2);
+    let config = DownloadConfig {
+        pacing: Pacing {
+            command_delay: Duration::from_millis(5),
+            fdl_start_delay: Duration::from_millis(100),
+        },
+        ..Default::default()
+    };
+    let started = std::time::Instant::now();
+    download(&emulator, &two_level_image(), &config).unwrap();
+    // Two flash downloaders and more than 20 commands.
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    assert_eq!(emulator.partition("spl"), Some(pattern(1000, 3)));
+}
+
+#[test]
+fn session_rejects_commands_out_of_order() {
+    let emulator = Emulator::new(2);
+    let mut device = emulator.dyn_device();
+    let mut session = Session::new(&mut device);
+    assert!(matches!(
+        session.start_ram_download(),
+        Err(AxdlError::InvalidState(_))
+    ));
+    session.wait_handshake("romcode").unwrap();
+    assert_eq!(session.state(), SessionState::Romcode);
+    assert!(matches!(
+        session.start_block(100),
+        Err(AxdlError::InvalidState(_))
+    ));
+    assert!(matches!(
+        session.set_partition_table(&PartitionTable::new(0, 0)),
+        Err(AxdlError::InvalidState(_))
+    ));
+    session.start_ram_download().unwrap();
+    assert_eq!(session.state(), SessionState::RamDownload);
+}
+
+#[test]
+fn download_with_prefetch() {
+    let image = two_level_image()
+        .partition("kernel", 0x10000)
+        .code("KERNEL", "kernel", pattern(30_000, 5))
+        .compression(zip::CompressionMethod::Deflated);
+    let emulator = Emulator::new(2);
+    // ROOTFS is too large to be read ahead, KERNEL is read while ROOTFS is flushed.
+    let config = DownloadConfig {
+        verify: true,
+        prefetch_limit: 100_000,
+        ..Default::default()
+    };
+    let report = download(&emulator, &image, &config).unwrap();
+
+    assert!(report.is_success());
+    assert_eq!(emulator.partition("spl"), Some(pattern(1000, 3)));
+    assert_eq!(emulator.partition("rootfs"), Some(pattern(200_000, 4)));
+    assert_eq!(emulator.partition("kernel"), Some(pattern(30_000, 5)));
+}
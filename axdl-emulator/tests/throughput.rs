@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Throughput and allocation budgets of a large download, so that changes to the transfer path
+//! do not slow it down unnoticed.
+//!
+//! The test is ignored by default as it needs a release build and a few GiB of memory. Run it
+//! with `cargo test --release --test throughput -- --ignored`. `AXDL_PERF_IMAGE_MIB` changes the
+//! image size and `AXDL_PERF_MIN_MIB_PER_S` the required throughput.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use axdl::partition::ImageType;
+use axdl::progress::NoProgress;
+use axdl::DownloadConfig;
+use axdl_emulator::axp::{pattern, AxpBuilder};
+use axdl_emulator::Emulator;
+
+/// Counts the bytes allocated by the test process.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size as u64, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const DEFAULT_IMAGE_MIB: usize = 512;
+/// Well below the throughput of a CI runner, so that only real regressions fail.
+const DEFAULT_MIN_MIB_PER_S: f64 = 500.0;
+/// Bytes allocated per byte flashed, by the host and the emulator together, which is about 3
+/// as the emulator keeps a copy of every block and of the whole partition.
+const MAX_ALLOCATED_PER_BYTE: f64 = 4.0;
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+#[test]
+#[ignore = "needs a release build and a few GiB of memory"]
+fn large_download_within_budget() {
+    let image_mib = env_or("AXDL_PERF_IMAGE_MIB", DEFAULT_IMAGE_MIB);
+    let min_mib_per_s = env_or("AXDL_PERF_MIN_MIB_PER_S", DEFAULT_MIN_MIB_PER_S);
+    let size = image_mib * 1024 * 1024;
+    let image = AxpBuilder::new(2)
+        .partition("rootfs", size as u64)
+        .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(12345, 1))
+        .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(70000, 2))
+        .code("ROOTFS", "rootfs", pattern(size, 4))
+        .build();
+    let emulator = Emulator::new(2);
+    let mut device = emulator.dyn_device();
+    let mut reader = std::io::Cursor::new(image);
+
+    let allocated = ALLOCATED.load(Ordering::Relaxed);
+    let started = Instant::now();
+    axdl::download_image(
+        &mut reader,
+        &mut device,
+        &DownloadConfig::default(),
+        &mut NoProgress,
+    )
+    .unwrap();
+    let seconds = started.elapsed().as_secs_f64();
+    let allocated_per_byte = (ALLOCATED.load(Ordering::Relaxed) - allocated) as f64 / size as f64;
+
+    let mib_per_s = image_mib as f64 / seconds;
+    println!(
+        "{} MiB in {:.2} s: {:.1} MiB/s, {:.2} bytes allocated per byte",
+        image_mib, seconds, mib_per_s, allocated_per_byte
+    );
+    assert_eq!(
+        emulator.partition("rootfs").map(|data| data.len()),
+        Some(size)
+    );
+    assert!(
+        mib_per_s >= min_mib_per_s,
+        "throughput {:.1} MiB/s is below {} MiB/s",
+        mib_per_s,
+        min_mib_per_s
+    );
+    assert!(
+        allocated_per_byte <= MAX_ALLOCATED_PER_BYTE,
+        "{:.2} bytes allocated per byte flashed, more than {}",
+        allocated_per_byte,
+        MAX_ALLOCATED_PER_BYTE
+    );
+}
@@ -1,3 +1,4 @@
 fn main() {
+    #[cfg(feature = "slint")]
     slint_build::compile("ui/app-window.slint").expect("Slint build failed");
 }
@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JavaScript API to drive the running GUI from browser automation such as Playwright.
+//!
+//! A typical test selects a device with `axdlSelectDevice`, loads an image with `axdlLoadFile`,
+//! calls `axdlStart` and polls `axdlStatus` until `downloading` becomes false.
+
+use std::{cell::RefCell, rc::Rc};
+
+use axdl::transport::{webusb::WebUsbTransport, AsyncTransport, DeviceInfo};
+use axdl_core_ui::Command;
+use js_sys::wasm_bindgen::{self, JsValue};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{
+    options, recent,
+    slint_ui::{self, AppWindow},
+    AxdlDevice,
+};
+
+/// State of the GUI shared with the exported functions.
+#[derive(Clone)]
+pub(crate) struct Handles {
+    pub ui: slint::Weak<AppWindow>,
+    pub axdl_device: Rc<RefCell<Option<AxdlDevice>>>,
+    pub image_file: Rc<RefCell<Option<web_sys::File>>>,
+    pub recent_images: Rc<RefCell<recent::RecentImages>>,
+    pub image_options: Rc<RefCell<options::ImageOptionsStore>>,
+    /// Result of the last download, `None` while downloading or before the first one.
+    pub last_result: Rc<RefCell<Option<String>>>,
+    pub flasher: Rc<RefCell<axdl_core_ui::Flasher>>,
+}
+
+thread_local! {
+    static HANDLES: RefCell<Option<Handles>> = const { RefCell::new(None) };
+    #[cfg(feature = "emulator")]
+    static EMULATOR: RefCell<Option<axdl_emulator::Emulator>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn register(handles: Handles) {
+    HANDLES.with(|h| *h.borrow_mut() = Some(handles));
+}
+
+fn handles() -> Result<(Handles, AppWindow), JsValue> {
+    let handles = HANDLES
+        .with(|h| h.borrow().clone())
+        .ok_or_else(|| JsValue::from_str("axdl-gui is not running"))?;
+    let ui = handles
+        .ui
+        .upgrade()
+        .ok_or_else(|| JsValue::from_str("axdl-gui window is closed"))?;
+    Ok((handles, ui))
+}
+
+fn to_js_error(e: impl std::fmt::Display) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+/// Lists the Axera USB devices the page is already authorized to access.
+#[wasm_bindgen(js_name = axdlListDevices)]
+pub async fn list_devices() -> Result<js_sys::Array, JsValue> {
+    let devices = WebUsbTransport::list_devices().await.map_err(to_js_error)?;
+    Ok(devices
+        .iter()
+        .map(|device| JsValue::from_str(&format!("{:?}", device)))
+        .collect())
+}
+
+/// Opens the device at `index` in the list returned by `axdlListDevices`.
+#[wasm_bindgen(js_name = axdlSelectDevice)]
+pub async fn select_device(index: usize) -> Result<(), JsValue> {
+    let (handles, ui) = handles()?;
+    let device = WebUsbTransport::list_devices()
+        .await
+        .map_err(to_js_error)?
+        .into_iter()
+        .nth(index)
+        .ok_or_else(|| JsValue::from_str(&format!("no device at index {}", index)))?;
+    let device = WebUsbTransport::open_device(&device)
+        .await
+        .map_err(to_js_error)?;
+    let name = device.display_name();
+    handles.axdl_device.replace(Some(AxdlDevice::Usb(device)));
+    slint_ui::dispatch(&ui, &handles.flasher, Command::SelectDevice(name));
+    Ok(())
+}
+
+/// Uses an emulated device instead of real hardware.
+#[cfg(feature = "emulator")]
+#[wasm_bindgen(js_name = axdlUseEmulator)]
+pub fn use_emulator(fdl_level: u32) -> Result<(), JsValue> {
+    let (handles, ui) = handles()?;
+    let emulator = axdl_emulator::Emulator::new(fdl_level);
+    let device = emulator.device();
+    let name = device.display_name();
+    handles
+        .axdl_device
+        .replace(Some(AxdlDevice::Emulator(device)));
+    EMULATOR.with(|e| *e.borrow_mut() = Some(emulator));
+    slint_ui::dispatch(&ui, &handles.flasher, Command::SelectDevice(name));
+    Ok(())
+}
+
+/// Returns the names of the partitions written to the emulated device.
+#[cfg(feature = "emulator")]
+#[wasm_bindgen(js_name = axdlEmulatorPartitions)]
+pub fn emulator_partitions() -> Result<js_sys::Array, JsValue> {
+    EMULATOR.with(|e| {
+        let emulator = e.borrow();
+        let emulator = emulator
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("emulator is not in use"))?;
+        Ok(emulator
+            .partition_names()
+            .iter()
+            .map(|name| JsValue::from_str(name))
+            .collect())
+    })
+}
+
+/// Loads the image to download, e.g. a `File` created by the test.
+#[wasm_bindgen(js_name = axdlLoadFile)]
+pub async fn load_file(file: web_sys::File) -> Result<(), JsValue> {
+    let (handles, ui) = handles()?;
+    slint_ui::set_image(
+        &ui,
+        &handles.flasher,
+        &handles.image_file,
+        &handles.recent_images,
+        &handles.image_options,
+        Some(file),
+        None,
+    )
+    .await;
+    Ok(())
+}
+
+/// Starts the download with the settings currently shown in the GUI.
+#[wasm_bindgen(js_name = axdlStart)]
+pub fn start() -> Result<(), JsValue> {
+    let (handles, ui) = handles()?;
+    let flasher = handles.flasher.borrow();
+    if flasher.is_flashing() {
+        return Err(JsValue::from_str("download is already running"));
+    }
+    if !flasher.can_flash() {
+        return Err(JsValue::from_str("device or image file is not selected"));
+    }
+    drop(flasher);
+    ui.invoke_download();
+    Ok(())
+}
+
+/// Returns the current state of the GUI as a plain object.
+#[wasm_bindgen(js_name = axdlStatus)]
+pub fn status() -> Result<JsValue, JsValue> {
+    let (handles, ui) = handles()?;
+    let status = serde_json::json!({
+        "deviceOpened": ui.get_device_opened(),
+        "imageFile": ui.get_image_file().as_str(),
+        "downloading": ui.get_downloading(),
+        "description": ui.get_description().as_str(),
+        "progress": ui.get_progress(),
+        "lastResult": handles.last_result.borrow().clone(),
+    });
+    js_sys::JSON::parse(&status.to_string())
+}
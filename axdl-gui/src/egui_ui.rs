@@ -0,0 +1,542 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! egui frontend, for builds without Slint: `--no-default-features --features egui`.
+//!
+//! It offers the same download flow as the Slint frontend on top of [`view_model`]. The
+//! automation API is only available with Slint.
+
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use axdl::{
+    report::DownloadReport,
+    transport::{
+        webserial::WebSerialTransport, webusb::WebUsbTransport, AsyncTransport, DeviceInfo,
+    },
+    AxdlError,
+};
+use axdl_core_ui::{timing, Command, Flasher, Image, Progress, State, Timing};
+use eframe::egui;
+use js_sys::wasm_bindgen::JsCast;
+use tracing_subscriber::filter::LevelFilter;
+
+use crate::{copy_to_clipboard, hash_file, log, options, recent, view_model, AxdlDevice};
+
+/// State shown in the UI.
+#[derive(Default)]
+struct Model {
+    flasher: Flasher,
+    settings: view_model::Settings,
+    show_advanced: bool,
+    /// Message about the last action which is not part of the flasher state.
+    message: String,
+    report_text: String,
+    recent_rows: Vec<view_model::RecentRow>,
+    show_log: bool,
+    show_frame_traces: bool,
+}
+
+/// User actions, run once the frame is drawn.
+enum Action {
+    OpenUsbDevice,
+    OpenSerialDevice,
+    OpenImage,
+    OpenRecent(usize),
+    Download,
+    Cancel,
+    CopyReport,
+    CopyLogs,
+    ClearLogs,
+}
+
+/// State shared between the UI and the tasks it spawns.
+#[derive(Clone)]
+struct Shared {
+    ctx: egui::Context,
+    model: Rc<RefCell<Model>>,
+    axdl_device: Rc<RefCell<Option<AxdlDevice>>>,
+    image_file: Rc<RefCell<Option<web_sys::File>>>,
+    recent_images: Rc<RefCell<recent::RecentImages>>,
+    image_options: Rc<RefCell<options::ImageOptionsStore>>,
+}
+
+impl Shared {
+    /// Updates the model from a task and repaints the UI.
+    fn update(&self, f: impl FnOnce(&mut Model)) {
+        f(&mut self.model.borrow_mut());
+        self.ctx.request_repaint();
+    }
+
+    /// Applies `command` to the flasher. Returns false if it was rejected.
+    fn apply(&self, command: Command) -> bool {
+        let result = self.model.borrow_mut().flasher.apply(command);
+        self.ctx.request_repaint();
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                tracing::warn!("{}", e);
+                false
+            }
+        }
+    }
+
+    fn set_message(&self, message: String) {
+        self.update(|model| model.message = message);
+    }
+
+    fn show_recent_images(&self) {
+        let rows = view_model::recent_rows(&self.recent_images.borrow());
+        self.update(|model| model.recent_rows = rows);
+    }
+
+    async fn open_device(self, usb: bool) {
+        let result: Result<AxdlDevice, AxdlError> = async {
+            if usb {
+                let device = WebUsbTransport::request_device().await?;
+                Ok(AxdlDevice::Usb(
+                    WebUsbTransport::open_device(&device).await?,
+                ))
+            } else {
+                let port = WebSerialTransport::request_port().await?;
+                Ok(AxdlDevice::Serial(
+                    WebSerialTransport::open_device(&port).await?,
+                ))
+            }
+        }
+        .await;
+        match result {
+            Ok(device) => {
+                let name = device.display_name();
+                self.axdl_device.replace(Some(device));
+                self.apply(Command::SelectDevice(name));
+            }
+            Err(e) => {
+                tracing::error!("Failed to open device: {:?}", e);
+                self.apply(Command::ReleaseDevice);
+                self.set_message(view_model::open_error_message(&e));
+            }
+        }
+    }
+
+    /// Lets the user pick an image file and records it in the recent images, like the Slint
+    /// frontend does.
+    async fn pick_image(self, expected: Option<recent::RecentImage>) {
+        let mut dialog = rfd::AsyncFileDialog::new().add_filter("AXDL Image", &["*.axp"]);
+        if let Some(expected) = &expected {
+            dialog = dialog.set_title(format!("Select {}", expected.name));
+        }
+        let file = dialog.pick_file().await.map(|file| file.inner().clone());
+        // The image file stays borrowed while it is downloaded.
+        if self.model.borrow().flasher.is_flashing() {
+            tracing::warn!("The image cannot be changed while downloading");
+            return;
+        }
+        let Some(file) = file else {
+            *self.image_file.borrow_mut() = None;
+            self.apply(Command::UnloadImage);
+            return;
+        };
+        let name = file.name();
+        let size = file.size() as u64;
+        tracing::info!("Selected file: {}", name);
+        if let Some(expected) = &expected {
+            if expected.name != name || expected.size != size {
+                self.set_message(format!(
+                    "Selected file differs from the recent image {}",
+                    expected.name
+                ));
+            }
+        }
+        {
+            let mut recent_images = self.recent_images.borrow_mut();
+            recent_images.touch(recent::RecentImage::new(name.clone(), size, None));
+            recent_images.save();
+        }
+        self.show_recent_images();
+        *self.image_file.borrow_mut() = Some(file.clone());
+        self.apply(Command::LoadImage(Image {
+            name: name.clone(),
+            size,
+        }));
+
+        match hash_file(&file).await {
+            Ok(hash) => {
+                if let Some(options) = self.image_options.borrow().get(&hash) {
+                    tracing::info!("Restored the options last used with {}", name);
+                    self.update(|model| options.apply(&mut model.settings));
+                    self.set_message("Restored the options last used with this image".into());
+                }
+                {
+                    let mut recent_images = self.recent_images.borrow_mut();
+                    recent_images.set_hash(&name, size, hash);
+                    recent_images.save();
+                }
+                self.show_recent_images();
+            }
+            Err(e) => tracing::warn!("Failed to hash {}: {:?}", name, e),
+        }
+    }
+
+    async fn download(self) {
+        if self.axdl_device.borrow().is_none() || self.image_file.borrow().is_none() {
+            tracing::error!("Device or image file is not selected");
+            return;
+        }
+        let settings = self.model.borrow().settings.clone();
+        if let Some(file) = self.image_file.borrow().as_ref() {
+            if let Some(hash) = self
+                .recent_images
+                .borrow()
+                .hash_of(&file.name(), file.size() as u64)
+            {
+                let mut image_options = self.image_options.borrow_mut();
+                image_options.set(hash, options::ImageOptions::from(&settings));
+                image_options.save();
+            }
+        }
+        if !self.apply(Command::StartFlash) {
+            return;
+        }
+        self.set_message(String::new());
+        let timing = Timing::start(axdl::progress::timestamp());
+
+        let cancellation = self.model.borrow().flasher.cancellation();
+        let result: Result<DownloadReport, AxdlError> = async {
+            let mut config = settings.download_config()?;
+            config.cancellation = cancellation;
+            let mut progress = EguiProgress {
+                shared: self.clone(),
+                timing: timing.clone(),
+            };
+            let image_file = self.image_file.borrow();
+            crate::download_file(
+                image_file.as_ref().unwrap(),
+                self.axdl_device.borrow_mut().as_mut().unwrap(),
+                &config,
+                &mut progress,
+            )
+            .await
+        }
+        .await;
+
+        let result_text = view_model::result_text(&result);
+        if let Some(file) = self.image_file.borrow().as_ref() {
+            let mut recent_images = self.recent_images.borrow_mut();
+            recent_images.set_result(&file.name(), file.size() as u64, result_text);
+            recent_images.save();
+        }
+        self.show_recent_images();
+
+        match &result {
+            Err(e) => tracing::error!("Failed to download image file: {:?}", e),
+            Ok(report) => {
+                let report_text = report.to_string();
+                self.update(|model| model.report_text = report_text);
+            }
+        }
+        self.apply(Command::ReportProgress(
+            timing.finished(axdl::progress::timestamp()),
+        ));
+        self.apply(Command::Finish(result.map_err(|e| e.to_string())));
+    }
+}
+
+struct EguiProgress {
+    shared: Shared,
+    timing: Timing,
+}
+
+impl axdl::DownloadProgress for EguiProgress {
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+    fn report_progress(&mut self, description: &str, progress: Option<f32>) {
+        self.shared.apply(Command::ReportProgress(Progress {
+            description: description.to_string(),
+            fraction: progress,
+            elapsed: self.timing.elapsed(axdl::progress::timestamp()),
+            ..Default::default()
+        }));
+    }
+    fn report_event(&mut self, event: &axdl::progress::ProgressEvent<'_>) {
+        let progress = self.timing.record(event);
+        self.shared.apply(Command::ReportProgress(progress));
+    }
+}
+
+struct App {
+    shared: Shared,
+    log_buffer: log::LogBuffer,
+}
+
+impl App {
+    fn draw(&self, ui: &mut egui::Ui, model: &mut Model, actions: &mut Vec<Action>) {
+        ui.heading("AXDL");
+        ui.horizontal(|ui| {
+            if ui.button("Open USB device").clicked() {
+                actions.push(Action::OpenUsbDevice);
+            }
+            if ui.button("Open serial port").clicked() {
+                actions.push(Action::OpenSerialDevice);
+            }
+            ui.label(model.flasher.device().unwrap_or("No device"));
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Open image").clicked() {
+                actions.push(Action::OpenImage);
+            }
+            ui.label(
+                model
+                    .flasher
+                    .image()
+                    .map(|image| image.name.as_str())
+                    .unwrap_or("No image"),
+            );
+        });
+        if !model.recent_rows.is_empty() {
+            ui.collapsing("Recent images", |ui| {
+                egui::Grid::new("recent_images")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for (index, row) in model.recent_rows.iter().enumerate() {
+                            if ui.button(&row.name).clicked() {
+                                actions.push(Action::OpenRecent(index));
+                            }
+                            ui.label(&row.size);
+                            ui.monospace(&row.hash);
+                            ui.label(&row.last_result);
+                            ui.end_row();
+                        }
+                    });
+            });
+        }
+
+        ui.separator();
+        let settings = &mut model.settings;
+        ui.checkbox(&mut settings.exclude_rootfs, "Exclude rootfs");
+        ui.checkbox(&mut settings.verify, "Verify");
+        ui.checkbox(&mut model.show_advanced, "Advanced settings");
+        if model.show_advanced {
+            egui::Grid::new("advanced_settings")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    for (label, value) in [
+                        ("Chunk size", &mut settings.chunk_size),
+                        ("Handshake retries", &mut settings.handshake_retries),
+                        ("Block retries", &mut settings.block_retries),
+                        ("Timeout (s)", &mut settings.timeout_secs),
+                        (
+                            "End partition timeout (s)",
+                            &mut settings.end_partition_timeout_secs,
+                        ),
+                        ("Include partitions", &mut settings.include_partitions),
+                        ("Exclude partitions", &mut settings.exclude_partitions),
+                    ] {
+                        ui.label(label);
+                        ui.text_edit_singleline(value);
+                        ui.end_row();
+                    }
+                });
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(model.flasher.can_flash(), egui::Button::new("Download"))
+                .clicked()
+            {
+                actions.push(Action::Download);
+            }
+            if model.flasher.is_flashing() && ui.button("Cancel").clicked() {
+                actions.push(Action::Cancel);
+            }
+        });
+        if !model.message.is_empty() {
+            ui.label(&model.message);
+        }
+        match model.flasher.state() {
+            State::Flashing(progress) => {
+                ui.label(&progress.description);
+                if let Some(fraction) = progress.fraction.filter(|fraction| *fraction >= 0.0) {
+                    ui.add(egui::ProgressBar::new(fraction).show_percentage());
+                }
+                ui.label(progress.timing_text());
+            }
+            State::Error(e) => {
+                let color = ui.visuals().error_fg_color;
+                ui.colored_label(color, format!("Failed to download image file: {}", e));
+            }
+            State::Done(report) => {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.strong(if report.is_success() {
+                        "Download succeeded"
+                    } else {
+                        "Download finished with verify failures"
+                    });
+                    if let Some(elapsed) = model.flasher.elapsed() {
+                        ui.label(format!("in {}", timing::format_duration(elapsed)));
+                    }
+                    if ui.button("Copy report").clicked() {
+                        actions.push(Action::CopyReport);
+                    }
+                });
+                egui::Grid::new("report").striped(true).show(ui, |ui| {
+                    for row in view_model::report_rows(report) {
+                        let color = if row.ok {
+                            ui.visuals().text_color()
+                        } else {
+                            ui.visuals().error_fg_color
+                        };
+                        ui.label(row.partition);
+                        ui.label(row.image);
+                        ui.label(row.bytes_written);
+                        ui.colored_label(color, row.verify);
+                        ui.label(row.duration);
+                        ui.end_row();
+                    }
+                });
+            }
+            State::Idle | State::DeviceSelected | State::ImageLoaded => {}
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut model.show_log, "Show log");
+            if model.show_log {
+                ui.checkbox(&mut model.show_frame_traces, "Frame traces");
+                let mut levels = self.log_buffer.levels();
+                level_combo_box(ui, "Console", &mut levels.console);
+                level_combo_box(ui, "Log pane", &mut levels.pane);
+                if levels != self.log_buffer.levels() {
+                    self.log_buffer.set_levels(levels);
+                    levels.save();
+                }
+                if ui.button("Copy").clicked() {
+                    actions.push(Action::CopyLogs);
+                }
+                if ui.button("Clear").clicked() {
+                    actions.push(Action::ClearLogs);
+                }
+            }
+        });
+        if model.show_log {
+            let text = self.log_buffer.text(model.show_frame_traces);
+            egui::ScrollArea::vertical()
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    ui.add(
+                        egui::TextEdit::multiline(&mut text.as_str())
+                            .font(egui::TextStyle::Monospace)
+                            .desired_width(f32::INFINITY),
+                    );
+                });
+            // Refresh the log pane while it is open.
+            ui.ctx().request_repaint_after(Duration::from_millis(500));
+        }
+    }
+
+    fn run(&self, action: Action) {
+        let shared = self.shared.clone();
+        match action {
+            Action::OpenUsbDevice => wasm_bindgen_futures::spawn_local(shared.open_device(true)),
+            Action::OpenSerialDevice => {
+                wasm_bindgen_futures::spawn_local(shared.open_device(false))
+            }
+            Action::OpenImage => wasm_bindgen_futures::spawn_local(shared.pick_image(None)),
+            Action::OpenRecent(index) => {
+                // Browsers cannot reopen a file by path, so the user picks it again and the
+                // selection is checked against the recent entry.
+                let expected = shared.recent_images.borrow().get(index).cloned();
+                if expected.is_some() {
+                    wasm_bindgen_futures::spawn_local(shared.pick_image(expected));
+                }
+            }
+            Action::Download => wasm_bindgen_futures::spawn_local(shared.download()),
+            Action::Cancel => {
+                shared.apply(Command::Cancel);
+            }
+            Action::CopyReport => copy_to_clipboard(shared.model.borrow().report_text.clone()),
+            Action::CopyLogs => {
+                let show_frame_traces = shared.model.borrow().show_frame_traces;
+                copy_to_clipboard(self.log_buffer.text(show_frame_traces));
+            }
+            Action::ClearLogs => self.log_buffer.clear(),
+        }
+    }
+}
+
+/// Selects one of [`log::LEVELS`].
+fn level_combo_box(ui: &mut egui::Ui, label: &str, level: &mut LevelFilter) {
+    egui::ComboBox::from_label(label)
+        .selected_text(level.to_string())
+        .show_ui(ui, |ui| {
+            for choice in log::LEVELS {
+                ui.selectable_value(level, choice, choice.to_string());
+            }
+        });
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let mut actions = Vec::new();
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                self.draw(ui, &mut self.shared.model.borrow_mut(), &mut actions);
+            });
+        });
+        for action in actions {
+            self.run(action);
+        }
+    }
+}
+
+pub(crate) fn gui_main() -> Result<(), Box<dyn std::error::Error>> {
+    let log_buffer = crate::init_tracing();
+    let canvas = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id("canvas"))
+        .and_then(|element| element.dyn_into::<web_sys::HtmlCanvasElement>().ok())
+        .ok_or("no canvas element in the page")?;
+
+    wasm_bindgen_futures::spawn_local(async move {
+        let result = eframe::WebRunner::new()
+            .start(
+                canvas,
+                eframe::WebOptions::default(),
+                Box::new(move |cc| {
+                    let recent_images = recent::RecentImages::load();
+                    let model = Model {
+                        recent_rows: view_model::recent_rows(&recent_images),
+                        ..Default::default()
+                    };
+                    let shared = Shared {
+                        ctx: cc.egui_ctx.clone(),
+                        model: Rc::new(RefCell::new(model)),
+                        axdl_device: Rc::new(RefCell::new(None)),
+                        image_file: Rc::new(RefCell::new(None)),
+                        recent_images: Rc::new(RefCell::new(recent_images)),
+                        image_options: Rc::new(RefCell::new(options::ImageOptionsStore::load())),
+                    };
+                    Ok(Box::new(App { shared, log_buffer }))
+                }),
+            )
+            .await;
+        if let Err(e) = result {
+            tracing::error!("Failed to start the UI: {:?}", e);
+        }
+    });
+    Ok(())
+}
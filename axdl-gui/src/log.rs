@@ -0,0 +1,215 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Ring buffer of log lines shown in the log pane, filled by a tracing layer, and the levels of
+//! the browser console and the log pane.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tracing::{field::Field, Event, Level, Subscriber};
+use tracing_subscriber::filter::{FilterFn, LevelFilter};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::storage;
+
+const CAPACITY: usize = 1000;
+/// Target of the frame traces emitted by the protocol layer.
+const FRAME_TARGET: &str = "axdl::communication";
+const STORAGE_NAME: &str = "log_levels";
+
+/// Levels which can be selected, least verbose first.
+pub const LEVELS: [LevelFilter; 5] = [
+    LevelFilter::ERROR,
+    LevelFilter::WARN,
+    LevelFilter::INFO,
+    LevelFilter::DEBUG,
+    LevelFilter::TRACE,
+];
+
+/// Most verbose level logged to each destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogLevels {
+    /// Browser console.
+    pub console: LevelFilter,
+    /// Log pane. WARN shows only warnings and errors on screen. Frame traces are recorded at
+    /// any level and shown on request.
+    pub pane: LevelFilter,
+}
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        Self {
+            console: LevelFilter::INFO,
+            pane: LevelFilter::INFO,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredLevels {
+    console: String,
+    pane: String,
+}
+
+impl LogLevels {
+    /// Loads the levels selected last, falling back to the default for unknown names.
+    pub fn load() -> Self {
+        let default = Self::default();
+        let Some(stored) = storage::load(STORAGE_NAME)
+            .and_then(|json| serde_json::from_str::<StoredLevels>(&json).ok())
+        else {
+            return default;
+        };
+        Self {
+            console: stored.console.parse().unwrap_or(default.console),
+            pane: stored.pane.parse().unwrap_or(default.pane),
+        }
+    }
+
+    pub fn save(&self) {
+        let stored = StoredLevels {
+            console: self.console.to_string(),
+            pane: self.pane.to_string(),
+        };
+        match serde_json::to_string(&stored) {
+            Ok(json) => storage::save(STORAGE_NAME, &json),
+            Err(e) => tracing::warn!("Failed to serialize the log levels: {:?}", e),
+        }
+    }
+}
+
+struct LogLine {
+    level: Level,
+    frame: bool,
+    text: String,
+}
+
+#[derive(Default)]
+struct Inner {
+    lines: VecDeque<LogLine>,
+    generation: u64,
+    levels: LogLevels,
+}
+
+#[derive(Clone, Default)]
+pub struct LogBuffer {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a layer which records events into this buffer.
+    ///
+    /// Events up to the pane level are recorded, and DEBUG events from the protocol layer as
+    /// frame traces.
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let buffer = self.clone();
+        LogLayer {
+            buffer: self.clone(),
+        }
+        .with_filter(tracing_subscriber::filter::filter_fn(move |metadata| {
+            buffer.levels().pane >= *metadata.level()
+                || (*metadata.level() == Level::DEBUG && metadata.target() == FRAME_TARGET)
+        }))
+    }
+
+    /// Returns a filter which passes the events up to the console level, for the console layer.
+    pub fn console_filter(&self) -> FilterFn {
+        let buffer = self.clone();
+        tracing_subscriber::filter::filter_fn(move |metadata| {
+            buffer.levels().console >= *metadata.level()
+        })
+    }
+
+    pub fn levels(&self) -> LogLevels {
+        self.inner.lock().unwrap().levels
+    }
+
+    /// Changes the levels of the following events.
+    pub fn set_levels(&self, levels: LogLevels) {
+        self.inner.lock().unwrap().levels = levels;
+    }
+
+    fn push(&self, line: LogLine) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.lines.len() == CAPACITY {
+            inner.lines.pop_front();
+        }
+        inner.lines.push_back(line);
+        inner.generation += 1;
+    }
+
+    /// Incremented whenever a line is added or the buffer is cleared.
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().unwrap().generation
+    }
+
+    pub fn text(&self, include_frames: bool) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut text = String::new();
+        for line in inner.lines.iter().filter(|l| include_frames || !l.frame) {
+            let _ = writeln!(text, "{:5} {}", line.level, line.text);
+        }
+        text
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.lines.clear();
+        inner.generation += 1;
+    }
+}
+
+struct LogLayer {
+    buffer: LogBuffer,
+}
+
+impl<S: Subscriber> Layer<S> for LogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut visitor = MessageVisitor(String::new());
+        event.record(&mut visitor);
+        self.buffer.push(LogLine {
+            level: *metadata.level(),
+            frame: *metadata.level() == Level::DEBUG,
+            text: visitor.0,
+        });
+    }
+}
+
+struct MessageVisitor(String);
+
+impl tracing::field::Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            let _ = write!(self.0, "{:?}", value);
+        } else {
+            let _ = write!(self.0, "{}={:?}", field.name(), value);
+        }
+    }
+}
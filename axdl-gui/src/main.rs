@@ -15,62 +15,76 @@
 
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use std::{cell::RefCell, mem::forget, rc::Rc, time::Duration};
+use std::mem::forget;
 
 use axdl::{
-    download_image,
-    transport::{AsyncTransport, DynDevice, Transport as _},
+    report::DownloadReport,
+    transport::{DeviceInfo, TransportKind},
     AxdlError, DownloadConfig, DownloadProgress,
 };
 use js_sys::wasm_bindgen::{self, JsCast};
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_wasm::WASMLayerConfig;
+use tracing_subscriber::{layer::SubscriberExt, Layer as _};
+
+#[cfg(not(any(feature = "slint", feature = "egui")))]
+compile_error!("axdl-gui needs a frontend, enable the slint or egui feature");
+
+#[cfg(feature = "slint")]
+mod automation;
+#[cfg(feature = "egui")]
+mod egui_ui;
+mod log;
+mod options;
+mod recent;
+#[cfg(feature = "slint")]
+mod slint_ui;
+mod storage;
+mod view_model;
 
-slint::include_modules!();
-
-struct GuiProgress {
-    ui: slint::Weak<AppWindow>,
-    cancelled: bool,
+enum AxdlDevice {
+    Serial(axdl::transport::webserial::WebSerialDevice),
+    Usb(webusb_web::OpenUsbDevice),
+    #[cfg(feature = "emulator")]
+    Emulator(axdl::transport::mock::MockDevice),
 }
 
-impl GuiProgress {
-    fn new(ui: slint::Weak<AppWindow>) -> Self {
-        Self {
-            ui,
-            cancelled: false,
+impl DeviceInfo for AxdlDevice {
+    fn transport_kind(&self) -> TransportKind {
+        match self {
+            AxdlDevice::Serial(device) => device.transport_kind(),
+            AxdlDevice::Usb(device) => device.transport_kind(),
+            #[cfg(feature = "emulator")]
+            AxdlDevice::Emulator(device) => device.transport_kind(),
         }
     }
 
-    fn set_cancelled(&mut self, cancelled: bool) {
-        self.cancelled = cancelled;
+    fn display_name(&self) -> String {
+        match self {
+            AxdlDevice::Serial(device) => device.display_name(),
+            AxdlDevice::Usb(device) => device.display_name(),
+            #[cfg(feature = "emulator")]
+            AxdlDevice::Emulator(device) => device.display_name(),
+        }
     }
-}
 
-impl axdl::DownloadProgress for GuiProgress {
-    fn is_cancelled(&self) -> bool {
-        self.cancelled
-    }
-    fn report_progress(&mut self, description: &str, progress: Option<f32>) {
-        let ui = self.ui.clone();
-        let description = description.to_string();
-        let _ = slint::invoke_from_event_loop(move || {
-            let ui = ui.unwrap();
-            let progress = progress.unwrap_or(-1.0);
-            ui.invoke_set_progress(description.into(), progress);
-        });
+    fn unique_id(&self) -> String {
+        match self {
+            AxdlDevice::Serial(device) => device.unique_id(),
+            AxdlDevice::Usb(device) => device.unique_id(),
+            #[cfg(feature = "emulator")]
+            AxdlDevice::Emulator(device) => device.unique_id(),
+        }
     }
 }
 
-enum AxdlDevice {
-    Serial(axdl::transport::webserial::WebSerialDevice),
-    Usb(webusb_web::OpenUsbDevice),
-}
-
 impl axdl::transport::AsyncDevice for AxdlDevice {
     async fn read(&mut self, buf: &mut [u8]) -> Result<usize, AxdlError> {
         match self {
             AxdlDevice::Serial(device) => device.read(buf).await,
             AxdlDevice::Usb(device) => device.read(buf).await,
+            #[cfg(feature = "emulator")]
+            AxdlDevice::Emulator(device) => {
+                axdl::transport::Device::read_timeout(device, buf, std::time::Duration::ZERO)
+            }
         }
     }
 
@@ -78,6 +92,17 @@ impl axdl::transport::AsyncDevice for AxdlDevice {
         match self {
             AxdlDevice::Serial(device) => device.write(buf).await,
             AxdlDevice::Usb(device) => device.write(buf).await,
+            #[cfg(feature = "emulator")]
+            AxdlDevice::Emulator(device) => {
+                axdl::transport::Device::write_timeout(device, buf, std::time::Duration::ZERO)
+            }
+        }
+    }
+
+    fn max_packet_size(&self) -> Option<usize> {
+        match self {
+            AxdlDevice::Usb(device) => axdl::transport::AsyncDevice::max_packet_size(device),
+            _ => None,
         }
     }
 }
@@ -365,193 +390,76 @@ impl<'a> std::io::Seek for FileWrapper<'a> {
     }
 }
 
-fn gui_main() -> Result<(), Box<dyn std::error::Error>> {
-    let tracing_layer = tracing_wasm::WASMLayer::new(
-        tracing_wasm::WASMLayerConfigBuilder::default()
-            .set_max_level(tracing::Level::INFO)
-            .build(),
-    );
-    let subscriber = tracing_subscriber::registry().with(tracing_layer);
-    tracing::subscriber::set_global_default(subscriber).unwrap();
-
-    let usb = Rc::new(webusb_web::Usb::new().unwrap());
-    let serial = Rc::new(axdl::transport::webserial::new_serial().unwrap());
-    let axdl_device: Rc<RefCell<Option<AxdlDevice>>> = Rc::new(RefCell::new(None));
-    let image_file = Rc::new(RefCell::new(None));
-
-    let ui = AppWindow::new()?;
-
-    {
-        let usb = usb.clone();
-        let axdl_device = axdl_device.clone();
-        let ui_handle = ui.as_weak();
-        ui.on_open_usb_device(move || {
-            let usb = usb.clone();
-            let axdl_device = axdl_device.clone();
-            let ui = ui_handle.unwrap();
-            slint::spawn_local(async move {
-                let result: Result<(), Box<dyn std::error::Error>> = async {
-                    let device = usb
-                        .request_device([axdl::transport::webusb::axdl_device_filter()])
-                        .await?;
-                    tracing::info!("Device selected: {:?}", device);
-                    let open_device = device.open().await?;
-                    tracing::info!("Device opened: {:?}", open_device);
-                    open_device.claim_interface(0).await?;
-                    axdl_device.replace(Some(AxdlDevice::Usb(open_device)));
-                    ui.set_device_opened(true);
-                    Ok(())
-                }
-                .await;
-
-                if let Err(e) = result {
-                    tracing::error!("Failed to open device: {:?}", e);
-                    ui.set_device_opened(false);
-                }
-            });
-        });
-    }
-
-    {
-        let serial = serial.clone();
-        let axdl_device = axdl_device.clone();
-        let ui_handle = ui.as_weak();
-        ui.on_open_serial_device(move || {
-            let serial = serial.clone();
-            let axdl_device = axdl_device.clone();
-            let ui = ui_handle.unwrap();
-            slint::spawn_local(async move {
-                let result: Result<(), Box<dyn std::error::Error>> = async {
-                    let options = web_sys::SerialPortRequestOptions::new();
-                    options.set_filters(&js_sys::Array::of1(
-                        &axdl::transport::webserial::axdl_device_filter(),
-                    ));
-                    let promise = serial.request_port_with_options(&options);
-                    let device = web_sys::SerialPort::from(
-                        wasm_bindgen_futures::JsFuture::from(promise)
-                            .await
-                            .map_err(AxdlError::WebSerialError)?,
-                    );
-                    tracing::info!("Device selected: {:?}", device);
-                    let options = web_sys::SerialOptions::new(115200);
-                    options.set_buffer_size(48000);
-                    wasm_bindgen_futures::JsFuture::from(device.open(&options))
-                        .await
-                        .map_err(AxdlError::WebSerialError)?;
-                    tracing::info!("Device opened: {:?}", device);
-                    axdl_device.replace(Some(AxdlDevice::Serial(
-                        axdl::transport::webserial::WebSerialDevice::new(device),
-                    )));
-                    ui.set_device_opened(true);
-                    Ok(())
-                }
-                .await;
-
-                if let Err(e) = result {
-                    tracing::error!("Failed to open device: {:?}", e);
-                    ui.set_device_opened(false);
-                }
-            });
-        });
-    }
-
-    {
-        let ui_handle = ui.as_weak();
-        let image_file = image_file.clone();
-        ui.on_open_image(move || {
-            let ui = ui_handle.unwrap();
-            let image_file = image_file.clone();
-            slint::spawn_local(async move {
-                let result: Result<(), Box<dyn std::error::Error>> = async {
-                    let file = rfd::AsyncFileDialog::new()
-                        .add_filter("AXDL Image", &["*.axp"])
-                        .pick_file()
-                        .await
-                        .inspect(|path| {
-                            tracing::info!("Selected file: {}", path.file_name());
-                        });
-
-                    ui.set_image_file_opened(file.is_some());
-                    ui.set_image_file(
-                        file.as_ref()
-                            .map(|f| f.file_name())
-                            .unwrap_or_default()
-                            .into(),
-                    );
-                    *image_file.borrow_mut() = file;
-                    Ok(())
-                }
-                .await;
-
-                if let Err(e) = result {
-                    tracing::error!("Failed to open image file: {:?}", e);
-                    ui.set_image_file_opened(false);
-                }
-            });
-        });
+/// Computes the hex encoded SHA-256 of a file.
+async fn hash_file(file: &web_sys::File) -> std::io::Result<String> {
+    use futures_util::io::AsyncReadExt;
+    use sha2::Digest;
+
+    let mut reader = FileWrapper::new(file);
+    let mut hasher = sha2::Sha256::new();
+    let mut buffer = vec![0u8; 1048576];
+    loop {
+        let bytes_read = reader.read(&mut buffer).await?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
     }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
 
-    {
-        let ui_handle = ui.as_weak();
-        let image_file = image_file.clone();
-        let axdl_device = axdl_device.clone();
-
-        ui.on_download(move || {
-            let ui_handle = ui_handle.clone();
-            let ui = ui_handle.unwrap();
-            if axdl_device.borrow().is_none() || image_file.borrow().is_none() {
-                tracing::error!("Device or image file is not selected");
-                return;
-            }
-
-            let image_file = image_file.clone();
-            let axdl_device = axdl_device.clone();
-
-            ui.set_downloading(true);
-
-            slint::spawn_local(async move {
-                let result: Result<(), Box<dyn std::error::Error>> = async {
-                    let mut progress = GuiProgress::new(ui_handle.clone());
-                    let config = DownloadConfig {
-                        exclude_rootfs: ui.get_exclude_rootfs(),
-                    };
-                    let image_file_ref = image_file.borrow();
-                    let file = FileWrapper::new(image_file_ref.as_ref().unwrap().inner());
-                    let mut buf_file = BufReader::new(file, 1048576);
-
-                    tracing::info!("Start downloading image file");
-                    let result = axdl::download_image_async(
-                        &mut buf_file,
-                        axdl_device.borrow_mut().as_mut().unwrap(),
-                        &config,
-                        &mut progress,
-                    )
-                    .await?;
-                    Ok(())
-                }
-                .await;
-
-                ui.set_downloading(false);
-
-                if let Err(e) = result {
-                    tracing::error!("Failed to download image file: {:?}", e);
-                    ui.invoke_set_progress(
-                        format!("Failed to download image file: {:?}", e).into(),
-                        -1.0,
-                    );
-                } else {
-                    ui.invoke_set_progress("Done".into(), -1.0);
-                }
-            });
-        });
-    }
+fn copy_to_clipboard(text: String) {
+    wasm_bindgen_futures::spawn_local(async move {
+        let Some(window) = web_sys::window() else {
+            return;
+        };
+        let promise = window.navigator().clipboard().write_text(&text);
+        if let Err(e) = wasm_bindgen_futures::JsFuture::from(promise).await {
+            tracing::error!("Failed to copy to the clipboard: {:?}", e);
+        }
+    });
+}
 
-    ui.run()?;
+/// Downloads the image `file` to `device`.
+async fn download_file(
+    file: &web_sys::File,
+    device: &mut AxdlDevice,
+    config: &DownloadConfig,
+    progress: &mut impl DownloadProgress,
+) -> Result<DownloadReport, AxdlError> {
+    let mut reader = BufReader::new(FileWrapper::new(file), 1048576);
+    tracing::info!("Start downloading image file");
+    axdl::download_image_async(&mut reader, device, config, progress).await
+}
 
-    Ok(())
+/// Sets up tracing with the levels selected last and returns the buffer which collects the
+/// logs for the log pane.
+fn init_tracing() -> log::LogBuffer {
+    let log_buffer = log::LogBuffer::new();
+    log_buffer.set_levels(log::LogLevels::load());
+    // Frame traces only go to the log pane unless the console level includes DEBUG.
+    let tracing_layer = tracing_wasm::WASMLayer::new(
+        tracing_wasm::WASMLayerConfigBuilder::default()
+            .set_max_level(tracing::Level::TRACE)
+            .build(),
+    )
+    .with_filter(log_buffer.console_filter());
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_layer)
+        .with(log_buffer.layer());
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+    log_buffer
 }
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen::prelude::wasm_bindgen(start))]
 fn main() {
-    gui_main().unwrap();
+    // Slint is the default frontend and wins when both are enabled.
+    #[cfg(feature = "slint")]
+    slint_ui::gui_main().unwrap();
+    #[cfg(all(feature = "egui", not(feature = "slint")))]
+    egui_ui::gui_main().unwrap();
 }
@@ -0,0 +1,102 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Download options last used with each image, keyed by the SHA-256 of the image, so that
+//! reopening the same image restores them.
+
+use axdl_core_ui::Settings;
+use serde::{Deserialize, Serialize};
+
+use crate::storage;
+
+const MAX_ENTRIES: usize = 50;
+const STORAGE_NAME: &str = "image_options";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImageOptions {
+    pub exclude_rootfs: bool,
+    pub verify: bool,
+    /// Comma separated partition names, as entered in the UI.
+    pub include_partitions: String,
+    pub exclude_partitions: String,
+}
+
+impl From<&Settings> for ImageOptions {
+    fn from(settings: &Settings) -> Self {
+        Self {
+            exclude_rootfs: settings.exclude_rootfs,
+            verify: settings.verify,
+            include_partitions: settings.include_partitions.clone(),
+            exclude_partitions: settings.exclude_partitions.clone(),
+        }
+    }
+}
+
+impl ImageOptions {
+    /// Restores the options into `settings`.
+    pub fn apply(&self, settings: &mut Settings) {
+        settings.exclude_rootfs = self.exclude_rootfs;
+        settings.verify = self.verify;
+        settings.include_partitions = self.include_partitions.clone();
+        settings.exclude_partitions = self.exclude_partitions.clone();
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    hash: String,
+    options: ImageOptions,
+}
+
+/// Options per image hash, most recently used first.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImageOptionsStore {
+    entries: Vec<Entry>,
+}
+
+impl ImageOptionsStore {
+    pub fn get(&self, hash: &str) -> Option<&ImageOptions> {
+        self.entries
+            .iter()
+            .find(|entry| entry.hash == hash)
+            .map(|entry| &entry.options)
+    }
+
+    /// Remembers `options` for the image, forgetting the least recently used image if full.
+    pub fn set(&mut self, hash: &str, options: ImageOptions) {
+        self.entries.retain(|entry| entry.hash != hash);
+        self.entries.insert(
+            0,
+            Entry {
+                hash: hash.to_string(),
+                options,
+            },
+        );
+        self.entries.truncate(MAX_ENTRIES);
+    }
+
+    pub fn load() -> Self {
+        storage::load(STORAGE_NAME)
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        match serde_json::to_string(self) {
+            Ok(json) => storage::save(STORAGE_NAME, &json),
+            Err(e) => tracing::warn!("Failed to serialize the image options: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Most recently used image files, persisted in localStorage on the web and in the user
+//! configuration directory on native builds.
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage;
+
+const MAX_ENTRIES: usize = 10;
+const STORAGE_NAME: &str = "recent_images";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecentImage {
+    pub name: String,
+    pub size: u64,
+    /// Hex encoded SHA-256 of the file, empty until hashing finishes.
+    #[serde(default)]
+    pub hash: String,
+    #[serde(default)]
+    pub last_result: String,
+    /// Path of the file; only available on native builds.
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+impl RecentImage {
+    pub fn new(name: String, size: u64, path: Option<String>) -> Self {
+        Self {
+            name,
+            size,
+            hash: String::new(),
+            last_result: String::new(),
+            path,
+        }
+    }
+
+    fn is_same_file(&self, name: &str, size: u64) -> bool {
+        self.name == name && self.size == size
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RecentImages {
+    entries: Vec<RecentImage>,
+}
+
+impl RecentImages {
+    pub fn entries(&self) -> &[RecentImage] {
+        &self.entries
+    }
+
+    pub fn get(&self, index: usize) -> Option<&RecentImage> {
+        self.entries.get(index)
+    }
+
+    /// Moves the image to the top of the list, adding it if it is not listed yet.
+    pub fn touch(&mut self, image: RecentImage) {
+        let previous = self
+            .entries
+            .iter()
+            .position(|entry| entry.is_same_file(&image.name, image.size))
+            .map(|index| self.entries.remove(index));
+        let image = match previous {
+            Some(previous) => RecentImage {
+                hash: if image.hash.is_empty() {
+                    previous.hash
+                } else {
+                    image.hash
+                },
+                last_result: previous.last_result,
+                path: image.path.or(previous.path),
+                ..image
+            },
+            None => image,
+        };
+        self.entries.insert(0, image);
+        self.entries.truncate(MAX_ENTRIES);
+    }
+
+    pub fn set_hash(&mut self, name: &str, size: u64, hash: String) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.is_same_file(name, size))
+        {
+            entry.hash = hash;
+        }
+    }
+
+    pub fn set_result(&mut self, name: &str, size: u64, result: String) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.is_same_file(name, size))
+        {
+            entry.last_result = result;
+        }
+    }
+
+    /// Returns the hash of the image, if it has been computed.
+    pub fn hash_of(&self, name: &str, size: u64) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|entry| entry.is_same_file(name, size) && !entry.hash.is_empty())
+            .map(|entry| entry.hash.as_str())
+    }
+
+    pub fn load() -> Self {
+        storage::load(STORAGE_NAME)
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        match serde_json::to_string(self) {
+            Ok(json) => storage::save(STORAGE_NAME, &json),
+            Err(e) => tracing::warn!("Failed to serialize the recent images: {:?}", e),
+        }
+    }
+}
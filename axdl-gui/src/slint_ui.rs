@@ -0,0 +1,572 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Slint frontend, the default one.
+
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use axdl::{
+    report::DownloadReport,
+    transport::{
+        webserial::WebSerialTransport, webusb::WebUsbTransport, AsyncTransport, DeviceInfo,
+    },
+    AxdlError,
+};
+use axdl_core_ui::{timing, Command, Event, Flasher, Image, Progress, State, Timing};
+
+use crate::{automation, copy_to_clipboard, hash_file, options, recent, view_model, AxdlDevice};
+
+slint::include_modules!();
+
+/// Returns the settings shown in the UI.
+fn settings(ui: &AppWindow) -> view_model::Settings {
+    view_model::Settings {
+        exclude_rootfs: ui.get_exclude_rootfs(),
+        verify: ui.get_verify(),
+        chunk_size: ui.get_chunk_size().into(),
+        handshake_retries: ui.get_handshake_retries().into(),
+        block_retries: ui.get_block_retries().into(),
+        timeout_secs: ui.get_timeout_secs().into(),
+        end_partition_timeout_secs: ui.get_end_partition_timeout_secs().into(),
+        include_partitions: ui.get_include_partitions().into(),
+        exclude_partitions: ui.get_exclude_partitions().into(),
+    }
+}
+
+fn apply_image_options(ui: &AppWindow, options: &options::ImageOptions) {
+    ui.set_exclude_rootfs(options.exclude_rootfs);
+    ui.set_verify(options.verify);
+    ui.set_include_partitions(options.include_partitions.as_str().into());
+    ui.set_exclude_partitions(options.exclude_partitions.as_str().into());
+}
+
+/// Applies `command` to the flasher and shows the changes. Returns false if it was rejected.
+pub(crate) fn dispatch(ui: &AppWindow, flasher: &RefCell<Flasher>, command: Command) -> bool {
+    let events = match flasher.borrow_mut().apply(command) {
+        Ok(events) => events,
+        Err(e) => {
+            tracing::warn!("{}", e);
+            return false;
+        }
+    };
+    for event in events {
+        match event {
+            Event::DeviceChanged(device) => ui.set_device_opened(device.is_some()),
+            Event::ImageChanged(image) => {
+                ui.set_image_file_opened(image.is_some());
+                ui.set_image_file(image.map(|image| image.name).unwrap_or_default().into());
+            }
+            Event::StateChanged(state) => {
+                ui.set_downloading(matches!(state, State::Flashing(_)));
+                match state {
+                    State::Flashing(progress) => {
+                        ui.invoke_set_progress(
+                            progress.description.clone().into(),
+                            progress.fraction.unwrap_or(-1.0),
+                        );
+                        ui.set_timing_text(progress.timing_text().into());
+                    }
+                    State::Done(report) => {
+                        ui.invoke_set_progress("Done".into(), -1.0);
+                        // Kept for comparing the throughput of hosts and browsers.
+                        let elapsed = flasher.borrow().elapsed().unwrap_or_default();
+                        ui.set_timing_text(
+                            format!("Finished in {}", timing::format_duration(elapsed)).into(),
+                        );
+                        show_report(ui, &report);
+                    }
+                    State::Error(e) => ui.invoke_set_progress(
+                        format!("Failed to download image file: {}", e).into(),
+                        -1.0,
+                    ),
+                    State::Idle | State::DeviceSelected | State::ImageLoaded => {}
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Feeds the progress of a download to the flasher. Downloads run on the UI thread.
+struct GuiProgress {
+    ui: slint::Weak<AppWindow>,
+    flasher: Rc<RefCell<Flasher>>,
+    timing: Timing,
+}
+
+impl GuiProgress {
+    fn report(&self, progress: Progress) {
+        let Some(ui) = self.ui.upgrade() else {
+            return;
+        };
+        dispatch(&ui, &self.flasher, Command::ReportProgress(progress));
+    }
+}
+
+impl axdl::DownloadProgress for GuiProgress {
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+    fn report_progress(&mut self, description: &str, progress: Option<f32>) {
+        self.report(Progress {
+            description: description.to_string(),
+            fraction: progress,
+            elapsed: self.timing.elapsed(axdl::progress::timestamp()),
+            ..Default::default()
+        });
+    }
+    fn report_event(&mut self, event: &axdl::progress::ProgressEvent<'_>) {
+        let progress = self.timing.record(event);
+        self.report(progress);
+    }
+}
+
+fn show_report(ui: &AppWindow, report: &DownloadReport) {
+    let rows = view_model::report_rows(report)
+        .into_iter()
+        .map(|row| PartitionResult {
+            partition: row.partition.into(),
+            image: row.image.into(),
+            bytes_written: row.bytes_written.into(),
+            verify: row.verify.into(),
+            duration: row.duration.into(),
+            ok: row.ok,
+        })
+        .collect::<Vec<_>>();
+    ui.set_report_rows(slint::ModelRc::new(slint::VecModel::from(rows)));
+    ui.set_report_success(report.is_success());
+    ui.set_show_report(true);
+}
+
+fn show_recent_images(ui: &AppWindow, recent_images: &recent::RecentImages) {
+    let items = view_model::recent_rows(recent_images)
+        .into_iter()
+        .map(|row| RecentImageItem {
+            name: row.name.into(),
+            size: row.size.into(),
+            hash: row.hash.into(),
+            last_result: row.last_result.into(),
+        })
+        .collect::<Vec<_>>();
+    ui.set_recent_images(slint::ModelRc::new(slint::VecModel::from(items)));
+}
+
+/// Lets the user pick an image file and records it in the recent images.
+///
+/// When `expected` is given, the picked file is compared with that recent entry.
+async fn pick_image(
+    ui: &AppWindow,
+    flasher: &RefCell<Flasher>,
+    image_file: &Rc<RefCell<Option<web_sys::File>>>,
+    recent_images: &Rc<RefCell<recent::RecentImages>>,
+    image_options: &Rc<RefCell<options::ImageOptionsStore>>,
+    expected: Option<recent::RecentImage>,
+) {
+    let mut dialog = rfd::AsyncFileDialog::new().add_filter("AXDL Image", &["*.axp"]);
+    if let Some(expected) = &expected {
+        dialog = dialog.set_title(format!("Select {}", expected.name));
+    }
+    let file = dialog.pick_file().await.map(|file| file.inner().clone());
+    set_image(
+        ui,
+        flasher,
+        image_file,
+        recent_images,
+        image_options,
+        file,
+        expected,
+    )
+    .await;
+}
+
+/// Makes `file` the image to download and records it in the recent images. The options last
+/// used with the same image are restored once it is hashed.
+pub(crate) async fn set_image(
+    ui: &AppWindow,
+    flasher: &RefCell<Flasher>,
+    image_file: &Rc<RefCell<Option<web_sys::File>>>,
+    recent_images: &Rc<RefCell<recent::RecentImages>>,
+    image_options: &Rc<RefCell<options::ImageOptionsStore>>,
+    file: Option<web_sys::File>,
+    expected: Option<recent::RecentImage>,
+) {
+    // The image file stays borrowed while it is downloaded.
+    if flasher.borrow().is_flashing() {
+        tracing::warn!("The image cannot be changed while downloading");
+        return;
+    }
+    let Some(picked) = file.as_ref() else {
+        *image_file.borrow_mut() = None;
+        dispatch(ui, flasher, Command::UnloadImage);
+        return;
+    };
+    tracing::info!("Selected file: {}", picked.name());
+    let name = picked.name();
+    let size = picked.size() as u64;
+    dispatch(
+        ui,
+        flasher,
+        Command::LoadImage(Image {
+            name: name.clone(),
+            size,
+        }),
+    );
+    if let Some(expected) = &expected {
+        if expected.name != name || expected.size != size {
+            tracing::warn!(
+                "Selected file {} ({} bytes) differs from the recent image {} ({} bytes)",
+                name,
+                size,
+                expected.name,
+                expected.size
+            );
+            ui.invoke_set_progress(
+                format!(
+                    "Selected file differs from the recent image {}",
+                    expected.name
+                )
+                .into(),
+                -1.0,
+            );
+        }
+    }
+    {
+        let mut recent_images = recent_images.borrow_mut();
+        recent_images.touch(recent::RecentImage::new(name.clone(), size, None));
+        recent_images.save();
+        show_recent_images(ui, &recent_images);
+    }
+    let inner = picked.clone();
+    *image_file.borrow_mut() = file;
+
+    match hash_file(&inner).await {
+        Ok(hash) => {
+            if let Some(expected) = expected.filter(|expected| !expected.hash.is_empty()) {
+                if expected.hash != hash {
+                    tracing::warn!("Hash of {} differs from the recent image", name);
+                }
+            }
+            if let Some(options) = image_options.borrow().get(&hash) {
+                tracing::info!("Restored the options last used with {}", name);
+                apply_image_options(ui, options);
+                ui.invoke_set_progress(
+                    "Restored the options last used with this image".into(),
+                    -1.0,
+                );
+            }
+            let mut recent_images = recent_images.borrow_mut();
+            recent_images.set_hash(&name, size, hash);
+            recent_images.save();
+            show_recent_images(ui, &recent_images);
+        }
+        Err(e) => tracing::warn!("Failed to hash {}: {:?}", name, e),
+    }
+}
+
+pub(crate) fn gui_main() -> Result<(), Box<dyn std::error::Error>> {
+    let log_buffer = crate::init_tracing();
+
+    let axdl_device: Rc<RefCell<Option<AxdlDevice>>> = Rc::new(RefCell::new(None));
+    let image_file = Rc::new(RefCell::new(None));
+    let report_text = Rc::new(RefCell::new(String::new()));
+    let recent_images = Rc::new(RefCell::new(recent::RecentImages::load()));
+    let image_options = Rc::new(RefCell::new(options::ImageOptionsStore::load()));
+    let last_result = Rc::new(RefCell::new(None));
+    let flasher = Rc::new(RefCell::new(Flasher::new()));
+
+    let ui = AppWindow::new()?;
+    show_recent_images(&ui, &recent_images.borrow());
+
+    automation::register(automation::Handles {
+        ui: ui.as_weak(),
+        axdl_device: axdl_device.clone(),
+        image_file: image_file.clone(),
+        recent_images: recent_images.clone(),
+        image_options: image_options.clone(),
+        last_result: last_result.clone(),
+        flasher: flasher.clone(),
+    });
+
+    {
+        let axdl_device = axdl_device.clone();
+        let flasher = flasher.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_open_usb_device(move || {
+            let axdl_device = axdl_device.clone();
+            let flasher = flasher.clone();
+            let ui = ui_handle.unwrap();
+            slint::spawn_local(async move {
+                let result: Result<(), AxdlError> = async {
+                    let device = WebUsbTransport::request_device().await?;
+                    let device = WebUsbTransport::open_device(&device).await?;
+                    let name = device.display_name();
+                    axdl_device.replace(Some(AxdlDevice::Usb(device)));
+                    dispatch(&ui, &flasher, Command::SelectDevice(name));
+                    Ok(())
+                }
+                .await;
+
+                if let Err(e) = result {
+                    tracing::error!("Failed to open device: {:?}", e);
+                    dispatch(&ui, &flasher, Command::ReleaseDevice);
+                    ui.invoke_set_progress(view_model::open_error_message(&e).into(), -1.0);
+                }
+            });
+        });
+    }
+
+    {
+        let axdl_device = axdl_device.clone();
+        let flasher = flasher.clone();
+        let ui_handle = ui.as_weak();
+        ui.on_open_serial_device(move || {
+            let axdl_device = axdl_device.clone();
+            let flasher = flasher.clone();
+            let ui = ui_handle.unwrap();
+            slint::spawn_local(async move {
+                let result: Result<(), AxdlError> = async {
+                    let port = WebSerialTransport::request_port().await?;
+                    let device = WebSerialTransport::open_device(&port).await?;
+                    let name = device.display_name();
+                    axdl_device.replace(Some(AxdlDevice::Serial(device)));
+                    dispatch(&ui, &flasher, Command::SelectDevice(name));
+                    Ok(())
+                }
+                .await;
+
+                if let Err(e) = result {
+                    tracing::error!("Failed to open device: {:?}", e);
+                    dispatch(&ui, &flasher, Command::ReleaseDevice);
+                }
+            });
+        });
+    }
+
+    {
+        let ui_handle = ui.as_weak();
+        let image_file = image_file.clone();
+        let recent_images = recent_images.clone();
+        let image_options = image_options.clone();
+        let flasher = flasher.clone();
+        ui.on_open_image(move || {
+            let ui = ui_handle.unwrap();
+            let flasher = flasher.clone();
+            let image_file = image_file.clone();
+            let recent_images = recent_images.clone();
+            let image_options = image_options.clone();
+            slint::spawn_local(async move {
+                pick_image(
+                    &ui,
+                    &flasher,
+                    &image_file,
+                    &recent_images,
+                    &image_options,
+                    None,
+                )
+                .await;
+            });
+        });
+    }
+
+    {
+        let ui_handle = ui.as_weak();
+        let image_file = image_file.clone();
+        let recent_images = recent_images.clone();
+        let image_options = image_options.clone();
+        let flasher = flasher.clone();
+        ui.on_open_recent(move |index| {
+            let ui = ui_handle.unwrap();
+            let flasher = flasher.clone();
+            let image_file = image_file.clone();
+            let recent_images = recent_images.clone();
+            let image_options = image_options.clone();
+            let Some(expected) = recent_images.borrow().get(index as usize).cloned() else {
+                return;
+            };
+            slint::spawn_local(async move {
+                // Browsers cannot reopen a file by path, so the user picks it again and
+                // the selection is checked against the recent entry.
+                pick_image(
+                    &ui,
+                    &flasher,
+                    &image_file,
+                    &recent_images,
+                    &image_options,
+                    Some(expected),
+                )
+                .await;
+            });
+        });
+    }
+
+    {
+        let report_text = report_text.clone();
+        ui.on_copy_report(move || {
+            copy_to_clipboard(report_text.borrow().clone());
+        });
+    }
+
+    {
+        let ui_handle = ui.as_weak();
+        let log_buffer = log_buffer.clone();
+        ui.on_copy_logs(move || {
+            let ui = ui_handle.unwrap();
+            copy_to_clipboard(log_buffer.text(ui.get_show_frame_traces()));
+        });
+    }
+
+    {
+        let log_buffer = log_buffer.clone();
+        ui.on_clear_logs(move || {
+            log_buffer.clear();
+        });
+    }
+
+    {
+        let levels = log_buffer.levels();
+        ui.set_console_level(levels.console.to_string().into());
+        ui.set_pane_level(levels.pane.to_string().into());
+        let ui_handle = ui.as_weak();
+        let log_buffer = log_buffer.clone();
+        ui.on_log_levels_changed(move || {
+            let ui = ui_handle.unwrap();
+            let mut levels = log_buffer.levels();
+            levels.console = ui.get_console_level().parse().unwrap_or(levels.console);
+            levels.pane = ui.get_pane_level().parse().unwrap_or(levels.pane);
+            log_buffer.set_levels(levels);
+            levels.save();
+        });
+    }
+
+    // Refresh the log pane while it is open.
+    let log_timer = slint::Timer::default();
+    {
+        let ui_handle = ui.as_weak();
+        let log_buffer = log_buffer.clone();
+        let mut shown = None;
+        log_timer.start(
+            slint::TimerMode::Repeated,
+            Duration::from_millis(500),
+            move || {
+                let Some(ui) = ui_handle.upgrade() else {
+                    return;
+                };
+                if !ui.get_show_log() {
+                    shown = None;
+                    return;
+                }
+                let state = (log_buffer.generation(), ui.get_show_frame_traces());
+                if shown != Some(state) {
+                    shown = Some(state);
+                    ui.set_log_text(log_buffer.text(state.1).into());
+                }
+            },
+        );
+    }
+
+    {
+        let ui_handle = ui.as_weak();
+        let image_file = image_file.clone();
+        let axdl_device = axdl_device.clone();
+        let report_text = report_text.clone();
+        let recent_images = recent_images.clone();
+        let image_options = image_options.clone();
+        let last_result = last_result.clone();
+        let flasher = flasher.clone();
+
+        ui.on_download(move || {
+            let ui_handle = ui_handle.clone();
+            let ui = ui_handle.unwrap();
+            if axdl_device.borrow().is_none() || image_file.borrow().is_none() {
+                tracing::error!("Device or image file is not selected");
+                return;
+            }
+            if let Some(file) = image_file.borrow().as_ref() {
+                if let Some(hash) = recent_images
+                    .borrow()
+                    .hash_of(&file.name(), file.size() as u64)
+                {
+                    let mut image_options = image_options.borrow_mut();
+                    image_options.set(hash, options::ImageOptions::from(&settings(&ui)));
+                    image_options.save();
+                }
+            }
+
+            let image_file = image_file.clone();
+            let axdl_device = axdl_device.clone();
+            let report_text = report_text.clone();
+            let recent_images = recent_images.clone();
+            let last_result = last_result.clone();
+            let flasher = flasher.clone();
+
+            ui.set_show_report(false);
+            if !dispatch(&ui, &flasher, Command::StartFlash) {
+                return;
+            }
+            *last_result.borrow_mut() = None;
+            let timing = Timing::start(axdl::progress::timestamp());
+
+            slint::spawn_local(async move {
+                let result: Result<DownloadReport, Box<dyn std::error::Error>> = async {
+                    let mut progress = GuiProgress {
+                        ui: ui_handle.clone(),
+                        flasher: flasher.clone(),
+                        timing: timing.clone(),
+                    };
+                    let mut config = settings(&ui).download_config()?;
+                    config.cancellation = flasher.borrow().cancellation();
+                    let image_file_ref = image_file.borrow();
+                    let report = crate::download_file(
+                        image_file_ref.as_ref().unwrap(),
+                        axdl_device.borrow_mut().as_mut().unwrap(),
+                        &config,
+                        &mut progress,
+                    )
+                    .await?;
+                    Ok(report)
+                }
+                .await;
+
+                let result_text = view_model::result_text(&result);
+                *last_result.borrow_mut() = Some(result_text.clone());
+                if let Some(file) = image_file.borrow().as_ref() {
+                    let mut recent_images = recent_images.borrow_mut();
+                    recent_images.set_result(&file.name(), file.size() as u64, result_text);
+                    recent_images.save();
+                    show_recent_images(&ui, &recent_images);
+                }
+
+                match &result {
+                    Err(e) => tracing::error!("Failed to download image file: {:?}", e),
+                    Ok(report) => *report_text.borrow_mut() = report.to_string(),
+                }
+                dispatch(
+                    &ui,
+                    &flasher,
+                    Command::ReportProgress(timing.finished(axdl::progress::timestamp())),
+                );
+                dispatch(
+                    &ui,
+                    &flasher,
+                    Command::Finish(result.map_err(|e| e.to_string())),
+                );
+            });
+        });
+    }
+
+    ui.run()?;
+
+    Ok(())
+}
@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Persistent JSON documents, stored in localStorage on the web and in the user configuration
+//! directory on native builds.
+
+#[cfg(target_arch = "wasm32")]
+mod imp {
+    fn local_storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok().flatten()
+    }
+
+    pub fn load(name: &str) -> Option<String> {
+        local_storage()?
+            .get_item(&format!("axdl.{}", name))
+            .ok()
+            .flatten()
+    }
+
+    pub fn save(name: &str, json: &str) {
+        if let Some(storage) = local_storage() {
+            if let Err(e) = storage.set_item(&format!("axdl.{}", name), json) {
+                tracing::warn!("Failed to store {}: {:?}", name, e);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod imp {
+    fn path(name: &str) -> Option<std::path::PathBuf> {
+        Some(
+            dirs::config_dir()?
+                .join("axdl")
+                .join(format!("{}.json", name)),
+        )
+    }
+
+    pub fn load(name: &str) -> Option<String> {
+        std::fs::read_to_string(path(name)?).ok()
+    }
+
+    pub fn save(name: &str, json: &str) {
+        let Some(path) = path(name) else {
+            return;
+        };
+        let result = path
+            .parent()
+            .map(std::fs::create_dir_all)
+            .transpose()
+            .and_then(|_| std::fs::write(&path, json));
+        if let Err(e) = result {
+            tracing::warn!("Failed to store {}: {:?}", name, e);
+        }
+    }
+}
+
+pub use imp::{load, save};
@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Formatting shared by the frontends on top of [`axdl_core_ui`], for the parts which depend
+//! on the web platform.
+
+use axdl::AxdlError;
+pub use axdl_core_ui::{report_rows, result_text, ReportRow, Settings};
+
+use crate::recent;
+
+/// Row of the recent images list.
+#[derive(Debug, Clone)]
+pub struct RecentRow {
+    pub name: String,
+    pub size: String,
+    /// Prefix of the hash, enough to tell images apart.
+    pub hash: String,
+    pub last_result: String,
+}
+
+pub fn recent_rows(recent_images: &recent::RecentImages) -> Vec<RecentRow> {
+    recent_images
+        .entries()
+        .iter()
+        .map(|entry| RecentRow {
+            name: entry.name.clone(),
+            size: format!("{:.1} MiB", entry.size as f64 / (1024.0 * 1024.0)),
+            hash: entry.hash.get(..16).unwrap_or(&entry.hash).to_string(),
+            last_result: entry.last_result.clone(),
+        })
+        .collect()
+}
+
+/// Message shown when a USB device cannot be opened, with the steps to free a claimed interface.
+pub fn open_error_message(error: &AxdlError) -> String {
+    match error.root_cause() {
+        AxdlError::InterfaceClaimFailed(_) => format!(
+            "{}\n\
+             Another driver or program is using the device:\n\
+             - Close other tabs, axdl-cli and the vendor tool if they are connected to it.\n\
+             - Linux: a kernel driver such as cdc_acm may have bound it. Unbind it with \
+             `echo <bus>-<port>:1.0 | sudo tee /sys/bus/usb/drivers/cdc_acm/unbind` and check \
+             that 99-axdl.rules is installed in /etc/udev/rules.d.\n\
+             - Windows: install the WinUSB driver for the device, e.g. with Zadig.",
+            error
+        ),
+        _ => format!("Failed to open device: {}", error),
+    }
+}
@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+/// Reference implementation folding one 16-bit word at a time.
+fn checksum_wordwise(bytes: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for word in bytes.chunks(2) {
+        let value = u16::from_le_bytes([word[0], *word.get(1).unwrap_or(&0)]);
+        sum += value as u32;
+        if sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+    }
+    sum as u16
+}
+
+fn bench_checksum(c: &mut Criterion) {
+    let mut group = c.benchmark_group("checksum");
+    for size in [64usize, 1000, 48000] {
+        let data = (0..size).map(|i| i as u8).collect::<Vec<_>>();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("checksum", size), &data, |b, data| {
+            b.iter(|| axdl::frame::checksum(black_box(data)))
+        });
+        group.bench_with_input(BenchmarkId::new("wordwise", size), &data, |b, data| {
+            b.iter(|| checksum_wordwise(black_box(data)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_checksum);
+criterion_main!(benches);
@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers shared by the benchmarks: a synthetic AXP image and a device responder.
+
+use std::io::Write;
+
+use axdl::frame::AxdlFrame;
+use axdl::partition::{Block, Image, ImageType, Partition, PartitionTable, Project};
+
+/// Builds an in-memory AXP image with FDL1, FDL2 and a single rootfs image of `rootfs_size` bytes.
+pub fn build_axp(rootfs_size: usize, method: zip::CompressionMethod) -> Vec<u8> {
+    let mut partition_table = PartitionTable::new(1, 2);
+    partition_table.add_partition(Partition::new("rootfs".into(), 0, rootfs_size as u64));
+    let mut project = Project::new(
+        "BENCH".into(),
+        "BENCH".into(),
+        "1".into(),
+        2,
+        partition_table,
+    );
+    project.add_image(Image::new(
+        "FDL1".into(),
+        ImageType::Fdl1,
+        Block::Absolute(0x3000),
+        Some("fdl1.bin".into()),
+    ));
+    project.add_image(Image::new(
+        "FDL2".into(),
+        ImageType::Fdl2,
+        Block::Absolute(0x5c00_0000),
+        Some("fdl2.bin".into()),
+    ));
+    project.add_image(Image::new(
+        "ROOTFS".into(),
+        ImageType::Code,
+        Block::Partition("rootfs".into()),
+        Some("rootfs.ext4".into()),
+    ));
+
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default().compression_method(method);
+    writer.start_file("bench.xml", options).unwrap();
+    writer
+        .write_all(axdl::partition::serialize::to_string(&project).as_bytes())
+        .unwrap();
+    for (name, size) in [
+        ("fdl1.bin", 16 * 1024),
+        ("fdl2.bin", 256 * 1024),
+        ("rootfs.ext4", rootfs_size),
+    ] {
+        writer.start_file(name, options).unwrap();
+        let data = (0..size).map(|i| (i / 4096) as u8).collect::<Vec<_>>();
+        writer.write_all(&data).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+fn version_frame(version: &str) -> Vec<u8> {
+    AxdlFrame::new(0x0081)
+        .with_payload(version)
+        .build()
+        .unwrap()
+}
+
+/// Returns a responder which reports romcode, then fdl1 on handshakes and acknowledges everything else.
+pub fn responder() -> impl FnMut(&[u8]) -> Vec<Vec<u8>> + Send + 'static {
+    let ack = AxdlFrame::new(0x0080).build().unwrap();
+    let mut handshakes = 0;
+    move |packet: &[u8]| {
+        if packet == [0x3c, 0x3c, 0x3c] {
+            handshakes += 1;
+            let version = if handshakes == 1 {
+                "romcode v1.0;raw"
+            } else {
+                "fdl1 v1.0;raw"
+            };
+            return vec![version_frame(version)];
+        }
+        // Commands and raw block data are both acknowledged.
+        vec![ack.clone()]
+    }
+}
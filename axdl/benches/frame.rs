@@ -0,0 +1,48 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use axdl::frame::{AxdlFrame, AxdlFrameView};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+fn bench_frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    for size in [12usize, 88, 48000] {
+        let payload = (0..size).map(|i| i as u8).collect::<Vec<_>>();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("encode", size), &payload, |b, payload| {
+            b.iter(|| {
+                AxdlFrame::new(0x0002)
+                    .with_payload(black_box(payload.as_slice()))
+                    .build()
+                    .unwrap()
+            })
+        });
+        let frame = AxdlFrame::new(0x0002)
+            .with_payload(payload)
+            .build()
+            .unwrap();
+        group.bench_with_input(BenchmarkId::new("decode", size), &frame, |b, frame| {
+            b.iter(|| {
+                let view = AxdlFrameView::new(black_box(frame));
+                assert!(view.is_valid());
+                view.payload().map(|payload| payload.len())
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_frame);
+criterion_main!(benches);
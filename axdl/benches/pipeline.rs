@@ -0,0 +1,75 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use std::io::Read;
+
+use axdl::progress::NoProgress;
+use axdl::transport::{mock::MockDevice, DynDevice};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const ROOTFS_SIZE: usize = 8 * 1024 * 1024;
+
+fn bench_zip_streaming(c: &mut Criterion) {
+    let mut group = c.benchmark_group("zip_streaming");
+    group.throughput(Throughput::Bytes(ROOTFS_SIZE as u64));
+    for (name, method) in [
+        ("stored", zip::CompressionMethod::Stored),
+        ("deflated", zip::CompressionMethod::Deflated),
+    ] {
+        let image = common::build_axp(ROOTFS_SIZE, method);
+        group.bench_with_input(BenchmarkId::from_parameter(name), &image, |b, image| {
+            let mut buffer = vec![0u8; 48000];
+            b.iter(|| {
+                let mut archive = zip::ZipArchive::new(std::io::Cursor::new(image)).unwrap();
+                let mut entry = archive.by_name("rootfs.ext4").unwrap();
+                let mut total = 0;
+                loop {
+                    let bytes_read = entry.read(&mut buffer).unwrap();
+                    if bytes_read == 0 {
+                        break total;
+                    }
+                    total += bytes_read;
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_download_image(c: &mut Criterion) {
+    let mut group = c.benchmark_group("download_image");
+    group.throughput(Throughput::Bytes(ROOTFS_SIZE as u64));
+    group.sample_size(10);
+    let image = common::build_axp(ROOTFS_SIZE, zip::CompressionMethod::Deflated);
+    group.bench_function("mock_device", |b| {
+        b.iter(|| {
+            let mut device: DynDevice = Box::new(MockDevice::new(common::responder()));
+            let config = axdl::DownloadConfig::default();
+            axdl::download_image(
+                &mut std::io::Cursor::new(&image),
+                &mut device,
+                &config,
+                &mut NoProgress,
+            )
+            .unwrap();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_zip_streaming, bench_download_image);
+criterion_main!(benches);
@@ -0,0 +1,67 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Downloads an AXP image to an emulated device over the mock transport.
+//!
+//! With real hardware, open the device with [`axdl::transport::NativeTransport`] instead of the
+//! emulator; the rest is the same.
+
+use axdl::{download_image, partition::ImageType, DownloadConfig, DownloadProgress};
+use axdl_emulator::{
+    axp::{pattern, AxpBuilder},
+    Emulator,
+};
+
+/// Prints the progress reported by the download.
+struct PrintProgress;
+
+impl DownloadProgress for PrintProgress {
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+    fn report_progress(&mut self, description: &str, progress: Option<f32>) {
+        match progress {
+            Some(progress) => println!("{} ({:.0} %)", description, progress * 100.0),
+            None => println!("{}", description),
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let image = AxpBuilder::new(2)
+        .partition("spl", 0x40000)
+        .partition("rootfs", 0x400000)
+        .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(1000, 1))
+        .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(2000, 2))
+        .code("SPL", "spl", pattern(1000, 3))
+        .code("ROOTFS", "rootfs", pattern(100_000, 4))
+        .build();
+
+    let emulator = Emulator::new(2);
+    let mut device = emulator.dyn_device();
+    let config = DownloadConfig {
+        verify: true,
+        ..Default::default()
+    };
+    let report = download_image(
+        &mut std::io::Cursor::new(image),
+        &mut device,
+        &config,
+        &mut PrintProgress,
+    )?;
+    println!("{}", report);
+    assert!(report.is_success());
+    Ok(())
+}
@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lists the partitions and images in an AXP image without a device.
+//!
+//! ```text
+//! cargo run --example inspect -- /path/to/image.axp
+//! ```
+//!
+//! Without an argument, a small image built with the emulator is inspected.
+
+use axdl::partition::ImageType;
+use axdl_emulator::axp::{pattern, AxpBuilder};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let project = match std::env::args_os().nth(1) {
+        Some(path) => axdl::read_project(&mut std::fs::File::open(path)?)?,
+        None => {
+            let image = AxpBuilder::new(2)
+                .partition("spl", 0x40000)
+                .partition("rootfs", 0x400000)
+                .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(1000, 1))
+                .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(2000, 2))
+                .code("SPL", "spl", pattern(1000, 3))
+                .code("ROOTFS", "rootfs", pattern(4000, 4))
+                .build();
+            axdl::read_project(&mut std::io::Cursor::new(image))?
+        }
+    };
+
+    println!(
+        "{} ({}) version {}, {}-level FDL",
+        project.name(),
+        project.alias(),
+        project.version(),
+        if project.is2_level_fdl() { 2 } else { 1 }
+    );
+    println!("Partitions:");
+    for partition in project.partition_table().partitions() {
+        println!("  {:<16} size {}", partition.name(), partition.size());
+    }
+    println!("Images:");
+    for image in project.images() {
+        println!(
+            "  {:<16} {:<10} {:?} {}",
+            image.name(),
+            image.r#type().as_str(),
+            image.block(),
+            image.file().unwrap_or("-")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reads a partition back from a device running FDL2 and compares it with the image contents.
+
+use axdl::{
+    communication::{Session, SessionState, DEFAULT_IMAGE_CHUNK_SIZE},
+    download_image,
+    partition::ImageType,
+    progress::NoProgress,
+    DownloadConfig,
+};
+use axdl_emulator::{
+    axp::{pattern, AxpBuilder},
+    Emulator,
+};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let rootfs = pattern(100_000, 4);
+    let image = AxpBuilder::new(2)
+        .partition("rootfs", 0x400000)
+        .fdl("FDL1", ImageType::Fdl1, 0x3000, pattern(1000, 1))
+        .fdl("FDL2", ImageType::Fdl2, 0x5c00_0000, pattern(2000, 2))
+        .code("ROOTFS", "rootfs", rootfs.clone())
+        .build();
+
+    // Bring the emulated device up to FDL2 by downloading the image.
+    let emulator = Emulator::new(2);
+    let mut device = emulator.dyn_device();
+    download_image(
+        &mut std::io::Cursor::new(image),
+        &mut device,
+        &DownloadConfig::default(),
+        &mut NoProgress,
+    )?;
+
+    // The download left the device running FDL2, so no handshake is expected.
+    let mut session = Session::new(&mut device).with_state(SessionState::Fdl);
+    let mut data = Vec::new();
+    session.read_partition_id(
+        "rootfs",
+        rootfs.len() as u64,
+        DEFAULT_IMAGE_CHUNK_SIZE,
+        &mut data,
+    )?;
+    println!(
+        "Read {} bytes from rootfs: {}",
+        data.len(),
+        if data == rootfs { "matches" } else { "differs" }
+    );
+    assert_eq!(data, rootfs);
+    Ok(())
+}
@@ -0,0 +1,223 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cancellation of a download from another thread or task, without implementing
+//! [`crate::DownloadProgress`]. Unlike [`crate::DownloadProgress::is_cancelled`], a token also
+//! interrupts waits for the device to respond.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use crate::{time::Deadline, AxdlError, DownloadProgress};
+
+/// How often blocking reads check the token while waiting for the device.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    /// Tasks waiting in [`CancellationToken::cancelled`], by the id of their future.
+    #[cfg(feature = "async")]
+    wakers: std::sync::Mutex<Vec<(u64, std::task::Waker)>>,
+    #[cfg(feature = "async")]
+    next_id: std::sync::atomic::AtomicU64,
+}
+
+/// Handle to cancel a download. Clones share the same state.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<Inner>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the downloads using this token. They fail with [`AxdlError::UserCancelled`].
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        #[cfg(feature = "async")]
+        for (_, waker) in self.wakers().drain(..) {
+            waker.wake();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fails with [`AxdlError::UserCancelled`] once the token is cancelled.
+    pub(crate) fn check(&self) -> Result<(), AxdlError> {
+        if self.is_cancelled() {
+            Err(AxdlError::UserCancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "async")]
+    fn wakers(&self) -> std::sync::MutexGuard<'_, Vec<(u64, std::task::Waker)>> {
+        self.0.wakers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns a future which completes once the token is cancelled.
+    #[cfg(feature = "async")]
+    pub fn cancelled(&self) -> Cancelled<'_> {
+        Cancelled {
+            token: self,
+            id: self.0.next_id.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Runs `future`, failing with [`AxdlError::UserCancelled`] if the token is cancelled first.
+    #[cfg(feature = "async")]
+    pub(crate) async fn run<T>(
+        &self,
+        future: impl std::future::Future<Output = Result<T, AxdlError>>,
+    ) -> Result<T, AxdlError> {
+        use futures_util::future::{select, Either};
+        let future = std::pin::pin!(future);
+        match select(future, self.cancelled()).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(AxdlError::UserCancelled),
+        }
+    }
+}
+
+impl std::fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Future returned by [`CancellationToken::cancelled`].
+#[cfg(feature = "async")]
+pub struct Cancelled<'a> {
+    token: &'a CancellationToken,
+    id: u64,
+}
+
+#[cfg(feature = "async")]
+impl std::future::Future for Cancelled<'_> {
+    type Output = ();
+    fn poll(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<()> {
+        if self.token.is_cancelled() {
+            return std::task::Poll::Ready(());
+        }
+        let mut wakers = self.token.wakers();
+        wakers.retain(|(id, _)| *id != self.id);
+        wakers.push((self.id, cx.waker().clone()));
+        drop(wakers);
+        // The token may have been cancelled before the waker was registered.
+        if self.token.is_cancelled() {
+            std::task::Poll::Ready(())
+        } else {
+            std::task::Poll::Pending
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for Cancelled<'_> {
+    fn drop(&mut self) {
+        self.token.wakers().retain(|(id, _)| *id != self.id);
+    }
+}
+
+/// Progress which also reports the download as cancelled once `token` is cancelled, or once
+/// its time limit has passed.
+pub(crate) struct WithToken<'p, P> {
+    progress: &'p mut P,
+    token: Option<CancellationToken>,
+    deadline: Option<(Deadline, Duration)>,
+}
+
+impl<'p, P: DownloadProgress> WithToken<'p, P> {
+    pub(crate) fn new(progress: &'p mut P, token: Option<CancellationToken>) -> Self {
+        Self {
+            progress,
+            token,
+            deadline: None,
+        }
+    }
+
+    /// Stops the download with [`AxdlError::DeadlineExceeded`] once `max_duration` has passed
+    /// from now.
+    pub(crate) fn with_max_duration(mut self, max_duration: Option<Duration>) -> Self {
+        self.deadline = max_duration.map(|limit| (Deadline::after(limit), limit));
+        self
+    }
+
+    fn is_expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|(deadline, _)| deadline.remaining().is_zero())
+    }
+}
+
+impl<P: DownloadProgress> DownloadProgress for WithToken<'_, P> {
+    fn is_cancelled(&self) -> bool {
+        self.token
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+            || self.progress.is_cancelled()
+            || self.is_expired()
+    }
+    fn check_is_cancelled(&self) -> Result<(), AxdlError> {
+        match self.deadline {
+            Some((_, limit)) if self.is_expired() => Err(AxdlError::DeadlineExceeded(limit)),
+            _ if self.is_cancelled() => Err(AxdlError::UserCancelled),
+            _ => Ok(()),
+        }
+    }
+    fn report_progress(&mut self, description: &str, progress: Option<f32>) {
+        self.progress.report_progress(description, progress);
+    }
+    fn report_event(&mut self, event: &crate::progress::ProgressEvent<'_>) {
+        self.progress.report_event(event);
+    }
+    fn report_flushing(&mut self, image_name: &str) {
+        self.progress.report_flushing(image_name);
+    }
+    fn report_stage(&mut self, event: &crate::progress::StageEvent<'_>) {
+        self.progress.report_stage(event);
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cancel_wakes_waiting_task() {
+        let token = CancellationToken::new();
+        let waiting = token.clone();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            token.cancel();
+        });
+        let pending = std::future::pending::<Result<(), AxdlError>>();
+        let result = crate::time::block_on(waiting.run(pending));
+        assert!(matches!(result, Err(AxdlError::UserCancelled)));
+        assert!(waiting.wakers().is_empty());
+        canceller.join().unwrap();
+    }
+}
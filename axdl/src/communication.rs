@@ -15,22 +15,128 @@
 
 use std::time::Duration;
 
+use crate::context::ResultExt;
+use crate::frame::commands;
+use crate::progress::{Phase, ReportPhase};
 use crate::AxdlError;
 
-const HANDSHAKE_REQUEST: [u8; 3] = [0x3c, 0x3c, 0x3c];
-pub const TIMEOUT: Duration = Duration::from_secs(10*60);
-pub const TIMEOUT_WRITE_IMAGE: Duration = TIMEOUT;
+pub mod vendor;
+
+/// Handshake request sent unless [`Session::with_handshake_request`] sets another one.
+pub const DEFAULT_HANDSHAKE_REQUEST: [u8; 3] = [commands::HANDSHAKE; 3];
+const START_RAM_DOWNLOAD_FRAME: [u8; crate::frame::MINIMUM_LENGTH] =
+    crate::frame::fixed_frame(commands::START_RAM_DOWNLOAD, &[]);
+const END_PARTITION_FRAME: [u8; crate::frame::MINIMUM_LENGTH] =
+    crate::frame::fixed_frame(commands::END_PARTITION, &[]);
+const END_RAM_DOWNLOAD_FRAME: [u8; crate::frame::MINIMUM_LENGTH] =
+    crate::frame::fixed_frame(commands::END_RAM_DOWNLOAD, &[]);
+const END_READ_PARTITION_FRAME: [u8; crate::frame::MINIMUM_LENGTH] =
+    crate::frame::fixed_frame(commands::END_READ_PARTITION, &[]);
+
+/// Builds the start block frame on the stack since it is sent for every block.
+fn start_block_frame(block_size: u16) -> [u8; crate::frame::MINIMUM_LENGTH + 12] {
+    let mut payload = [0u8; 12];
+    payload[0..2].copy_from_slice(&block_size.to_le_bytes());
+    crate::frame::fixed_frame(commands::START_BLOCK, &payload)
+}
 
-pub fn wait_handshake(
-    device: &mut crate::transport::DynDevice,
-    expected_handshake: &str,
-) -> Result<(), AxdlError> {
-    device.write_timeout(&HANDSHAKE_REQUEST, TIMEOUT)?;
-    let mut buf = [0u8; 64];
-    let length = device.read_timeout(&mut buf, TIMEOUT)?;
+fn start_partition_absolute_32_frame(
+    start_address: u32,
+    partition_length: u32,
+) -> [u8; crate::frame::MINIMUM_LENGTH + 8] {
+    let mut payload = [0u8; 8];
+    payload[0..4].copy_from_slice(&start_address.to_le_bytes());
+    payload[4..8].copy_from_slice(&partition_length.to_le_bytes());
+    crate::frame::fixed_frame(commands::START_PARTITION, &payload)
+}
+
+fn start_partition_absolute_frame(
+    start_address: u64,
+    partition_length: u64,
+) -> [u8; crate::frame::MINIMUM_LENGTH + 16] {
+    let mut payload = [0u8; 16];
+    payload[0..8].copy_from_slice(&start_address.to_le_bytes());
+    payload[8..16].copy_from_slice(&partition_length.to_le_bytes());
+    crate::frame::fixed_frame(commands::START_PARTITION, &payload)
+}
+
+/// Length of the partition name field, which holds up to 36 UTF-16 units.
+const PARTITION_NAME_LENGTH: usize = 72;
+
+fn partition_id_payload(partition_name: &str, total_length: u64) -> Result<[u8; 88], AxdlError> {
+    let mut payload = [0u8; 88];
+    let partition_name_bytes = partition_name
+        .encode_utf16()
+        .flat_map(|c| c.to_le_bytes())
+        .collect::<Vec<_>>();
+    if partition_name_bytes.len() > PARTITION_NAME_LENGTH {
+        return Err(AxdlError::PartitionNameTooLong(partition_name.to_string()));
+    }
+    payload[0..partition_name_bytes.len()].copy_from_slice(&partition_name_bytes);
+    payload[72..80].copy_from_slice(&total_length.to_le_bytes());
+    Ok(payload)
+}
+
+fn start_partition_id_frame(
+    partition_name: &str,
+    total_length: u64,
+) -> Result<[u8; crate::frame::MINIMUM_LENGTH + 88], AxdlError> {
+    let payload = partition_id_payload(partition_name, total_length)?;
+    Ok(crate::frame::fixed_frame(
+        commands::START_PARTITION,
+        &payload,
+    ))
+}
+
+fn start_read_partition_frame(
+    partition_name: &str,
+    total_length: u64,
+) -> Result<[u8; crate::frame::MINIMUM_LENGTH + 88], AxdlError> {
+    let payload = partition_id_payload(partition_name, total_length)?;
+    Ok(crate::frame::fixed_frame(
+        commands::START_READ_PARTITION,
+        &payload,
+    ))
+}
+
+fn read_block_frame(offset: u64, block_size: u32) -> [u8; crate::frame::MINIMUM_LENGTH + 12] {
+    let mut payload = [0u8; 12];
+    payload[0..4].copy_from_slice(&block_size.to_le_bytes());
+    payload[4..12].copy_from_slice(&offset.to_le_bytes());
+    crate::frame::fixed_frame(commands::READ_BLOCK, &payload)
+}
+
+/// Builds the set partition table frame, failing if it exceeds `max_frame_size`.
+///
+/// The command replaces the whole table, so a table too large for one frame cannot be split.
+pub(crate) fn set_partition_table_frame(
+    partition_table: &crate::partition::PartitionTable,
+    max_frame_size: usize,
+) -> Result<Vec<u8>, AxdlError> {
+    let frame = crate::frame::AxdlFrame::new(commands::SET_PARTITION_TABLE)
+        .with_payload(partition_table.to_bytes()?);
+    let length = crate::frame::MINIMUM_LENGTH + frame.payload().len();
+    if length > max_frame_size {
+        return Err(AxdlError::FrameTooLarge {
+            length,
+            max: max_frame_size,
+        });
+    }
+    Ok(frame.build()?)
+}
+
+/// Logs a frame about to be sent. Data blocks are summarized to keep the trace readable.
+fn trace_request(request: &Request, packet: &[u8]) {
+    match request {
+        Request::Data => tracing::debug!("sent: {} data bytes", packet.len()),
+        _ => tracing::debug!("sent: {:02X?}", packet),
+    }
+}
 
-    tracing::debug!("received: {:02X?}", &buf[..length]);
-    let view = crate::frame::AxdlFrameView::new(&buf[..length]);
+/// Validates a received frame.
+fn check_frame(data: &[u8]) -> Result<(), AxdlError> {
+    tracing::debug!("received: {:02X?}", data);
+    let view = crate::frame::AxdlFrameView::new(data);
     tracing::debug!(
         "view: {}, checksum={:04X}",
         view,
@@ -39,6 +145,128 @@ pub fn wait_handshake(
     if !view.is_valid() {
         return Err(AxdlError::InvalidFrame);
     }
+    Ok(())
+}
+
+/// Returns the length of the frame starting with `received` if the rest of it is still to be
+/// read, as a response longer than one bulk transfer arrives in several reads. Frames which do
+/// not fit in `capacity` are left to fail the checksum.
+fn incomplete_frame_length(received: &[u8], capacity: usize) -> Option<usize> {
+    crate::frame::AxdlFrameView::new(received)
+        .frame_length()
+        .filter(|&length| length > received.len() && length <= capacity)
+}
+
+/// Checks the read of the rest of a frame, of which `received` bytes out of `frame_length`
+/// arrived so far.
+fn check_continuation(
+    result: Result<usize, AxdlError>,
+    received: usize,
+    frame_length: usize,
+) -> Result<usize, AxdlError> {
+    match result {
+        Err(AxdlError::DeviceTimeout) => {
+            tracing::warn!(
+                "timed out after {} of {} bytes of a frame",
+                received,
+                frame_length
+            );
+            Err(AxdlError::InvalidFrame)
+        }
+        result => result,
+    }
+}
+
+fn check_read_block(response: &[u8], block_size: u32) -> Result<&[u8], AxdlError> {
+    let view = crate::frame::AxdlFrameView::new(response);
+    match view.command_response() {
+        Some(commands::READ_BLOCK_DATA) => {}
+        Some(response) => return Err(AxdlError::UnexpectedResponse(response)),
+        None => return Err(AxdlError::InvalidFrame),
+    }
+    let data = view.payload().ok_or(AxdlError::NoPayload)?;
+    if data.is_empty() || data.len() > block_size as usize {
+        return Err(AxdlError::InvalidFrame);
+    }
+    Ok(data)
+}
+
+fn check_ack(response: &[u8]) -> Result<(), AxdlError> {
+    let response_view = crate::frame::AxdlFrameView::new(response);
+    match response_view.command_response() {
+        Some(commands::ACK) => Ok(()),
+        Some(response) => Err(AxdlError::UnexpectedResponse(response)),
+        None => Err(AxdlError::InvalidFrame),
+    }
+}
+
+/// Handshake reported by a boot stage, e.g. `romcode v1.0;raw`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HandshakeInfo {
+    /// The handshake as received.
+    pub raw: String,
+    /// First word, naming the boot stage, e.g. `romcode`.
+    pub stage: String,
+    /// Version following the stage name without its `v` prefix, e.g. `1.0`.
+    pub version: Option<String>,
+    /// Options following `;`, e.g. `raw`.
+    pub options: Vec<String>,
+}
+
+impl HandshakeInfo {
+    pub fn parse(handshake: &str) -> Self {
+        let mut fields = handshake.split(';');
+        let mut words = fields.next().unwrap_or_default().split_whitespace();
+        let stage = words.next().unwrap_or_default().to_string();
+        let version = words
+            .next()
+            .map(|version| version.strip_prefix('v').unwrap_or(version).to_string());
+        Self {
+            raw: handshake.to_string(),
+            stage,
+            version,
+            options: fields
+                .map(str::trim)
+                .filter(|option| !option.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+
+    /// Returns the numeric components of the version, e.g. `[1, 0]` for `1.0`.
+    pub fn version_numbers(&self) -> Option<Vec<u32>> {
+        self.version
+            .as_ref()?
+            .split('.')
+            .map(|number| number.parse().ok())
+            .collect()
+    }
+
+    /// Returns whether the stage reports at least version `minimum`, e.g. `&[1, 1]`.
+    ///
+    /// A stage which reports no version is treated as older than any version.
+    pub fn version_at_least(&self, minimum: &[u32]) -> bool {
+        self.version_numbers()
+            .is_some_and(|version| version.as_slice() >= minimum)
+    }
+
+    pub fn has_option(&self, option: &str) -> bool {
+        self.options.iter().any(|o| o == option)
+    }
+}
+
+impl std::fmt::Display for HandshakeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+/// Checks that the handshake contains one of `expected`.
+fn parse_handshake<S: AsRef<str>>(
+    response: &[u8],
+    expected: &[S],
+) -> Result<HandshakeInfo, AxdlError> {
+    let view = crate::frame::AxdlFrameView::new(response);
     let handshake = view
         .payload()
         .map(|payload| {
@@ -49,389 +277,2335 @@ pub fn wait_handshake(
         .transpose()?
         .ok_or(AxdlError::NoPayload)?;
 
-    tracing::debug!("handshake: {}", handshake);
-    if !handshake.contains(expected_handshake) {
-        return Err(AxdlError::UnexpectedHandshake(handshake));
-    }
-    Ok(())
-}
+    tracing::debug!("handshake: {}", handshake);
+    if !expected
+        .iter()
+        .any(|pattern| handshake.contains(pattern.as_ref()))
+    {
+        let expected = expected.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        tracing::info!(
+            "unexpected handshake {:?}, expected one of {:?}",
+            handshake,
+            expected
+        );
+        return Err(AxdlError::UnexpectedHandshake(handshake));
+    }
+    Ok(HandshakeInfo::parse(&handshake))
+}
+
+/// Strings accepted in the handshake of each boot stage.
+///
+/// A handshake matches if it contains any of the patterns of its stage. Firmware variants which
+/// report a different string can be supported by adding a pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakePatterns {
+    pub romcode: Vec<String>,
+    pub fdl1: Vec<String>,
+    pub fdl2: Vec<String>,
+}
+
+impl Default for HandshakePatterns {
+    fn default() -> Self {
+        Self {
+            romcode: vec!["romcode".into()],
+            fdl1: vec!["fdl1".into()],
+            fdl2: vec!["fdl2".into()],
+        }
+    }
+}
+
+impl HandshakePatterns {
+    /// Returns the patterns of the stage named `romcode`, `fdl1` or `fdl2`.
+    pub fn stage_mut(&mut self, stage: &str) -> Option<&mut Vec<String>> {
+        match stage {
+            "romcode" => Some(&mut self.romcode),
+            "fdl1" => Some(&mut self.fdl1),
+            "fdl2" => Some(&mut self.fdl2),
+            _ => None,
+        }
+    }
+}
+
+pub const TIMEOUT: Duration = Duration::from_secs(10 * 60);
+pub const TIMEOUT_WRITE_IMAGE: Duration = TIMEOUT;
+
+/// Timeouts used by a [`Session`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Timeout of commands and handshakes.
+    pub command: Duration,
+    /// Timeout of block data transfers.
+    pub data: Duration,
+    /// Timeout of the end partition command after a partition image, which waits for the flash write.
+    pub end_partition: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            command: TIMEOUT,
+            data: TIMEOUT_WRITE_IMAGE,
+            end_partition: Duration::from_secs(60),
+        }
+    }
+}
+
+/// How many times an operation is retried when the device does not answer properly.
+///
+/// Nothing is retried by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries of the handshake request.
+    pub handshake: u32,
+    /// Retries of a block, resending the start block command and its data.
+    pub block: u32,
+}
+
+/// Pauses inserted into the command sequence, for firmware revisions which fail when driven at
+/// full speed.
+///
+/// No pauses by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pacing {
+    /// Pause before each command other than the block transfers.
+    pub command_delay: Duration,
+    /// Pause after ending a RAM download, while the downloaded FDL starts up.
+    pub fdl_start_delay: Duration,
+}
+
+impl Pacing {
+    /// Slower pace, closer to the one of the vendor tool.
+    pub const VENDOR_COMPAT: Self = Self {
+        command_delay: Duration::from_millis(10),
+        fdl_start_delay: Duration::from_millis(500),
+    };
+}
+
+/// Protocol state of a [`Session`], tracked so that a command the device would not accept in
+/// this state fails with [`AxdlError::InvalidState`] before it is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    /// No handshake received yet.
+    Handshake,
+    /// The romcode is running.
+    Romcode,
+    /// A RAM download, i.e. of an FDL, has been started.
+    RamDownload,
+    /// A downloaded FDL is running.
+    Fdl,
+}
+
+/// Partition transfer in progress within a [`SessionState`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transfer {
+    None,
+    Write,
+    Read,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Handshake,
+    StartRamDownload,
+    StartPartition,
+    Block,
+    EndPartition,
+    EndRamDownload,
+    SetPartitionTable,
+    StartReadPartition,
+    ReadBlock,
+    EndReadPartition,
+    Vendor,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Guard {
+    state: SessionState,
+    transfer: Transfer,
+    /// Whether steps are checked, off for the free functions which send a single command.
+    checked: bool,
+}
+
+impl Guard {
+    fn new(state: SessionState) -> Self {
+        Self {
+            state,
+            transfer: Transfer::None,
+            checked: true,
+        }
+    }
+
+    fn unchecked() -> Self {
+        Self {
+            checked: false,
+            ..Self::new(SessionState::Handshake)
+        }
+    }
+
+    fn check(&self, step: Step) -> Result<(), AxdlError> {
+        if !self.checked {
+            return Ok(());
+        }
+        use SessionState::*;
+        let idle = self.transfer == Transfer::None;
+        let allowed = match step {
+            Step::Handshake => idle,
+            Step::StartRamDownload => idle && matches!(self.state, Romcode | Fdl),
+            Step::StartPartition => idle && matches!(self.state, RamDownload | Fdl),
+            Step::Block | Step::EndPartition => self.transfer == Transfer::Write,
+            Step::EndRamDownload => idle && self.state == RamDownload,
+            Step::SetPartitionTable | Step::StartReadPartition | Step::Vendor => {
+                idle && self.state == Fdl
+            }
+            Step::ReadBlock | Step::EndReadPartition => self.transfer == Transfer::Read,
+        };
+        if allowed {
+            return Ok(());
+        }
+        let transfer = match self.transfer {
+            Transfer::None => "",
+            Transfer::Write => " with a partition open for writing",
+            Transfer::Read => " with a partition open for reading",
+        };
+        Err(AxdlError::InvalidState(format!(
+            "{:?} is not allowed in state {:?}{}",
+            step, self.state, transfer
+        )))
+    }
+
+    /// Moves to the state after `step` succeeded.
+    fn advance(&mut self, step: Step) {
+        match step {
+            Step::Handshake if self.state == SessionState::Handshake => {
+                self.state = SessionState::Romcode
+            }
+            Step::StartRamDownload => self.state = SessionState::RamDownload,
+            Step::StartPartition => self.transfer = Transfer::Write,
+            Step::StartReadPartition => self.transfer = Transfer::Read,
+            Step::EndPartition | Step::EndReadPartition => self.transfer = Transfer::None,
+            // FDL2 of a two level FDL is started without a handshake.
+            Step::EndRamDownload => self.state = SessionState::Fdl,
+            _ => {}
+        }
+    }
+}
+
+fn is_retryable(error: &AxdlError) -> bool {
+    matches!(
+        error,
+        AxdlError::DeviceTimeout | AxdlError::InvalidFrame | AxdlError::UnexpectedResponse(_)
+    )
+}
+
+/// Largest block the start block command can describe; its size field is 16 bits wide, see
+/// [`crate::frame::commands::START_BLOCK`].
+///
+/// No FDL2 capability for larger blocks or a streaming mode is known, so chunk sizes are
+/// validated against this limit instead of being truncated.
+pub const MAX_BLOCK_SIZE: usize = u16::MAX as usize;
+/// Default block size for partition images.
+pub const DEFAULT_IMAGE_CHUNK_SIZE: usize = 48000;
+
+/// Rounds `chunk_size` down to a multiple of the endpoint `max_packet_size`, so that every
+/// block fills whole packets. Chunks smaller than a packet are left as they are.
+///
+/// ```
+/// use axdl::communication::tune_chunk_size;
+///
+/// assert_eq!(tune_chunk_size(48000, Some(512)), 47616);
+/// assert_eq!(tune_chunk_size(48000, Some(64)), 48000);
+/// assert_eq!(tune_chunk_size(48000, None), 48000);
+/// assert_eq!(tune_chunk_size(100, Some(512)), 100);
+/// ```
+pub fn tune_chunk_size(chunk_size: usize, max_packet_size: Option<usize>) -> usize {
+    match max_packet_size {
+        Some(packet) if packet > 0 && chunk_size >= packet => chunk_size - chunk_size % packet,
+        _ => chunk_size,
+    }
+}
+
+/// Default size of the receive buffer, which bounds the largest frame that can be received.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 65536;
+
+/// Checks that `chunk_size` can be sent as a single block.
+pub fn validate_block_size(chunk_size: usize) -> Result<(), AxdlError> {
+    if chunk_size == 0 || chunk_size > MAX_BLOCK_SIZE {
+        return Err(AxdlError::InvalidConfig(format!(
+            "chunk size {} must be between 1 and {} bytes",
+            chunk_size, MAX_BLOCK_SIZE
+        )));
+    }
+    Ok(())
+}
+
+/// Request which a response answers, as recorded in a [`Deviation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    Handshake,
+    Command(u16),
+    /// Raw data following a start block command.
+    Data,
+}
+
+impl Request {
+    fn of_frame(frame: &[u8]) -> Self {
+        crate::frame::AxdlFrameView::new(frame)
+            .command_response()
+            .map(Self::Command)
+            .unwrap_or(Self::Data)
+    }
+}
+
+/// Response which does not match the expected protocol behavior, found in strict mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deviation {
+    pub request: Request,
+    pub response: Vec<u8>,
+    pub description: String,
+}
+
+/// Checks a valid response frame beyond its status and records anything unexpected.
+fn check_conformance(
+    request: Request,
+    expected_response: u16,
+    response: &[u8],
+    deviations: &mut Vec<Deviation>,
+) {
+    let view = crate::frame::AxdlFrameView::new(response);
+    let mut found = Vec::new();
+    if let Some(length) = view.length() {
+        let frame_length = crate::frame::MINIMUM_LENGTH + length as usize;
+        if frame_length != response.len() {
+            found.push(format!(
+                "frame length {} does not match the received length {}",
+                frame_length,
+                response.len()
+            ));
+        }
+    }
+    match view.command_response() {
+        Some(code) if code != expected_response => found.push(format!(
+            "response {:#06X} instead of {:#06X}",
+            code, expected_response
+        )),
+        _ => {}
+    }
+    let payload_length = view.payload().map(|payload| payload.len()).unwrap_or(0);
+    if expected_response == commands::ACK && payload_length != 0 {
+        found.push(format!(
+            "acknowledge carries {} bytes of payload",
+            payload_length
+        ));
+    }
+    if expected_response == commands::VERSION && payload_length == 0 {
+        found.push("version response carries no payload".to_string());
+    }
+    for description in found {
+        tracing::warn!("protocol deviation on {:?}: {}", request, description);
+        deviations.push(Deviation {
+            request,
+            response: response.to_vec(),
+            description,
+        });
+    }
+}
+
+/// Copy of an acknowledge which some firmware sends twice, looked for before the next request
+/// so that it is not taken for the response to that request.
+#[derive(Debug, Default)]
+struct DuplicateAcks {
+    window: Option<Duration>,
+    /// The last acknowledge, which is reused to avoid allocating for every block.
+    ack: Vec<u8>,
+    /// Request answered by `ack` and when, until the next request is sent.
+    received: Option<(Request, crate::time::Stopwatch)>,
+    /// Length of a frame in the receive buffer which was read while looking for a copy.
+    pending: Option<usize>,
+}
+
+/// Protocol session over a device.
+///
+/// The session owns the receive buffer, so receiving responses does not allocate
+/// for every command during the block loop.
+///
+/// In strict mode every response is also checked against the expected length and
+/// response code. Deviations are logged and kept in [`Session::deviations`]; they do not abort the session.
+pub struct Session<'a> {
+    device: &'a mut crate::transport::DynDevice,
+    rx_buffer: Vec<u8>,
+    strict: bool,
+    deviations: Vec<Deviation>,
+    timeouts: Timeouts,
+    retry: RetryPolicy,
+    rate_limit: Option<u64>,
+    pacing: Pacing,
+    handshake_request: Vec<u8>,
+    cancellation: Option<crate::cancel::CancellationToken>,
+    guard: Guard,
+    duplicate_acks: DuplicateAcks,
+    coalesce_writes: bool,
+    zero_length_packets: bool,
+    /// Start block frame and block sent in one write, reused for every block.
+    tx_buffer: Vec<u8>,
+}
+
+impl<'a> Session<'a> {
+    pub fn new(device: &'a mut crate::transport::DynDevice) -> Self {
+        Self::with_max_frame_size(device, DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    /// Session which does not check the protocol state, for the free functions which send a
+    /// single command to a device in a state the session does not know.
+    fn unchecked(device: &'a mut crate::transport::DynDevice) -> Self {
+        let mut session = Self::new(device);
+        session.guard = Guard::unchecked();
+        session
+    }
+
+    pub fn with_max_frame_size(
+        device: &'a mut crate::transport::DynDevice,
+        max_frame_size: usize,
+    ) -> Self {
+        Self {
+            device,
+            rx_buffer: vec![0u8; max_frame_size.max(crate::frame::MINIMUM_LENGTH)],
+            strict: false,
+            deviations: Vec::new(),
+            timeouts: Timeouts::default(),
+            retry: RetryPolicy::default(),
+            rate_limit: None,
+            pacing: Pacing::default(),
+            handshake_request: DEFAULT_HANDSHAKE_REQUEST.to_vec(),
+            cancellation: None,
+            guard: Guard::new(SessionState::Handshake),
+            duplicate_acks: DuplicateAcks::default(),
+            coalesce_writes: true,
+            zero_length_packets: false,
+            tx_buffer: Vec::new(),
+        }
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Drops an exact copy of an acknowledge received within `window` of it, for firmware which
+    /// sometimes sends it twice. In strict mode a dropped copy is recorded as a deviation.
+    ///
+    /// Every request after an acknowledge waits for the rest of the window, and at least 1 ms,
+    /// for a copy.
+    pub fn with_duplicate_ack_window(mut self, window: Option<Duration>) -> Self {
+        self.duplicate_acks.window = window;
+        self
+    }
+
+    /// Sends `request` instead of [`DEFAULT_HANDSHAKE_REQUEST`], for boot stages which expect
+    /// version negotiation bytes in the handshake.
+    pub fn with_handshake_request(mut self, request: Vec<u8>) -> Self {
+        self.handshake_request = request;
+        self
+    }
+
+    /// Sends the start block command and the block in one write on serial transports, which
+    /// saves a write per block. It is on by default and not used with a command delay or a
+    /// duplicate acknowledge window, which need the acknowledge of the command first.
+    pub fn with_coalesced_writes(mut self, coalesce: bool) -> Self {
+        self.coalesce_writes = coalesce;
+        self
+    }
+
+    /// Terminates every write which ends on a packet boundary of the device with a zero-length
+    /// packet, for firmware which otherwise waits for more data. It is off by default, as the
+    /// device knows the length of commands and blocks and most firmware does not expect one.
+    pub fn with_zero_length_packets(mut self, enabled: bool) -> Self {
+        self.zero_length_packets = enabled;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Limits image downloads to `bytes_per_second`, or runs them at full speed if `None`.
+    pub fn with_rate_limit(mut self, bytes_per_second: Option<u64>) -> Self {
+        self.rate_limit = bytes_per_second;
+        self
+    }
+
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Stops waiting for the device with [`AxdlError::UserCancelled`] once `token` is cancelled.
+    pub fn with_cancellation(mut self, token: Option<crate::cancel::CancellationToken>) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    /// Starts in `state` instead of waiting for a handshake, e.g. on a device already in FDL2.
+    pub fn with_state(mut self, state: SessionState) -> Self {
+        self.guard = Guard::new(state);
+        self
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.guard.state
+    }
+
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Returns the deviations found so far in strict mode.
+    pub fn deviations(&self) -> &[Deviation] {
+        &self.deviations
+    }
+
+    pub fn transport_kind(&self) -> crate::transport::TransportKind {
+        self.device.transport_kind()
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.rx_buffer.len()
+    }
+
+    pub fn device(&mut self) -> &mut crate::transport::DynDevice {
+        self.device
+    }
+
+    /// Reads a frame into the session buffer, joining the reads of a frame which arrives in
+    /// several transfers.
+    fn read_frame(&mut self, timeout: Duration) -> Result<usize, AxdlError> {
+        let deadline = crate::time::Deadline::after(timeout);
+        let length = match self.duplicate_acks.pending.take() {
+            Some(length) => length,
+            None => self.read_into(0, timeout)?,
+        };
+        self.complete_frame(length, &deadline)
+    }
+
+    /// Reads the rest of the frame of which `length` bytes are in the receive buffer, if it
+    /// was split across transfers, and returns its length.
+    fn complete_frame(
+        &mut self,
+        mut length: usize,
+        deadline: &crate::time::Deadline,
+    ) -> Result<usize, AxdlError> {
+        while let Some(frame_length) =
+            incomplete_frame_length(&self.rx_buffer[..length], self.rx_buffer.len())
+        {
+            let remaining = deadline.remaining();
+            let result = if remaining.is_zero() {
+                Err(AxdlError::DeviceTimeout)
+            } else {
+                self.read_into(length, remaining)
+            };
+            length += check_continuation(result, length, frame_length)?;
+        }
+        Ok(length)
+    }
+
+    /// Reads into the session buffer from `offset`. With a cancellation token, the read is
+    /// split into short ones so that the token is checked while waiting.
+    fn read_into(&mut self, offset: usize, timeout: Duration) -> Result<usize, AxdlError> {
+        let buffer = &mut self.rx_buffer[offset..];
+        let Some(token) = &self.cancellation else {
+            return self.device.read_timeout(buffer, timeout);
+        };
+        let deadline = crate::time::Deadline::after(timeout);
+        loop {
+            token.check()?;
+            let wait = deadline.remaining().min(crate::cancel::POLL_INTERVAL);
+            match self.device.read_timeout(buffer, wait) {
+                Err(AxdlError::DeviceTimeout) if !deadline.remaining().is_zero() => {}
+                result => return result,
+            }
+        }
+    }
+
+    /// Receives a frame into the session buffer and returns it.
+    pub fn receive_response(&mut self, timeout: Duration) -> Result<&[u8], AxdlError> {
+        let length = self.read_frame(timeout)?;
+        let response = &self.rx_buffer[..length];
+        check_frame(response)?;
+        Ok(response)
+    }
+
+    /// Reads a copy of the last acknowledge if one arrives within the window and drops it.
+    /// Any other frame is kept as the response to the next request.
+    fn drop_duplicate_ack(&mut self) -> Result<(), AxdlError> {
+        let (Some(window), Some((request, received))) = (
+            self.duplicate_acks.window,
+            self.duplicate_acks.received.take(),
+        ) else {
+            return Ok(());
+        };
+        let wait = window
+            .saturating_sub(received.elapsed())
+            .max(Duration::from_millis(1));
+        let length = match self.device.read_timeout(&mut self.rx_buffer, wait) {
+            Ok(length) => length,
+            Err(AxdlError::DeviceTimeout) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        // Once a frame started to arrive, the rest of it is waited for like for a response.
+        let deadline = crate::time::Deadline::after(self.timeouts.command);
+        let length = self.complete_frame(length, &deadline)?;
+        let frame = &self.rx_buffer[..length];
+        if frame != self.duplicate_acks.ack.as_slice() {
+            self.duplicate_acks.pending = Some(length);
+            return Ok(());
+        }
+        tracing::warn!("dropped a duplicate acknowledge to {:?}", request);
+        if self.strict {
+            self.deviations.push(Deviation {
+                request,
+                response: frame.to_vec(),
+                description: "duplicate acknowledge dropped".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Remembers the response in the receive buffer if it is an acknowledge which may be sent
+    /// twice.
+    fn note_response(&mut self, request: Request, length: usize) {
+        if self.duplicate_acks.window.is_none() {
+            return;
+        }
+        let response = &self.rx_buffer[..length];
+        if crate::frame::AxdlFrameView::new(response).command_response() == Some(commands::ACK) {
+            self.duplicate_acks.ack.clear();
+            self.duplicate_acks.ack.extend_from_slice(response);
+            self.duplicate_acks.received = Some((request, crate::time::Stopwatch::start()));
+        }
+    }
+
+    fn exchange(
+        &mut self,
+        request: Request,
+        expected_response: u16,
+        packet: &[u8],
+        timeout: Duration,
+    ) -> Result<&[u8], AxdlError> {
+        self.drop_duplicate_ack()?;
+        trace_request(&request, packet);
+        self.write_packet(packet, timeout)?;
+        self.receive(request, expected_response, timeout)
+    }
+
+    /// Writes `packet`, followed by a zero-length packet if enabled and needed.
+    fn write_packet(&mut self, packet: &[u8], timeout: Duration) -> Result<(), AxdlError> {
+        self.device.write_timeout(packet, timeout)?;
+        if self.zero_length_packets
+            && self
+                .device
+                .max_packet_size()
+                .is_some_and(|max_packet_size| {
+                    crate::transport::needs_zero_length_packet(packet.len(), max_packet_size)
+                })
+        {
+            self.device.write_timeout(&[], timeout)?;
+        }
+        Ok(())
+    }
+
+    /// Receives the response to `request`.
+    fn receive(
+        &mut self,
+        request: Request,
+        expected_response: u16,
+        timeout: Duration,
+    ) -> Result<&[u8], AxdlError> {
+        let length = self.read_frame(timeout)?;
+        check_frame(&self.rx_buffer[..length])?;
+        self.note_response(request, length);
+        let response = &self.rx_buffer[..length];
+        if self.strict {
+            check_conformance(request, expected_response, response, &mut self.deviations);
+        }
+        Ok(response)
+    }
+
+    fn command(&mut self, frame: &[u8], timeout: Duration) -> Result<(), AxdlError> {
+        let response = self.exchange(Request::of_frame(frame), commands::ACK, frame, timeout)?;
+        check_ack(response)
+    }
+
+    /// Sends a command if `step` is allowed in the current state and moves to the next state.
+    fn step(&mut self, step: Step, frame: &[u8], timeout: Duration) -> Result<(), AxdlError> {
+        self.guard.check(step)?;
+        if !self.pacing.command_delay.is_zero() {
+            std::thread::sleep(self.pacing.command_delay);
+        }
+        self.command(frame, timeout)?;
+        self.guard.advance(step);
+        Ok(())
+    }
+
+    pub fn wait_handshake(&mut self, expected_handshake: &str) -> Result<HandshakeInfo, AxdlError> {
+        self.wait_handshake_matching(&[expected_handshake])
+    }
+
+    /// Waits for a handshake which contains any of `patterns`.
+    pub fn wait_handshake_matching<S: AsRef<str>>(
+        &mut self,
+        patterns: &[S],
+    ) -> Result<HandshakeInfo, AxdlError> {
+        self.guard.check(Step::Handshake)?;
+        let retries = self.retry.handshake;
+        let request = self.handshake_request.clone();
+        let mut attempt = 0;
+        loop {
+            let timeout = self.timeouts.command;
+            match self.exchange(Request::Handshake, commands::VERSION, &request, timeout) {
+                Ok(response) => {
+                    let handshake = parse_handshake(response, patterns)?;
+                    self.guard.advance(Step::Handshake);
+                    return Ok(handshake);
+                }
+                Err(e) if attempt < retries && is_retryable(&e) => {
+                    attempt += 1;
+                    tracing::warn!("handshake failed ({}), retrying {}", e, attempt);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn start_ram_download(&mut self) -> Result<(), AxdlError> {
+        tracing::debug!("start_ram_download");
+        self.step(
+            Step::StartRamDownload,
+            &START_RAM_DOWNLOAD_FRAME,
+            self.timeouts.command,
+        )
+    }
+
+    pub fn start_partition_absolute_32(
+        &mut self,
+        start_address: u32,
+        partition_length: u32,
+    ) -> Result<(), AxdlError> {
+        tracing::debug!(
+            "start_partition_absolute: start_address={:#X}, partition_length={}",
+            start_address,
+            partition_length
+        );
+        let buf = start_partition_absolute_32_frame(start_address, partition_length);
+        self.step(Step::StartPartition, &buf, self.timeouts.command)
+    }
+
+    pub fn start_partition_absolute(
+        &mut self,
+        start_address: u64,
+        partition_length: u64,
+    ) -> Result<(), AxdlError> {
+        tracing::debug!(
+            "start_partition_absolute: start_address={:#X}, partition_length={}",
+            start_address,
+            partition_length
+        );
+        let buf = start_partition_absolute_frame(start_address, partition_length);
+        self.step(Step::StartPartition, &buf, self.timeouts.command)
+    }
+
+    pub fn start_partition_id(
+        &mut self,
+        partition_name: &str,
+        total_length: u64,
+    ) -> Result<(), AxdlError> {
+        tracing::debug!(
+            "start_partition_id: partition_name={}, total_length={}",
+            partition_name,
+            total_length
+        );
+        let buf = start_partition_id_frame(partition_name, total_length)?;
+        self.step(Step::StartPartition, &buf, self.timeouts.command)
+    }
+
+    pub fn start_block(&mut self, block_size: u16) -> Result<(), AxdlError> {
+        tracing::debug!("start_block: block_size={}", block_size);
+        let timeout = self.timeouts.command;
+        self.step(Step::Block, &start_block_frame(block_size), timeout)
+    }
+
+    pub fn end_partition(&mut self, timeout: Duration) -> Result<(), AxdlError> {
+        tracing::debug!("end_partition");
+        self.step(Step::EndPartition, &END_PARTITION_FRAME, timeout)
+    }
+
+    /// Ends the partition like [`Session::end_partition`], running `meanwhile` after the command
+    /// is sent and before the answer is read, i.e. while the device flushes the partition.
+    pub fn end_partition_with<T>(
+        &mut self,
+        timeout: Duration,
+        meanwhile: impl FnOnce() -> T,
+    ) -> Result<T, AxdlError> {
+        tracing::debug!("end_partition");
+        self.guard.check(Step::EndPartition)?;
+        if !self.pacing.command_delay.is_zero() {
+            std::thread::sleep(self.pacing.command_delay);
+        }
+        self.drop_duplicate_ack()?;
+        let request = Request::of_frame(&END_PARTITION_FRAME);
+        trace_request(&request, &END_PARTITION_FRAME);
+        self.write_packet(&END_PARTITION_FRAME, timeout)?;
+        let value = meanwhile();
+        let length = self.read_frame(timeout)?;
+        check_frame(&self.rx_buffer[..length])?;
+        self.note_response(request, length);
+        let response = &self.rx_buffer[..length];
+        if self.strict {
+            check_conformance(request, commands::ACK, response, &mut self.deviations);
+        }
+        check_ack(response)?;
+        self.guard.advance(Step::EndPartition);
+        Ok(value)
+    }
+
+    pub fn end_ram_download(&mut self) -> Result<(), AxdlError> {
+        tracing::debug!("end_ram_download");
+        self.step(
+            Step::EndRamDownload,
+            &END_RAM_DOWNLOAD_FRAME,
+            self.timeouts.command,
+        )?;
+        if !self.pacing.fdl_start_delay.is_zero() {
+            std::thread::sleep(self.pacing.fdl_start_delay);
+        }
+        Ok(())
+    }
+
+    /// Sends the partition table, failing with [`AxdlError::FrameTooLarge`] before anything is
+    /// sent if its frame exceeds [`Session::max_frame_size`].
+    pub fn set_partition_table(
+        &mut self,
+        partition_table: &crate::partition::PartitionTable,
+    ) -> Result<(), AxdlError> {
+        tracing::debug!("set_partition_table: {:?}", partition_table);
+        self.step(
+            Step::SetPartitionTable,
+            &set_partition_table_frame(partition_table, self.max_frame_size())?,
+            self.timeouts.command,
+        )
+    }
+
+    /// Sends the vendor `command` with `payload` to the running FDL and returns the payload of
+    /// its acknowledgement. It fails without sending anything unless `handshake` advertises
+    /// the command, see [`vendor`].
+    pub fn vendor_command(
+        &mut self,
+        handshake: &HandshakeInfo,
+        command: &vendor::VendorCommand,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, AxdlError> {
+        command.check(handshake)?;
+        tracing::debug!(
+            "vendor_command: {} ({:#06X})",
+            command.capability,
+            command.command
+        );
+        self.guard.check(Step::Vendor)?;
+        if !self.pacing.command_delay.is_zero() {
+            std::thread::sleep(self.pacing.command_delay);
+        }
+        let frame = crate::frame::AxdlFrame::new(command.command)
+            .with_payload(payload)
+            .build()?;
+        let timeout = self.timeouts.command;
+        let response = self.exchange(
+            Request::Command(command.command),
+            commands::ACK,
+            &frame,
+            timeout,
+        )?;
+        vendor::ack_payload(response)
+    }
+
+    /// Starts reading back a partition.
+    ///
+    /// Readback uses the read flash commands of the Spreadtrum BSL (0x10-0x12), which FDL2
+    /// is assumed to implement in the same way.
+    pub fn start_read_partition(
+        &mut self,
+        partition_name: &str,
+        total_length: u64,
+    ) -> Result<(), AxdlError> {
+        tracing::debug!(
+            "start_read_partition: partition_name={}, total_length={}",
+            partition_name,
+            total_length
+        );
+        self.step(
+            Step::StartReadPartition,
+            &start_read_partition_frame(partition_name, total_length)?,
+            self.timeouts.command,
+        )
+    }
+
+    /// Reads up to `block_size` bytes at `offset` of the partition being read.
+    pub fn read_block(&mut self, offset: u64, block_size: u32) -> Result<&[u8], AxdlError> {
+        tracing::debug!(
+            "read_block: offset={:#X}, block_size={}",
+            offset,
+            block_size
+        );
+        self.guard.check(Step::ReadBlock)?;
+        let frame = read_block_frame(offset, block_size);
+        let timeout = self.timeouts.command;
+        let response = self.exchange(
+            Request::Command(commands::READ_BLOCK),
+            commands::READ_BLOCK_DATA,
+            &frame,
+            timeout,
+        )?;
+        check_read_block(response, block_size)
+    }
+
+    pub fn end_read_partition(&mut self) -> Result<(), AxdlError> {
+        tracing::debug!("end_read_partition");
+        self.step(
+            Step::EndReadPartition,
+            &END_READ_PARTITION_FRAME,
+            self.timeouts.command,
+        )
+    }
+
+    /// Reads `total_length` bytes of a partition into `writer`.
+    pub fn read_partition_id<W: std::io::Write>(
+        &mut self,
+        partition_name: &str,
+        total_length: u64,
+        chunk_size: usize,
+        writer: &mut W,
+    ) -> Result<(), AxdlError> {
+        self.block_reader(
+            partition_name,
+            total_length,
+            chunk_size,
+            &mut crate::progress::NoProgress,
+        )
+        .read_all(writer)
+    }
+
+    /// Returns a [`BlockReader`] of `total_length` bytes of a partition with the retry policy
+    /// of the session, e.g. to dump a large partition with progress reports.
+    pub fn block_reader<'s, P: crate::DownloadProgress>(
+        &'s mut self,
+        partition_name: &'s str,
+        total_length: u64,
+        chunk_size: usize,
+        progress: &'s mut P,
+    ) -> BlockReader<'s, Self, P> {
+        let (retry, transport) = (self.retry, self.transport_kind());
+        let mut reader = BlockReader::new(self, partition_name, total_length, chunk_size, progress)
+            .with_retry(retry);
+        reader.transport = Some(transport);
+        reader
+    }
+
+    /// Returns whether blocks are sent in one write with their start block command.
+    fn coalesces_writes(&self) -> bool {
+        self.coalesce_writes
+            && self.transport_kind() == crate::transport::TransportKind::Serial
+            && self.pacing.command_delay.is_zero()
+            && self.duplicate_acks.window.is_none()
+    }
+
+    /// Sends a single block of at most [`MAX_BLOCK_SIZE`] bytes.
+    fn write_block(&mut self, chunk: &[u8]) -> Result<(), AxdlError> {
+        if self.coalesces_writes() {
+            return self.write_block_coalesced(chunk);
+        }
+        self.start_block(chunk.len() as u16)?; // chunk.len() <= MAX_BLOCK_SIZE
+        let timeout = self.timeouts.data;
+        let response = self.exchange(Request::Data, commands::ACK, chunk, timeout)?;
+        check_ack(response)
+    }
+
+    /// Sends the start block command and the block in one write, then receives both
+    /// acknowledges.
+    fn write_block_coalesced(&mut self, chunk: &[u8]) -> Result<(), AxdlError> {
+        self.guard.check(Step::Block)?;
+        let frame = start_block_frame(chunk.len() as u16); // chunk.len() <= MAX_BLOCK_SIZE
+        let request = Request::of_frame(&frame);
+        trace_request(&request, &frame);
+        trace_request(&Request::Data, chunk);
+        self.tx_buffer.clear();
+        self.tx_buffer.extend_from_slice(&frame);
+        self.tx_buffer.extend_from_slice(chunk);
+        self.device
+            .write_timeout(&self.tx_buffer, self.timeouts.data)?;
+        let started = self
+            .receive(request, commands::ACK, self.timeouts.command)
+            .and_then(check_ack);
+        if started.is_ok() {
+            self.guard.advance(Step::Block);
+        }
+        // The response to the data is read even if the command failed, so that it is not taken
+        // for the response to the next request.
+        let sent = self
+            .receive(Request::Data, commands::ACK, self.timeouts.data)
+            .and_then(check_ack);
+        started.and(sent)
+    }
+
+    pub fn write_image<R: std::io::Read>(
+        &mut self,
+        reader: &mut R,
+        chunk_size: usize,
+        image_name: &str,
+        image_size: usize,
+        report_every: Option<usize>,
+        progress: &mut impl crate::DownloadProgress,
+    ) -> Result<(), AxdlError> {
+        let mut writer = self
+            .block_writer(chunk_size, progress)
+            .with_image_name(image_name);
+        if let Some(report_every) = report_every {
+            writer = writer.with_progress_report(image_name, image_size, report_every);
+        }
+        writer.write_all(reader)
+    }
+
+    /// Returns a [`BlockWriter`] with the retry policy and rate limit of the session, e.g. to
+    /// write an image split into several files with one progress report.
+    pub fn block_writer<'s, P: crate::DownloadProgress>(
+        &'s mut self,
+        chunk_size: usize,
+        progress: &'s mut P,
+    ) -> BlockWriter<'s, Self, P> {
+        let (retry, rate_limit, transport) = (self.retry, self.rate_limit, self.transport_kind());
+        let mut writer = BlockWriter::new(self, chunk_size, progress)
+            .with_retry(retry)
+            .with_rate_limit(rate_limit);
+        writer.transport = Some(transport);
+        writer
+    }
+
+    /// Writes `data` to the partition named `partition`, e.g. a generated environment block,
+    /// and reads it back if `options.verify` is set. Options which are not set use the
+    /// defaults of [`crate::DownloadConfig`] and the retry policy of the session.
+    pub fn write_partition_bytes(
+        &mut self,
+        partition: &str,
+        data: &[u8],
+        options: &crate::PartitionOptions,
+        progress: &mut impl crate::DownloadProgress,
+    ) -> Result<crate::report::VerifyResult, AxdlError> {
+        let chunk_size = options.chunk_size.unwrap_or(DEFAULT_IMAGE_CHUNK_SIZE);
+        validate_block_size(chunk_size)?;
+        let retry = RetryPolicy {
+            block: options.block_retries.unwrap_or(self.retry.block),
+            ..self.retry
+        };
+        self.start_partition_id(partition, data.len() as u64)?;
+        self.block_writer(chunk_size, progress)
+            .with_retry(retry)
+            .with_image_name(partition)
+            .with_progress_report(partition, data.len(), 100)
+            .write_all(&mut &*data)?;
+        self.end_partition(self.timeouts.end_partition)?;
+        if options.verify == Some(true) {
+            crate::verify_partition(
+                self,
+                partition,
+                data.len() as u64,
+                chunk_size,
+                &mut &data[..],
+                progress,
+            )
+        } else {
+            Ok(crate::report::VerifyResult::Skipped)
+        }
+    }
+}
+
+/// Writes an image block by block, retrying failed blocks and reporting the progress.
+///
+/// The sync and async sessions share the retry and progress bookkeeping here; only
+/// `write_all` is implemented per session type.
+pub struct BlockWriter<'s, S, P> {
+    device: &'s mut S,
+    chunk_size: usize,
+    retry: RetryPolicy,
+    progress: &'s mut P,
+    report: Option<ProgressReport<'s>>,
+    rate_limit: Option<u64>,
+    filters: crate::filter::Filters,
+    limit: usize,
+    digests: Option<&'s mut crate::digest::SegmentHasher>,
+    /// Name of the image for error messages.
+    image_name: &'s str,
+    transport: Option<crate::transport::TransportKind>,
+    started: crate::time::Stopwatch,
+    bytes_transferred: usize,
+    blocks_sent: u64,
+    blocks_since_report: usize,
+}
+
+struct ProgressReport<'s> {
+    image_name: &'s str,
+    image_size: usize,
+    every: usize,
+}
+
+impl<'s, S, P: crate::DownloadProgress> BlockWriter<'s, S, P> {
+    pub fn new(device: &'s mut S, chunk_size: usize, progress: &'s mut P) -> Self {
+        Self {
+            device,
+            chunk_size,
+            retry: RetryPolicy::default(),
+            progress,
+            report: None,
+            rate_limit: None,
+            filters: crate::filter::Filters::default(),
+            limit: usize::MAX,
+            digests: None,
+            image_name: "image",
+            transport: None,
+            started: crate::time::Stopwatch::start(),
+            bytes_transferred: 0,
+            blocks_sent: 0,
+            blocks_since_report: 0,
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Waits between blocks so that the average speed stays below `bytes_per_second`.
+    pub fn with_rate_limit(mut self, bytes_per_second: Option<u64>) -> Self {
+        self.rate_limit = bytes_per_second.filter(|&rate| rate > 0);
+        self
+    }
+
+    /// Applies `filters` to an image of `image_size` bytes, which is sent up to its filtered size.
+    pub fn with_filters(mut self, filters: crate::filter::Filters, image_size: u64) -> Self {
+        self.limit = filters.filtered_size(image_size) as usize;
+        self.filters = filters;
+        self
+    }
+
+    /// Hashes every block sent with `digests`, for verifying the partition afterwards.
+    pub(crate) fn with_digests(mut self, digests: &'s mut crate::digest::SegmentHasher) -> Self {
+        self.digests = Some(digests);
+        self
+    }
+
+    /// Names the image in the context of errors.
+    pub fn with_image_name(mut self, image_name: &'s str) -> Self {
+        self.image_name = image_name;
+        self
+    }
+
+    /// Reports the progress of `image_name` every `every` blocks.
+    pub fn with_progress_report(
+        mut self,
+        image_name: &'s str,
+        image_size: usize,
+        every: usize,
+    ) -> Self {
+        self.image_name = image_name;
+        self.report = Some(ProgressReport {
+            image_name,
+            image_size,
+            every,
+        });
+        self
+    }
+
+    pub fn bytes_transferred(&self) -> usize {
+        self.bytes_transferred
+    }
+
+    /// Returns the context of an error while sending the current block.
+    fn error_context(&self) -> crate::context::ErrorContext {
+        let context = crate::context::ErrorContext::new(Phase::Write, self.image_name)
+            .with_block(self.blocks_sent, self.bytes_transferred as u64);
+        match self.transport {
+            Some(transport) => context.with_transport(transport),
+            None => context,
+        }
+    }
+
+    /// Returns whether a block which failed with `error` is sent again.
+    fn should_retry(&self, attempt: &mut u32, error: &AxdlError) -> bool {
+        if *attempt < self.retry.block && is_retryable(error) {
+            *attempt += 1;
+            tracing::warn!("block write failed ({}), retrying {}", error, attempt);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Counts a block once the device has acknowledged it, so that buffering in the transport
+    /// does not make the progress run ahead of the device.
+    fn block_sent(&mut self, block: &[u8]) {
+        if let Some(digests) = &mut self.digests {
+            digests.feed(block);
+        }
+        self.bytes_transferred += block.len();
+        self.blocks_sent += 1;
+        let Some(report) = &self.report else {
+            return;
+        };
+        self.blocks_since_report += 1;
+        if self.blocks_since_report >= report.every {
+            self.blocks_since_report = 0;
+            tracing::debug!(
+                "{}/{} bytes sent",
+                self.bytes_transferred,
+                report.image_size
+            );
+            self.progress.report_transfer(
+                Phase::Write,
+                report.image_name,
+                self.bytes_transferred as u64,
+                report.image_size as u64,
+            );
+        }
+    }
+
+    /// Returns the size of the next block to read, or zero once the filtered image was sent.
+    fn next_block_size(&self) -> usize {
+        self.chunk_size
+            .min(self.limit.saturating_sub(self.bytes_transferred))
+    }
+
+    /// Returns how long to wait before the next block to keep to the rate limit.
+    fn throttle_delay(&self) -> Option<Duration> {
+        let rate = self.rate_limit?;
+        let due = Duration::from_secs_f64(self.bytes_transferred as f64 / rate as f64);
+        due.checked_sub(self.started.elapsed())
+            .filter(|delay| !delay.is_zero())
+    }
+
+    /// Reports the flushing phase once the whole image was sent, which may take several
+    /// `write_all` calls for an image split into several files.
+    fn finished(&mut self) {
+        if let Some(report) = &self.report {
+            if self.bytes_transferred >= report.image_size {
+                self.progress.report_flushing(report.image_name);
+            }
+        }
+    }
+}
+
+impl<P: crate::DownloadProgress> BlockWriter<'_, Session<'_>, P> {
+    /// Sends everything `reader` returns, in blocks of at most the chunk size.
+    pub fn write_all<R: std::io::Read + ?Sized>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<(), AxdlError> {
+        validate_block_size(self.chunk_size)?;
+        let mut buffer = vec![0u8; self.chunk_size];
+        loop {
+            self.progress.check_is_cancelled()?;
+
+            let block_size = self.next_block_size();
+            if block_size == 0 {
+                break;
+            }
+            let bytes_read = reader
+                .read(&mut buffer[..block_size])
+                .map_err(|e| AxdlError::IoError("read error".to_string(), e))?;
+            if bytes_read == 0 {
+                break;
+            }
+            let chunk = &mut buffer[..bytes_read];
+            self.filters.apply(self.bytes_transferred as u64, chunk);
+            let chunk = &*chunk;
+            let mut attempt = 0;
+            while let Err(e) = self.device.write_block(chunk) {
+                if !self.should_retry(&mut attempt, &e) {
+                    return Err(e).context(|| self.error_context());
+                }
+            }
+            self.block_sent(chunk);
+            if let Some(delay) = self.throttle_delay() {
+                std::thread::sleep(delay);
+            }
+        }
+        self.finished();
+        Ok(())
+    }
+}
+
+/// Reads a partition block by block, retrying failed blocks and reporting the progress, e.g.
+/// to dump it to a file.
+///
+/// Like [`BlockWriter`], only `read_all` is implemented per session type.
+pub struct BlockReader<'s, S, P> {
+    device: &'s mut S,
+    partition_name: &'s str,
+    total_length: u64,
+    chunk_size: usize,
+    retry: RetryPolicy,
+    progress: &'s mut P,
+    report_every: Option<usize>,
+    transport: Option<crate::transport::TransportKind>,
+    buffer: Vec<u8>,
+    bytes_read: u64,
+    blocks_read: u64,
+    blocks_since_report: usize,
+}
+
+impl<'s, S, P: crate::DownloadProgress> BlockReader<'s, S, P> {
+    pub fn new(
+        device: &'s mut S,
+        partition_name: &'s str,
+        total_length: u64,
+        chunk_size: usize,
+        progress: &'s mut P,
+    ) -> Self {
+        Self {
+            device,
+            partition_name,
+            total_length,
+            chunk_size,
+            retry: RetryPolicy::default(),
+            progress,
+            report_every: None,
+            transport: None,
+            buffer: Vec::new(),
+            bytes_read: 0,
+            blocks_read: 0,
+            blocks_since_report: 0,
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Reports the progress every `every` blocks and once the partition was read.
+    pub fn with_progress_report(mut self, every: usize) -> Self {
+        self.report_every = Some(every.max(1));
+        self
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the context of an error while reading the current block.
+    fn error_context(&self) -> crate::context::ErrorContext {
+        let context = crate::context::ErrorContext::new(Phase::Read, self.partition_name)
+            .with_block(self.blocks_read, self.bytes_read);
+        match self.transport {
+            Some(transport) => context.with_transport(transport),
+            None => context,
+        }
+    }
+
+    /// Returns whether a block which failed with `error` is read again. Reading a block has
+    /// no side effect on the device, so it is safe to repeat.
+    fn should_retry(&self, attempt: &mut u32, error: &AxdlError) -> bool {
+        if *attempt < self.retry.block && is_retryable(error) {
+            *attempt += 1;
+            tracing::warn!("block read failed ({}), retrying {}", error, attempt);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the size of the next block to read, or zero once the whole partition was read.
+    fn next_block_size(&self) -> u32 {
+        (self.total_length - self.bytes_read).min(self.chunk_size as u64) as u32
+    }
+
+    /// Counts a block of `length` bytes once it was passed on.
+    fn block_read(&mut self, length: usize) {
+        self.bytes_read += length as u64;
+        self.blocks_read += 1;
+        let Some(every) = self.report_every else {
+            return;
+        };
+        self.blocks_since_report += 1;
+        if self.blocks_since_report >= every || self.bytes_read >= self.total_length {
+            self.blocks_since_report = 0;
+            self.progress.report_transfer(
+                Phase::Read,
+                self.partition_name,
+                self.bytes_read,
+                self.total_length,
+            );
+        }
+    }
+}
+
+impl<P: crate::DownloadProgress> BlockReader<'_, Session<'_>, P> {
+    /// Reads the whole partition into `writer`, in blocks of at most the chunk size.
+    pub fn read_all<W: std::io::Write + ?Sized>(
+        &mut self,
+        writer: &mut W,
+    ) -> Result<(), AxdlError> {
+        validate_block_size(self.chunk_size)?;
+        self.device
+            .start_read_partition(self.partition_name, self.total_length)?;
+        self.buffer.resize(self.chunk_size, 0);
+        loop {
+            self.progress.check_is_cancelled()?;
+
+            let block_size = self.next_block_size();
+            if block_size == 0 {
+                break;
+            }
+            let mut attempt = 0;
+            let length = loop {
+                let result = self
+                    .device
+                    .read_block(self.bytes_read, block_size)
+                    .map(|data| {
+                        self.buffer[..data.len()].copy_from_slice(data);
+                        data.len()
+                    });
+                match result {
+                    Ok(length) => break length,
+                    Err(e) if self.should_retry(&mut attempt, &e) => {}
+                    Err(e) => return Err(e).context(|| self.error_context()),
+                }
+            };
+            writer
+                .write_all(&self.buffer[..length])
+                .map_err(|e| AxdlError::IoError("write error".to_string(), e))?;
+            self.block_read(length);
+        }
+        self.device.end_read_partition()
+    }
+}
+
+// The free functions send commands on a session which does not track the protocol state, since
+// the caller drives the device through the steps. Use a `Session` to have commands sent out of
+// order rejected before they reach the device.
+
+pub fn wait_handshake(
+    device: &mut crate::transport::DynDevice,
+    expected_handshake: &str,
+) -> Result<HandshakeInfo, AxdlError> {
+    Session::unchecked(device).wait_handshake(expected_handshake)
+}
+
+pub fn receive_response(
+    device: &mut crate::transport::DynDevice,
+    timeout: Duration,
+) -> Result<Vec<u8>, AxdlError> {
+    Session::unchecked(device)
+        .receive_response(timeout)
+        .map(|response| response.to_vec())
+}
+
+pub fn start_ram_download(device: &mut crate::transport::DynDevice) -> Result<(), AxdlError> {
+    Session::unchecked(device).start_ram_download()
+}
+
+pub fn start_partition_absolute_32(
+    device: &mut crate::transport::DynDevice,
+    start_address: u32,
+    partition_length: u32,
+) -> Result<(), AxdlError> {
+    Session::unchecked(device).start_partition_absolute_32(start_address, partition_length)
+}
+
+pub fn start_partition_absolute(
+    device: &mut crate::transport::DynDevice,
+    start_address: u64,
+    partition_length: u64,
+) -> Result<(), AxdlError> {
+    Session::unchecked(device).start_partition_absolute(start_address, partition_length)
+}
+
+pub fn start_partition_id(
+    device: &mut crate::transport::DynDevice,
+    partition_name: &str,
+    total_length: u64,
+) -> Result<(), AxdlError> {
+    Session::unchecked(device).start_partition_id(partition_name, total_length)
+}
+
+pub fn start_block(
+    device: &mut crate::transport::DynDevice,
+    block_size: u16,
+) -> Result<(), AxdlError> {
+    Session::unchecked(device).start_block(block_size)
+}
+
+pub fn end_partition(
+    device: &mut crate::transport::DynDevice,
+    timeout: Duration,
+) -> Result<(), AxdlError> {
+    Session::unchecked(device).end_partition(timeout)
+}
+
+pub fn end_ram_download(device: &mut crate::transport::DynDevice) -> Result<(), AxdlError> {
+    Session::unchecked(device).end_ram_download()
+}
+
+pub fn set_partition_table(
+    device: &mut crate::transport::DynDevice,
+    partition_table: &crate::partition::PartitionTable,
+) -> Result<(), AxdlError> {
+    Session::unchecked(device).set_partition_table(partition_table)
+}
+
+/// Reads `total_length` bytes of a partition of a device running FDL2 into `writer`, e.g. to
+/// back it up before reflashing.
+pub fn read_partition_id<W: std::io::Write>(
+    device: &mut crate::transport::DynDevice,
+    partition_name: &str,
+    total_length: u64,
+    chunk_size: usize,
+    writer: &mut W,
+) -> Result<(), AxdlError> {
+    Session::unchecked(device).read_partition_id(partition_name, total_length, chunk_size, writer)
+}
+
+/// Writes `data` to a partition of a device running FDL2, see [`Session::write_partition_bytes`].
+pub fn write_partition_bytes(
+    device: &mut crate::transport::DynDevice,
+    partition: &str,
+    data: &[u8],
+    options: &crate::PartitionOptions,
+    progress: &mut impl crate::DownloadProgress,
+) -> Result<crate::report::VerifyResult, AxdlError> {
+    Session::unchecked(device).write_partition_bytes(partition, data, options, progress)
+}
+
+pub fn write_image<R: std::io::Read>(
+    device: &mut crate::transport::DynDevice,
+    reader: &mut R,
+    chunk_size: usize,
+    image_name: &str,
+    image_size: usize,
+    report_every: Option<usize>,
+    progress: &mut impl crate::DownloadProgress,
+) -> Result<(), AxdlError> {
+    Session::unchecked(device).write_image(
+        reader,
+        chunk_size,
+        image_name,
+        image_size,
+        report_every,
+        progress,
+    )
+}
+
+#[cfg(feature = "async")]
+pub mod r#async {
+    use super::{
+        check_ack, check_conformance, check_continuation, check_frame, check_read_block,
+        incomplete_frame_length, is_retryable, parse_handshake, read_block_frame,
+        set_partition_table_frame, start_block_frame, start_partition_absolute_32_frame,
+        start_partition_absolute_frame, start_partition_id_frame, start_read_partition_frame,
+        trace_request, validate_block_size, BlockReader, BlockWriter, Deviation, DuplicateAcks,
+        Guard, HandshakeInfo, Pacing, Request, RetryPolicy, SessionState, Step, Timeouts,
+        DEFAULT_HANDSHAKE_REQUEST, DEFAULT_IMAGE_CHUNK_SIZE, DEFAULT_MAX_FRAME_SIZE,
+        END_PARTITION_FRAME, END_RAM_DOWNLOAD_FRAME, END_READ_PARTITION_FRAME,
+        START_RAM_DOWNLOAD_FRAME,
+    };
+    use crate::{context::ResultExt, frame::commands, transport::AsyncDevice, AxdlError};
+
+    /// Adapter to pass a [`std::io::Read`] where an async reader is expected, e.g. to
+    /// [`Session::write_image`]. Reads block, so it suits in-memory data only.
+    pub use futures_util::io::AllowStdIo;
+
+    /// Writes `packet` to `device`, failing on a short write.
+    async fn write_all<D: AsyncDevice>(device: &mut D, packet: &[u8]) -> Result<(), AxdlError> {
+        let bytes_written = device.write(packet).await?;
+        if bytes_written != packet.len() {
+            return Err(AxdlError::IoError(
+                "write error".to_string(),
+                std::io::Error::other("short write"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Protocol session over an asynchronous device.
+    ///
+    /// See [`super::Session`].
+    pub struct Session<'a, D: AsyncDevice> {
+        device: &'a mut D,
+        rx_buffer: Vec<u8>,
+        strict: bool,
+        deviations: Vec<Deviation>,
+        timeouts: Timeouts,
+        retry: RetryPolicy,
+        rate_limit: Option<u64>,
+        pacing: Pacing,
+        handshake_request: Vec<u8>,
+        cancellation: Option<crate::cancel::CancellationToken>,
+        guard: Guard,
+        duplicate_acks: DuplicateAcks,
+        coalesce_writes: bool,
+        zero_length_packets: bool,
+        tx_buffer: Vec<u8>,
+    }
+
+    impl<'a, D: AsyncDevice> Session<'a, D> {
+        pub fn new(device: &'a mut D) -> Self {
+            Self::with_max_frame_size(device, DEFAULT_MAX_FRAME_SIZE)
+        }
+
+        /// See [`super::Session::unchecked`].
+        fn unchecked(device: &'a mut D) -> Self {
+            let mut session = Self::new(device);
+            session.guard = Guard::unchecked();
+            session
+        }
+
+        pub fn with_max_frame_size(device: &'a mut D, max_frame_size: usize) -> Self {
+            Self {
+                device,
+                rx_buffer: vec![0u8; max_frame_size.max(crate::frame::MINIMUM_LENGTH)],
+                strict: false,
+                deviations: Vec::new(),
+                timeouts: Timeouts::default(),
+                retry: RetryPolicy::default(),
+                rate_limit: None,
+                pacing: Pacing::default(),
+                handshake_request: DEFAULT_HANDSHAKE_REQUEST.to_vec(),
+                cancellation: None,
+                guard: Guard::new(SessionState::Handshake),
+                duplicate_acks: DuplicateAcks::default(),
+                coalesce_writes: true,
+                zero_length_packets: false,
+                tx_buffer: Vec::new(),
+            }
+        }
+
+        pub fn with_strict(mut self, strict: bool) -> Self {
+            self.strict = strict;
+            self
+        }
+
+        /// See [`super::Session::with_duplicate_ack_window`].
+        pub fn with_duplicate_ack_window(mut self, window: Option<std::time::Duration>) -> Self {
+            self.duplicate_acks.window = window;
+            self
+        }
+
+        /// See [`super::Session::with_handshake_request`].
+        pub fn with_handshake_request(mut self, request: Vec<u8>) -> Self {
+            self.handshake_request = request;
+            self
+        }
+
+        /// See [`super::Session::with_coalesced_writes`].
+        pub fn with_coalesced_writes(mut self, coalesce: bool) -> Self {
+            self.coalesce_writes = coalesce;
+            self
+        }
+
+        /// See [`super::Session::with_zero_length_packets`].
+        pub fn with_zero_length_packets(mut self, enabled: bool) -> Self {
+            self.zero_length_packets = enabled;
+            self
+        }
+
+        /// Sets the timeouts. They are only enforced where a timer is available, i.e. in the browser.
+        pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+            self.timeouts = timeouts;
+            self
+        }
+
+        pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+            self.retry = retry;
+            self
+        }
+
+        /// See [`super::Session::with_rate_limit`].
+        pub fn with_rate_limit(mut self, bytes_per_second: Option<u64>) -> Self {
+            self.rate_limit = bytes_per_second;
+            self
+        }
+
+        pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+            self.pacing = pacing;
+            self
+        }
+
+        /// See [`super::Session::with_cancellation`].
+        pub fn with_cancellation(
+            mut self,
+            token: Option<crate::cancel::CancellationToken>,
+        ) -> Self {
+            self.cancellation = token;
+            self
+        }
+
+        /// See [`super::Session::with_state`].
+        pub fn with_state(mut self, state: SessionState) -> Self {
+            self.guard = Guard::new(state);
+            self
+        }
+
+        pub fn state(&self) -> SessionState {
+            self.guard.state
+        }
+
+        pub fn timeouts(&self) -> &Timeouts {
+            &self.timeouts
+        }
+
+        pub fn is_strict(&self) -> bool {
+            self.strict
+        }
+
+        /// Returns the deviations found so far in strict mode.
+        pub fn deviations(&self) -> &[Deviation] {
+            &self.deviations
+        }
+
+        pub fn transport_kind(&self) -> crate::transport::TransportKind {
+            self.device.transport_kind()
+        }
+
+        pub fn max_frame_size(&self) -> usize {
+            self.rx_buffer.len()
+        }
+
+        pub fn device(&mut self) -> &mut D {
+            self.device
+        }
+
+        /// Reads a frame into the session buffer, joining the reads of a frame which arrives in
+        /// several transfers.
+        async fn read_frame(&mut self, timeout: std::time::Duration) -> Result<usize, AxdlError> {
+            let deadline = crate::time::Deadline::after(timeout);
+            let length = match self.duplicate_acks.pending.take() {
+                Some(length) => length,
+                None => self.read_into(0, timeout).await?,
+            };
+            self.complete_frame(length, &deadline).await
+        }
+
+        /// See [`super::Session::complete_frame`].
+        async fn complete_frame(
+            &mut self,
+            mut length: usize,
+            deadline: &crate::time::Deadline,
+        ) -> Result<usize, AxdlError> {
+            while let Some(frame_length) =
+                incomplete_frame_length(&self.rx_buffer[..length], self.rx_buffer.len())
+            {
+                let result = self.read_into(length, deadline.remaining()).await;
+                length += check_continuation(result, length, frame_length)?;
+            }
+            Ok(length)
+        }
+
+        /// Reads into the session buffer from `offset`, stopping early if the session is
+        /// cancelled.
+        async fn read_into(
+            &mut self,
+            offset: usize,
+            timeout: std::time::Duration,
+        ) -> Result<usize, AxdlError> {
+            let read =
+                crate::time::timeout(timeout, self.device.read(&mut self.rx_buffer[offset..]));
+            match &self.cancellation {
+                Some(token) => token.run(read).await,
+                None => read.await,
+            }
+        }
+
+        /// Receives a frame into the session buffer and returns it.
+        pub async fn receive_response(&mut self) -> Result<&[u8], AxdlError> {
+            let length = self.read_frame(self.timeouts.command).await?;
+            let response = &self.rx_buffer[..length];
+            check_frame(response)?;
+            Ok(response)
+        }
+
+        /// See [`super::Session::drop_duplicate_ack`].
+        async fn drop_duplicate_ack(&mut self) -> Result<(), AxdlError> {
+            let (Some(window), Some((request, received))) = (
+                self.duplicate_acks.window,
+                self.duplicate_acks.received.take(),
+            ) else {
+                return Ok(());
+            };
+            let wait = window
+                .saturating_sub(received.elapsed())
+                .max(std::time::Duration::from_millis(1));
+            let length = match self.read_into(0, wait).await {
+                Ok(length) => length,
+                Err(AxdlError::DeviceTimeout) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let deadline = crate::time::Deadline::after(self.timeouts.command);
+            let length = self.complete_frame(length, &deadline).await?;
+            let frame = &self.rx_buffer[..length];
+            if frame != self.duplicate_acks.ack.as_slice() {
+                self.duplicate_acks.pending = Some(length);
+                return Ok(());
+            }
+            tracing::warn!("dropped a duplicate acknowledge to {:?}", request);
+            if self.strict {
+                self.deviations.push(Deviation {
+                    request,
+                    response: frame.to_vec(),
+                    description: "duplicate acknowledge dropped".to_string(),
+                });
+            }
+            Ok(())
+        }
+
+        /// See [`super::Session::note_response`].
+        fn note_response(&mut self, request: Request, length: usize) {
+            if self.duplicate_acks.window.is_none() {
+                return;
+            }
+            let response = &self.rx_buffer[..length];
+            if crate::frame::AxdlFrameView::new(response).command_response() == Some(commands::ACK)
+            {
+                self.duplicate_acks.ack.clear();
+                self.duplicate_acks.ack.extend_from_slice(response);
+                self.duplicate_acks.received = Some((request, crate::time::Stopwatch::start()));
+            }
+        }
+
+        async fn exchange(
+            &mut self,
+            request: Request,
+            expected_response: u16,
+            packet: &[u8],
+            timeout: std::time::Duration,
+        ) -> Result<&[u8], AxdlError> {
+            self.drop_duplicate_ack().await?;
+            trace_request(&request, packet);
+            self.write_packet(packet).await?;
+            self.receive(request, expected_response, timeout).await
+        }
+
+        /// See [`super::Session::write_packet`].
+        async fn write_packet(&mut self, packet: &[u8]) -> Result<(), AxdlError> {
+            write_all(self.device, packet).await?;
+            if self.zero_length_packets
+                && self
+                    .device
+                    .max_packet_size()
+                    .is_some_and(|max_packet_size| {
+                        crate::transport::needs_zero_length_packet(packet.len(), max_packet_size)
+                    })
+            {
+                self.device.write(&[]).await?;
+            }
+            Ok(())
+        }
+
+        /// Receives the response to `request`.
+        async fn receive(
+            &mut self,
+            request: Request,
+            expected_response: u16,
+            timeout: std::time::Duration,
+        ) -> Result<&[u8], AxdlError> {
+            let length = self.read_frame(timeout).await?;
+            check_frame(&self.rx_buffer[..length])?;
+            self.note_response(request, length);
+            let response = &self.rx_buffer[..length];
+            if self.strict {
+                check_conformance(request, expected_response, response, &mut self.deviations);
+            }
+            Ok(response)
+        }
+
+        async fn command(&mut self, frame: &[u8]) -> Result<(), AxdlError> {
+            let timeout = self.timeouts.command;
+            let response = self
+                .exchange(Request::of_frame(frame), commands::ACK, frame, timeout)
+                .await?;
+            check_ack(response)
+        }
+
+        /// See [`super::Session::step`].
+        async fn step(&mut self, step: Step, frame: &[u8]) -> Result<(), AxdlError> {
+            self.guard.check(step)?;
+            if !self.pacing.command_delay.is_zero() {
+                crate::time::sleep(self.pacing.command_delay).await;
+            }
+            self.command(frame).await?;
+            self.guard.advance(step);
+            Ok(())
+        }
+
+        pub async fn wait_handshake(
+            &mut self,
+            expected_handshake: &str,
+        ) -> Result<HandshakeInfo, AxdlError> {
+            self.wait_handshake_matching(&[expected_handshake]).await
+        }
 
-pub fn receive_response(
-    device: &mut crate::transport::DynDevice,
-    timeout: Duration,
-) -> Result<Vec<u8>, AxdlError> {
-    let mut buf = Vec::with_capacity(65536);
-    buf.resize(buf.capacity(), 0);
-    let length = device.read_timeout(&mut buf, timeout)?;
+        /// Waits for a handshake which contains any of `patterns`.
+        pub async fn wait_handshake_matching<S: AsRef<str>>(
+            &mut self,
+            patterns: &[S],
+        ) -> Result<HandshakeInfo, AxdlError> {
+            self.guard.check(Step::Handshake)?;
+            let retries = self.retry.handshake;
+            let request = self.handshake_request.clone();
+            let mut attempt = 0;
+            loop {
+                let timeout = self.timeouts.command;
+                match self
+                    .exchange(Request::Handshake, commands::VERSION, &request, timeout)
+                    .await
+                {
+                    Ok(response) => {
+                        let handshake = parse_handshake(response, patterns)?;
+                        self.guard.advance(Step::Handshake);
+                        return Ok(handshake);
+                    }
+                    Err(e) if attempt < retries && is_retryable(&e) => {
+                        attempt += 1;
+                        tracing::warn!("handshake failed ({}), retrying {}", e, attempt);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
 
-    tracing::debug!("received: {:02X?}", &buf[..length]);
-    let view = crate::frame::AxdlFrameView::new(&buf[..length]);
-    tracing::debug!(
-        "view: {}, checksum={:04X}",
-        view,
-        view.calculate_checksum().unwrap_or(0)
-    );
-    if !view.is_valid() {
-        return Err(AxdlError::InvalidFrame);
-    }
+        pub async fn start_ram_download(&mut self) -> Result<(), AxdlError> {
+            tracing::debug!("start_ram_download");
+            self.step(Step::StartRamDownload, &START_RAM_DOWNLOAD_FRAME)
+                .await
+        }
 
-    buf.resize(length, 0);
-    Ok(buf)
-}
+        pub async fn start_partition_absolute_32(
+            &mut self,
+            start_address: u32,
+            partition_length: u32,
+        ) -> Result<(), AxdlError> {
+            tracing::debug!(
+                "start_partition_absolute: start_address={:#X}, partition_length={}",
+                start_address,
+                partition_length
+            );
+            let buf = start_partition_absolute_32_frame(start_address, partition_length);
+            self.step(Step::StartPartition, &buf).await
+        }
 
-pub fn start_ram_download(device: &mut crate::transport::DynDevice) -> Result<(), AxdlError> {
-    tracing::debug!("start_ram_download");
-    let mut buf = [0u8; crate::frame::MINIMUM_LENGTH];
-    let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-    frame.init();
-    frame.finalize();
-
-    device.write_timeout(&buf, TIMEOUT)?;
-
-    let response = receive_response(device, TIMEOUT)?;
-    let response_view = crate::frame::AxdlFrameView::new(&response);
-    if response_view.command_response() != Some(0x0080) {
-        return Err(AxdlError::UnexpectedResponse(
-            response_view.command_response().unwrap(),
-        ));
-    }
-    Ok(())
-}
+        pub async fn start_partition_absolute(
+            &mut self,
+            start_address: u64,
+            partition_length: u64,
+        ) -> Result<(), AxdlError> {
+            tracing::debug!(
+                "start_partition_absolute: start_address={:#X}, partition_length={}",
+                start_address,
+                partition_length
+            );
+            let buf = start_partition_absolute_frame(start_address, partition_length);
+            self.step(Step::StartPartition, &buf).await
+        }
 
-pub fn start_partition_absolute_32(
-    device: &mut crate::transport::DynDevice,
-    start_address: u32,
-    partition_length: u32,
-) -> Result<(), AxdlError> {
-    tracing::debug!(
-        "start_partition_absolute: start_address={:#X}, partition_length={}",
-        start_address,
-        partition_length
-    );
-    let mut buf = [0u8; crate::frame::MINIMUM_LENGTH + 8];
-    let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-    frame.init();
-    frame.set_command_response(0x0001); // Start partition
-    {
-        let payload = frame.payload_mut();
-        payload[0..4].copy_from_slice(&start_address.to_le_bytes());
-        payload[4..8].copy_from_slice(&partition_length.to_le_bytes());
-    }
-    frame.finalize();
+        pub async fn start_partition_id(
+            &mut self,
+            partition_name: &str,
+            total_length: u64,
+        ) -> Result<(), AxdlError> {
+            tracing::debug!(
+                "start_partition_id: partition_name={}, total_length={}",
+                partition_name,
+                total_length
+            );
+            let buf = start_partition_id_frame(partition_name, total_length)?;
+            self.step(Step::StartPartition, &buf).await
+        }
 
-    device.write_timeout(&buf, TIMEOUT)?;
+        pub async fn start_block(&mut self, block_size: u16) -> Result<(), AxdlError> {
+            tracing::debug!("start_block: block_size={}", block_size);
+            self.step(Step::Block, &start_block_frame(block_size)).await
+        }
 
-    let response = receive_response(device, TIMEOUT)?;
-    let response_view = crate::frame::AxdlFrameView::new(&response);
-    if response_view.command_response() != Some(0x0080) {
-        return Err(AxdlError::UnexpectedResponse(
-            response_view.command_response().unwrap(),
-        ));
-    }
-    Ok(())
-}
+        pub async fn end_partition(&mut self) -> Result<(), AxdlError> {
+            tracing::debug!("end_partition");
+            self.step(Step::EndPartition, &END_PARTITION_FRAME).await
+        }
 
-pub fn start_partition_absolute(
-    device: &mut crate::transport::DynDevice,
-    start_address: u64,
-    partition_length: u64,
-) -> Result<(), AxdlError> {
-    tracing::debug!(
-        "start_partition_absolute: start_address={:#X}, partition_length={}",
-        start_address,
-        partition_length
-    );
-    let mut buf = [0u8; crate::frame::MINIMUM_LENGTH + 16];
-    let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-    frame.init();
-    frame.set_command_response(0x0001); // Start partition
-    {
-        let payload = frame.payload_mut();
-        payload[0..8].copy_from_slice(&start_address.to_le_bytes());
-        payload[8..16].copy_from_slice(&partition_length.to_le_bytes());
-    }
-    frame.finalize();
+        pub async fn end_ram_download(&mut self) -> Result<(), AxdlError> {
+            tracing::debug!("end_ram_download");
+            self.step(Step::EndRamDownload, &END_RAM_DOWNLOAD_FRAME)
+                .await?;
+            if !self.pacing.fdl_start_delay.is_zero() {
+                crate::time::sleep(self.pacing.fdl_start_delay).await;
+            }
+            Ok(())
+        }
 
-    device.write_timeout(&buf, TIMEOUT)?;
+        pub async fn set_partition_table(
+            &mut self,
+            partition_table: &crate::partition::PartitionTable,
+        ) -> Result<(), AxdlError> {
+            tracing::debug!("set_partition_table: {:?}", partition_table);
+            self.step(
+                Step::SetPartitionTable,
+                &set_partition_table_frame(partition_table, self.max_frame_size())?,
+            )
+            .await
+        }
 
-    let response = receive_response(device, TIMEOUT)?;
-    let response_view = crate::frame::AxdlFrameView::new(&response);
-    if response_view.command_response() != Some(0x0080) {
-        return Err(AxdlError::UnexpectedResponse(
-            response_view.command_response().unwrap(),
-        ));
-    }
-    Ok(())
-}
+        /// See [`super::Session::vendor_command`].
+        pub async fn vendor_command(
+            &mut self,
+            handshake: &HandshakeInfo,
+            command: &super::vendor::VendorCommand,
+            payload: &[u8],
+        ) -> Result<Vec<u8>, AxdlError> {
+            command.check(handshake)?;
+            tracing::debug!(
+                "vendor_command: {} ({:#06X})",
+                command.capability,
+                command.command
+            );
+            self.guard.check(Step::Vendor)?;
+            if !self.pacing.command_delay.is_zero() {
+                crate::time::sleep(self.pacing.command_delay).await;
+            }
+            let frame = crate::frame::AxdlFrame::new(command.command)
+                .with_payload(payload)
+                .build()?;
+            let timeout = self.timeouts.command;
+            let response = self
+                .exchange(
+                    Request::Command(command.command),
+                    commands::ACK,
+                    &frame,
+                    timeout,
+                )
+                .await?;
+            super::vendor::ack_payload(response)
+        }
 
-pub fn start_partition_id(
-    device: &mut crate::transport::DynDevice,
-    partition_name: &str,
-    total_length: u64,
-) -> Result<(), AxdlError> {
-    tracing::debug!(
-        "start_partition_id: partition_name={}, total_length={}",
-        partition_name,
-        total_length
-    );
-    let mut buf = [0u8; crate::frame::MINIMUM_LENGTH + 88];
-    let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-    frame.init();
-    frame.set_command_response(0x0001); // Start partition
-    {
-        let payload = frame.payload_mut();
-        let partition_name_bytes = partition_name
-            .encode_utf16()
-            .map(|c| c.to_le_bytes())
-            .flatten()
-            .collect::<Vec<_>>();
-        payload[0..partition_name_bytes.len()].copy_from_slice(&partition_name_bytes);
-        payload[72..80].copy_from_slice(&total_length.to_le_bytes());
-    }
-    frame.finalize();
-
-    device.write_timeout(&buf, TIMEOUT)?;
-
-    let response = receive_response(device, TIMEOUT)?;
-    let response_view = crate::frame::AxdlFrameView::new(&response);
-    if response_view.command_response() != Some(0x0080) {
-        return Err(AxdlError::UnexpectedResponse(
-            response_view.command_response().unwrap(),
-        ));
-    }
-    Ok(())
-}
+        /// Starts reading back a partition. See [`super::Session::start_read_partition`].
+        pub async fn start_read_partition(
+            &mut self,
+            partition_name: &str,
+            total_length: u64,
+        ) -> Result<(), AxdlError> {
+            tracing::debug!(
+                "start_read_partition: partition_name={}, total_length={}",
+                partition_name,
+                total_length
+            );
+            self.step(
+                Step::StartReadPartition,
+                &start_read_partition_frame(partition_name, total_length)?,
+            )
+            .await
+        }
 
-pub fn start_block(
-    device: &mut crate::transport::DynDevice,
-    block_size: u16,
-) -> Result<(), AxdlError> {
-    tracing::debug!("start_block: block_size={}", block_size);
-    let mut buf = [0u8; crate::frame::MINIMUM_LENGTH + 12];
-    let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-    frame.init();
-    frame.set_command_response(0x0002); // Start block
-    {
-        let payload = frame.payload_mut();
-        payload[0..2].copy_from_slice(&block_size.to_le_bytes());
-    }
-    frame.finalize();
+        /// Reads up to `block_size` bytes at `offset` of the partition being read.
+        pub async fn read_block(
+            &mut self,
+            offset: u64,
+            block_size: u32,
+        ) -> Result<&[u8], AxdlError> {
+            tracing::debug!(
+                "read_block: offset={:#X}, block_size={}",
+                offset,
+                block_size
+            );
+            self.guard.check(Step::ReadBlock)?;
+            let frame = read_block_frame(offset, block_size);
+            let timeout = self.timeouts.command;
+            let response = self
+                .exchange(
+                    Request::Command(commands::READ_BLOCK),
+                    commands::READ_BLOCK_DATA,
+                    &frame,
+                    timeout,
+                )
+                .await?;
+            check_read_block(response, block_size)
+        }
 
-    device.write_timeout(&buf, TIMEOUT)?;
+        pub async fn end_read_partition(&mut self) -> Result<(), AxdlError> {
+            tracing::debug!("end_read_partition");
+            self.step(Step::EndReadPartition, &END_READ_PARTITION_FRAME)
+                .await
+        }
 
-    let response = receive_response(device, TIMEOUT)?;
-    let response_view = crate::frame::AxdlFrameView::new(&response);
-    if response_view.command_response() != Some(0x0080) {
-        return Err(AxdlError::UnexpectedResponse(
-            response_view.command_response().unwrap(),
-        ));
-    }
-    Ok(())
-}
+        /// See [`super::Session::coalesces_writes`].
+        fn coalesces_writes(&self) -> bool {
+            self.coalesce_writes
+                && self.transport_kind() == crate::transport::TransportKind::Serial
+                && self.pacing.command_delay.is_zero()
+                && self.duplicate_acks.window.is_none()
+        }
 
-pub fn end_partition(
-    device: &mut crate::transport::DynDevice,
-    timeout: Duration,
-) -> Result<(), AxdlError> {
-    tracing::debug!("end_partition");
-    let mut buf = [0u8; crate::frame::MINIMUM_LENGTH];
-    let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-    frame.init();
-    frame.set_command_response(0x0003); // End partition
-    frame.finalize();
-
-    device.write_timeout(&buf, timeout)?;
-
-    let response = receive_response(device, timeout)?;
-    let response_view = crate::frame::AxdlFrameView::new(&response);
-    if response_view.command_response() != Some(0x0080) {
-        return Err(AxdlError::UnexpectedResponse(
-            response_view.command_response().unwrap(),
-        ));
-    }
-    Ok(())
-}
+        /// See [`super::Session::write_block`].
+        async fn write_block(&mut self, chunk: &[u8]) -> Result<(), AxdlError> {
+            if self.coalesces_writes() {
+                return self.write_block_coalesced(chunk).await;
+            }
+            self.start_block(chunk.len() as u16).await?; // chunk.len() <= MAX_BLOCK_SIZE
+            let timeout = self.timeouts.data;
+            let response = self
+                .exchange(Request::Data, commands::ACK, chunk, timeout)
+                .await?;
+            check_ack(response)
+        }
 
-pub fn end_ram_download(device: &mut crate::transport::DynDevice) -> Result<(), AxdlError> {
-    tracing::debug!("end_ram_download");
-    let mut buf = [0u8; crate::frame::MINIMUM_LENGTH];
-    let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-    frame.init();
-    frame.set_command_response(0x0004); // End RAM download
-    frame.finalize();
-
-    device.write_timeout(&buf, TIMEOUT)?;
-
-    let response = receive_response(device, TIMEOUT)?;
-    let response_view = crate::frame::AxdlFrameView::new(&response);
-    if response_view.command_response() != Some(0x0080) {
-        return Err(AxdlError::UnexpectedResponse(
-            response_view.command_response().unwrap(),
-        ));
-    }
-    Ok(())
-}
+        /// See [`super::Session::write_block_coalesced`].
+        async fn write_block_coalesced(&mut self, chunk: &[u8]) -> Result<(), AxdlError> {
+            self.guard.check(Step::Block)?;
+            let frame = start_block_frame(chunk.len() as u16); // chunk.len() <= MAX_BLOCK_SIZE
+            let request = Request::of_frame(&frame);
+            trace_request(&request, &frame);
+            trace_request(&Request::Data, chunk);
+            self.tx_buffer.clear();
+            self.tx_buffer.extend_from_slice(&frame);
+            self.tx_buffer.extend_from_slice(chunk);
+            write_all(self.device, &self.tx_buffer).await?;
+            let started = self
+                .receive(request, commands::ACK, self.timeouts.command)
+                .await
+                .and_then(check_ack);
+            if started.is_ok() {
+                self.guard.advance(Step::Block);
+            }
+            let sent = self
+                .receive(Request::Data, commands::ACK, self.timeouts.data)
+                .await
+                .and_then(check_ack);
+            started.and(sent)
+        }
 
-pub fn set_partition_table(
-    device: &mut crate::transport::DynDevice,
-    partition_table: &crate::partition::PartitionTable,
-) -> Result<(), AxdlError> {
-    tracing::debug!("set_partition_table: {:?}", partition_table);
-    let partition_table_image = partition_table.to_bytes();
-    let mut buf = Vec::with_capacity(crate::frame::MINIMUM_LENGTH + partition_table_image.len());
-    buf.resize(
-        crate::frame::MINIMUM_LENGTH + partition_table_image.len(),
-        0,
-    );
+        /// Writes an image read asynchronously, so that decompressing it does not block the
+        /// event loop. A [`std::io::Read`] can be passed wrapped in [`AllowStdIo`].
+        pub async fn write_image<R: futures_io::AsyncRead + Unpin>(
+            &mut self,
+            reader: &mut R,
+            chunk_size: usize,
+            image_name: &str,
+            image_size: usize,
+            report_every: Option<usize>,
+            progress: &mut impl crate::DownloadProgress,
+        ) -> Result<(), AxdlError> {
+            let mut writer = self.block_writer(chunk_size, progress);
+            if let Some(report_every) = report_every {
+                writer = writer.with_progress_report(image_name, image_size, report_every);
+            }
+            writer.write_all(reader).await
+        }
 
-    let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-    frame.init();
-    frame.set_command_response(0x000b); // Set partition table
-    {
-        let payload = frame.payload_mut();
-        payload.copy_from_slice(&partition_table_image);
-    }
-    frame.finalize();
+        /// See [`super::Session::block_writer`].
+        pub fn block_writer<'s, P: crate::DownloadProgress>(
+            &'s mut self,
+            chunk_size: usize,
+            progress: &'s mut P,
+        ) -> BlockWriter<'s, Self, P> {
+            let (retry, rate_limit, transport) =
+                (self.retry, self.rate_limit, self.transport_kind());
+            let mut writer = BlockWriter::new(self, chunk_size, progress)
+                .with_retry(retry)
+                .with_rate_limit(rate_limit);
+            writer.transport = Some(transport);
+            writer
+        }
 
-    device.write_timeout(&buf, TIMEOUT)?;
+        /// See [`super::Session::write_partition_bytes`].
+        pub async fn write_partition_bytes(
+            &mut self,
+            partition: &str,
+            data: &[u8],
+            options: &crate::PartitionOptions,
+            progress: &mut impl crate::DownloadProgress,
+        ) -> Result<crate::report::VerifyResult, AxdlError> {
+            let chunk_size = options.chunk_size.unwrap_or(DEFAULT_IMAGE_CHUNK_SIZE);
+            validate_block_size(chunk_size)?;
+            let retry = RetryPolicy {
+                block: options.block_retries.unwrap_or(self.retry.block),
+                ..self.retry
+            };
+            self.start_partition_id(partition, data.len() as u64)
+                .await?;
+            self.block_writer(chunk_size, progress)
+                .with_retry(retry)
+                .with_image_name(partition)
+                .with_progress_report(partition, data.len(), 100)
+                .write_all(&mut futures_util::io::Cursor::new(data))
+                .await?;
+            self.end_partition().await?;
+            if options.verify == Some(true) {
+                crate::r#async::verify_partition_async(
+                    self,
+                    partition,
+                    data.len() as u64,
+                    chunk_size,
+                    &mut futures_util::io::Cursor::new(data),
+                    progress,
+                )
+                .await
+            } else {
+                Ok(crate::report::VerifyResult::Skipped)
+            }
+        }
 
-    let response = receive_response(device, TIMEOUT)?;
-    let response_view = crate::frame::AxdlFrameView::new(&response);
-    if response_view.command_response() != Some(0x0080) {
-        return Err(AxdlError::UnexpectedResponse(
-            response_view.command_response().unwrap(),
-        ));
-    }
-    Ok(())
-}
+        /// See [`super::Session::read_partition_id`].
+        pub async fn read_partition_id<W: futures_io::AsyncWrite + Unpin>(
+            &mut self,
+            partition_name: &str,
+            total_length: u64,
+            chunk_size: usize,
+            writer: &mut W,
+        ) -> Result<(), AxdlError> {
+            self.block_reader(
+                partition_name,
+                total_length,
+                chunk_size,
+                &mut crate::progress::NoProgress,
+            )
+            .read_all(writer)
+            .await
+        }
 
-pub fn write_image<R: std::io::Read>(
-    device: &mut crate::transport::DynDevice,
-    reader: &mut R,
-    chunk_size: usize,
-    image_name: &str,
-    image_size: usize,
-    report_every: Option<usize>,
-    progress: &mut impl crate::DownloadProgress,
-) -> Result<(), AxdlError> {
-    let mut buffer = Vec::with_capacity(chunk_size);
-    buffer.resize(chunk_size, 0);
-
-    let mut report_every_counter = 0;
-    let mut bytes_transferred: usize = 0;
-    loop {
-        progress.check_is_cancelled()?;
-
-        let bytes_read = reader
-            .read(&mut buffer)
-            .map_err(|e| AxdlError::IoError("read error".to_string(), e))?;
-        if bytes_read == 0 {
-            break;
-        }
-        let chunk = &buffer[..bytes_read];
-        start_block(device, chunk.len() as u16)?;
-        device.write_timeout(chunk, TIMEOUT_WRITE_IMAGE)?;
-        let response = receive_response(device, TIMEOUT_WRITE_IMAGE)?;
-        let response_view = crate::frame::AxdlFrameView::new(&response);
-        if response_view.command_response() != Some(0x0080) {
-            return Err(AxdlError::UnexpectedResponse(
-                response_view.command_response().unwrap(),
-            ));
+        /// See [`super::Session::block_reader`].
+        pub fn block_reader<'s, P: crate::DownloadProgress>(
+            &'s mut self,
+            partition_name: &'s str,
+            total_length: u64,
+            chunk_size: usize,
+            progress: &'s mut P,
+        ) -> BlockReader<'s, Self, P> {
+            let (retry, transport) = (self.retry, self.transport_kind());
+            let mut reader =
+                BlockReader::new(self, partition_name, total_length, chunk_size, progress)
+                    .with_retry(retry);
+            reader.transport = Some(transport);
+            reader
         }
-        bytes_transferred += chunk.len();
-        if let Some(report_every) = report_every {
-            report_every_counter += 1;
-            if report_every_counter >= report_every {
-                report_every_counter = 0;
-                tracing::debug!("{}/{} bytes sent", bytes_transferred, image_size);
-                progress.report_progress(
-                    &format!("Downloading image {}", image_name),
-                    Some(bytes_transferred as f32 / image_size as f32),
-                );
+    }
+
+    impl<D: AsyncDevice, P: crate::DownloadProgress> BlockReader<'_, Session<'_, D>, P> {
+        /// Reads the whole partition into `writer`, in blocks of at most the chunk size.
+        pub async fn read_all<W: futures_io::AsyncWrite + Unpin>(
+            &mut self,
+            writer: &mut W,
+        ) -> Result<(), AxdlError> {
+            use futures_util::io::AsyncWriteExt;
+
+            validate_block_size(self.chunk_size)?;
+            self.device
+                .start_read_partition(self.partition_name, self.total_length)
+                .await?;
+            self.buffer.resize(self.chunk_size, 0);
+            loop {
+                self.progress.check_is_cancelled()?;
+
+                let block_size = self.next_block_size();
+                if block_size == 0 {
+                    break;
+                }
+                let mut attempt = 0;
+                let length = loop {
+                    let result = self
+                        .device
+                        .read_block(self.bytes_read, block_size)
+                        .await
+                        .map(|data| {
+                            self.buffer[..data.len()].copy_from_slice(data);
+                            data.len()
+                        });
+                    match result {
+                        Ok(length) => break length,
+                        Err(e) if self.should_retry(&mut attempt, &e) => {}
+                        Err(e) => return Err(e).context(|| self.error_context()),
+                    }
+                };
+                writer
+                    .write_all(&self.buffer[..length])
+                    .await
+                    .map_err(|e| AxdlError::IoError("write error".to_string(), e))?;
+                self.block_read(length);
             }
+            self.device.end_read_partition().await
         }
     }
-    Ok(())
-}
 
-#[cfg(feature = "async")]
-pub mod r#async {
-    use crate::{communication::HANDSHAKE_REQUEST, transport::AsyncDevice, AxdlError};
+    impl<D: AsyncDevice, P: crate::DownloadProgress> BlockWriter<'_, Session<'_, D>, P> {
+        /// Sends everything `reader` returns, in blocks of at most the chunk size.
+        pub async fn write_all<R: futures_io::AsyncRead + Unpin>(
+            &mut self,
+            reader: &mut R,
+        ) -> Result<(), AxdlError> {
+            use futures_util::io::AsyncReadExt;
+
+            validate_block_size(self.chunk_size)?;
+            let mut buffer = vec![0u8; self.chunk_size];
+            loop {
+                self.progress.check_is_cancelled()?;
+
+                let block_size = self.next_block_size();
+                if block_size == 0 {
+                    break;
+                }
+                let bytes_read = reader
+                    .read(&mut buffer[..block_size])
+                    .await
+                    .map_err(|e| AxdlError::IoError("read error".to_string(), e))?;
+                if bytes_read == 0 {
+                    break;
+                }
+                let chunk = &mut buffer[..bytes_read];
+                self.filters.apply(self.bytes_transferred as u64, chunk);
+                let chunk = &*chunk;
+                let mut attempt = 0;
+                while let Err(e) = self.device.write_block(chunk).await {
+                    if !self.should_retry(&mut attempt, &e) {
+                        return Err(e).context(|| self.error_context());
+                    }
+                }
+                self.block_sent(chunk);
+                if let Some(delay) = self.throttle_delay() {
+                    crate::time::sleep(delay).await;
+                }
+            }
+            self.finished();
+            Ok(())
+        }
+    }
 
     pub async fn wait_handshake<D: AsyncDevice>(
         device: &mut D,
         expected_handshake: &str,
-    ) -> Result<(), AxdlError> {
-        device.write(&HANDSHAKE_REQUEST).await?;
-        let mut buf = [0u8; 64];
-        let length = device.read(&mut buf).await?;
-
-        tracing::debug!("received: {:02X?}", &buf[..length]);
-        let view = crate::frame::AxdlFrameView::new(&buf[..length]);
-        tracing::debug!(
-            "view: {}, checksum={:04X}",
-            view,
-            view.calculate_checksum().unwrap_or(0)
-        );
-        if !view.is_valid() {
-            return Err(AxdlError::InvalidFrame);
-        }
-        let handshake = view
-            .payload()
-            .map(|payload| {
-                std::str::from_utf8(payload)
-                    .map_err(AxdlError::HandshakeDecodeError)
-                    .map(|s| s.to_string())
-            })
-            .transpose()?
-            .ok_or(AxdlError::NoPayload)?;
-
-        tracing::debug!("handshake: {}", handshake);
-        if !handshake.contains(expected_handshake) {
-            return Err(AxdlError::UnexpectedHandshake(handshake));
-        }
-        Ok(())
+    ) -> Result<HandshakeInfo, AxdlError> {
+        Session::unchecked(device)
+            .wait_handshake(expected_handshake)
+            .await
     }
 
-    pub async fn receive_response<D: crate::transport::AsyncDevice>(
-        device: &mut D,
-    ) -> Result<Vec<u8>, AxdlError> {
-        let mut buf = Vec::with_capacity(65536);
-        buf.resize(buf.capacity(), 0);
-        let length = device.read(&mut buf).await?;
-
-        tracing::debug!("received: {:02X?}", &buf[..length]);
-        let view = crate::frame::AxdlFrameView::new(&buf[..length]);
-        tracing::debug!(
-            "view: {}, checksum={:04X}",
-            view,
-            view.calculate_checksum().unwrap_or(0)
-        );
-        if !view.is_valid() {
-            return Err(AxdlError::InvalidFrame);
-        }
-
-        buf.resize(length, 0);
-        Ok(buf)
+    pub async fn receive_response<D: AsyncDevice>(device: &mut D) -> Result<Vec<u8>, AxdlError> {
+        Session::unchecked(device)
+            .receive_response()
+            .await
+            .map(|response| response.to_vec())
     }
 
     pub async fn start_ram_download<D: AsyncDevice>(device: &mut D) -> Result<(), AxdlError> {
-        tracing::debug!("start_ram_download");
-        let mut buf = [0u8; crate::frame::MINIMUM_LENGTH];
-        let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-        frame.init();
-        frame.finalize();
-
-        device.write(&buf).await?;
-
-        let response = receive_response(device).await?;
-        let response_view = crate::frame::AxdlFrameView::new(&response);
-        if response_view.command_response() != Some(0x0080) {
-            return Err(AxdlError::UnexpectedResponse(
-                response_view.command_response().unwrap(),
-            ));
-        }
-        Ok(())
+        Session::unchecked(device).start_ram_download().await
     }
 
     pub async fn start_partition_absolute_32<D: AsyncDevice>(
@@ -439,32 +2613,9 @@ pub mod r#async {
         start_address: u32,
         partition_length: u32,
     ) -> Result<(), AxdlError> {
-        tracing::debug!(
-            "start_partition_absolute: start_address={:#X}, partition_length={}",
-            start_address,
-            partition_length
-        );
-        let mut buf = [0u8; crate::frame::MINIMUM_LENGTH + 8];
-        let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-        frame.init();
-        frame.set_command_response(0x0001); // Start partition
-        {
-            let payload = frame.payload_mut();
-            payload[0..4].copy_from_slice(&start_address.to_le_bytes());
-            payload[4..8].copy_from_slice(&partition_length.to_le_bytes());
-        }
-        frame.finalize();
-
-        device.write(&buf).await?;
-
-        let response = receive_response(device).await?;
-        let response_view = crate::frame::AxdlFrameView::new(&response);
-        if response_view.command_response() != Some(0x0080) {
-            return Err(AxdlError::UnexpectedResponse(
-                response_view.command_response().unwrap(),
-            ));
-        }
-        Ok(())
+        Session::unchecked(device)
+            .start_partition_absolute_32(start_address, partition_length)
+            .await
     }
 
     pub async fn start_partition_absolute<D: AsyncDevice>(
@@ -472,175 +2623,69 @@ pub mod r#async {
         start_address: u64,
         partition_length: u64,
     ) -> Result<(), AxdlError> {
-        tracing::debug!(
-            "start_partition_absolute: start_address={:#X}, partition_length={}",
-            start_address,
-            partition_length
-        );
-        let mut buf = [0u8; crate::frame::MINIMUM_LENGTH + 16];
-        let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-        frame.init();
-        frame.set_command_response(0x0001); // Start partition
-        {
-            let payload = frame.payload_mut();
-            payload[0..8].copy_from_slice(&start_address.to_le_bytes());
-            payload[8..16].copy_from_slice(&partition_length.to_le_bytes());
-        }
-        frame.finalize();
-
-        device.write(&buf).await?;
-
-        let response = receive_response(device).await?;
-        let response_view = crate::frame::AxdlFrameView::new(&response);
-        if response_view.command_response() != Some(0x0080) {
-            return Err(AxdlError::UnexpectedResponse(
-                response_view.command_response().unwrap(),
-            ));
-        }
-        Ok(())
+        Session::unchecked(device)
+            .start_partition_absolute(start_address, partition_length)
+            .await
     }
 
-    pub async fn start_partition_id<D: crate::transport::AsyncDevice>(
+    pub async fn start_partition_id<D: AsyncDevice>(
         device: &mut D,
         partition_name: &str,
         total_length: u64,
     ) -> Result<(), AxdlError> {
-        tracing::debug!(
-            "start_partition_id: partition_name={}, total_length={}",
-            partition_name,
-            total_length
-        );
-        let mut buf = [0u8; crate::frame::MINIMUM_LENGTH + 88];
-        let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-        frame.init();
-        frame.set_command_response(0x0001); // Start partition
-        {
-            let payload = frame.payload_mut();
-            let partition_name_bytes = partition_name
-                .encode_utf16()
-                .map(|c| c.to_le_bytes())
-                .flatten()
-                .collect::<Vec<_>>();
-            payload[0..partition_name_bytes.len()].copy_from_slice(&partition_name_bytes);
-            payload[72..80].copy_from_slice(&total_length.to_le_bytes());
-        }
-        frame.finalize();
-
-        device.write(&buf).await?;
-
-        let response = receive_response(device).await?;
-        let response_view = crate::frame::AxdlFrameView::new(&response);
-        if response_view.command_response() != Some(0x0080) {
-            return Err(AxdlError::UnexpectedResponse(
-                response_view.command_response().unwrap(),
-            ));
-        }
-        Ok(())
+        Session::unchecked(device)
+            .start_partition_id(partition_name, total_length)
+            .await
     }
 
-    pub async fn start_block<D: crate::transport::AsyncDevice>(
+    pub async fn start_block<D: AsyncDevice>(
         device: &mut D,
         block_size: u16,
     ) -> Result<(), AxdlError> {
-        tracing::debug!("start_block: block_size={}", block_size);
-        let mut buf = [0u8; crate::frame::MINIMUM_LENGTH + 12];
-        let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-        frame.init();
-        frame.set_command_response(0x0002); // Start block
-        {
-            let payload = frame.payload_mut();
-            payload[0..2].copy_from_slice(&block_size.to_le_bytes());
-        }
-        frame.finalize();
+        Session::unchecked(device).start_block(block_size).await
+    }
 
-        device.write(&buf).await?;
+    pub async fn end_partition<D: AsyncDevice>(device: &mut D) -> Result<(), AxdlError> {
+        Session::unchecked(device).end_partition().await
+    }
 
-        let response = receive_response(device).await?;
-        let response_view = crate::frame::AxdlFrameView::new(&response);
-        if response_view.command_response() != Some(0x0080) {
-            return Err(AxdlError::UnexpectedResponse(
-                response_view.command_response().unwrap(),
-            ));
-        }
-        Ok(())
+    pub async fn end_ram_download<D: AsyncDevice>(device: &mut D) -> Result<(), AxdlError> {
+        Session::unchecked(device).end_ram_download().await
     }
 
-    pub async fn end_partition<D: crate::transport::AsyncDevice>(
+    pub async fn set_partition_table<D: AsyncDevice>(
         device: &mut D,
+        partition_table: &crate::partition::PartitionTable,
     ) -> Result<(), AxdlError> {
-        tracing::debug!("end_partition");
-        let mut buf = [0u8; crate::frame::MINIMUM_LENGTH];
-        let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-        frame.init();
-        frame.set_command_response(0x0003); // End partition
-        frame.finalize();
-
-        device.write(&buf).await?;
-
-        let response = receive_response(device).await?;
-        let response_view = crate::frame::AxdlFrameView::new(&response);
-        if response_view.command_response() != Some(0x0080) {
-            return Err(AxdlError::UnexpectedResponse(
-                response_view.command_response().unwrap(),
-            ));
-        }
-        Ok(())
+        Session::unchecked(device)
+            .set_partition_table(partition_table)
+            .await
     }
 
-    pub async fn end_ram_download<D: crate::transport::AsyncDevice>(
+    /// See [`super::read_partition_id`].
+    pub async fn read_partition_id<D: AsyncDevice, W: futures_io::AsyncWrite + Unpin>(
         device: &mut D,
+        partition_name: &str,
+        total_length: u64,
+        chunk_size: usize,
+        writer: &mut W,
     ) -> Result<(), AxdlError> {
-        tracing::debug!("end_ram_download");
-        let mut buf = [0u8; crate::frame::MINIMUM_LENGTH];
-        let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-        frame.init();
-        frame.set_command_response(0x0004); // End RAM download
-        frame.finalize();
-
-        device.write(&buf).await?;
-
-        let response = receive_response(device).await?;
-        let response_view = crate::frame::AxdlFrameView::new(&response);
-        if response_view.command_response() != Some(0x0080) {
-            return Err(AxdlError::UnexpectedResponse(
-                response_view.command_response().unwrap(),
-            ));
-        }
-        Ok(())
+        Session::unchecked(device)
+            .read_partition_id(partition_name, total_length, chunk_size, writer)
+            .await
     }
 
-    pub async fn set_partition_table<D: crate::transport::AsyncDevice>(
+    /// See [`super::write_partition_bytes`].
+    pub async fn write_partition_bytes<D: AsyncDevice>(
         device: &mut D,
-        partition_table: &crate::partition::PartitionTable,
-    ) -> Result<(), AxdlError> {
-        tracing::debug!("set_partition_table: {:?}", partition_table);
-        let partition_table_image = partition_table.to_bytes();
-        let mut buf =
-            Vec::with_capacity(crate::frame::MINIMUM_LENGTH + partition_table_image.len());
-        buf.resize(
-            crate::frame::MINIMUM_LENGTH + partition_table_image.len(),
-            0,
-        );
-
-        let mut frame = crate::frame::AxdlFrameViewMut::new(&mut buf);
-        frame.init();
-        frame.set_command_response(0x000b); // Set partition table
-        {
-            let payload = frame.payload_mut();
-            payload.copy_from_slice(&partition_table_image);
-        }
-        frame.finalize();
-
-        device.write(&buf).await?;
-
-        let response = receive_response(device).await?;
-        let response_view = crate::frame::AxdlFrameView::new(&response);
-        if response_view.command_response() != Some(0x0080) {
-            return Err(AxdlError::UnexpectedResponse(
-                response_view.command_response().unwrap(),
-            ));
-        }
-        Ok(())
+        partition: &str,
+        data: &[u8],
+        options: &crate::PartitionOptions,
+        progress: &mut impl crate::DownloadProgress,
+    ) -> Result<crate::report::VerifyResult, AxdlError> {
+        Session::unchecked(device)
+            .write_partition_bytes(partition, data, options, progress)
+            .await
     }
 
     pub async fn write_image<D: AsyncDevice, R: futures_io::AsyncRead + Unpin>(
@@ -652,52 +2697,73 @@ pub mod r#async {
         report_every: Option<usize>,
         progress: &mut impl crate::DownloadProgress,
     ) -> Result<(), AxdlError> {
-        use futures_util::io::AsyncReadExt;
-
-        let mut buffer = Vec::with_capacity(chunk_size);
-        buffer.resize(chunk_size, 0);
-
-        let mut report_every_counter = 0;
-        let mut bytes_transferred: usize = 0;
-        loop {
-            progress.check_is_cancelled()?;
+        Session::unchecked(device)
+            .write_image(
+                reader,
+                chunk_size,
+                image_name,
+                image_size,
+                report_every,
+                progress,
+            )
+            .await
+    }
 
-            let bytes_read = reader
-                .read(&mut buffer)
-                .await
-                .map_err(|e| AxdlError::IoError("read error".to_string(), e))?;
-            if bytes_read == 0 {
-                break;
-            }
-            let chunk = &buffer[..bytes_read];
-            start_block(device, chunk.len() as u16).await?;
-            let bytes_written = device.write(chunk).await?;
-            if bytes_written != chunk.len() {
-                return Err(AxdlError::IoError(
-                    "write error".to_string(),
-                    std::io::Error::new(std::io::ErrorKind::Other, "short write for data packet"),
-                ));
-            }
-            let response = receive_response(device).await?;
-            let response_view = crate::frame::AxdlFrameView::new(&response);
-            if response_view.command_response() != Some(0x0080) {
-                return Err(AxdlError::UnexpectedResponse(
-                    response_view.command_response().unwrap(),
-                ));
-            }
-            bytes_transferred += chunk.len();
-            if let Some(report_every) = report_every {
-                report_every_counter += 1;
-                if report_every_counter >= report_every {
-                    report_every_counter = 0;
-                    tracing::debug!("{}/{} bytes sent", bytes_transferred, image_size);
-                    progress.report_progress(
-                        &format!("Downloading image {}", image_name),
-                        Some(bytes_transferred as f32 / image_size as f32),
-                    );
-                }
-            }
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use crate::transport::mock::MockDevice;
+
+        #[test]
+        fn duplicate_acks_are_dropped() {
+            let ack = crate::frame::AxdlFrame::new(commands::ACK).build().unwrap();
+            let data = vec![0x55u8; 1000];
+            let write = |window: Option<std::time::Duration>| {
+                // The first block is acknowledged twice.
+                let mut repeated = false;
+                let ack = ack.clone();
+                let mut device = MockDevice::new(move |packet| {
+                    let copies = if packet[0] == 0x55 && !std::mem::replace(&mut repeated, true) {
+                        2
+                    } else {
+                        1
+                    };
+                    vec![ack.clone(); copies]
+                });
+                let mut session = Session::new(&mut device)
+                    .with_strict(true)
+                    .with_state(SessionState::Fdl)
+                    .with_duplicate_ack_window(window);
+                crate::time::block_on(async {
+                    session
+                        .start_partition_absolute_32(0, data.len() as u32 * 2)
+                        .await?;
+                    let mut reader =
+                        AllowStdIo::new(std::io::Cursor::new([data.as_slice(); 2].concat()));
+                    session
+                        .write_image(
+                            &mut reader,
+                            data.len(),
+                            "image",
+                            2000,
+                            None,
+                            &mut crate::progress::NoProgress,
+                        )
+                        .await?;
+                    session.end_partition().await?;
+                    Ok::<_, AxdlError>(session.receive_response().await.is_ok())
+                })
+                .map(|out_of_step| (session.deviations().len(), out_of_step))
+                .unwrap()
+            };
+
+            // The copy is taken for the next response, and every later one is read one step
+            // late.
+            assert_eq!(write(None), (0, true));
+            assert_eq!(
+                write(Some(std::time::Duration::from_millis(20))),
+                (1, false)
+            );
         }
-        Ok(())
     }
 }
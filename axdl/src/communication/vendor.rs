@@ -0,0 +1,111 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Vendor specific commands of FDL2, e.g. to set the RTC or to write a vendor data block in the
+//! same session as the download instead of booting the device again.
+//!
+//! The stock FDL2 documents no such commands, so their codes and payloads are given by the
+//! caller, typically for a customized FDL2. A command is only sent if the handshake of the
+//! running FDL advertises its capability as an option, e.g. `rtc` in `fdl2 v1.0;raw;rtc`, so
+//! that a downloader which does not know it never receives it. FDL2 of a two-level image sends
+//! no handshake, so there the capabilities are those advertised by FDL1.
+
+use super::HandshakeInfo;
+use crate::frame::commands;
+use crate::AxdlError;
+
+/// Vendor command of FDL2, sent with [`super::Session::vendor_command`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendorCommand {
+    /// Handshake option by which FDL2 advertises the command.
+    pub capability: String,
+    /// Command code, which must not be a code of [`crate::frame::commands`].
+    pub command: u16,
+}
+
+impl VendorCommand {
+    pub fn new(capability: impl Into<String>, command: u16) -> Self {
+        Self {
+            capability: capability.into(),
+            command,
+        }
+    }
+
+    /// Checks that the command does not take the code of a standard command or response, and
+    /// that `handshake` advertises it.
+    pub fn check(&self, handshake: &HandshakeInfo) -> Result<(), AxdlError> {
+        if is_standard(self.command) {
+            return Err(AxdlError::InvalidConfig(format!(
+                "vendor command {} uses the code {:#06X} of a standard command or response",
+                self.capability, self.command
+            )));
+        }
+        if !handshake.has_option(&self.capability) {
+            return Err(AxdlError::Unsupported(format!(
+                "vendor command {}, which is not advertised by the handshake {}",
+                self.capability, handshake
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Returns whether `command` is used by the download protocol itself.
+fn is_standard(command: u16) -> bool {
+    matches!(
+        command,
+        commands::START_RAM_DOWNLOAD
+            | commands::START_PARTITION
+            | commands::START_BLOCK
+            | commands::END_PARTITION
+            | commands::END_RAM_DOWNLOAD
+            | commands::SET_PARTITION_TABLE
+            | commands::START_READ_PARTITION
+            | commands::READ_BLOCK
+            | commands::END_READ_PARTITION
+    ) || command >= commands::ACK
+}
+
+/// Returns the payload of the acknowledgement of a vendor command.
+pub(super) fn ack_payload(response: &[u8]) -> Result<Vec<u8>, AxdlError> {
+    super::check_ack(response)?;
+    Ok(crate::frame::AxdlFrameView::new(response)
+        .payload()
+        .unwrap_or_default()
+        .to_vec())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let handshake = HandshakeInfo::parse("fdl2 v1.0;raw;rtc");
+        assert!(VendorCommand::new("rtc", 0x0030).check(&handshake).is_ok());
+        assert!(matches!(
+            VendorCommand::new("vendor-block", 0x0031).check(&handshake),
+            Err(AxdlError::Unsupported(_))
+        ));
+        assert!(matches!(
+            VendorCommand::new("rtc", commands::SET_PARTITION_TABLE).check(&handshake),
+            Err(AxdlError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            VendorCommand::new("rtc", commands::ACK).check(&handshake),
+            Err(AxdlError::InvalidConfig(_))
+        ));
+    }
+}
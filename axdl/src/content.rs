@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recognizes the content of partition images from their magic numbers, to catch an image
+//! assigned to the wrong partition before it is written.
+
+/// Number of bytes at the beginning of an image needed by [`detect`].
+pub const HEADER_SIZE: usize = 4096;
+
+const EXT4_MAGIC_OFFSET: usize = 1024 + 0x38;
+const EXT4_MAGIC: [u8; 2] = 0xef53u16.to_le_bytes();
+const SQUASHFS_MAGIC: &[u8] = b"hsqs";
+const UIMAGE_MAGIC: [u8; 4] = 0x2705_1956u32.to_be_bytes();
+const FDT_MAGIC: [u8; 4] = 0xd00d_feedu32.to_be_bytes();
+const ARM64_IMAGE_MAGIC_OFFSET: usize = 0x38;
+const ARM64_IMAGE_MAGIC: &[u8] = b"ARM\x64";
+/// FDT_BEGIN_NODE token of the `/images` node, which makes a device tree a FIT image.
+const FIT_IMAGES_NODE: &[u8] = b"\0\0\0\x01images\0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    Ext4,
+    Squashfs,
+    /// Flattened image tree, a device tree holding kernel images.
+    Fit,
+    UImage,
+    Dtb,
+    /// Uncompressed arm64 Linux kernel.
+    Arm64Image,
+}
+
+impl std::fmt::Display for ContentType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Ext4 => "ext4",
+            Self::Squashfs => "squashfs",
+            Self::Fit => "FIT",
+            Self::UImage => "uImage",
+            Self::Dtb => "dtb",
+            Self::Arm64Image => "arm64 kernel",
+        })
+    }
+}
+
+fn has_magic(header: &[u8], offset: usize, magic: &[u8]) -> bool {
+    header.get(offset..offset + magic.len()) == Some(magic)
+}
+
+/// Recognizes the content type from the first [`HEADER_SIZE`] bytes of an image.
+pub fn detect(header: &[u8]) -> Option<ContentType> {
+    if has_magic(header, 0, &FDT_MAGIC) {
+        // The structure block follows the header, and /images is the first node of a FIT.
+        let is_fit = header
+            .windows(FIT_IMAGES_NODE.len())
+            .any(|window| window == FIT_IMAGES_NODE);
+        return Some(if is_fit {
+            ContentType::Fit
+        } else {
+            ContentType::Dtb
+        });
+    }
+    if has_magic(header, 0, SQUASHFS_MAGIC) {
+        return Some(ContentType::Squashfs);
+    }
+    if has_magic(header, 0, &UIMAGE_MAGIC) {
+        return Some(ContentType::UImage);
+    }
+    if has_magic(header, ARM64_IMAGE_MAGIC_OFFSET, ARM64_IMAGE_MAGIC) {
+        return Some(ContentType::Arm64Image);
+    }
+    if has_magic(header, EXT4_MAGIC_OFFSET, &EXT4_MAGIC) {
+        return Some(ContentType::Ext4);
+    }
+    None
+}
+
+/// Returns the content types expected in the partition named `partition`, or an empty slice
+/// if any content is fine.
+pub fn expected_types(partition: &str) -> &'static [ContentType] {
+    match partition.to_ascii_lowercase().as_str() {
+        "rootfs" => &[ContentType::Ext4, ContentType::Squashfs],
+        "kernel" => &[
+            ContentType::Fit,
+            ContentType::UImage,
+            ContentType::Arm64Image,
+        ],
+        "dtb" => &[ContentType::Dtb],
+        _ => &[],
+    }
+}
+
+/// Checks an image for `partition` by its `header`, returning a warning if it does not look
+/// like the content expected there.
+pub fn check(partition: &str, header: &[u8]) -> Option<String> {
+    let expected = expected_types(partition);
+    if expected.is_empty() {
+        return None;
+    }
+    let names = expected
+        .iter()
+        .map(ContentType::to_string)
+        .collect::<Vec<_>>()
+        .join(" or ");
+    match detect(header) {
+        Some(found) if expected.contains(&found) => None,
+        Some(found) => Some(format!(
+            "image for partition {} looks like {}, expected {}",
+            partition, found, names
+        )),
+        None => Some(format!(
+            "image for partition {} does not look like {}",
+            partition, names
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let mut ext4 = vec![0u8; 2048];
+        ext4[EXT4_MAGIC_OFFSET..EXT4_MAGIC_OFFSET + 2].copy_from_slice(&EXT4_MAGIC);
+        assert_eq!(detect(&ext4), Some(ContentType::Ext4));
+        assert_eq!(detect(b"hsqs\x10\0\0\0"), Some(ContentType::Squashfs));
+        assert_eq!(
+            detect(&[0x27, 0x05, 0x19, 0x56, 0, 0]),
+            Some(ContentType::UImage)
+        );
+
+        let mut dtb = FDT_MAGIC.to_vec();
+        dtb.extend_from_slice(&[0u8; 52]);
+        dtb.extend_from_slice(b"\0\0\0\x01\0\0\0\0\0\0\0\x01cpus\0");
+        assert_eq!(detect(&dtb), Some(ContentType::Dtb));
+        dtb.extend_from_slice(FIT_IMAGES_NODE);
+        assert_eq!(detect(&dtb), Some(ContentType::Fit));
+
+        assert_eq!(detect(&[0u8; 16]), None);
+        // Too short for the ext4 superblock.
+        assert_eq!(detect(&ext4[..1024]), None);
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check("rootfs", b"hsqs"), None);
+        assert_eq!(check("spl", b"anything"), None);
+        assert_eq!(
+            check("ROOTFS", &[0x27, 0x05, 0x19, 0x56]),
+            Some("image for partition ROOTFS looks like uImage, expected ext4 or squashfs".into())
+        );
+        assert_eq!(
+            check("kernel", &[0u8; 64]),
+            Some(
+                "image for partition kernel does not look like FIT or uImage or arm64 kernel"
+                    .into()
+            )
+        );
+    }
+}
@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Context attached to low-level errors, so that e.g. a failed USB transfer tells which block
+//! of which partition was being written.
+
+use crate::{progress::Phase, transport::TransportKind, AxdlError};
+
+/// Where in a download an error happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub phase: Phase,
+    /// Image or partition being worked on.
+    pub target: String,
+    /// Index of the block being transferred, counted from zero.
+    pub block: Option<u64>,
+    /// Offset of the block in the image or partition.
+    pub offset: Option<u64>,
+    pub transport: Option<TransportKind>,
+}
+
+impl ErrorContext {
+    pub fn new(phase: Phase, target: &str) -> Self {
+        Self {
+            phase,
+            target: target.to_string(),
+            block: None,
+            offset: None,
+            transport: None,
+        }
+    }
+
+    /// Sets the block with its `index` and its `offset` in bytes.
+    pub fn with_block(mut self, index: u64, offset: u64) -> Self {
+        self.block = Some(index);
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn with_transport(mut self, transport: TransportKind) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+}
+
+impl std::fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action = match self.phase {
+            Phase::Write | Phase::Provision => "writing",
+            Phase::Verify | Phase::Compare => "reading back",
+            Phase::Read => "reading",
+            Phase::Flush => "waiting for the device to finish writing",
+            phase => phase.id(),
+        };
+        f.write_str(action)?;
+        if let Some(block) = self.block {
+            write!(f, " block {} of", block)?;
+        }
+        write!(f, " {}", self.target)?;
+        if let Some(offset) = self.offset {
+            write!(f, " at offset {:#x}", offset)?;
+        }
+        if let Some(transport) = self.transport {
+            write!(f, " over {}", transport)?;
+        }
+        Ok(())
+    }
+}
+
+/// Attaches an [`ErrorContext`] to the error of a result.
+pub(crate) trait ResultExt<T> {
+    fn context(self, context: impl FnOnce() -> ErrorContext) -> Result<T, AxdlError>;
+}
+
+impl<T> ResultExt<T> for Result<T, AxdlError> {
+    fn context(self, context: impl FnOnce() -> ErrorContext) -> Result<T, AxdlError> {
+        self.map_err(|source| AxdlError::Context {
+            context: context(),
+            source: Box::new(source),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let context = ErrorContext::new(Phase::Write, "rootfs")
+            .with_block(1234, 0x3a0_0000)
+            .with_transport(TransportKind::Usb);
+        assert_eq!(
+            context.to_string(),
+            "writing block 1234 of rootfs at offset 0x3a00000 over USB"
+        );
+        assert_eq!(
+            ErrorContext::new(Phase::Flush, "SPL").to_string(),
+            "waiting for the device to finish writing SPL"
+        );
+    }
+}
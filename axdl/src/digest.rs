@@ -0,0 +1,257 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Digests of fixed-size segments of a partition, computed on a worker thread while the
+//! partition is written and read back. Verification then overlaps hashing with the transfer
+//! and does not need to decompress the image a second time.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::JoinHandle,
+};
+
+use crate::hash::{Digest, Hasher};
+use crate::AxdlError;
+
+/// Blocks queued for the worker before `feed` waits for it.
+const QUEUE_LENGTH: usize = 4;
+
+/// First segment whose digest differs, with the data it was fed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Mismatch {
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+/// Result of a [`SegmentHasher`].
+#[derive(Debug, Default)]
+pub(crate) struct Segments {
+    /// Digest the segments were hashed with.
+    pub digest: Digest,
+    pub digests: Vec<Vec<u8>>,
+    pub mismatch: Option<Mismatch>,
+}
+
+struct Worker {
+    segment_size: usize,
+    digest: Digest,
+    hasher: Hasher,
+    filled: usize,
+    /// Expected digests, and the data of the current segment kept to report a mismatch.
+    expected: Option<(Vec<Vec<u8>>, Vec<u8>)>,
+    result: Segments,
+    mismatch_found: Arc<AtomicBool>,
+}
+
+impl Worker {
+    fn push(&mut self, mut data: &[u8]) {
+        while !data.is_empty() && self.result.mismatch.is_none() {
+            let length = data.len().min(self.segment_size - self.filled);
+            self.hasher.update(&data[..length]);
+            if let Some((_, segment)) = &mut self.expected {
+                segment.extend_from_slice(&data[..length]);
+            }
+            self.filled += length;
+            data = &data[length..];
+            if self.filled == self.segment_size {
+                self.end_segment();
+            }
+        }
+    }
+
+    fn end_segment(&mut self) {
+        let digest = std::mem::replace(&mut self.hasher, self.digest.hasher()).finalize();
+        let index = self.result.digests.len();
+        if let Some((expected, segment)) = &mut self.expected {
+            if expected.get(index) != Some(&digest) {
+                self.result.mismatch = Some(Mismatch {
+                    offset: (index * self.segment_size) as u64,
+                    data: std::mem::take(segment),
+                });
+                self.mismatch_found.store(true, Ordering::Relaxed);
+            }
+            segment.clear();
+        }
+        self.result.digests.push(digest);
+        self.filled = 0;
+    }
+
+    fn finish(mut self) -> Segments {
+        if self.filled > 0 && self.result.mismatch.is_none() {
+            self.end_segment();
+        }
+        if let Some((expected, _)) = &self.expected {
+            // The data ended before the expected segments did.
+            if self.result.mismatch.is_none() && self.result.digests.len() < expected.len() {
+                self.result.mismatch = Some(Mismatch {
+                    offset: (self.result.digests.len() * self.segment_size) as u64,
+                    data: Vec::new(),
+                });
+            }
+        }
+        self.result
+    }
+}
+
+/// Hashes the data fed to it in segments of `segment_size` bytes with a [`Digest`] on a worker
+/// thread.
+pub(crate) struct SegmentHasher {
+    sender: Option<mpsc::SyncSender<Vec<u8>>>,
+    recycled: mpsc::Receiver<Vec<u8>>,
+    worker: Option<JoinHandle<Segments>>,
+    mismatch_found: Arc<AtomicBool>,
+}
+
+impl SegmentHasher {
+    /// Records the digests of the data, e.g. while it is written.
+    pub fn record(digest: Digest, segment_size: usize) -> Self {
+        Self::spawn(digest, segment_size, None)
+    }
+
+    /// Compares the digests of the data with `expected`, e.g. while it is read back.
+    pub fn check(digest: Digest, segment_size: usize, expected: Vec<Vec<u8>>) -> Self {
+        Self::spawn(digest, segment_size, Some(expected))
+    }
+
+    fn spawn(digest: Digest, segment_size: usize, expected: Option<Vec<Vec<u8>>>) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(QUEUE_LENGTH);
+        let (recycle, recycled) = mpsc::channel();
+        let mismatch_found = Arc::new(AtomicBool::new(false));
+        let mut worker = Worker {
+            segment_size: segment_size.max(1),
+            digest,
+            hasher: digest.hasher(),
+            filled: 0,
+            expected: expected.map(|expected| (expected, Vec::new())),
+            result: Segments {
+                digest,
+                ..Default::default()
+            },
+            mismatch_found: mismatch_found.clone(),
+        };
+        let worker = std::thread::spawn(move || {
+            for block in receiver {
+                worker.push(&block);
+                // The hasher may be gone already, then the buffer is simply dropped.
+                let _ = recycle.send(block);
+            }
+            worker.finish()
+        });
+        Self {
+            sender: Some(sender),
+            recycled,
+            worker: Some(worker),
+            mismatch_found,
+        }
+    }
+
+    /// Queues a copy of `data` for the worker.
+    pub fn feed(&mut self, data: &[u8]) {
+        let mut block = self.recycled.try_recv().unwrap_or_default();
+        block.clear();
+        block.extend_from_slice(data);
+        if let Some(sender) = &self.sender {
+            // Only fails if the worker panicked, which `finish` reports.
+            let _ = sender.send(block);
+        }
+    }
+
+    /// Returns whether a differing segment has been found, so that reading back can stop.
+    pub fn has_mismatch(&self) -> bool {
+        self.mismatch_found.load(Ordering::Relaxed)
+    }
+
+    /// Waits for the worker to hash everything fed so far. Fails if the worker panicked, as
+    /// the data was then not completely hashed.
+    pub fn finish(mut self) -> Result<Segments, AxdlError> {
+        self.sender = None;
+        let worker = self.worker.take().expect("the worker is only taken here");
+        worker.join().map_err(|_| {
+            AxdlError::IoError(
+                "failed to hash the data".to_string(),
+                std::io::Error::other("the digest worker panicked"),
+            )
+        })
+    }
+}
+
+impl Drop for SegmentHasher {
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_digests_do_not_depend_on_blocks() {
+        for digest in Digest::ALL {
+            check_segments(digest);
+        }
+    }
+
+    fn check_segments(digest: Digest) {
+        let data = (0..10_000u32).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let mut hasher = SegmentHasher::record(digest, 1000);
+        for block in data.chunks(333) {
+            hasher.feed(block);
+        }
+        let recorded = hasher.finish().unwrap();
+        assert_eq!(recorded.digests.len(), 10);
+        assert_eq!(recorded.mismatch, None);
+
+        let mut checker = SegmentHasher::check(digest, 1000, recorded.digests.clone());
+        for block in data.chunks(1000) {
+            checker.feed(block);
+        }
+        assert!(!checker.has_mismatch());
+        assert_eq!(checker.finish().unwrap().mismatch, None);
+
+        let mut changed = data.clone();
+        changed[4321] ^= 1;
+        let mut checker = SegmentHasher::check(digest, 1000, recorded.digests);
+        for block in changed.chunks(4096) {
+            checker.feed(block);
+        }
+        let mismatch = checker.finish().unwrap().mismatch.unwrap();
+        assert_eq!(mismatch.offset, 4000);
+        assert_eq!(mismatch.data, changed[4000..5000]);
+    }
+
+    #[test]
+    fn test_panicked_worker_is_an_error() {
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(QUEUE_LENGTH);
+        let (_recycle, recycled) = mpsc::channel();
+        let mut hasher = SegmentHasher {
+            sender: Some(sender),
+            recycled,
+            worker: Some(std::thread::spawn(move || -> Segments {
+                let _block = receiver.recv();
+                panic!("the worker failed to hash a block");
+            })),
+            mismatch_found: Arc::new(AtomicBool::new(false)),
+        };
+        hasher.feed(&[0; 16]);
+        assert!(matches!(hasher.finish(), Err(AxdlError::IoError(..))));
+    }
+}
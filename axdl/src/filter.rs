@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Filters which change partition images while they are written, e.g. to patch the boot
+//! arguments or to cut off trailing padding without repacking the AXP image.
+
+use std::sync::Arc;
+
+/// Transforms the data of a partition image block by block.
+///
+/// Filters are shared between downloads, so they do not keep state between blocks.
+pub trait ImageFilter: std::fmt::Debug + Send + Sync {
+    /// Returns the size of the filtered image of `size` bytes.
+    ///
+    /// Filters can only shorten an image; the data after the returned size is not written.
+    fn filtered_size(&self, size: u64) -> u64 {
+        size
+    }
+
+    /// Changes `data`, which starts at `offset` in the image, in place.
+    fn apply(&self, offset: u64, data: &mut [u8]);
+}
+
+/// Overwrites the image with `data` at `offset`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+impl ImageFilter for Patch {
+    fn apply(&self, offset: u64, data: &mut [u8]) {
+        let start = self.offset.max(offset);
+        let end = (self.offset + self.data.len() as u64).min(offset + data.len() as u64);
+        if start >= end {
+            return;
+        }
+        let (start, end, source) = (
+            (start - offset) as usize,
+            (end - offset) as usize,
+            (start - self.offset) as usize,
+        );
+        data[start..end].copy_from_slice(&self.data[source..source + end - start]);
+    }
+}
+
+/// Cuts the image at `size`. Shorter images are written as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Truncate {
+    pub size: u64,
+}
+
+impl ImageFilter for Truncate {
+    fn filtered_size(&self, size: u64) -> u64 {
+        size.min(self.size)
+    }
+
+    fn apply(&self, _offset: u64, _data: &mut [u8]) {}
+}
+
+/// Filters applied to one image, in order.
+#[derive(Debug, Clone, Default)]
+pub struct Filters(Vec<Arc<dyn ImageFilter>>);
+
+impl Filters {
+    pub fn new(filters: Vec<Arc<dyn ImageFilter>>) -> Self {
+        Self(filters)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn filtered_size(&self, size: u64) -> u64 {
+        self.0
+            .iter()
+            .fold(size, |size, filter| filter.filtered_size(size))
+    }
+
+    pub fn apply(&self, offset: u64, data: &mut [u8]) {
+        for filter in &self.0 {
+            filter.apply(offset, data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_patch_across_blocks() {
+        let filters = Filters::new(vec![
+            Arc::new(Patch {
+                offset: 6,
+                data: b"abcd".to_vec(),
+            }),
+            Arc::new(Truncate { size: 12 }),
+        ]);
+        assert_eq!(filters.filtered_size(16), 12);
+        assert_eq!(filters.filtered_size(10), 10);
+
+        let mut image = [b'.'; 16];
+        for (index, block) in image.chunks_mut(4).enumerate() {
+            filters.apply(index as u64 * 4, block);
+        }
+        assert_eq!(&image, b"......abcd......");
+    }
+}
@@ -13,15 +13,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use serde::{Deserialize, Serialize};
-use serde_bytes::ByteBuf;
-use thiserror::Error;
+//! Frame encoding and decoding.
+//!
+//! This module only depends on `core`, `alloc` and `thiserror`, so that firmware or bridges
+//! written in `no_std` Rust can reuse it as is.
+#![deny(clippy::std_instead_of_core, clippy::std_instead_of_alloc)]
 
-/// USBフレーム構造体
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct UsbFrame {
-    pub raw: ByteBuf,
-}
+use alloc::vec::Vec;
+use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum UsbFrameError {
@@ -36,13 +35,343 @@ pub enum UsbFrameError {
 pub const MINIMUM_LENGTH: usize = 4 + 2 + 2 + 2; // signature + length + command_response + checksum
 pub const SIGNATURE: u32 = 0x5c6d8e9f;
 
+/// Command and response codes of the AXDL protocol.
+///
+/// A session starts with the host sending [`HANDSHAKE`] bytes until the romcode answers with
+/// [`VERSION`]. The host then downloads FDL1 (and FDL2 for two level images) to RAM with
+/// [`START_RAM_DOWNLOAD`], [`START_PARTITION`], [`START_BLOCK`] and a data packet per block,
+/// [`END_PARTITION`] and [`END_RAM_DOWNLOAD`], which runs the downloaded program. FDL2 takes
+/// the partition table with [`SET_PARTITION_TABLE`] and writes each partition the same way,
+/// addressed by name. Every command is answered with [`ACK`] or an error code.
+///
+/// The codes follow the Spreadtrum BSL numbering, from which the error codes are taken.
+pub mod commands {
+    /// Byte sent, unframed and repeated, to ask the running program for its version.
+    pub const HANDSHAKE: u8 = 0x3c;
+
+    /// Starts a download to RAM. Payload: none.
+    pub const START_RAM_DOWNLOAD: u16 = 0x0000;
+    /// Starts a partition. Payload: a 32-bit or 64-bit address and length in RAM, or a
+    /// UTF-16LE partition name of 36 characters followed by a 64-bit length.
+    pub const START_PARTITION: u16 = 0x0001;
+    /// Announces the next data packet. Payload: the 16-bit block size, 10 reserved bytes.
+    pub const START_BLOCK: u16 = 0x0002;
+    /// Ends a partition once all its blocks were sent. Payload: none.
+    pub const END_PARTITION: u16 = 0x0003;
+    /// Ends the download to RAM and runs the downloaded program. Payload: none.
+    pub const END_RAM_DOWNLOAD: u16 = 0x0004;
+    /// Sets the partition table. Payload: a 16-bit header followed by 0x58 byte entries.
+    pub const SET_PARTITION_TABLE: u16 = 0x000b;
+    /// Starts reading back a partition. Payload: as [`START_PARTITION`] by name.
+    pub const START_READ_PARTITION: u16 = 0x0010;
+    /// Reads a block of the partition being read. Payload: 32-bit size, 64-bit offset.
+    pub const READ_BLOCK: u16 = 0x0011;
+    /// Ends reading back a partition. Payload: none.
+    pub const END_READ_PARTITION: u16 = 0x0012;
+
+    /// The command succeeded. Payload: none.
+    pub const ACK: u16 = 0x0080;
+    /// Answer to the handshake. Payload: the version string, e.g. `romcode v1.0;raw`.
+    pub const VERSION: u16 = 0x0081;
+    /// The command is known but not valid in the current state.
+    pub const INVALID_COMMAND: u16 = 0x0082;
+    pub const UNKNOWN_COMMAND: u16 = 0x0083;
+    /// A block was sent before a partition was started.
+    pub const DOWNLOAD_NOT_STARTED: u16 = 0x0086;
+    /// The partition or address does not exist.
+    pub const DESTINATION_ERROR: u16 = 0x0089;
+    /// The data does not fit the partition or block.
+    pub const SIZE_ERROR: u16 = 0x008a;
+    pub const VERIFY_ERROR: u16 = 0x008b;
+    /// Answer to [`READ_BLOCK`]. Payload: the data read.
+    pub const READ_BLOCK_DATA: u16 = 0x0093;
+
+    /// Type of a payload field. Integers are little endian.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum FieldType {
+        U16,
+        U32,
+        U64,
+        /// NUL padded UTF-16LE string of `length` bytes.
+        Utf16 {
+            length: usize,
+        },
+        /// ASCII string up to the end of the payload.
+        Ascii,
+        /// Raw bytes up to the end of the payload.
+        Bytes,
+    }
+
+    /// A field of a payload.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Field {
+        pub name: &'static str,
+        pub offset: usize,
+        pub field_type: FieldType,
+    }
+
+    /// Payload layout, used when the payload has the given length.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Layout {
+        /// Payload length this layout applies to, or `None` for any length.
+        pub length: Option<usize>,
+        pub fields: &'static [Field],
+    }
+
+    /// A command or response code and the layouts of its payload.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Code {
+        pub value: u16,
+        pub name: &'static str,
+        pub is_response: bool,
+        pub layouts: &'static [Layout],
+    }
+
+    impl Code {
+        /// Returns the layout matching a payload of `length` bytes.
+        pub fn layout(&self, length: usize) -> Option<&'static Layout> {
+            self.layouts
+                .iter()
+                .find(|layout| layout.length.is_none_or(|l| l == length))
+        }
+    }
+
+    const fn field(name: &'static str, offset: usize, field_type: FieldType) -> Field {
+        Field {
+            name,
+            offset,
+            field_type,
+        }
+    }
+
+    const fn code(
+        value: u16,
+        name: &'static str,
+        is_response: bool,
+        layouts: &'static [Layout],
+    ) -> Code {
+        Code {
+            value,
+            name,
+            is_response,
+            layouts,
+        }
+    }
+
+    const PARTITION_ID: Layout = Layout {
+        length: Some(88),
+        fields: &[
+            field("partition", 0, FieldType::Utf16 { length: 72 }),
+            field("length", 72, FieldType::U64),
+        ],
+    };
+
+    /// All known codes, for tools such as protocol dissectors.
+    pub const CODES: &[Code] = &[
+        code(START_RAM_DOWNLOAD, "START_RAM_DOWNLOAD", false, &[]),
+        code(
+            START_PARTITION,
+            "START_PARTITION",
+            false,
+            &[
+                Layout {
+                    length: Some(8),
+                    fields: &[
+                        field("address", 0, FieldType::U32),
+                        field("length", 4, FieldType::U32),
+                    ],
+                },
+                Layout {
+                    length: Some(16),
+                    fields: &[
+                        field("address", 0, FieldType::U64),
+                        field("length", 8, FieldType::U64),
+                    ],
+                },
+                PARTITION_ID,
+            ],
+        ),
+        code(
+            START_BLOCK,
+            "START_BLOCK",
+            false,
+            &[Layout {
+                length: Some(12),
+                fields: &[field("block_size", 0, FieldType::U16)],
+            }],
+        ),
+        code(END_PARTITION, "END_PARTITION", false, &[]),
+        code(END_RAM_DOWNLOAD, "END_RAM_DOWNLOAD", false, &[]),
+        code(
+            SET_PARTITION_TABLE,
+            "SET_PARTITION_TABLE",
+            false,
+            &[Layout {
+                length: None,
+                fields: &[field("table", 0, FieldType::Bytes)],
+            }],
+        ),
+        code(
+            START_READ_PARTITION,
+            "START_READ_PARTITION",
+            false,
+            &[PARTITION_ID],
+        ),
+        code(
+            READ_BLOCK,
+            "READ_BLOCK",
+            false,
+            &[Layout {
+                length: Some(12),
+                fields: &[
+                    field("block_size", 0, FieldType::U32),
+                    field("offset", 4, FieldType::U64),
+                ],
+            }],
+        ),
+        code(END_READ_PARTITION, "END_READ_PARTITION", false, &[]),
+        code(ACK, "ACK", true, &[]),
+        code(
+            VERSION,
+            "VERSION",
+            true,
+            &[Layout {
+                length: None,
+                fields: &[field("version", 0, FieldType::Ascii)],
+            }],
+        ),
+        code(INVALID_COMMAND, "INVALID_COMMAND", true, &[]),
+        code(UNKNOWN_COMMAND, "UNKNOWN_COMMAND", true, &[]),
+        code(DOWNLOAD_NOT_STARTED, "DOWNLOAD_NOT_STARTED", true, &[]),
+        code(DESTINATION_ERROR, "DESTINATION_ERROR", true, &[]),
+        code(SIZE_ERROR, "SIZE_ERROR", true, &[]),
+        code(VERIFY_ERROR, "VERIFY_ERROR", true, &[]),
+        code(
+            READ_BLOCK_DATA,
+            "READ_BLOCK_DATA",
+            true,
+            &[Layout {
+                length: None,
+                fields: &[field("data", 0, FieldType::Bytes)],
+            }],
+        ),
+    ];
+
+    /// Looks up a command or response code.
+    pub fn lookup(value: u16) -> Option<&'static Code> {
+        CODES.iter().find(|code| code.value == value)
+    }
+}
+
+const fn ones_complement_add(lhs: u16, rhs: u16) -> u16 {
+    let mut sum = lhs as u32 + rhs as u32;
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+/// Adds the little endian 16-bit words of `bytes` to `initial`. An odd trailing byte is padded with zero.
+const fn ones_complement_sum(initial: u16, bytes: &[u8]) -> u16 {
+    let mut checksum = initial;
+    let mut i = 0;
+    while i + 1 < bytes.len() {
+        checksum = ones_complement_add(checksum, u16::from_le_bytes([bytes[i], bytes[i + 1]]));
+        i += 2;
+    }
+    if bytes.len() % 2 == 1 {
+        checksum = ones_complement_add(checksum, bytes[bytes.len() - 1] as u16);
+    }
+    checksum
+}
+
+/// Builds a complete frame in a fixed size array without allocation.
+///
+/// `N` must be `MINIMUM_LENGTH + payload.len()`. This is a `const fn`,
+/// so frames whose contents are known in advance can be built at compile time.
+///
+/// ```
+/// use axdl::frame::{fixed_frame, AxdlFrameView, MINIMUM_LENGTH};
+///
+/// const END_PARTITION: [u8; MINIMUM_LENGTH] = fixed_frame(0x0003, &[]);
+/// assert!(AxdlFrameView::new(&END_PARTITION).is_valid());
+/// ```
+pub const fn fixed_frame<const N: usize>(command_response: u16, payload: &[u8]) -> [u8; N] {
+    assert!(
+        N == MINIMUM_LENGTH + payload.len(),
+        "frame size does not match the payload length"
+    );
+    assert!(payload.len() <= u16::MAX as usize, "payload is too large");
+
+    let mut buffer = [0u8; N];
+    let signature = SIGNATURE.to_le_bytes();
+    let length = payload.len() as u16;
+    buffer[0] = signature[0];
+    buffer[1] = signature[1];
+    buffer[2] = signature[2];
+    buffer[3] = signature[3];
+    buffer[4] = length as u8;
+    buffer[5] = (length >> 8) as u8;
+    buffer[6] = command_response as u8;
+    buffer[7] = (command_response >> 8) as u8;
+    let mut i = 0;
+    while i < payload.len() {
+        buffer[8 + i] = payload[i];
+        i += 1;
+    }
+
+    let mut checksum = ones_complement_add(length, command_response);
+    checksum = ones_complement_sum(checksum, payload);
+    let checksum = !checksum;
+    buffer[N - 2] = checksum as u8;
+    buffer[N - 1] = (checksum >> 8) as u8;
+    buffer
+}
+
+/// Calculates the 16-bit ones' complement sum of `bytes` taken as little endian words.
+///
+/// An odd trailing byte is padded with zero. The frame checksum field holds the
+/// complement of this sum over the length, command/response and payload fields.
+///
+/// 32-bit words are summed into four independent 64-bit lanes, which is equivalent to adding
+/// each 16-bit word with end-around carry. The lanes cannot overflow for inputs below 64 GiB,
+/// so the loop needs no carry handling and vectorizes on NEON and SSE2.
+pub fn checksum(bytes: &[u8]) -> u16 {
+    let mut lanes = [0u64; 4];
+    let (chunks, rest) = bytes.as_chunks::<16>();
+    for chunk in chunks {
+        for (lane, word) in lanes.iter_mut().zip(chunk.as_chunks::<4>().0) {
+            *lane += u32::from_le_bytes(*word) as u64;
+        }
+    }
+    let mut remainder = [0u8; 16];
+    remainder[..rest.len()].copy_from_slice(rest);
+    for (lane, word) in lanes.iter_mut().zip(remainder.as_chunks::<4>().0) {
+        *lane += u32::from_le_bytes(*word) as u64;
+    }
+    let sum = lanes.iter().fold(0u64, |sum, &lane| {
+        let (result, carry) = sum.overflowing_add(lane);
+        result + carry as u64
+    });
+
+    // Fold the 64-bit sum into 16 bits.
+    let mut folded = (sum & 0xffff_ffff) + (sum >> 32);
+    folded = (folded & 0xffff_ffff) + (folded >> 32);
+    let mut folded = (folded & 0xffff) + (folded >> 16);
+    while folded > 0xffff {
+        folded = (folded & 0xffff) + (folded >> 16);
+    }
+    folded as u16
+}
+
 #[derive(Debug)]
 pub struct AxdlFrameView<'a> {
     data: &'a [u8],
 }
 
-impl<'a> std::fmt::Display for AxdlFrameView<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl<'a> core::fmt::Display for AxdlFrameView<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
             "AxdlFrameView({:08X}, {}, {:04X}, {:02X?}, {:04X})",
@@ -79,6 +408,15 @@ impl<'a> AxdlFrameView<'a> {
         Some(u16::from_le_bytes([self.data[4], self.data[5]]))
     }
 
+    /// Returns the length of the whole frame according to its header, or `None` if the data
+    /// does not start with a frame header.
+    pub fn frame_length(&self) -> Option<usize> {
+        if self.signature()? != SIGNATURE {
+            return None;
+        }
+        Some(MINIMUM_LENGTH + self.length()? as usize)
+    }
+
     pub fn command_response(&self) -> Option<u16> {
         if self.data.len() < 4 + 2 + 2 {
             return None;
@@ -86,7 +424,7 @@ impl<'a> AxdlFrameView<'a> {
         Some(u16::from_le_bytes([self.data[6], self.data[7]]))
     }
 
-    pub fn payload(&self) -> Option<&[u8]> {
+    pub fn payload(&self) -> Option<&'a [u8]> {
         let payload_length = self.length()? as usize;
 
         if self.data.len() < 4 + 2 + 2 + payload_length + 2 {
@@ -96,7 +434,7 @@ impl<'a> AxdlFrameView<'a> {
         Some(&self.data[4 + 2 + 2..4 + 2 + 2 + payload_length])
     }
 
-    pub fn payload_unchecked(&self) -> Option<&[u8]> {
+    pub fn payload_unchecked(&self) -> Option<&'a [u8]> {
         if self.data.len() < 4 + 2 + 2 {
             return None;
         }
@@ -113,36 +451,15 @@ impl<'a> AxdlFrameView<'a> {
         ]))
     }
 
-    fn ones_complement_add(lhs: u16, rhs: u16) -> u16 {
-        let mut sum = lhs as u32 + rhs as u32;
-
-        while sum > 0xffff {
-            sum = (sum & 0xffff) + (sum >> 16);
-        }
-        sum as u16
-    }
     pub fn calculate_checksum(&self) -> Option<u16> {
-        let payload = if let Some(payload) = self.payload() {
-            payload
-        } else {
-            return None;
-        };
+        let payload = self.payload()?;
 
-        let length = self.length().unwrap();
-        let command_response = self.command_response().unwrap();
-        let mut checksum = self.checksum().unwrap();
-        checksum = Self::ones_complement_add(checksum, length);
-        checksum = Self::ones_complement_add(checksum, command_response);
-        for i in 0..payload.len() / 2 {
-            let value = u16::from_le_bytes([payload[i * 2], payload[i * 2 + 1]]);
-            checksum = Self::ones_complement_add(checksum, value);
-        }
-        if payload.len() % 2 == 1 {
-            checksum = Self::ones_complement_add(
-                checksum,
-                u16::from_le_bytes([payload[payload.len() - 1], 0]),
-            );
-        }
+        let length = self.length()?;
+        let command_response = self.command_response()?;
+        let mut checksum = self.checksum()?;
+        checksum = ones_complement_add(checksum, length);
+        checksum = ones_complement_add(checksum, command_response);
+        checksum = ones_complement_add(checksum, crate::frame::checksum(payload));
 
         Some(checksum)
     }
@@ -158,37 +475,58 @@ impl<'a> AxdlFrameView<'a> {
     }
 }
 
+/// Mutable view to build a frame in a caller supplied buffer.
+///
+/// The buffer is at least [`MINIMUM_LENGTH`] bytes long and its length determines the payload
+/// length set by [`AxdlFrameViewMut::init`].
 pub struct AxdlFrameViewMut<'a> {
     buffer: &'a mut [u8],
 }
 
 impl<'a> AxdlFrameViewMut<'a> {
-    pub fn new(buffer: &'a mut [u8]) -> Self {
-        Self { buffer }
+    /// Fails with [`UsbFrameError::Length`] if `buffer` cannot hold a frame header and checksum,
+    /// or is too long for the 16-bit length field.
+    pub fn new(buffer: &'a mut [u8]) -> Result<Self, UsbFrameError> {
+        if buffer.len() < MINIMUM_LENGTH || buffer.len() - MINIMUM_LENGTH > u16::MAX as usize {
+            return Err(UsbFrameError::Length);
+        }
+        Ok(Self { buffer })
     }
 
     pub fn init(&mut self) -> &mut Self {
-        let length = self.buffer.len();
+        // The length was checked in new().
+        let length = (self.buffer.len() - MINIMUM_LENGTH) as u16;
         self.set_signature(SIGNATURE);
-        self.set_length((length - MINIMUM_LENGTH) as u16);
+        self.buffer[4..6].copy_from_slice(&length.to_le_bytes());
 
         self
     }
 
     pub fn signature(&self) -> u32 {
-        AxdlFrameView::new(self.buffer).signature().unwrap()
+        u32::from_le_bytes([
+            self.buffer[0],
+            self.buffer[1],
+            self.buffer[2],
+            self.buffer[3],
+        ])
     }
 
     pub fn length(&self) -> u16 {
-        AxdlFrameView::new(self.buffer).length().unwrap()
+        u16::from_le_bytes([self.buffer[4], self.buffer[5]])
     }
 
     pub fn command_response(&self) -> u16 {
-        AxdlFrameView::new(self.buffer).command_response().unwrap()
+        u16::from_le_bytes([self.buffer[6], self.buffer[7]])
     }
 
     pub fn checksum(&self) -> u16 {
-        AxdlFrameView::new(self.buffer).checksum().unwrap()
+        let end = self.payload_end();
+        u16::from_le_bytes([self.buffer[end], self.buffer[end + 1]])
+    }
+
+    /// Offset of the end of the payload, i.e. of the checksum, clamped to the buffer.
+    fn payload_end(&self) -> usize {
+        (4 + 2 + 2 + self.length() as usize).min(self.buffer.len() - 2)
     }
 
     pub fn set_signature(&mut self, signature: u32) -> &mut Self {
@@ -198,13 +536,16 @@ impl<'a> AxdlFrameViewMut<'a> {
         self.buffer[3] = ((signature >> 24) & 0xff) as u8;
         self
     }
-    pub fn set_length(&mut self, length: u16) -> &mut Self {
-        assert!(length as usize + 4 + 2 + 2 + 2 <= self.buffer.len());
+    /// Fails with [`UsbFrameError::Length`] if the payload does not fit in the buffer.
+    pub fn set_length(&mut self, length: u16) -> Result<&mut Self, UsbFrameError> {
+        if length as usize + MINIMUM_LENGTH > self.buffer.len() {
+            return Err(UsbFrameError::Length);
+        }
 
         self.buffer[4] = (length & 0xff) as u8;
         self.buffer[5] = ((length >> 8) & 0xff) as u8;
 
-        self
+        Ok(self)
     }
     pub fn set_command_response(&mut self, command_response: u16) -> &mut Self {
         self.buffer[6] = (command_response & 0xff) as u8;
@@ -214,24 +555,161 @@ impl<'a> AxdlFrameViewMut<'a> {
     }
 
     pub fn payload_mut(&mut self) -> &mut [u8] {
-        let length = self.length() as usize;
-        &mut self.buffer[4 + 2 + 2..4 + 2 + 2 + length]
+        let end = self.payload_end();
+        &mut self.buffer[4 + 2 + 2..end]
     }
 
     pub fn set_checksum(&mut self, checksum: u16) -> &mut Self {
-        let length = self.length() as usize;
-        self.buffer[4 + 2 + 2 + length + 0] = (checksum & 0xff) as u8;
-        self.buffer[4 + 2 + 2 + length + 1] = (checksum >> 8) as u8;
+        let end = self.payload_end();
+        self.buffer[end] = (checksum & 0xff) as u8;
+        self.buffer[end + 1] = (checksum >> 8) as u8;
 
         self
     }
 
-    pub fn finalize(mut self) {
+    /// Computes and sets the checksum. Fails if the length field does not match the buffer.
+    pub fn finalize(mut self) -> Result<(), UsbFrameError> {
         self.set_checksum(0);
         let checksum = AxdlFrameView::new(self.buffer)
             .calculate_checksum()
-            .unwrap();
+            .ok_or(UsbFrameError::Length)?;
         self.set_checksum(!checksum);
+        Ok(())
+    }
+}
+
+/// Owned frame builder which allocates a buffer sized for its payload.
+///
+/// ```
+/// use axdl::frame::{AxdlFrame, AxdlFrameView};
+///
+/// let frame = AxdlFrame::new(0x0002).with_payload([0x00, 0x10]).build().unwrap();
+/// let view = AxdlFrameView::new(&frame);
+/// assert!(view.is_valid());
+/// assert_eq!(view.payload(), Some(&[0x00, 0x10][..]));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct AxdlFrame {
+    command_response: u16,
+    payload: Vec<u8>,
+}
+
+impl AxdlFrame {
+    pub fn new(command_response: u16) -> Self {
+        Self {
+            command_response,
+            payload: Vec::new(),
+        }
+    }
+
+    pub fn with_payload(mut self, payload: impl Into<Vec<u8>>) -> Self {
+        self.payload = payload.into();
+        self
+    }
+
+    pub fn command_response(&self) -> u16 {
+        self.command_response
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Returns the encoded frame including the signature, length and checksum.
+    ///
+    /// Fails with [`UsbFrameError::Length`] if the payload is longer than 65535 bytes.
+    pub fn build(&self) -> Result<Vec<u8>, UsbFrameError> {
+        let mut buffer = vec![0u8; MINIMUM_LENGTH + self.payload.len()];
+        let mut frame = AxdlFrameViewMut::new(&mut buffer)?;
+        frame.init().set_command_response(self.command_response);
+        frame.payload_mut().copy_from_slice(&self.payload);
+        frame.finalize()?;
+        Ok(buffer)
+    }
+}
+
+/// Reassembles frames from a byte stream which may split a frame across reads or join several
+/// frames in one read, as serial ports do.
+///
+/// Bytes before a frame signature are discarded. The checksum is not verified here.
+///
+/// ```
+/// use axdl::frame::{AxdlFrame, FrameAccumulator};
+///
+/// let frame = AxdlFrame::new(0x0080).build().unwrap();
+/// let mut accumulator = FrameAccumulator::new(1024);
+/// let mut buf = [0u8; 1024];
+/// accumulator.push(&frame[..5]);
+/// assert_eq!(accumulator.pop_frame(&mut buf).unwrap(), None);
+/// accumulator.push(&frame[5..]);
+/// assert_eq!(accumulator.pop_frame(&mut buf).unwrap(), Some(frame.len()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct FrameAccumulator {
+    buffer: Vec<u8>,
+    max_frame_length: usize,
+}
+
+impl FrameAccumulator {
+    pub fn new(max_frame_length: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            max_frame_length,
+        }
+    }
+
+    pub fn max_frame_length(&self) -> usize {
+        self.max_frame_length
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Returns whether no bytes of an incomplete frame are buffered.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Moves the first complete frame into `buf` and returns its length, or `None` if more data is needed.
+    ///
+    /// A frame longer than the maximum frame length or `buf` is dropped with [`UsbFrameError::Length`].
+    pub fn pop_frame(&mut self, buf: &mut [u8]) -> Result<Option<usize>, UsbFrameError> {
+        let signature = SIGNATURE.to_le_bytes();
+        match self
+            .buffer
+            .windows(signature.len())
+            .position(|window| window == signature)
+        {
+            Some(start) => {
+                self.buffer.drain(..start);
+            }
+            None => {
+                // Keep a tail which may be the beginning of a signature.
+                let keep = self.buffer.len().min(signature.len() - 1);
+                self.buffer.drain(..self.buffer.len() - keep);
+                return Ok(None);
+            }
+        }
+
+        let Some(length) = AxdlFrameView::new(&self.buffer).frame_length() else {
+            return Ok(None);
+        };
+        if length > self.max_frame_length || length > buf.len() {
+            // Skip the signature so that the next call resynchronizes on the following frame.
+            self.buffer.drain(..signature.len());
+            return Err(UsbFrameError::Length);
+        }
+        if self.buffer.len() < length {
+            return Ok(None);
+        }
+        buf[..length].copy_from_slice(&self.buffer[..length]);
+        self.buffer.drain(..length);
+        Ok(Some(length))
     }
 }
 
@@ -245,12 +723,13 @@ mod test {
         let view = AxdlFrameView::new(&data);
         assert_eq!(view.signature(), Some(SIGNATURE));
         assert_eq!(view.length(), Some(0));
+        assert_eq!(view.frame_length(), Some(10));
         assert_eq!(view.command_response(), Some(0x0001));
-        assert_eq!(view.payload(), Some(&data[4 + 2 + 2..4 + 2 + 2 as usize]));
+        assert_eq!(view.payload(), Some(&data[4 + 2 + 2..4 + 2 + 2]));
         assert_eq!(view.checksum(), Some(0xfffe));
         assert_eq!(view.calculate_checksum(), Some(0xffff));
-        assert_eq!(view.verify_checksum(), true);
-        assert_eq!(view.is_valid(), true);
+        assert!(view.verify_checksum());
+        assert!(view.is_valid());
     }
 
     #[test]
@@ -260,14 +739,11 @@ mod test {
         assert_eq!(view.signature(), Some(SIGNATURE));
         assert_eq!(view.length(), Some(8));
         assert_eq!(view.command_response(), Some(0x0001));
-        assert_eq!(
-            view.payload(),
-            Some(&data[4 + 2 + 2..4 + 2 + 8 + 2 as usize])
-        );
+        assert_eq!(view.payload(), Some(&data[4 + 2 + 2..4 + 2 + 8 + 2]));
         assert_eq!(view.checksum(), Some(0x94f5));
         assert_eq!(view.calculate_checksum(), Some(0xffff));
-        assert_eq!(view.verify_checksum(), true);
-        assert_eq!(view.is_valid(), true);
+        assert!(view.verify_checksum());
+        assert!(view.is_valid());
     }
 
     #[test]
@@ -279,28 +755,24 @@ mod test {
         assert_eq!(view.signature(), Some(SIGNATURE));
         assert_eq!(view.length(), Some(16));
         assert_eq!(view.command_response(), Some(0x0081));
-        assert_eq!(
-            view.payload(),
-            Some(&data[4 + 2 + 2..4 + 2 + 16 + 2 as usize])
-        );
+        assert_eq!(view.payload(), Some(&data[4 + 2 + 2..4 + 2 + 16 + 2]));
         assert_eq!(view.checksum(), Some(0x5c79));
         assert_eq!(view.calculate_checksum(), Some(0xffff));
-        assert_eq!(view.verify_checksum(), true);
-        assert_eq!(view.is_valid(), true);
+        assert!(view.verify_checksum());
+        assert!(view.is_valid());
     }
 
     #[test]
     fn test_axdl_frame_view_mut() {
         let mut data = [0u8; 12];
 
-        let mut view_mut = AxdlFrameViewMut::new(&mut data);
+        let mut view_mut = AxdlFrameViewMut::new(&mut data).unwrap();
         view_mut
             .init()
             .set_command_response(0x1234)
             .set_checksum(0x5678)
             .payload_mut()
             .copy_from_slice(&[0x9a, 0xbc]);
-        drop(view_mut);
 
         let view = AxdlFrameView::new(&data);
         assert_eq!(view.signature(), Some(SIGNATURE));
@@ -313,46 +785,46 @@ mod test {
     #[test]
     fn test_axdl_frame_view_mut_empty() {
         let mut data = [0u8; 10];
-        let mut view_mut = AxdlFrameViewMut::new(&mut data);
+        let mut view_mut = AxdlFrameViewMut::new(&mut data).unwrap();
         view_mut.init();
-        view_mut.finalize();
+        view_mut.finalize().unwrap();
 
         let view = AxdlFrameView::new(&data);
         assert_eq!(view.signature(), Some(SIGNATURE));
         assert_eq!(view.length(), Some(0));
         assert_eq!(view.command_response(), Some(0x0000));
-        assert_eq!(view.payload(), Some(&data[4 + 2 + 2..4 + 2 + 2 as usize]));
+        assert_eq!(view.payload(), Some(&data[4 + 2 + 2..4 + 2 + 2]));
         assert_eq!(view.checksum(), Some(0xffff));
         assert_eq!(view.calculate_checksum(), Some(0xffff));
-        assert_eq!(view.verify_checksum(), true);
-        assert_eq!(view.is_valid(), true);
+        assert!(view.verify_checksum());
+        assert!(view.is_valid());
     }
 
     #[test]
     fn test_axdl_frame_view_mut_empty_command() {
         let mut data = [0u8; 10];
-        let mut view_mut = AxdlFrameViewMut::new(&mut data);
+        let mut view_mut = AxdlFrameViewMut::new(&mut data).unwrap();
         view_mut.init().set_command_response(0xcafe);
-        view_mut.finalize();
+        view_mut.finalize().unwrap();
 
         let view = AxdlFrameView::new(&data);
         assert_eq!(view.signature(), Some(SIGNATURE));
         assert_eq!(view.length(), Some(0));
         assert_eq!(view.command_response(), Some(0xcafe));
-        assert_eq!(view.payload(), Some(&data[4 + 2 + 2..4 + 2 + 2 as usize]));
+        assert_eq!(view.payload(), Some(&data[4 + 2 + 2..4 + 2 + 2]));
         assert_eq!(view.checksum(), Some(!0xcafe));
         assert_eq!(view.calculate_checksum(), Some(0xffff));
-        assert_eq!(view.verify_checksum(), true);
-        assert_eq!(view.is_valid(), true);
+        assert!(view.verify_checksum());
+        assert!(view.is_valid());
     }
 
     #[test]
     fn test_axdl_frame_view_mut_with_payload() {
         let mut data = [0u8; 12];
-        let mut view_mut = AxdlFrameViewMut::new(&mut data);
+        let mut view_mut = AxdlFrameViewMut::new(&mut data).unwrap();
         view_mut.init().set_command_response(0xcafe);
         view_mut.payload_mut().copy_from_slice(&[0x01, 0x02]);
-        view_mut.finalize();
+        view_mut.finalize().unwrap();
 
         let view = AxdlFrameView::new(&data);
         assert_eq!(view.signature(), Some(SIGNATURE));
@@ -361,7 +833,164 @@ mod test {
         assert_eq!(view.payload(), Some(&[0x01, 0x02][..]));
         assert_eq!(view.checksum(), Some(!(0xcafe + 0x0002 + 0x0201)));
         assert_eq!(view.calculate_checksum(), Some(0xffff));
-        assert_eq!(view.verify_checksum(), true);
-        assert_eq!(view.is_valid(), true);
+        assert!(view.verify_checksum());
+        assert!(view.is_valid());
+    }
+
+    #[test]
+    fn test_axdl_frame_build() {
+        let data = AxdlFrame::new(0x0001)
+            .with_payload(hex_literal::hex!("00 00 00 03 00 68 01 00"))
+            .build()
+            .unwrap();
+        assert_eq!(
+            data,
+            hex_literal::hex!("9f 8e 6d 5c 08 00 01 00 00 00 00 03 00 68 01 00 f5 94")
+        );
+    }
+
+    #[test]
+    fn test_axdl_frame_length_errors() {
+        assert!(matches!(
+            AxdlFrameViewMut::new(&mut [0u8; MINIMUM_LENGTH - 1]),
+            Err(UsbFrameError::Length)
+        ));
+        let mut data = [0u8; 12];
+        let mut view_mut = AxdlFrameViewMut::new(&mut data).unwrap();
+        assert!(matches!(view_mut.set_length(3), Err(UsbFrameError::Length)));
+        assert!(matches!(
+            AxdlFrame::new(0x000b)
+                .with_payload(vec![0u8; u16::MAX as usize + 1])
+                .build(),
+            Err(UsbFrameError::Length)
+        ));
+    }
+
+    #[test]
+    fn test_axdl_frame_build_empty() {
+        let data = AxdlFrame::new(0xcafe).build().unwrap();
+        let view = AxdlFrameView::new(&data);
+        assert_eq!(view.length(), Some(0));
+        assert_eq!(view.command_response(), Some(0xcafe));
+        assert!(view.is_valid());
+    }
+
+    #[test]
+    fn test_fixed_frame() {
+        const EMPTY: [u8; MINIMUM_LENGTH] = fixed_frame(0x0003, &[]);
+        assert_eq!(EMPTY.as_slice(), AxdlFrame::new(0x0003).build().unwrap());
+
+        let payload = hex_literal::hex!("00 00 00 03 00 68 01 00");
+        let frame: [u8; MINIMUM_LENGTH + 8] = fixed_frame(0x0001, &payload);
+        assert_eq!(
+            frame,
+            hex_literal::hex!("9f 8e 6d 5c 08 00 01 00 00 00 00 03 00 68 01 00 f5 94")
+        );
+
+        let frame: [u8; MINIMUM_LENGTH + 3] = fixed_frame(0xcafe, &[0x01, 0x02, 0x03]);
+        assert!(AxdlFrameView::new(&frame).is_valid());
+        assert_eq!(
+            frame.as_slice(),
+            AxdlFrame::new(0xcafe)
+                .with_payload([0x01, 0x02, 0x03])
+                .build()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_checksum_matches_word_sum() {
+        let data = (0..1027u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        for length in [0, 1, 2, 7, 8, 9, 15, 16, 17, 1026, 1027] {
+            let bytes = &data[..length];
+            assert_eq!(
+                checksum(bytes),
+                ones_complement_sum(0, bytes),
+                "length={}",
+                length
+            );
+        }
+        assert_eq!(checksum(&[0xff; 4096]), 0xffff);
+        assert_eq!(checksum(&[0x01, 0x02, 0x03]), 0x0201 + 0x0003);
+    }
+
+    #[test]
+    fn test_frame_accumulator() {
+        let first = AxdlFrame::new(0x0081)
+            .with_payload(*b"romcode")
+            .build()
+            .unwrap();
+        let second = AxdlFrame::new(0x0080).build().unwrap();
+        let mut stream = vec![0x00, 0x9f, 0x8e];
+        stream.extend_from_slice(&first);
+        stream.extend_from_slice(&second);
+
+        let mut accumulator = FrameAccumulator::new(64);
+        let mut buf = [0u8; 64];
+        let mut frames = Vec::new();
+        for chunk in stream.chunks(3) {
+            accumulator.push(chunk);
+            while let Some(length) = accumulator.pop_frame(&mut buf).unwrap() {
+                frames.push(buf[..length].to_vec());
+            }
+        }
+        assert_eq!(frames, vec![first, second]);
+        assert!(accumulator.is_empty());
+    }
+
+    #[test]
+    fn test_frame_accumulator_too_long() {
+        let long = AxdlFrame::new(0x0093)
+            .with_payload([0u8; 32])
+            .build()
+            .unwrap();
+        let short = AxdlFrame::new(0x0080).build().unwrap();
+        let mut accumulator = FrameAccumulator::new(16);
+        let mut buf = [0u8; 64];
+        accumulator.push(&long);
+        accumulator.push(&short);
+        assert!(matches!(
+            accumulator.pop_frame(&mut buf),
+            Err(UsbFrameError::Length)
+        ));
+        assert_eq!(accumulator.pop_frame(&mut buf).unwrap(), Some(short.len()));
+    }
+
+    #[test]
+    fn test_command_layouts() {
+        for (i, code) in commands::CODES.iter().enumerate() {
+            assert_eq!(commands::lookup(code.value), Some(code));
+            assert!(commands::CODES[i + 1..]
+                .iter()
+                .all(|other| other.value != code.value));
+            for layout in code.layouts {
+                let Some(length) = layout.length else {
+                    continue;
+                };
+                for field in layout.fields {
+                    let size = match field.field_type {
+                        commands::FieldType::U16 => 2,
+                        commands::FieldType::U32 => 4,
+                        commands::FieldType::U64 => 8,
+                        commands::FieldType::Utf16 { length } => length,
+                        _ => 0,
+                    };
+                    assert!(field.offset + size <= length, "{}", field.name);
+                }
+            }
+        }
+        let start_partition = commands::lookup(commands::START_PARTITION).unwrap();
+        assert_eq!(
+            start_partition.layout(88).unwrap().fields[0].name,
+            "partition"
+        );
+        assert!(start_partition.layout(4).is_none());
+        let start_block = commands::lookup(commands::START_BLOCK).unwrap();
+        assert_eq!(
+            start_block.layout(12).unwrap().fields[0].field_type,
+            commands::FieldType::U16
+        );
     }
 }
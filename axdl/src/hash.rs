@@ -0,0 +1,306 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hash algorithms for verification, comparing images and manifests.
+//!
+//! Which one fits depends on the job: factory specifications often ask for CRC-32 or SHA-256,
+//! while xxHash64 is much faster for comparisons on the same host.
+
+use sha2::Digest as _;
+
+/// Hash algorithm, named `crc32`, `sha256` or `xxhash64` in its textual form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Digest {
+    Crc32,
+    Sha256,
+    #[default]
+    XxHash64,
+}
+
+impl Digest {
+    pub const ALL: [Digest; 3] = [Digest::Crc32, Digest::Sha256, Digest::XxHash64];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Digest::Crc32 => "crc32",
+            Digest::Sha256 => "sha256",
+            Digest::XxHash64 => "xxhash64",
+        }
+    }
+
+    pub fn hasher(self) -> Hasher {
+        Hasher(match self {
+            Digest::Crc32 => State::Crc32(crc32fast::Hasher::new()),
+            Digest::Sha256 => State::Sha256(sha2::Sha256::new()),
+            Digest::XxHash64 => State::XxHash64(XxHash64::new(0)),
+        })
+    }
+
+    /// Returns the digest of `data`.
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// Returns the digest of everything read from `reader`.
+    pub fn digest_reader(self, reader: &mut impl std::io::Read) -> std::io::Result<Vec<u8>> {
+        let mut hasher = self.hasher();
+        std::io::copy(reader, &mut hasher)?;
+        Ok(hasher.finalize())
+    }
+}
+
+impl std::fmt::Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for Digest {
+    type Err = crate::AxdlError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|digest| digest.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| {
+                crate::AxdlError::InvalidConfig(format!(
+                    "unknown digest {}, expected crc32, sha256 or xxhash64",
+                    s
+                ))
+            })
+    }
+}
+
+/// Formats a digest as lowercase hexadecimal.
+pub fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Incremental hasher of a [`Digest`]. It can also be written to, e.g. with `std::io::copy`.
+#[derive(Clone)]
+pub struct Hasher(State);
+
+#[derive(Clone)]
+enum State {
+    Crc32(crc32fast::Hasher),
+    Sha256(sha2::Sha256),
+    XxHash64(XxHash64),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.0 {
+            State::Crc32(hasher) => hasher.update(data),
+            State::Sha256(hasher) => hasher.update(data),
+            State::XxHash64(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Returns the digest in its canonical byte order, i.e. big endian for CRC-32 and xxHash64,
+    /// so that its hexadecimal form matches that of other tools.
+    pub fn finalize(self) -> Vec<u8> {
+        match self.0 {
+            State::Crc32(hasher) => hasher.finalize().to_be_bytes().to_vec(),
+            State::Sha256(hasher) => hasher.finalize().to_vec(),
+            State::XxHash64(hasher) => hasher.finish().to_be_bytes().to_vec(),
+        }
+    }
+}
+
+impl std::io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+const PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME64_5: u64 = 0x27D4_EB2F_1656_67C5;
+/// Bytes consumed by one round of the four lanes.
+const STRIPE: usize = 32;
+
+/// Streaming xxHash64, see <https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md>.
+#[derive(Clone)]
+struct XxHash64 {
+    seed: u64,
+    lanes: [u64; 4],
+    buffer: [u8; STRIPE],
+    buffered: usize,
+    length: u64,
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(word)
+}
+
+fn round(lane: u64, input: u64) -> u64 {
+    lane.wrapping_add(input.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1)
+}
+
+fn merge_round(hash: u64, lane: u64) -> u64 {
+    (hash ^ round(0, lane))
+        .wrapping_mul(PRIME64_1)
+        .wrapping_add(PRIME64_4)
+}
+
+impl XxHash64 {
+    fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            lanes: [
+                seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+                seed.wrapping_add(PRIME64_2),
+                seed,
+                seed.wrapping_sub(PRIME64_1),
+            ],
+            buffer: [0; STRIPE],
+            buffered: 0,
+            length: 0,
+        }
+    }
+
+    fn consume(lanes: &mut [u64; 4], stripe: &[u8]) {
+        for (i, lane) in lanes.iter_mut().enumerate() {
+            *lane = round(*lane, read_u64(&stripe[i * 8..]));
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if self.buffered > 0 {
+            let length = data.len().min(STRIPE - self.buffered);
+            self.buffer[self.buffered..self.buffered + length].copy_from_slice(&data[..length]);
+            self.buffered += length;
+            data = &data[length..];
+            if self.buffered < STRIPE {
+                return;
+            }
+            Self::consume(&mut self.lanes, &self.buffer);
+            self.buffered = 0;
+        }
+        let mut stripes = data.chunks_exact(STRIPE);
+        for stripe in &mut stripes {
+            Self::consume(&mut self.lanes, stripe);
+        }
+        let rest = stripes.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    fn finish(&self) -> u64 {
+        let mut hash = if self.length >= STRIPE as u64 {
+            let [v1, v2, v3, v4] = self.lanes;
+            let hash = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            self.lanes
+                .iter()
+                .fold(hash, |hash, &lane| merge_round(hash, lane))
+        } else {
+            self.seed.wrapping_add(PRIME64_5)
+        };
+        hash = hash.wrapping_add(self.length);
+
+        let mut rest = &self.buffer[..self.buffered];
+        while rest.len() >= 8 {
+            hash = (hash ^ round(0, read_u64(rest)))
+                .rotate_left(27)
+                .wrapping_mul(PRIME64_1)
+                .wrapping_add(PRIME64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let word = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as u64;
+            hash = (hash ^ word.wrapping_mul(PRIME64_1))
+                .rotate_left(23)
+                .wrapping_mul(PRIME64_2)
+                .wrapping_add(PRIME64_3);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            hash = (hash ^ (byte as u64).wrapping_mul(PRIME64_5))
+                .rotate_left(11)
+                .wrapping_mul(PRIME64_1);
+        }
+
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME64_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME64_3);
+        hash ^ (hash >> 32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_known_digests() {
+        let hex = |digest: Digest, data: &[u8]| hex::encode(digest.digest(data));
+        assert_eq!(hex(Digest::Crc32, b"123456789"), "cbf43926");
+        assert_eq!(
+            hex(Digest::Sha256, b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(hex(Digest::XxHash64, b""), "ef46db3751d8e999");
+        assert_eq!(hex(Digest::XxHash64, b"a"), "d24ec4f1a98c6e5b");
+        assert_eq!(hex(Digest::XxHash64, b"abc"), "44bc2cf5ad770999");
+        assert_eq!(
+            hex(Digest::XxHash64, b"Nobody inspects the spammish repetition"),
+            "fbcea83c8a378bf1"
+        );
+    }
+
+    #[test]
+    fn test_streaming_matches_one_shot() {
+        let data = (0..1024u32).map(|i| i as u8).collect::<Vec<_>>();
+        assert_eq!(
+            hex::encode(Digest::XxHash64.digest(&data)),
+            "6f3914f18fe4df57"
+        );
+        for digest in Digest::ALL {
+            for chunk_size in [1, 7, 32, 33, 1000] {
+                let mut hasher = digest.hasher();
+                for chunk in data.chunks(chunk_size) {
+                    hasher.update(chunk);
+                }
+                assert_eq!(hasher.finalize(), digest.digest(&data), "{}", digest);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("SHA256".parse::<Digest>().unwrap(), Digest::Sha256);
+        assert_eq!(Digest::XxHash64.to_string(), "xxhash64");
+        assert!("md5".parse::<Digest>().is_err());
+    }
+}
@@ -13,65 +13,546 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::time::Duration;
+// Errors are returned to the caller; only tests may unwrap.
+#![cfg_attr(not(test), deny(clippy::unwrap_used))]
 
+extern crate alloc;
+
+pub mod cancel;
 pub mod communication;
+pub mod content;
+pub mod context;
+mod digest;
+pub mod filter;
 pub mod frame;
+pub mod hash;
 pub mod partition;
+pub mod progress;
+pub mod provision;
+pub mod report;
+pub mod source;
+mod time;
 pub mod transport;
 
+use context::ResultExt;
+use progress::{BootStage, Phase, ReportPhase};
+use report::{DownloadReport, PartitionReport, VerifyResult};
+
 #[derive(Debug, thiserror::Error)]
 pub enum AxdlError {
     #[cfg(feature = "usb")]
-    #[error("USB error: {0}")]
+    #[error("[AXDL-USB-001] USB error: {0}")]
     UsbError(rusb::Error),
     #[cfg(feature = "serial")]
-    #[error("Serial communication error: {0}")]
+    #[error("[AXDL-SER-001] Serial communication error: {0}")]
     SerialError(serialport::Error),
     #[cfg(feature = "webusb")]
-    #[error("WebUSB error: {0}")]
+    #[error("[AXDL-WEB-001] WebUSB error: {0}")]
     WebUsbError(webusb_web::Error),
+    /// The USB interface of the device could not be claimed, typically because an OS driver
+    /// such as cdc_acm on Linux or another program holds it.
     #[cfg(feature = "webusb")]
-    #[error("WebSerial error: {0:?}")]
+    #[error("[AXDL-WEB-003] Failed to claim the USB interface, another driver or program may be using the device: {0}")]
+    InterfaceClaimFailed(webusb_web::Error),
+    #[cfg(feature = "webserial")]
+    #[error("[AXDL-WEB-002] WebSerial error: {0:?}")]
     WebSerialError(js_sys::wasm_bindgen::JsValue),
-    #[error("Invalid frame received")]
+    #[error("[AXDL-PROTO-001] Invalid frame received")]
     InvalidFrame,
-    #[error("Failed to decode handshake: {0}")]
+    #[error("[AXDL-PROTO-002] Frame error: {0}")]
+    FrameError(#[from] frame::UsbFrameError),
+    #[error("[AXDL-PROTO-003] Failed to decode handshake: {0}")]
     HandshakeDecodeError(std::str::Utf8Error),
-    #[error("Unexpected handshake: {0}")]
+    #[error("[AXDL-PROTO-004] Unexpected handshake: {0}")]
     UnexpectedHandshake(String),
-    #[error("Frame has no payload")]
+    #[error("[AXDL-PROTO-005] Frame has no payload")]
     NoPayload,
-    #[error("Unexpected response: {0:02X}")]
+    #[error("[AXDL-PROTO-006] Unexpected response: {0:02X}")]
     UnexpectedResponse(u16),
-    #[error("IO Error: {0}, {1}")]
+    #[error("[AXDL-IO-001] IO Error: {0}, {1}")]
     IoError(String, std::io::Error),
-    #[error("AXP image zip error: {0}")]
+    #[error("[AXDL-IMG-001] AXP image zip error: {0}")]
     ImageZipError(#[from] zip::result::ZipError),
     #[cfg(feature = "async")]
-    #[error("AXP image zip error: {0}")]
+    #[error("[AXDL-IMG-002] AXP image zip error: {0}")]
     ImageAsyncZipError(#[from] async_zip::error::ZipError),
-    #[error("Image error: {0}")]
+    #[error("[AXDL-IMG-003] Image error: {0}")]
     ImageError(String),
-    #[error("Device not found")]
+    #[error("[AXDL-IMG-004] Partition name is too long: {0}")]
+    PartitionNameTooLong(String),
+    #[error("[AXDL-DEV-001] Device not found")]
     DeviceNotFound,
-    #[error("Device timeout")]
+    #[error("[AXDL-DEV-002] Device timeout")]
     DeviceTimeout,
-    #[error("User cancelled the operation")]
+    #[error("[AXDL-DEV-003] Device disconnected; reconnect it in download mode and start again")]
+    DeviceDisconnected,
+    #[error("[AXDL-USER-001] User cancelled the operation")]
     UserCancelled,
-    #[error("Unsupported: {0}")]
+    #[error("[AXDL-DEV-004] Device {0} is in use by another process")]
+    DeviceBusy(String),
+    #[error("[AXDL-CFG-001] Unsupported: {0}")]
     Unsupported(String),
+    #[error("[AXDL-CFG-002] Invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("[AXDL-PROTO-007] Invalid session state: {0}")]
+    InvalidState(String),
+    #[error("[AXDL-CFG-003] Partition table needs {required} bytes, but the flash holds only {capacity} bytes")]
+    FlashTooSmall { required: u64, capacity: u64 },
+    #[error("[AXDL-PROTO-008] Frame of {length} bytes exceeds the max frame size of {max} bytes")]
+    FrameTooLarge { length: usize, max: usize },
+    #[error("[AXDL-DL-002] The session did not finish within the time limit of {0:?}")]
+    DeadlineExceeded(std::time::Duration),
+    #[error("[AXDL-DL-001] Download of {failed} failed: {source} (completed: {completed:?}, not downloaded: {remaining:?})")]
+    PartialFailure {
+        /// Images downloaded before the failure.
+        completed: Vec<String>,
+        failed: String,
+        /// Images not downloaded because of the failure.
+        remaining: Vec<String>,
+        source: Box<AxdlError>,
+    },
+    #[error("{source} ({context})")]
+    Context {
+        context: context::ErrorContext,
+        source: Box<AxdlError>,
+    },
 }
 
-#[derive(Debug)]
+impl AxdlError {
+    /// Returns the stable code of the error, which its message starts with.
+    ///
+    /// Codes identify the kind of error across versions, also when the message changes, so a
+    /// code is never reused for another kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "usb")]
+            AxdlError::UsbError(..) => "AXDL-USB-001",
+            #[cfg(feature = "serial")]
+            AxdlError::SerialError(..) => "AXDL-SER-001",
+            #[cfg(feature = "webusb")]
+            AxdlError::WebUsbError(..) => "AXDL-WEB-001",
+            #[cfg(feature = "webusb")]
+            AxdlError::InterfaceClaimFailed(..) => "AXDL-WEB-003",
+            #[cfg(feature = "webserial")]
+            AxdlError::WebSerialError(..) => "AXDL-WEB-002",
+            AxdlError::InvalidFrame => "AXDL-PROTO-001",
+            AxdlError::FrameError(..) => "AXDL-PROTO-002",
+            AxdlError::HandshakeDecodeError(..) => "AXDL-PROTO-003",
+            AxdlError::UnexpectedHandshake(..) => "AXDL-PROTO-004",
+            AxdlError::NoPayload => "AXDL-PROTO-005",
+            AxdlError::UnexpectedResponse(..) => "AXDL-PROTO-006",
+            AxdlError::IoError(..) => "AXDL-IO-001",
+            AxdlError::ImageZipError(..) => "AXDL-IMG-001",
+            #[cfg(feature = "async")]
+            AxdlError::ImageAsyncZipError(..) => "AXDL-IMG-002",
+            AxdlError::ImageError(..) => "AXDL-IMG-003",
+            AxdlError::PartitionNameTooLong(..) => "AXDL-IMG-004",
+            AxdlError::DeviceNotFound => "AXDL-DEV-001",
+            AxdlError::DeviceTimeout => "AXDL-DEV-002",
+            AxdlError::DeviceDisconnected => "AXDL-DEV-003",
+            AxdlError::UserCancelled => "AXDL-USER-001",
+            AxdlError::DeviceBusy(..) => "AXDL-DEV-004",
+            AxdlError::Unsupported(..) => "AXDL-CFG-001",
+            AxdlError::InvalidConfig(..) => "AXDL-CFG-002",
+            AxdlError::InvalidState(..) => "AXDL-PROTO-007",
+            AxdlError::FlashTooSmall { .. } => "AXDL-CFG-003",
+            AxdlError::FrameTooLarge { .. } => "AXDL-PROTO-008",
+            AxdlError::PartialFailure { .. } => "AXDL-DL-001",
+            AxdlError::DeadlineExceeded(..) => "AXDL-DL-002",
+            AxdlError::Context { source, .. } => source.code(),
+        }
+    }
+
+    /// Returns the error without the [`context::ErrorContext`] attached to it.
+    pub fn root_cause(&self) -> &AxdlError {
+        match self {
+            AxdlError::Context { source, .. } => source.root_cause(),
+            e => e,
+        }
+    }
+
+    /// Returns whether the device went away, also when that stopped a download midway.
+    pub fn is_disconnected(&self) -> bool {
+        match self {
+            AxdlError::DeviceDisconnected => true,
+            AxdlError::PartialFailure { source, .. } | AxdlError::Context { source, .. } => {
+                source.is_disconnected()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Options which differ for some partitions, e.g. small blocks and more retries for a tiny spl
+/// partition. `None` keeps the value of the [`DownloadConfig`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PartitionOptions {
+    /// Block size used to download the partition, see [`DownloadConfig::image_chunk_size`].
+    pub chunk_size: Option<usize>,
+    /// Whether the partition is read back after writing it, see [`DownloadConfig::verify`].
+    pub verify: Option<bool>,
+    /// Retries of a failed block, see [`communication::RetryPolicy::block`].
+    pub block_retries: Option<u32>,
+}
+
+impl PartitionOptions {
+    /// Returns these options with those set in `other` replacing them.
+    pub fn merge(self, other: &PartitionOptions) -> Self {
+        Self {
+            chunk_size: other.chunk_size.or(self.chunk_size),
+            verify: other.verify.or(self.verify),
+            block_retries: other.block_retries.or(self.block_retries),
+        }
+    }
+}
+
+/// Settings of one partition, after applying its [`PartitionOptions`].
+struct PartitionSettings {
+    chunk_size: usize,
+    verify: bool,
+    retry: communication::RetryPolicy,
+}
+
+#[derive(Debug, Clone)]
 pub struct DownloadConfig {
     pub exclude_rootfs: bool,
+    /// Size of the receive buffer, i.e. the largest response frame accepted from the device.
+    /// The partition table is not sent in a larger frame either.
+    pub max_frame_size: usize,
+    /// Block size used to download partition images. Must not exceed [`communication::MAX_BLOCK_SIZE`].
+    pub image_chunk_size: usize,
+    /// Rounds `image_chunk_size` down to a multiple of the endpoint max packet size once the
+    /// device is open. See [`communication::tune_chunk_size`].
+    pub auto_chunk_size: bool,
+    /// Checks every response against the protocol expectations and logs deviations.
+    pub strict: bool,
+    /// Drops a copy of an acknowledge received within this window, for firmware which sometimes
+    /// sends it twice. See [`communication::Session::with_duplicate_ack_window`].
+    pub duplicate_ack_window: Option<std::time::Duration>,
+    /// Reads back every written partition and compares it with the image.
+    ///
+    /// A mismatch does not abort the download; it is recorded in the returned [`DownloadReport`].
+    pub verify: bool,
+    /// Reads back each partition before writing it and skips it if it already holds the image.
+    pub skip_same: bool,
+    /// Per-device data written after all images.
+    pub provision: Option<provision::ProvisionData>,
+    pub timeouts: communication::Timeouts,
+    pub retry: communication::RetryPolicy,
+    pub handshakes: communication::HandshakePatterns,
+    /// Bytes sent to request a handshake, [`communication::DEFAULT_HANDSHAKE_REQUEST`] by
+    /// default.
+    pub handshake_request: Vec<u8>,
+    /// Partitions to download. All partitions are downloaded when empty.
+    ///
+    /// Entries match either the image name or the partition name, ignoring case.
+    pub include_partitions: Vec<String>,
+    /// Partitions to skip, matched the same way as `include_partitions`.
+    pub exclude_partitions: Vec<String>,
+    /// Image download speed limit in bytes per second, for the transports listed.
+    ///
+    /// Some USB-serial bridges drop data when flashed at full speed.
+    pub rate_limits: std::collections::HashMap<transport::TransportKind, u64>,
+    /// Pauses between commands, e.g. [`communication::Pacing::VENDOR_COMPAT`] for firmware
+    /// which only works at the pace of the vendor tool.
+    pub pacing: communication::Pacing,
+    /// Sends each block in one write with its start block command on serial transports, see
+    /// [`communication::Session::with_coalesced_writes`].
+    pub coalesce_writes: bool,
+    /// Sends a zero-length packet after every write which ends on a packet boundary, for
+    /// firmware which waits for one. See [`communication::Session::with_zero_length_packets`].
+    pub zero_length_packets: bool,
+    /// Filters applied to partition images while they are written, as pairs of an image or
+    /// partition name (matched like `include_partitions`) and a filter.
+    pub filters: Vec<(String, std::sync::Arc<dyn filter::ImageFilter>)>,
+    /// Data written instead of the images in the archive, as pairs of an image or partition
+    /// name (matched like `include_partitions`) and a source.
+    pub sources: Vec<(String, std::sync::Arc<source::PartitionSource>)>,
+    /// Options overriding the ones above for some partitions, as pairs of an image or partition
+    /// name (matched like `include_partitions`) and the options. Later entries win.
+    pub partition_options: Vec<(String, PartitionOptions)>,
+    /// Cancels the download from another thread or task, also while waiting for the device.
+    pub cancellation: Option<cancel::CancellationToken>,
+    /// Time limit of the whole session from the start of the download. It is checked between
+    /// blocks and commands, after which the download fails with
+    /// [`AxdlError::DeadlineExceeded`].
+    pub max_duration: Option<std::time::Duration>,
+    /// Digest of the segments compared by `verify`. The default is fast, a cryptographic one
+    /// also detects deliberate changes of the flash.
+    pub verify_digest: hash::Digest,
+    /// Storage capacity of the device in bytes. The partition table is checked against it
+    /// before anything is sent, as the device cannot be asked for it.
+    pub flash_capacity: Option<u64>,
+    /// Largest image in bytes which is read into memory while the device flushes the previous
+    /// partition, so that the next one starts without waiting for decompression. 0 disables it.
+    ///
+    /// Only used by [`download_image`].
+    pub prefetch_limit: u64,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            exclude_rootfs: false,
+            max_frame_size: communication::DEFAULT_MAX_FRAME_SIZE,
+            image_chunk_size: communication::DEFAULT_IMAGE_CHUNK_SIZE,
+            auto_chunk_size: true,
+            strict: false,
+            duplicate_ack_window: None,
+            verify: false,
+            skip_same: false,
+            provision: None,
+            timeouts: communication::Timeouts::default(),
+            retry: communication::RetryPolicy::default(),
+            handshakes: communication::HandshakePatterns::default(),
+            handshake_request: communication::DEFAULT_HANDSHAKE_REQUEST.to_vec(),
+            include_partitions: Vec::new(),
+            exclude_partitions: Vec::new(),
+            rate_limits: std::collections::HashMap::new(),
+            pacing: communication::Pacing::default(),
+            coalesce_writes: true,
+            zero_length_packets: false,
+            filters: Vec::new(),
+            sources: Vec::new(),
+            partition_options: Vec::new(),
+            cancellation: None,
+            max_duration: None,
+            verify_digest: hash::Digest::default(),
+            flash_capacity: None,
+            prefetch_limit: 0,
+        }
+    }
+}
+
+impl DownloadConfig {
+    /// Checks the configuration before any command is sent to the device.
+    pub fn validate(&self) -> Result<(), AxdlError> {
+        communication::validate_block_size(self.image_chunk_size)?;
+        if self.max_frame_size < frame::MINIMUM_LENGTH {
+            return Err(AxdlError::InvalidConfig(format!(
+                "max frame size {} is smaller than the minimum frame length {}",
+                self.max_frame_size,
+                frame::MINIMUM_LENGTH
+            )));
+        }
+        if let Some((kind, _)) = self.rate_limits.iter().find(|(_, &rate)| rate == 0) {
+            return Err(AxdlError::InvalidConfig(format!(
+                "rate limit for {} must be at least 1 byte per second",
+                kind
+            )));
+        }
+        if (self.verify || self.skip_same)
+            && self.image_chunk_size + frame::MINIMUM_LENGTH > self.max_frame_size
+        {
+            return Err(AxdlError::InvalidConfig(format!(
+                "chunk size {} does not fit in read responses with max frame size {}",
+                self.image_chunk_size, self.max_frame_size
+            )));
+        }
+        for (name, options) in &self.partition_options {
+            let Some(chunk_size) = options.chunk_size else {
+                continue;
+            };
+            communication::validate_block_size(chunk_size)?;
+            if (options.verify.unwrap_or(self.verify) || self.skip_same)
+                && chunk_size + frame::MINIMUM_LENGTH > self.max_frame_size
+            {
+                return Err(AxdlError::InvalidConfig(format!(
+                    "chunk size {} of {} does not fit in read responses with max frame size {}",
+                    chunk_size, name, self.max_frame_size
+                )));
+            }
+        }
+        if self.handshake_request.is_empty() {
+            return Err(AxdlError::InvalidConfig(
+                "handshake request must not be empty".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns whether the code image should be downloaded.
+    pub fn is_selected(&self, image: &partition::Image) -> bool {
+        if self.exclude_rootfs && image.name() == "ROOTFS" {
+            return false;
+        }
+        let matches = |names: &[String]| names.iter().any(|name| Self::image_matches(image, name));
+        (self.include_partitions.is_empty() || matches(&self.include_partitions))
+            && !matches(&self.exclude_partitions)
+    }
+
+    /// Checks that every included partition and the provisioned partition exist in the project,
+    /// and that the partition table fits in the flash and in a frame.
+    fn check_selection(&self, project: &partition::Project) -> Result<(), AxdlError> {
+        communication::set_partition_table_frame(project.partition_table(), self.max_frame_size)?;
+        if let Some(capacity) = self.flash_capacity {
+            match project.partition_table().required_size() {
+                Some(required) if required > capacity => {
+                    return Err(AxdlError::FlashTooSmall { required, capacity });
+                }
+                Some(_) => {}
+                None => tracing::warn!(
+                    "unknown partition table unit {}, not checking the flash capacity",
+                    project.partition_table().unit()
+                ),
+            }
+        }
+        if let Some(provision) = &self.provision {
+            if !project
+                .partition_table()
+                .partitions()
+                .iter()
+                .any(|partition| partition.name() == provision.partition)
+            {
+                return Err(AxdlError::InvalidConfig(format!(
+                    "provisioning partition {} not found in the partition table",
+                    provision.partition
+                )));
+            }
+        }
+        let filtered = self.filters.iter().map(|(name, _)| name);
+        let sourced = self.sources.iter().map(|(name, _)| name);
+        let overridden = self.partition_options.iter().map(|(name, _)| name);
+        for name in self
+            .include_partitions
+            .iter()
+            .chain(filtered)
+            .chain(sourced)
+            .chain(overridden)
+        {
+            if !project
+                .images_of_type(partition::ImageType::Code)
+                .any(|image| Self::image_matches(image, name))
+            {
+                return Err(AxdlError::InvalidConfig(format!(
+                    "partition {} not found in the image",
+                    name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the block size for partition images on a device with `max_packet_size`.
+    pub fn image_chunk_size_for(&self, max_packet_size: Option<usize>) -> usize {
+        self.tune_chunk_size(self.image_chunk_size, max_packet_size)
+    }
+
+    fn tune_chunk_size(&self, chunk_size: usize, max_packet_size: Option<usize>) -> usize {
+        let tuned = if self.auto_chunk_size {
+            communication::tune_chunk_size(chunk_size, max_packet_size)
+        } else {
+            chunk_size
+        };
+        tracing::debug!(
+            "image chunk size: {} (max packet size {:?})",
+            tuned,
+            max_packet_size
+        );
+        tuned
+    }
+
+    /// Returns the options given for `image`, merged in order.
+    pub fn options_for(&self, image: &partition::Image) -> PartitionOptions {
+        self.partition_options
+            .iter()
+            .filter(|(name, _)| Self::image_matches(image, name))
+            .fold(PartitionOptions::default(), |options, (_, other)| {
+                options.merge(other)
+            })
+    }
+
+    /// Returns the settings of `image`, where `chunk_size` is the tuned default block size.
+    fn settings_for(
+        &self,
+        image: &partition::Image,
+        chunk_size: usize,
+        max_packet_size: Option<usize>,
+    ) -> PartitionSettings {
+        let options = self.options_for(image);
+        PartitionSettings {
+            chunk_size: options.chunk_size.map_or(chunk_size, |size| {
+                self.tune_chunk_size(size, max_packet_size)
+            }),
+            verify: options.verify.unwrap_or(self.verify),
+            retry: communication::RetryPolicy {
+                block: options.block_retries.unwrap_or(self.retry.block),
+                ..self.retry
+            },
+        }
+    }
+
+    /// Returns the filters applied to `image`.
+    pub fn filters_for(&self, image: &partition::Image) -> filter::Filters {
+        filter::Filters::new(
+            self.filters
+                .iter()
+                .filter(|(name, _)| Self::image_matches(image, name))
+                .map(|(_, filter)| filter.clone())
+                .collect(),
+        )
+    }
+
+    /// Returns the source which replaces the data of `image` in the archive, if any.
+    pub fn source_for(
+        &self,
+        image: &partition::Image,
+    ) -> Option<std::sync::Arc<source::PartitionSource>> {
+        self.sources
+            .iter()
+            .find(|(name, _)| Self::image_matches(image, name))
+            .map(|(_, source)| source.clone())
+    }
+
+    fn image_matches(image: &partition::Image, name: &str) -> bool {
+        image.name().eq_ignore_ascii_case(name)
+            || matches!(image.block(), partition::Block::Partition(id) if id.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Wraps an error raised while downloading `failed` with the outcome of the other images.
+fn partial_failure(
+    error: AxdlError,
+    report: &DownloadReport,
+    failed: &str,
+    remaining: &[&partition::Image],
+) -> AxdlError {
+    AxdlError::PartialFailure {
+        completed: report
+            .partitions
+            .iter()
+            .map(|partition| partition.image.clone())
+            .collect(),
+        failed: failed.to_string(),
+        remaining: remaining
+            .iter()
+            .map(|image| image.name().to_string())
+            .collect(),
+        source: Box::new(error),
+    }
 }
 
 pub trait DownloadProgress {
     fn is_cancelled(&self) -> bool;
     fn report_progress(&mut self, description: &str, progress: Option<f32>);
 
+    /// Called for every step of the download with its phase and a timestamp. The default
+    /// passes the description and progress on to [`Self::report_progress`].
+    fn report_event(&mut self, event: &progress::ProgressEvent<'_>) {
+        self.report_progress(event.description, event.progress);
+    }
+
+    /// Called once every block of `image_name` has been acknowledged, while the device is still
+    /// writing it out. This can take much longer than the transfer itself on slow storage.
+    fn report_flushing(&mut self, image_name: &str) {
+        self.report_phase(progress::Phase::Flush, Some(image_name), None);
+    }
+
+    /// Called when the device enters a boot stage, i.e. the romcode answered or a flash
+    /// downloader runs, e.g. to show the state of the device or to check the FDL versions.
+    /// The default ignores it.
+    fn report_stage(&mut self, _event: &progress::StageEvent<'_>) {}
+
     fn check_is_cancelled(&self) -> Result<(), AxdlError> {
         if self.is_cancelled() {
             Err(AxdlError::UserCancelled)
@@ -81,57 +562,477 @@ pub trait DownloadProgress {
     }
 }
 
-pub fn download_image<R: std::io::Read + std::io::Seek, Progress: DownloadProgress>(
-    image_reader: &mut R,
-    device: &mut transport::DynDevice,
-    config: &DownloadConfig,
-    progress: &mut Progress,
-) -> Result<(), AxdlError> {
-    // Open the specified image file and find the configuration XML file.
-    let mut archive = zip::ZipArchive::new(image_reader).map_err(AxdlError::ImageZipError)?;
-    let mut config_string = None;
+/// Reads back a partition block by block and compares it with its image, which may be
+/// given in several parts.
+struct Readback<'p> {
+    partition: &'p str,
+    length: u64,
+    offset: u64,
+    buffer: Vec<u8>,
+    filters: filter::Filters,
+}
 
-    progress.report_progress("Loading the AXP image configuration", None);
-    // Load the axp image configuration.
-    let project = {
-        for i in 0..archive.len() {
-            let mut file = archive.by_index(i)?;
-            if file.name().ends_with(".xml") {
-                config_string = Some(String::new());
-                std::io::Read::read_to_string(&mut file, config_string.as_mut().unwrap()).map_err(
-                    |e| AxdlError::ImageError(format!("failed to read configuration file: {}", e)),
-                )?;
-                break;
+impl<'p> Readback<'p> {
+    fn new(partition: &'p str, length: u64, chunk_size: usize) -> Self {
+        Self {
+            partition,
+            length,
+            offset: 0,
+            buffer: vec![0u8; chunk_size],
+            filters: filter::Filters::default(),
+        }
+    }
+
+    /// Compares the partition with the image as transformed by `filters`.
+    fn with_filters(mut self, filters: filter::Filters) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Returns the context of an error while reading back the current block.
+    fn error_context(&self, transport: transport::TransportKind) -> context::ErrorContext {
+        context::ErrorContext::new(Phase::Verify, self.partition)
+            .with_block(self.offset / self.buffer.len() as u64, self.offset)
+            .with_transport(transport)
+    }
+
+    /// Size of the next block to read back, which ends at `end` at the latest.
+    fn block_size(&self, end: u64) -> u32 {
+        (end - self.offset).min(self.buffer.len() as u64) as u32
+    }
+
+    /// Compares a block read back with the expected data in the front of the buffer.
+    ///
+    /// Returns the result if they differ.
+    fn check_block(
+        &mut self,
+        data: &[u8],
+        progress: &mut impl DownloadProgress,
+    ) -> Option<VerifyResult> {
+        self.filters
+            .apply(self.offset, &mut self.buffer[..data.len()]);
+        if let Some(position) = data
+            .iter()
+            .zip(self.buffer.iter())
+            .position(|(a, b)| a != b)
+        {
+            return Some(VerifyResult::Failed {
+                offset: self.offset + position as u64,
+            });
+        }
+        self.offset += data.len() as u64;
+        progress.report_transfer(Phase::Verify, self.partition, self.offset, self.length);
+        None
+    }
+
+    /// Compares the next `size` bytes of the partition with `expected`, stopping at the end
+    /// of the partition.
+    fn compare<R: std::io::Read + ?Sized>(
+        &mut self,
+        session: &mut communication::Session,
+        expected: &mut R,
+        size: u64,
+        progress: &mut impl DownloadProgress,
+    ) -> Result<Option<VerifyResult>, AxdlError> {
+        let end = (self.offset + size).min(self.length);
+        let transport = session.transport_kind();
+        while self.offset < end {
+            progress.check_is_cancelled()?;
+            let data = session
+                .read_block(self.offset, self.block_size(end))
+                .context(|| self.error_context(transport))?;
+            expected
+                .read_exact(&mut self.buffer[..data.len()])
+                .map_err(|e| AxdlError::IoError("read error".to_string(), e))?;
+            if let Some(result) = self.check_block(data, progress) {
+                return Ok(Some(result));
             }
         }
-        let config_string = config_string.ok_or(AxdlError::ImageError(
-            "configuration file not found in the image".into(),
-        ))?;
-        let config: partition::deserialize::Config = serde_xml_rs::from_str(&config_string)
-            .map_err(|e| {
-                AxdlError::ImageError(format!("failed to parse the configuration file: {}", e))
+        Ok(None)
+    }
+}
+
+/// Reads back `length` bytes of a partition and compares them with `expected`.
+fn verify_partition<R: std::io::Read>(
+    session: &mut communication::Session,
+    partition: &str,
+    length: u64,
+    chunk_size: usize,
+    expected: &mut R,
+    progress: &mut impl DownloadProgress,
+) -> Result<VerifyResult, AxdlError> {
+    session.start_read_partition(partition, length)?;
+    let result = Readback::new(partition, length, chunk_size)
+        .compare(session, expected, length, progress)?;
+    session.end_read_partition()?;
+    Ok(result.unwrap_or(VerifyResult::Passed))
+}
+
+/// Files of a partition image in the archive and the filters applied while writing it.
+struct PartitionImage {
+    name: String,
+    parts: Vec<String>,
+    /// Source of the data given in the configuration, which replaces the parts.
+    source: Option<std::sync::Arc<source::PartitionSource>>,
+    filters: filter::Filters,
+    /// Size of the image after filtering, i.e. the number of bytes written.
+    size: u64,
+    /// Data of the parts read ahead with [`PartitionImage::prefetch`], used instead of them.
+    prefetched: Option<Vec<u8>>,
+}
+
+impl PartitionImage {
+    /// Finds the data of `image` in the archive or the sources of `config`, returning the
+    /// partition image and its size before filtering.
+    fn new<'a>(
+        image: &partition::Image,
+        file_names: impl Iterator<Item = &'a str>,
+        config: &DownloadConfig,
+    ) -> Result<(Self, Option<u64>), AxdlError> {
+        let source = config.source_for(image);
+        let parts = if source.is_some() {
+            Vec::new()
+        } else {
+            if image.file().is_none() {
+                return Err(AxdlError::ImageError(format!(
+                    "image {} file not specified in the project",
+                    image.name()
+                )));
+            }
+            let parts = image.parts(file_names);
+            if parts.is_empty() {
+                return Err(AxdlError::ImageError(format!(
+                    "image {} was not found in the archive",
+                    image.name()
+                )));
+            }
+            parts
+        };
+        let size = source.as_ref().map(|source| source.size());
+        let partition_image = Self {
+            name: image.name().to_string(),
+            parts,
+            source,
+            filters: config.filters_for(image),
+            size: 0,
+            prefetched: None,
+        };
+        Ok((partition_image, size))
+    }
+
+    /// Sets the size of the data before filtering, once the parts have been measured.
+    fn with_data_size(mut self, size: u64) -> Self {
+        self.size = self.filters.filtered_size(size);
+        self
+    }
+
+    /// Calls `f` with the reader and the size of each part of the image in order, until it
+    /// returns false.
+    fn read_parts<R: std::io::Read + std::io::Seek>(
+        &self,
+        archive: &mut zip::ZipArchive<R>,
+        mut f: impl FnMut(&mut dyn std::io::Read, u64) -> Result<bool, AxdlError>,
+    ) -> Result<(), AxdlError> {
+        if let Some(source) = &self.source {
+            f(&mut source.open(&self.name)?, source.size())?;
+            return Ok(());
+        }
+        if let Some(data) = &self.prefetched {
+            f(&mut data.as_slice(), data.len() as u64)?;
+            return Ok(());
+        }
+        for part in &self.parts {
+            let mut file = archive.by_name(part).map_err(|e| {
+                AxdlError::ImageError(format!("failed to reopen image {}: {}", part, e))
             })?;
-        partition::Project::from(config.project)
+            let size = file.size();
+            if !f(&mut file, size)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the parts of `image` into memory if they are at most `config.prefetch_limit` bytes,
+    /// which also checks their CRC. Failures are left to the download of the image to report.
+    fn prefetch<R: std::io::Read + std::io::Seek>(
+        archive: &mut zip::ZipArchive<R>,
+        image: &partition::Image,
+        config: &DownloadConfig,
+    ) -> Option<(String, Vec<u8>)> {
+        let (partition_image, _) = PartitionImage::new(image, archive.file_names(), config).ok()?;
+        if partition_image.source.is_some() {
+            return None;
+        }
+        let mut size = 0;
+        for part in &partition_image.parts {
+            size += archive.by_name(part).ok()?.size();
+        }
+        if size > config.prefetch_limit {
+            return None;
+        }
+        let mut data = Vec::with_capacity(size as usize);
+        let result = partition_image.read_parts(archive, |file, _| {
+            file.read_to_end(&mut data)
+                .map_err(|e| AxdlError::IoError("failed to read the image".into(), e))?;
+            Ok(true)
+        });
+        match result {
+            Ok(()) => {
+                tracing::debug!("Prefetched {} bytes of {}", data.len(), image.name());
+                Some((image.name().to_string(), data))
+            }
+            Err(e) => {
+                tracing::debug!("Failed to prefetch {}: {}", image.name(), e);
+                None
+            }
+        }
+    }
+}
+
+/// Reads back a partition and compares it with its `image` in the archive.
+fn verify_partition_parts<R: std::io::Read + std::io::Seek>(
+    session: &mut communication::Session,
+    archive: &mut zip::ZipArchive<R>,
+    partition: &str,
+    image: &PartitionImage,
+    chunk_size: usize,
+    progress: &mut impl DownloadProgress,
+) -> Result<VerifyResult, AxdlError> {
+    session.start_read_partition(partition, image.size)?;
+    let mut readback =
+        Readback::new(partition, image.size, chunk_size).with_filters(image.filters.clone());
+    let mut result = None;
+    image.read_parts(archive, |file, size| {
+        result = readback.compare(session, file, size, progress)?;
+        Ok(result.is_none())
+    })?;
+    session.end_read_partition()?;
+    Ok(result.unwrap_or(VerifyResult::Passed))
+}
+
+/// Reads `length` bytes of `image` from `offset` on, as they were written.
+fn read_image_range<R: std::io::Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    image: &PartitionImage,
+    offset: u64,
+    length: usize,
+) -> Result<Vec<u8>, AxdlError> {
+    use std::io::Read as _;
+
+    let mut data = Vec::with_capacity(length);
+    let mut skip = offset;
+    image.read_parts(archive, |file, _| {
+        skip -= std::io::copy(
+            &mut std::io::Read::take(&mut *file, skip),
+            &mut std::io::sink(),
+        )
+        .map_err(|e| AxdlError::IoError("read error".to_string(), e))?;
+        std::io::Read::take(&mut *file, (length - data.len()) as u64)
+            .read_to_end(&mut data)
+            .map_err(|e| AxdlError::IoError("read error".to_string(), e))?;
+        Ok(data.len() < length)
+    })?;
+    image.filters.apply(offset, &mut data);
+    Ok(data)
+}
+
+/// Reads back a partition and compares the digests of its segments with those `recorded`
+/// while it was written, on a worker thread. Only the first differing segment is read from
+/// the archive again, to find the exact offset.
+fn verify_partition_digests<R: std::io::Read + std::io::Seek>(
+    session: &mut communication::Session,
+    archive: &mut zip::ZipArchive<R>,
+    partition: &str,
+    image: &PartitionImage,
+    recorded: digest::Segments,
+    chunk_size: usize,
+    progress: &mut impl DownloadProgress,
+) -> Result<VerifyResult, AxdlError> {
+    session.start_read_partition(partition, image.size)?;
+    let mut checker = digest::SegmentHasher::check(recorded.digest, chunk_size, recorded.digests);
+    let transport = session.transport_kind();
+    let mut offset = 0;
+    while offset < image.size && !checker.has_mismatch() {
+        progress.check_is_cancelled()?;
+        let block_size = (image.size - offset).min(chunk_size as u64) as u32;
+        let data = session.read_block(offset, block_size).context(|| {
+            context::ErrorContext::new(Phase::Verify, partition)
+                .with_block(offset / chunk_size as u64, offset)
+                .with_transport(transport)
+        })?;
+        if data.is_empty() {
+            break;
+        }
+        checker.feed(data);
+        offset += data.len() as u64;
+        progress.report_transfer(Phase::Verify, partition, offset, image.size);
+    }
+    session.end_read_partition()?;
+    let Some(mismatch) = checker.finish()?.mismatch else {
+        return Ok(VerifyResult::Passed);
     };
+    let expected = read_image_range(archive, image, mismatch.offset, chunk_size)?;
+    let position = mismatch
+        .data
+        .iter()
+        .zip(&expected)
+        .position(|(a, b)| a != b)
+        .unwrap_or(mismatch.data.len().min(expected.len()));
+    Ok(VerifyResult::Failed {
+        offset: mismatch.offset + position as u64,
+    })
+}
 
-    tracing::debug!("{:#?}", project);
-    let partition_table = project.partition_table();
-    tracing::debug!("{:#?}", partition_table);
+/// Interprets the readback done for [`DownloadConfig::skip_same`].
+///
+/// A partition the device refuses to read back is treated as different, so it is written.
+/// A refusal after the first block leaves the read open, which the caller has to end before
+/// writing.
+fn readback_matches(
+    partition: &str,
+    result: Result<VerifyResult, AxdlError>,
+) -> Result<bool, AxdlError> {
+    match result {
+        Ok(result) => Ok(result == VerifyResult::Passed),
+        Err(e) => match e.root_cause() {
+            &AxdlError::UnexpectedResponse(code) => {
+                tracing::debug!(
+                    "partition {} could not be read back: {:#06X}",
+                    partition,
+                    code
+                );
+                Ok(false)
+            }
+            _ => Err(e),
+        },
+    }
+}
 
-    tracing::debug!("Starting the download process...");
-    progress.report_progress("Start download", None);
+/// Warns about selected images which do not look like the content expected in their
+/// partition, e.g. a kernel assigned to rootfs in a hand-edited project.
+///
+/// Images which cannot be read are left to the download to report.
+fn check_contents<R: std::io::Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    project: &partition::Project,
+    config: &DownloadConfig,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for image in project
+        .images_of_type(partition::ImageType::Code)
+        .filter(|image| config.is_selected(image))
+    {
+        let partition::Block::Partition(partition) = image.block() else {
+            continue;
+        };
+        let file: Box<dyn std::io::Read + '_> = if let Some(source) = config.source_for(image) {
+            let Ok(reader) = source.open(image.name()) else {
+                continue;
+            };
+            reader
+        } else {
+            let Some(first) = image.parts(archive.file_names()).into_iter().next() else {
+                continue;
+            };
+            let Ok(file) = archive.by_name(&first) else {
+                continue;
+            };
+            Box::new(file)
+        };
+        let mut header = Vec::new();
+        if std::io::Read::read_to_end(
+            &mut std::io::Read::take(file, content::HEADER_SIZE as u64),
+            &mut header,
+        )
+        .is_err()
+        {
+            continue;
+        }
+        config.filters_for(image).apply(0, &mut header);
+        if let Some(warning) = content::check(partition, &header) {
+            tracing::warn!("{}", warning);
+            warnings.push(warning);
+        }
+    }
+    warnings
+}
 
+/// Loads the project configuration XML of an opened AXP image.
+fn load_project<R: std::io::Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+) -> Result<partition::Project, AxdlError> {
+    let mut config_string = None;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if file.name().ends_with(".xml") {
+            let mut string = String::new();
+            std::io::Read::read_to_string(&mut file, &mut string).map_err(|e| {
+                AxdlError::ImageError(format!("failed to read configuration file: {}", e))
+            })?;
+            config_string = Some(string);
+            break;
+        }
+    }
+    let config_string = config_string.ok_or(AxdlError::ImageError(
+        "configuration file not found in the image".into(),
+    ))?;
+    let config: partition::deserialize::Config =
+        serde_xml_rs::from_str(&config_string).map_err(|e| {
+            AxdlError::ImageError(format!("failed to parse the configuration file: {}", e))
+        })?;
+    Ok(partition::Project::from(config.project))
+}
+
+/// Reads the project configuration of an AXP image, e.g. to list its partitions and images
+/// without a device.
+pub fn read_project<R: std::io::Read + std::io::Seek>(
+    image_reader: &mut R,
+) -> Result<partition::Project, AxdlError> {
+    let mut archive = zip::ZipArchive::new(image_reader).map_err(AxdlError::ImageZipError)?;
+    load_project(&mut archive)
+}
+
+/// Opens a protocol session on `device` with the settings of `config`.
+fn open_session<'d>(
+    device: &'d mut transport::DynDevice,
+    config: &DownloadConfig,
+) -> communication::Session<'d> {
+    let rate_limit = config.rate_limits.get(&device.transport_kind()).copied();
+    communication::Session::with_max_frame_size(device, config.max_frame_size)
+        .with_strict(config.strict)
+        .with_duplicate_ack_window(config.duplicate_ack_window)
+        .with_timeouts(config.timeouts)
+        .with_retry(config.retry)
+        .with_rate_limit(rate_limit)
+        .with_pacing(config.pacing)
+        .with_coalesced_writes(config.coalesce_writes)
+        .with_zero_length_packets(config.zero_length_packets)
+        .with_handshake_request(config.handshake_request.clone())
+        .with_cancellation(config.cancellation.clone())
+}
+
+/// Waits for the romcode, loads the flash downloaders of `project` to RAM and waits until the
+/// last one answers.
+fn load_flash_downloaders<R: std::io::Read + std::io::Seek>(
+    session: &mut communication::Session,
+    archive: &mut zip::ZipArchive<R>,
+    project: &partition::Project,
+    config: &DownloadConfig,
+    report: &mut DownloadReport,
+    progress: &mut impl DownloadProgress,
+) -> Result<(), AxdlError> {
     // Check if romcode is running on the device.
-    progress.report_progress("Handshaking with the device", None);
-    communication::wait_handshake(device, "romcode")?;
+    progress.report_phase(Phase::Handshake, None, None);
+    let handshake = session.wait_handshake_matching(&config.handshakes.romcode)?;
+    progress.enter_stage(BootStage::Romcode, Some(&handshake));
+    report.handshakes.push(handshake);
 
-    progress.report_progress("Downloading the flash downloaders", None);
-    if project.is2_level_fdl() { 
+    progress.report_phase(Phase::FlashDownloader, None, None);
+    if project.is2_level_fdl() {
         // Find the FDL1 image and download it.
         let fdl1_image = project
-            .images()
-            .iter()
-            .find(|image| image.name() == "FDL1")
+            .image("FDL1")
             .ok_or(AxdlError::ImageError("FDL1 image not found".into()))?;
         let fdl1_image_file = fdl1_image.file().ok_or(AxdlError::ImageError(
             "FDL1 image file not specified in the project".into(),
@@ -145,15 +1046,10 @@ pub fn download_image<R: std::io::Read + std::io::Seek, Progress: DownloadProgre
         };
 
         // Start the RAM download (FDL1)
-        communication::start_ram_download(device)?;
+        session.start_ram_download()?;
         let fdl1_image_size = fdl1.size();
-        communication::start_partition_absolute_32(
-            device,
-            *fdl1_address as u32,
-            fdl1_image_size as u32,
-        )?;
-        communication::write_image(
-            device,
+        session.start_partition_absolute_32(*fdl1_address as u32, fdl1_image_size as u32)?;
+        session.write_image(
             &mut fdl1,
             1000,
             "FDL1",
@@ -162,16 +1058,16 @@ pub fn download_image<R: std::io::Read + std::io::Seek, Progress: DownloadProgre
             progress,
         )?;
         drop(fdl1);
-        communication::end_partition(device, communication::TIMEOUT)?;
-        communication::end_ram_download(device)?;
+        session.end_partition(communication::TIMEOUT)?;
+        session.end_ram_download()?;
 
-        communication::wait_handshake(device, "fdl1")?;
+        let handshake = session.wait_handshake_matching(&config.handshakes.fdl1)?;
+        progress.enter_stage(BootStage::Fdl1, Some(&handshake));
+        report.handshakes.push(handshake);
 
         // Find the FDL2 image and download it.
         let fdl2_image = project
-            .images()
-            .iter()
-            .find(|image| image.name() == "FDL2")
+            .image("FDL2")
             .ok_or(AxdlError::ImageError("FDL2 image not found".into()))?;
         let fdl2_image_file = fdl2_image.file().ok_or(AxdlError::ImageError(
             "FDL2 image file not specified in the project".into(),
@@ -184,12 +1080,11 @@ pub fn download_image<R: std::io::Read + std::io::Seek, Progress: DownloadProgre
             _ => return Err(AxdlError::ImageError("FDL2 block is not absolute".into())),
         };
         // Start the RAM download (FDL2)
-        communication::start_ram_download(device)?;
+        session.start_ram_download()?;
 
         let fdl2_image_size = fdl2.size();
-        communication::start_partition_absolute(device, *fdl2_address, fdl2_image_size)?;
-        communication::write_image(
-            device,
+        session.start_partition_absolute(*fdl2_address, fdl2_image_size)?;
+        session.write_image(
             &mut fdl2,
             1000,
             "FDL2",
@@ -198,13 +1093,12 @@ pub fn download_image<R: std::io::Read + std::io::Seek, Progress: DownloadProgre
             progress,
         )?;
         drop(fdl2);
-        communication::end_partition(device, communication::TIMEOUT)?;
-        communication::end_ram_download(device)?;
-    }else{
+        session.end_partition(communication::TIMEOUT)?;
+        session.end_ram_download()?;
+        progress.enter_stage(BootStage::Fdl2, None);
+    } else {
         let fdl1_image = project
-            .images()
-            .iter()
-            .find(|image| image.name() == "FDL")
+            .image("FDL")
             .ok_or(AxdlError::ImageError("FDL image not found".into()))?;
         let fdl1_image_file = fdl1_image.file().ok_or(AxdlError::ImageError(
             "FDL image file not specified in the project".into(),
@@ -218,15 +1112,10 @@ pub fn download_image<R: std::io::Read + std::io::Seek, Progress: DownloadProgre
         };
 
         // Start the RAM download (FDL1)
-        communication::start_ram_download(device)?;
+        session.start_ram_download()?;
         let fdl1_image_size = fdl1.size();
-        communication::start_partition_absolute_32(
-            device,
-            *fdl1_address as u32,
-            fdl1_image_size as u32,
-        )?;
-        communication::write_image(
-            device,
+        session.start_partition_absolute_32(*fdl1_address as u32, fdl1_image_size as u32)?;
+        session.write_image(
             &mut fdl1,
             1000,
             "FDL",
@@ -235,69 +1124,352 @@ pub fn download_image<R: std::io::Read + std::io::Seek, Progress: DownloadProgre
             progress,
         )?;
         drop(fdl1);
-        communication::end_partition(device, communication::TIMEOUT)?;
-        communication::end_ram_download(device)?;
+        session.end_partition(communication::TIMEOUT)?;
+        session.end_ram_download()?;
 
-        communication::wait_handshake(device, "fdl2")?;
+        let handshake = session.wait_handshake_matching(&config.handshakes.fdl2)?;
+        progress.enter_stage(BootStage::Fdl2, Some(&handshake));
+        report.handshakes.push(handshake);
     }
+    Ok(())
+}
+
+pub fn download_image<R: std::io::Read + std::io::Seek, Progress: DownloadProgress>(
+    image_reader: &mut R,
+    device: &mut transport::DynDevice,
+    config: &DownloadConfig,
+    progress: &mut Progress,
+) -> Result<DownloadReport, AxdlError> {
+    config.validate()?;
+    let progress = &mut cancel::WithToken::new(progress, config.cancellation.clone())
+        .with_max_duration(config.max_duration);
+    let _span = tracing::info_span!("download", device = %device.unique_id()).entered();
+    tracing::info!("Downloading to {}", device.display_name());
+
+    // Open the specified image file and find the configuration XML file.
+    let mut archive = zip::ZipArchive::new(image_reader).map_err(AxdlError::ImageZipError)?;
+
+    progress.report_phase(Phase::LoadImage, None, None);
+    let project = load_project(&mut archive)?;
+    let mut report = DownloadReport {
+        project: project.name().to_string(),
+        ..Default::default()
+    };
+
+    tracing::debug!("{:#?}", project);
+    config.check_selection(&project)?;
+    report.warnings = check_contents(&mut archive, &project, config);
+    let partition_table = project.partition_table();
+    tracing::debug!("{:#?}", partition_table);
+
+    tracing::debug!("Starting the download process...");
+    progress.report_phase(Phase::Start, None, None);
+
+    let mut session = open_session(device, config);
+    let chunk_size = config.image_chunk_size_for(session.device().max_packet_size());
+
+    load_flash_downloaders(
+        &mut session,
+        &mut archive,
+        &project,
+        config,
+        &mut report,
+        progress,
+    )?;
 
     // Download the partition table.
-    progress.report_progress("Downloading the partition table", None);
-    communication::set_partition_table(device, &partition_table)?;
+    progress.report_phase(Phase::PartitionTable, None, None);
+    session.set_partition_table(partition_table)?;
 
     // Download all of "CODE" images
-    for image in project.images().iter().filter(|image| {
-        image.r#type() == partition::ImageType::Code
-            && (!config.exclude_rootfs || image.name() != "ROOTFS")
-    }) {
-        tracing::debug!("Downloading image: {}", image.name());
-        progress.report_progress(&format!("Downloading image {}", image.name()), None);
+    let images = project
+        .images_of_type(partition::ImageType::Code)
+        .filter(|image| config.is_selected(image))
+        .collect::<Vec<_>>();
+    // Image read ahead while the previous partition was flushed.
+    let mut prefetched = None;
+    for (index, image) in images.iter().enumerate() {
+        let result = (|| -> Result<PartitionReport, AxdlError> {
+            tracing::debug!("Downloading image: {}", image.name());
+            progress.report_phase(Phase::Write, Some(image.name()), None);
 
-        progress.check_is_cancelled()?;
+            progress.check_is_cancelled()?;
 
-        let image_file_name = image.file().ok_or(AxdlError::ImageError(format!(
-            "image {} file not specified in the project",
-            image.name()
-        )))?;
-        let mut image_data = archive.by_name(&image_file_name).map_err(|e| {
-            AxdlError::ImageError(format!(
-                "image {} was not found in the archive: {}",
-                image.name(),
-                e
-            ))
-        })?;
-        let image_id = match image.block() {
-            partition::Block::Partition(id) => id,
-            _ => {
-                return Err(AxdlError::ImageError(format!(
-                    "image {} block is not partition",
-                    image.name()
-                )))
+            let image_id = match image.block() {
+                partition::Block::Partition(id) => id,
+                _ => {
+                    return Err(AxdlError::ImageError(format!(
+                        "image {} block is not partition",
+                        image.name()
+                    )))
+                }
+            };
+            let settings =
+                config.settings_for(image, chunk_size, session.device().max_packet_size());
+            let chunk_size = settings.chunk_size;
+            let (mut partition_image, source_size) =
+                PartitionImage::new(image, archive.file_names(), config)?;
+            partition_image.prefetched = prefetched
+                .take()
+                .filter(|(name, _)| name == image.name())
+                .map(|(_, data)| data);
+            let mut image_data_size = source_size.unwrap_or(0);
+            for part in &partition_image.parts {
+                image_data_size += archive
+                    .by_name(part)
+                    .map_err(|e| {
+                        AxdlError::ImageError(format!(
+                            "image {} was not found in the archive: {}",
+                            part, e
+                        ))
+                    })?
+                    .size();
             }
+            let partition_image = partition_image.with_data_size(image_data_size);
+            let stopwatch = time::Stopwatch::start();
+            if config.skip_same {
+                progress.report_phase(Phase::Compare, Some(image_id), None);
+                let result = verify_partition_parts(
+                    &mut session,
+                    &mut archive,
+                    image_id,
+                    &partition_image,
+                    chunk_size,
+                    progress,
+                );
+                if readback_matches(image_id, result)? {
+                    tracing::info!("Partition {} is already up to date", image_id);
+                    return Ok(PartitionReport {
+                        image: image.name().to_string(),
+                        partition: image_id.clone(),
+                        bytes_written: 0,
+                        verify: VerifyResult::Skipped,
+                        skipped: true,
+                        duration: stopwatch.elapsed(),
+                    });
+                }
+                // Ends a read refused partway; the session rejects this without sending
+                // anything if the readback ended it already.
+                let _ = session.end_read_partition();
+            }
+            session.start_partition_id(image_id, partition_image.size)?;
+            // Hash the image while it is written, so that verifying only has to read back.
+            let mut digests = settings
+                .verify
+                .then(|| digest::SegmentHasher::record(config.verify_digest, chunk_size));
+            let mut writer = session
+                .block_writer(chunk_size, progress)
+                .with_retry(settings.retry)
+                .with_filters(partition_image.filters.clone(), image_data_size)
+                .with_progress_report(image.name(), partition_image.size as usize, 100);
+            if let Some(digests) = &mut digests {
+                writer = writer.with_digests(digests);
+            }
+            partition_image.read_parts(&mut archive, |image_data, _| {
+                writer.write_all(image_data)?;
+                Ok(true)
+            })?;
+            let next = images.get(index + 1).filter(|_| config.prefetch_limit > 0);
+            prefetched = session
+                .end_partition_with(config.timeouts.end_partition, || {
+                    next.and_then(|next| PartitionImage::prefetch(&mut archive, next, config))
+                })
+                .context(|| context::ErrorContext::new(Phase::Flush, image.name()))?;
+
+            let verify = if let Some(digests) = digests {
+                progress.report_phase(Phase::Verify, Some(image_id), None);
+                verify_partition_digests(
+                    &mut session,
+                    &mut archive,
+                    image_id,
+                    &partition_image,
+                    digests.finish()?,
+                    chunk_size,
+                    progress,
+                )?
+            } else {
+                VerifyResult::Skipped
+            };
+            Ok(PartitionReport {
+                image: image.name().to_string(),
+                partition: image_id.clone(),
+                bytes_written: partition_image.size,
+                verify,
+                skipped: false,
+                duration: stopwatch.elapsed(),
+            })
+        })();
+        match result {
+            Ok(partition) => report.partitions.push(partition),
+            Err(e) => {
+                return Err(partial_failure(
+                    e,
+                    &report,
+                    image.name(),
+                    &images[index + 1..],
+                ))
+            }
+        }
+    }
+
+    // Write the per-device data last so that no image overwrites it.
+    if let Some(provision) = &config.provision {
+        let result = (|| -> Result<PartitionReport, AxdlError> {
+            progress.check_is_cancelled()?;
+            progress.report_phase(Phase::Provision, Some(&provision.partition), None);
+            let stopwatch = time::Stopwatch::start();
+            let length = provision.data.len() as u64;
+            session.start_partition_id(&provision.partition, length)?;
+            session.write_image(
+                &mut provision.data.as_slice(),
+                chunk_size,
+                provision::IMAGE_NAME,
+                provision.data.len(),
+                Some(100),
+                progress,
+            )?;
+            session
+                .end_partition(config.timeouts.end_partition)
+                .context(|| context::ErrorContext::new(Phase::Flush, &provision.partition))?;
+            let verify = if config.verify {
+                verify_partition(
+                    &mut session,
+                    &provision.partition,
+                    length,
+                    chunk_size,
+                    &mut provision.data.as_slice(),
+                    progress,
+                )?
+            } else {
+                VerifyResult::Skipped
+            };
+            Ok(PartitionReport {
+                image: provision::IMAGE_NAME.to_string(),
+                partition: provision.partition.clone(),
+                bytes_written: length,
+                verify,
+                skipped: false,
+                duration: stopwatch.elapsed(),
+            })
+        })();
+        match result {
+            Ok(partition) => report.partitions.push(partition),
+            Err(e) => return Err(partial_failure(e, &report, provision::IMAGE_NAME, &[])),
+        }
+    }
+    if config.strict {
+        tracing::info!(
+            "{} protocol deviations observed",
+            session.deviations().len()
+        );
+    }
+    tracing::info!("Done");
+    Ok(report)
+}
+
+/// Compares the partitions of an AXP image with those on the device without writing to its
+/// flash, e.g. to audit a unit returned from the field.
+///
+/// The flash downloaders are loaded to RAM as for a download, but the partition table is not
+/// set, so the device reads the partitions it already knows. Each selected image is read back
+/// and the outcome is in [`PartitionReport::verify`]; nothing is reported as written.
+pub fn verify_image<R: std::io::Read + std::io::Seek, Progress: DownloadProgress>(
+    image_reader: &mut R,
+    device: &mut transport::DynDevice,
+    config: &DownloadConfig,
+    progress: &mut Progress,
+) -> Result<DownloadReport, AxdlError> {
+    config.validate()?;
+    let progress = &mut cancel::WithToken::new(progress, config.cancellation.clone())
+        .with_max_duration(config.max_duration);
+    let _span = tracing::info_span!("verify", device = %device.unique_id()).entered();
+    tracing::info!("Verifying {}", device.display_name());
+
+    let mut archive = zip::ZipArchive::new(image_reader).map_err(AxdlError::ImageZipError)?;
+    progress.report_phase(Phase::LoadImage, None, None);
+    let project = load_project(&mut archive)?;
+    let mut report = DownloadReport {
+        project: project.name().to_string(),
+        ..Default::default()
+    };
+    config.check_selection(&project)?;
+
+    progress.report_phase(Phase::Start, None, None);
+    let mut session = open_session(device, config);
+    let chunk_size = config.image_chunk_size_for(session.device().max_packet_size());
+    load_flash_downloaders(
+        &mut session,
+        &mut archive,
+        &project,
+        config,
+        &mut report,
+        progress,
+    )?;
+
+    for image in project
+        .images_of_type(partition::ImageType::Code)
+        .filter(|image| config.is_selected(image))
+    {
+        progress.check_is_cancelled()?;
+        let partition::Block::Partition(image_id) = image.block() else {
+            return Err(AxdlError::ImageError(format!(
+                "image {} block is not partition",
+                image.name()
+            )));
         };
-        let image_data_size = image_data.size();
-        communication::start_partition_id(device, &image_id, image_data_size)?;
-        communication::write_image(
-            device,
-            &mut image_data,
-            48000,
-            image.name(),
-            image_data_size as usize,
-            Some(100),
+        let chunk_size = config
+            .settings_for(image, chunk_size, session.device().max_packet_size())
+            .chunk_size;
+        let (partition_image, source_size) =
+            PartitionImage::new(image, archive.file_names(), config)?;
+        let mut image_data_size = source_size.unwrap_or(0);
+        for part in &partition_image.parts {
+            image_data_size += archive.by_name(part)?.size();
+        }
+        let partition_image = partition_image.with_data_size(image_data_size);
+        let stopwatch = time::Stopwatch::start();
+        progress.report_phase(Phase::Compare, Some(image_id), None);
+        let verify = verify_partition_parts(
+            &mut session,
+            &mut archive,
+            image_id,
+            &partition_image,
+            chunk_size,
             progress,
-        )?;
-        communication::end_partition(device, Duration::from_secs(60))?;
+        )
+        .context(|| context::ErrorContext::new(Phase::Compare, image.name()))?;
+        match verify {
+            VerifyResult::Failed { offset } => {
+                tracing::warn!("Partition {} differs at {:#X}", image_id, offset)
+            }
+            _ => tracing::info!("Partition {} matches", image_id),
+        }
+        report.partitions.push(PartitionReport {
+            image: image.name().to_string(),
+            partition: image_id.clone(),
+            bytes_written: 0,
+            verify,
+            skipped: false,
+            duration: stopwatch.elapsed(),
+        });
     }
     tracing::info!("Done");
-    Ok(())
+    Ok(report)
 }
 
 #[cfg(feature = "async")]
 mod r#async {
-    use crate::{AxdlError, DownloadProgress, DownloadConfig, communication, partition, transport::AsyncDevice};
-
-    type AsyncZipEntryReaderWithEntry<'a, R> =
-        async_zip::base::read::ZipEntryReader<'a, R, async_zip::base::read::WithEntry<'a>>;
+    use crate::{
+        communication, content,
+        context::{self, ResultExt},
+        partial_failure, partition,
+        progress::{BootStage, Phase, ReportPhase},
+        provision, readback_matches,
+        report::{DownloadReport, PartitionReport, VerifyResult},
+        time,
+        transport::AsyncDevice,
+        AxdlError, DownloadConfig, DownloadProgress, PartitionImage, Readback,
+    };
 
     async fn read_zip_entry_as_string<
         R: futures_io::AsyncBufRead + futures_io::AsyncSeek + Unpin,
@@ -319,7 +1491,7 @@ mod r#async {
                     }
                 }
                 Err(async_zip::error::ZipError::EntryIndexOutOfBounds) => break,
-                Err(e) => return Err(AxdlError::ImageAsyncZipError(e.into())),
+                Err(e) => return Err(AxdlError::ImageAsyncZipError(e)),
             }
         }
         Ok(None)
@@ -328,14 +1500,14 @@ mod r#async {
     enum WriteImagePartition {
         Absolute32(u32),
         Absolute64(u64),
-        PartitionId(String),
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn write_partition_from_zip_file_async<
         R: futures_io::AsyncBufRead + futures_io::AsyncSeek + Unpin,
         D: AsyncDevice,
     >(
-        device: &mut D,
+        session: &mut communication::r#async::Session<'_, D>,
         archive: &mut async_zip::base::read::seek::ZipFileReader<R>,
         image_name: &str,
         partition: &WriteImagePartition,
@@ -343,7 +1515,7 @@ mod r#async {
         chunk_size: usize,
         report_every: Option<usize>,
         progress: &mut impl DownloadProgress,
-    ) -> Result<(), AxdlError> {
+    ) -> Result<u64, AxdlError> {
         for i in 0.. {
             match archive.reader_with_entry(i).await {
                 Ok(mut reader) => {
@@ -357,40 +1529,32 @@ mod r#async {
                         let image_size = reader.entry().uncompressed_size();
                         match partition {
                             WriteImagePartition::Absolute32(address) => {
-                                communication::r#async::start_partition_absolute_32(
-                                    device,
-                                    *address,
-                                    image_size as u32,
-                                )
-                                .await?;
+                                session
+                                    .start_partition_absolute_32(*address, image_size as u32)
+                                    .await?;
                             }
                             WriteImagePartition::Absolute64(address) => {
-                                communication::r#async::start_partition_absolute(
-                                    device, *address, image_size,
-                                )
-                                .await?;
-                            }
-                            WriteImagePartition::PartitionId(id) => {
-                                communication::r#async::start_partition_id(device, id, image_size)
+                                session
+                                    .start_partition_absolute(*address, image_size)
                                     .await?;
                             }
                         }
-                        communication::r#async::write_image(
-                            device,
-                            &mut reader,
-                            chunk_size,
-                            image_name,
-                            image_size as usize,
-                            report_every,
-                            progress,
-                        )
-                        .await?;
-                        communication::r#async::end_partition(device).await?;
-                        return Ok(());
+                        session
+                            .write_image(
+                                &mut reader,
+                                chunk_size,
+                                image_name,
+                                image_size as usize,
+                                report_every,
+                                progress,
+                            )
+                            .await?;
+                        session.end_partition().await?;
+                        return Ok(image_size);
                     }
                 }
                 Err(async_zip::error::ZipError::EntryIndexOutOfBounds) => break,
-                Err(e) => return Err(AxdlError::ImageAsyncZipError(e.into())),
+                Err(e) => return Err(AxdlError::ImageAsyncZipError(e)),
             }
         }
         Err(AxdlError::ImageError(format!(
@@ -399,6 +1563,173 @@ mod r#async {
         )))
     }
 
+    impl crate::Readback<'_> {
+        /// See [`crate::Readback::compare`].
+        async fn compare_async<R: futures_io::AsyncRead + Unpin, D: AsyncDevice>(
+            &mut self,
+            session: &mut communication::r#async::Session<'_, D>,
+            expected: &mut R,
+            size: u64,
+            progress: &mut impl DownloadProgress,
+        ) -> Result<Option<VerifyResult>, AxdlError> {
+            use futures_util::io::AsyncReadExt;
+
+            let end = (self.offset + size).min(self.length);
+            let transport = session.transport_kind();
+            while self.offset < end {
+                progress.check_is_cancelled()?;
+                let data = session
+                    .read_block(self.offset, self.block_size(end))
+                    .await
+                    .context(|| self.error_context(transport))?;
+                expected
+                    .read_exact(&mut self.buffer[..data.len()])
+                    .await
+                    .map_err(|e| AxdlError::IoError("read error".to_string(), e))?;
+                if let Some(result) = self.check_block(data, progress) {
+                    return Ok(Some(result));
+                }
+            }
+            Ok(None)
+        }
+    }
+
+    /// Reads back `length` bytes of a partition and compares them with `expected`.
+    pub(crate) async fn verify_partition_async<R: futures_io::AsyncRead + Unpin, D: AsyncDevice>(
+        session: &mut communication::r#async::Session<'_, D>,
+        partition: &str,
+        length: u64,
+        chunk_size: usize,
+        expected: &mut R,
+        progress: &mut impl DownloadProgress,
+    ) -> Result<VerifyResult, AxdlError> {
+        session.start_read_partition(partition, length).await?;
+        let result = Readback::new(partition, length, chunk_size)
+            .compare_async(session, expected, length, progress)
+            .await?;
+        session.end_read_partition().await?;
+        Ok(result.unwrap_or(VerifyResult::Passed))
+    }
+
+    /// Returns the names of the files in the archive.
+    fn file_names<R: futures_io::AsyncBufRead + futures_io::AsyncSeek + Unpin>(
+        archive: &async_zip::base::read::seek::ZipFileReader<R>,
+    ) -> impl Iterator<Item = &str> {
+        archive
+            .file()
+            .entries()
+            .iter()
+            .filter_map(|entry| entry.filename().as_str().ok())
+    }
+
+    /// Returns the index and the uncompressed size of the file named `file_name`.
+    fn find_entry<R: futures_io::AsyncBufRead + futures_io::AsyncSeek + Unpin>(
+        archive: &async_zip::base::read::seek::ZipFileReader<R>,
+        file_name: &str,
+    ) -> Result<(usize, u64), AxdlError> {
+        archive
+            .file()
+            .entries()
+            .iter()
+            .enumerate()
+            .find(|(_, entry)| entry.filename().as_str().ok() == Some(file_name))
+            .map(|(index, entry)| (index, entry.uncompressed_size()))
+            .ok_or_else(|| {
+                AxdlError::ImageError(format!(
+                    "image was not found in the image file: {}",
+                    file_name
+                ))
+            })
+    }
+
+    /// See [`crate::check_contents`].
+    async fn check_contents_async<R: futures_io::AsyncBufRead + futures_io::AsyncSeek + Unpin>(
+        archive: &mut async_zip::base::read::seek::ZipFileReader<R>,
+        project: &partition::Project,
+        config: &DownloadConfig,
+    ) -> Vec<String> {
+        use futures_util::io::AsyncReadExt;
+
+        let mut warnings = Vec::new();
+        for image in project
+            .images_of_type(partition::ImageType::Code)
+            .filter(|image| config.is_selected(image))
+        {
+            let partition::Block::Partition(partition) = image.block() else {
+                continue;
+            };
+            let reader: std::pin::Pin<Box<dyn futures_io::AsyncRead + '_>> =
+                if let Some(source) = config.source_for(image) {
+                    let Ok(reader) = source.open_async(image.name()) else {
+                        continue;
+                    };
+                    reader
+                } else {
+                    let Some(first) = image.parts(file_names(archive)).into_iter().next() else {
+                        continue;
+                    };
+                    let Ok((index, _)) = find_entry(archive, &first) else {
+                        continue;
+                    };
+                    let Ok(reader) = archive.reader_with_entry(index).await else {
+                        continue;
+                    };
+                    Box::pin(reader)
+                };
+            let mut header = Vec::new();
+            if reader
+                .take(content::HEADER_SIZE as u64)
+                .read_to_end(&mut header)
+                .await
+                .is_err()
+            {
+                continue;
+            }
+            config.filters_for(image).apply(0, &mut header);
+            if let Some(warning) = content::check(partition, &header) {
+                tracing::warn!("{}", warning);
+                warnings.push(warning);
+            }
+        }
+        warnings
+    }
+
+    /// Reads back a partition and compares it with its `image` in the archive.
+    async fn verify_partition_parts_async<
+        R: futures_io::AsyncBufRead + futures_io::AsyncSeek + Unpin,
+        D: AsyncDevice,
+    >(
+        session: &mut communication::r#async::Session<'_, D>,
+        archive: &mut async_zip::base::read::seek::ZipFileReader<R>,
+        partition: &str,
+        image: &PartitionImage,
+        chunk_size: usize,
+        progress: &mut impl DownloadProgress,
+    ) -> Result<VerifyResult, AxdlError> {
+        session.start_read_partition(partition, image.size).await?;
+        let mut readback =
+            Readback::new(partition, image.size, chunk_size).with_filters(image.filters.clone());
+        let mut result = None;
+        if let Some(source) = &image.source {
+            let mut reader = source.open_async(&image.name)?;
+            result = readback
+                .compare_async(session, &mut reader, source.size(), progress)
+                .await?;
+        }
+        for part in &image.parts {
+            let (index, size) = find_entry(archive, part)?;
+            let mut reader = archive.reader_with_entry(index).await?;
+            result = readback
+                .compare_async(session, &mut reader, size, progress)
+                .await?;
+            if result.is_some() {
+                break;
+            }
+        }
+        session.end_read_partition().await?;
+        Ok(result.unwrap_or(VerifyResult::Passed))
+    }
+
     #[cfg(feature = "async")]
     pub async fn download_image_async<
         R: futures_io::AsyncBufRead + futures_io::AsyncSeek + Unpin,
@@ -409,14 +1740,21 @@ mod r#async {
         device: &mut D,
         config: &DownloadConfig,
         progress: &mut Progress,
-    ) -> Result<(), AxdlError> {
-        tracing::info!("download_image_async");
+    ) -> Result<DownloadReport, AxdlError> {
+        tracing::info!(
+            "Downloading to {} ({})",
+            device.display_name(),
+            device.unique_id()
+        );
+        config.validate()?;
+        let progress = &mut crate::cancel::WithToken::new(progress, config.cancellation.clone())
+            .with_max_duration(config.max_duration);
         // Open the specified image file and find the configuration XML file.
         let mut archive = async_zip::base::read::seek::ZipFileReader::new(image_reader)
             .await
             .map_err(AxdlError::ImageAsyncZipError)?;
         tracing::info!("image file opened");
-        progress.report_progress("Loading the AXP image configuration", None);
+        progress.report_phase(Phase::LoadImage, None, None);
         // Load the axp image configuration.
         let project = {
             let config_string = read_zip_entry_as_string(&mut archive, |entry| {
@@ -436,24 +1774,47 @@ mod r#async {
                 })?;
             partition::Project::from(config.project)
         };
+        let mut report = DownloadReport {
+            project: project.name().to_string(),
+            ..Default::default()
+        };
 
         tracing::debug!("{:#?}", project);
+        config.check_selection(&project)?;
+        report.warnings = check_contents_async(&mut archive, &project, config).await;
         let partition_table = project.partition_table();
         tracing::debug!("{:#?}", partition_table);
 
         tracing::debug!("Starting the download process...");
-        progress.report_progress("Start download", None);
+        progress.report_phase(Phase::Start, None, None);
+
+        let rate_limit = config.rate_limits.get(&device.transport_kind()).copied();
+        let mut session =
+            communication::r#async::Session::with_max_frame_size(device, config.max_frame_size)
+                .with_strict(config.strict)
+                .with_duplicate_ack_window(config.duplicate_ack_window)
+                .with_timeouts(config.timeouts)
+                .with_retry(config.retry)
+                .with_rate_limit(rate_limit)
+                .with_pacing(config.pacing)
+                .with_coalesced_writes(config.coalesce_writes)
+                .with_zero_length_packets(config.zero_length_packets)
+                .with_handshake_request(config.handshake_request.clone())
+                .with_cancellation(config.cancellation.clone());
+        let chunk_size = config.image_chunk_size_for(session.device().max_packet_size());
 
         // Check if romcode is running on the device.
-        progress.report_progress("Handshaking with the device", None);
-        communication::r#async::wait_handshake(device, "romcode").await?;
+        progress.report_phase(Phase::Handshake, None, None);
+        let handshake = session
+            .wait_handshake_matching(&config.handshakes.romcode)
+            .await?;
+        progress.enter_stage(BootStage::Romcode, Some(&handshake));
+        report.handshakes.push(handshake);
 
-        progress.report_progress("Downloading the flash downloaders", None);
+        progress.report_phase(Phase::FlashDownloader, None, None);
         // Find the FDL1 image and download it.
         let fdl1_image = project
-            .images()
-            .iter()
-            .find(|image| image.name() == "FDL1")
+            .image("FDL1")
             .ok_or(AxdlError::ImageError("FDL1 image not found".into()))?;
         let fdl1_image_file = fdl1_image.file().ok_or(AxdlError::ImageError(
             "FDL1 image file not specified in the project".into(),
@@ -464,9 +1825,9 @@ mod r#async {
         };
 
         // Start the RAM download (FDL1)
-        communication::r#async::start_ram_download(device).await?;
+        session.start_ram_download().await?;
         write_partition_from_zip_file_async(
-            device,
+            &mut session,
             &mut archive,
             "FDL1",
             &WriteImagePartition::Absolute32(*fdl1_address as u32),
@@ -476,15 +1837,17 @@ mod r#async {
             progress,
         )
         .await?;
-        communication::r#async::end_ram_download(device).await?;
+        session.end_ram_download().await?;
 
-        communication::r#async::wait_handshake(device, "fdl1").await?;
+        let handshake = session
+            .wait_handshake_matching(&config.handshakes.fdl1)
+            .await?;
+        progress.enter_stage(BootStage::Fdl1, Some(&handshake));
+        report.handshakes.push(handshake);
 
         // Find the FDL2 image and download it.
         let fdl2_image = project
-            .images()
-            .iter()
-            .find(|image| image.name() == "FDL2")
+            .image("FDL2")
             .ok_or(AxdlError::ImageError("FDL2 image not found".into()))?;
         let fdl2_image_file = fdl2_image.file().ok_or(AxdlError::ImageError(
             "FDL2 image file not specified in the project".into(),
@@ -494,9 +1857,9 @@ mod r#async {
             _ => return Err(AxdlError::ImageError("FDL2 block is not absolute".into())),
         };
         // Start the RAM download (FDL2)
-        communication::r#async::start_ram_download(device).await?;
+        session.start_ram_download().await?;
         write_partition_from_zip_file_async(
-            device,
+            &mut session,
             &mut archive,
             "FDL2",
             &WriteImagePartition::Absolute64(*fdl2_address),
@@ -506,51 +1869,192 @@ mod r#async {
             progress,
         )
         .await?;
-        communication::r#async::end_ram_download(device).await?;
+        session.end_ram_download().await?;
+        progress.enter_stage(BootStage::Fdl2, None);
 
         // Download the partition table.
-        progress.report_progress("Downloading the partition table", None);
-        communication::r#async::set_partition_table(device, &partition_table).await?;
+        progress.report_phase(Phase::PartitionTable, None, None);
+        session.set_partition_table(partition_table).await?;
 
         // Download all of "CODE" images
-        for image in project.images().iter().filter(|image| {
-            image.r#type() == partition::ImageType::Code
-                && (!config.exclude_rootfs || image.name() != "ROOTFS")
-        }) {
-            tracing::debug!("Downloading image: {}", image.name());
-            progress.report_progress(&format!("Downloading image {}", image.name()), None);
+        let images = project
+            .images_of_type(partition::ImageType::Code)
+            .filter(|image| config.is_selected(image))
+            .collect::<Vec<_>>();
+        for (index, image) in images.iter().enumerate() {
+            let result: Result<PartitionReport, AxdlError> = async {
+                tracing::debug!("Downloading image: {}", image.name());
+                progress.report_phase(Phase::Write, Some(image.name()), None);
 
-            progress.check_is_cancelled()?;
+                progress.check_is_cancelled()?;
 
-            let image_file_name = image.file().ok_or(AxdlError::ImageError(format!(
-                "image {} file not specified in the project",
-                image.name()
-            )))?;
+                let image_id = match image.block() {
+                    partition::Block::Partition(id) => id,
+                    _ => {
+                        return Err(AxdlError::ImageError(format!(
+                            "image {} block is not partition",
+                            image.name()
+                        )))
+                    }
+                };
+                let settings =
+                    config.settings_for(image, chunk_size, session.device().max_packet_size());
+                let chunk_size = settings.chunk_size;
+                let (partition_image, source_size) =
+                    PartitionImage::new(image, file_names(&archive), config)?;
+                let mut image_size = source_size.unwrap_or(0);
+                for part in &partition_image.parts {
+                    image_size += find_entry(&archive, part)?.1;
+                }
+                let partition_image = partition_image.with_data_size(image_size);
 
-            let image_id = match image.block() {
-                partition::Block::Partition(id) => id,
-                _ => {
-                    return Err(AxdlError::ImageError(format!(
-                        "image {} block is not partition",
-                        image.name()
-                    )))
+                let stopwatch = time::Stopwatch::start();
+                if config.skip_same {
+                    progress.report_phase(Phase::Compare, Some(image_id), None);
+                    let result = verify_partition_parts_async(
+                        &mut session,
+                        &mut archive,
+                        image_id,
+                        &partition_image,
+                        chunk_size,
+                        progress,
+                    )
+                    .await;
+                    if readback_matches(image_id, result)? {
+                        tracing::info!("Partition {} is already up to date", image_id);
+                        return Ok(PartitionReport {
+                            image: image.name().to_string(),
+                            partition: image_id.clone(),
+                            bytes_written: 0,
+                            verify: VerifyResult::Skipped,
+                            skipped: true,
+                            duration: stopwatch.elapsed(),
+                        });
+                    }
+                    // Ends a read refused partway; the session rejects this without sending
+                    // anything if the readback ended it already.
+                    let _ = session.end_read_partition().await;
                 }
-            };
+                session
+                    .start_partition_id(image_id, partition_image.size)
+                    .await?;
+                let mut writer = session
+                    .block_writer(chunk_size, progress)
+                    .with_retry(settings.retry)
+                    .with_filters(partition_image.filters.clone(), image_size)
+                    .with_progress_report(image.name(), partition_image.size as usize, 100);
+                if let Some(source) = &partition_image.source {
+                    writer
+                        .write_all(&mut source.open_async(image.name())?)
+                        .await?;
+                }
+                for part in &partition_image.parts {
+                    let (index, _) = find_entry(&archive, part)?;
+                    let mut reader = archive.reader_with_entry(index).await?;
+                    writer.write_all(&mut reader).await?;
+                }
+                session
+                    .end_partition()
+                    .await
+                    .context(|| context::ErrorContext::new(Phase::Flush, image.name()))?;
 
-            write_partition_from_zip_file_async(
-                device,
-                &mut archive,
-                image.name(),
-                &WriteImagePartition::PartitionId(image_id.clone()),
-                image_file_name,
-                48000,
-                Some(100),
-                progress,
-            )
-            .await?;
+                let verify = if settings.verify {
+                    progress.report_phase(Phase::Verify, Some(image_id), None);
+                    verify_partition_parts_async(
+                        &mut session,
+                        &mut archive,
+                        image_id,
+                        &partition_image,
+                        chunk_size,
+                        progress,
+                    )
+                    .await?
+                } else {
+                    VerifyResult::Skipped
+                };
+                Ok(PartitionReport {
+                    image: image.name().to_string(),
+                    partition: image_id.clone(),
+                    bytes_written: partition_image.size,
+                    verify,
+                    skipped: false,
+                    duration: stopwatch.elapsed(),
+                })
+            }
+            .await;
+            match result {
+                Ok(partition) => report.partitions.push(partition),
+                Err(e) => {
+                    return Err(partial_failure(
+                        e,
+                        &report,
+                        image.name(),
+                        &images[index + 1..],
+                    ))
+                }
+            }
+        }
+
+        // Write the per-device data last so that no image overwrites it.
+        if let Some(provision) = &config.provision {
+            let result: Result<PartitionReport, AxdlError> = async {
+                progress.check_is_cancelled()?;
+                progress.report_phase(Phase::Provision, Some(&provision.partition), None);
+                let stopwatch = time::Stopwatch::start();
+                let length = provision.data.len() as u64;
+                session
+                    .start_partition_id(&provision.partition, length)
+                    .await?;
+                session
+                    .write_image(
+                        &mut futures_util::io::Cursor::new(&provision.data),
+                        chunk_size,
+                        provision::IMAGE_NAME,
+                        provision.data.len(),
+                        Some(100),
+                        progress,
+                    )
+                    .await?;
+                session
+                    .end_partition()
+                    .await
+                    .context(|| context::ErrorContext::new(Phase::Flush, &provision.partition))?;
+                let verify = if config.verify {
+                    verify_partition_async(
+                        &mut session,
+                        &provision.partition,
+                        length,
+                        chunk_size,
+                        &mut futures_util::io::Cursor::new(&provision.data),
+                        progress,
+                    )
+                    .await?
+                } else {
+                    VerifyResult::Skipped
+                };
+                Ok(PartitionReport {
+                    image: provision::IMAGE_NAME.to_string(),
+                    partition: provision.partition.clone(),
+                    bytes_written: length,
+                    verify,
+                    skipped: false,
+                    duration: stopwatch.elapsed(),
+                })
+            }
+            .await;
+            match result {
+                Ok(partition) => report.partitions.push(partition),
+                Err(e) => return Err(partial_failure(e, &report, provision::IMAGE_NAME, &[])),
+            }
+        }
+        if config.strict {
+            tracing::info!(
+                "{} protocol deviations observed",
+                session.deviations().len()
+            );
         }
         tracing::info!("Done");
-        Ok(())
+        Ok(report)
     }
 }
 
@@ -15,7 +15,9 @@
 
 use std::str::FromStr;
 
-#[derive(Debug)]
+use crate::AxdlError;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct PartitionTable {
     strategy: u8,
     unit: u8,
@@ -39,6 +41,29 @@ impl PartitionTable {
         self.unit
     }
 
+    /// Size in bytes of the unit the partition sizes and gaps are given in, if known.
+    pub fn unit_size(&self) -> Option<u64> {
+        match self.unit {
+            0 => Some(1024 * 1024),
+            2 => Some(1024),
+            _ => None,
+        }
+    }
+
+    /// Returns the flash space in bytes taken by the partitions and the gaps between them,
+    /// or `None` if the unit is unknown.
+    pub fn required_size(&self) -> Option<u64> {
+        let unit_size = self.unit_size()?;
+        self.partitions
+            .iter()
+            .try_fold(0u64, |total, partition| {
+                total
+                    .checked_add(partition.gap)?
+                    .checked_add(partition.size)
+            })?
+            .checked_mul(unit_size)
+    }
+
     pub fn add_partition(&mut self, partition: Partition) {
         self.partitions.push(partition);
     }
@@ -47,19 +72,41 @@ impl PartitionTable {
         &self.partitions
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, AxdlError> {
         let mut bytes = Vec::new();
         // Add header
         bytes.extend_from_slice(&[0x70, 0x61, 0x72, 0x3a, self.strategy, self.unit]); //"par:"" strategy, unit
-        bytes.extend_from_slice(&(self.partitions.len() as u16).to_le_bytes());
+        let count = u16::try_from(self.partitions.len()).map_err(|_| {
+            AxdlError::ImageError(format!("too many partitions: {}", self.partitions.len()))
+        })?;
+        bytes.extend_from_slice(&count.to_le_bytes());
         for partition in &self.partitions {
-            bytes.extend_from_slice(&partition.to_bytes());
+            bytes.extend_from_slice(&partition.to_bytes()?);
         }
-        bytes
+        Ok(bytes)
+    }
+
+    /// Decodes a partition table encoded by [`PartitionTable::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AxdlError> {
+        let invalid =
+            |reason: &str| AxdlError::ImageError(format!("invalid partition table: {}", reason));
+        if bytes.len() < 8 || &bytes[0..4] != b"par:" {
+            return Err(invalid("missing header"));
+        }
+        let count = u16::from_le_bytes([bytes[6], bytes[7]]) as usize;
+        let entries = &bytes[8..];
+        if entries.len() != count * 0x58 {
+            return Err(invalid("length does not match the partition count"));
+        }
+        let mut table = Self::new(bytes[4], bytes[5]);
+        for entry in entries.chunks_exact(0x58) {
+            table.add_partition(Partition::from_bytes(entry));
+        }
+        Ok(table)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Partition {
     name: String,
     gap: u64,
@@ -83,19 +130,34 @@ impl Partition {
         self.size
     }
 
-    pub fn to_bytes(&self) -> [u8; 0x58] {
+    /// Encodes the partition table entry. Fails if the name is longer than 32 UTF-16 units.
+    pub fn to_bytes(&self) -> Result<[u8; 0x58], AxdlError> {
         let mut bytes = [0u8; 0x58];
         let name_utf16: Vec<u8> = str::encode_utf16(&self.name)
-            .map(|c| [(c & 0xff) as u8, (c >> 8) as u8])
-            .flatten()
+            .flat_map(|c| [(c & 0xff) as u8, (c >> 8) as u8])
             .collect();
         if name_utf16.len() > 0x40 {
-            panic!("Partition name is too long");
+            return Err(AxdlError::PartitionNameTooLong(self.name.clone()));
         }
         bytes[..name_utf16.len()].copy_from_slice(&name_utf16);
         bytes[0x40..0x48].copy_from_slice(&self.gap.to_le_bytes());
         bytes[0x48..0x50].copy_from_slice(&self.size.to_le_bytes());
-        bytes
+        Ok(bytes)
+    }
+
+    /// Decodes a partition table entry. `bytes` must be 0x58 bytes long.
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let name: Vec<u16> = bytes[..0x40]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect();
+        let u64_at = |offset: usize| {
+            let mut value = [0u8; 8];
+            value.copy_from_slice(&bytes[offset..offset + 8]);
+            u64::from_le_bytes(value)
+        };
+        Self::new(String::from_utf16_lossy(&name), u64_at(0x40), u64_at(0x48))
     }
 }
 
@@ -126,51 +188,203 @@ impl FromStr for ImageType {
     }
 }
 
-#[derive(Debug, PartialEq)]
+impl ImageType {
+    /// Returns the type name used in the project XML.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Init => "INIT",
+            Self::Eip => "EIP",
+            Self::Fdl1 => "FDL1",
+            Self::Fdl2 => "FDL2",
+            Self::EraseFlash => "ERASEFLASH",
+            Self::Code => "CODE",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Block {
     Absolute(u64),
     Partition(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Image {
     flag: u32,
     name: String,
+    select: u32,
+    id: String,
     r#type: ImageType,
     block: Block,
+    block_size: u64,
     file: Option<String>,
+    /// Files following `file`, for images split into several files.
+    extra_files: Vec<String>,
+    auth_algo: u32,
     description: String,
 }
 impl Image {
-    pub(crate) fn name(&self) -> &str {
+    pub fn new(name: String, r#type: ImageType, block: Block, file: Option<String>) -> Self {
+        Self {
+            flag: 0,
+            id: name.clone(),
+            name,
+            select: 1,
+            r#type,
+            block,
+            block_size: 0,
+            file,
+            extra_files: Vec::new(),
+            auth_algo: 0,
+            description: String::new(),
+        }
+    }
+
+    pub fn set_description(&mut self, description: String) -> &mut Self {
+        self.description = description;
+        self
+    }
+
+    pub fn flag(&self) -> u32 {
+        self.flag
+    }
+
+    pub fn name(&self) -> &str {
         self.name.as_str()
     }
 
-    pub(crate) fn r#type(&self) -> ImageType {
+    /// Returns whether the image is selected for download in the vendor tool.
+    pub fn is_selected(&self) -> bool {
+        self.select != 0
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn r#type(&self) -> ImageType {
         self.r#type
     }
 
-    pub(crate) fn block(&self) -> &Block {
+    pub fn block(&self) -> &Block {
         &self.block
     }
 
-    pub(crate) fn description(&self) -> &str {
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    pub fn auth_algo(&self) -> u32 {
+        self.auth_algo
+    }
+
+    pub fn description(&self) -> &str {
         &self.description
     }
 
     pub fn file(&self) -> Option<&str> {
         self.file.as_deref()
     }
+
+    /// Adds a file written after the ones already listed, for images split into several files.
+    pub fn add_file(&mut self, file: String) -> &mut Self {
+        match self.file {
+            None => self.file = Some(file),
+            Some(_) => self.extra_files.push(file),
+        }
+        self
+    }
+
+    /// Returns all files of the image, in the order they are written to the partition.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.file
+            .iter()
+            .chain(self.extra_files.iter())
+            .map(String::as_str)
+    }
+
+    /// Resolves the files to write from the names of the files in the archive.
+    ///
+    /// An image with a single file may be split into numbered chunks instead, e.g.
+    /// `rootfs.img.000`, `rootfs.img.001`, which are written in numeric order. Returns an empty
+    /// list if the files are not found.
+    pub fn parts<'a>(&self, archive_files: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+        let archive_files: Vec<&str> = archive_files.into_iter().collect();
+        if !self.extra_files.is_empty() {
+            return self.files().map(String::from).collect();
+        }
+        let Some(file) = self.file.as_deref() else {
+            return Vec::new();
+        };
+        if archive_files.contains(&file) {
+            return vec![file.to_string()];
+        }
+        let mut chunks: Vec<(u64, &str)> = archive_files
+            .iter()
+            .filter_map(|name| {
+                let suffix = name.strip_prefix(file)?.strip_prefix('.')?;
+                if suffix.is_empty() || !suffix.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                Some((suffix.parse().ok()?, *name))
+            })
+            .collect();
+        chunks.sort();
+        chunks
+            .into_iter()
+            .map(|(_, name)| name.to_string())
+            .collect()
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Project {
+    alias: String,
+    name: String,
+    version: String,
     partition_table: PartitionTable,
     images: Vec<Image>,
-    fdl_level: u32
+    fdl_level: u32,
 }
 
 impl Project {
+    pub fn new(
+        alias: String,
+        name: String,
+        version: String,
+        fdl_level: u32,
+        partition_table: PartitionTable,
+    ) -> Self {
+        Self {
+            alias,
+            name,
+            version,
+            partition_table,
+            images: Vec::new(),
+            fdl_level,
+        }
+    }
+
+    pub fn add_image(&mut self, image: Image) {
+        self.images.push(image);
+    }
+
+    pub fn fdl_level(&self) -> u32 {
+        self.fdl_level
+    }
+
+    pub fn alias(&self) -> &str {
+        &self.alias
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
     pub fn partition_table(&self) -> &PartitionTable {
         &self.partition_table
     }
@@ -179,11 +393,238 @@ impl Project {
         &self.images
     }
 
+    /// Returns the image with the specified name.
+    pub fn image(&self, name: &str) -> Option<&Image> {
+        self.images.iter().find(|image| image.name() == name)
+    }
+
+    /// Returns an iterator over the images of the specified type.
+    pub fn images_of_type(&self, r#type: ImageType) -> impl Iterator<Item = &Image> {
+        self.images
+            .iter()
+            .filter(move |image| image.r#type() == r#type)
+    }
+
+    /// Returns an iterator over the flash downloader images (FDL1 and FDL2) in the project order.
+    pub fn fdl_images(&self) -> impl Iterator<Item = &Image> {
+        self.images
+            .iter()
+            .filter(|image| matches!(image.r#type(), ImageType::Fdl1 | ImageType::Fdl2))
+    }
+
     pub fn is2_level_fdl(&self) -> bool {
         self.fdl_level == 2
     }
 }
 
+pub mod serialize {
+    //! Serializes a [`Project`](super::Project) back into the vendor `<Config><Project>` XML.
+
+    use std::fmt::Write;
+
+    fn escape(s: &str) -> String {
+        let mut escaped = String::with_capacity(s.len());
+        for c in s.chars() {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&apos;"),
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    /// Returns the type name written for the image, preserving `FDL` for single level FDL projects.
+    fn image_type_name(project: &super::Project, image: &super::Image) -> &'static str {
+        if image.r#type() == super::ImageType::Fdl2 && !project.is2_level_fdl() {
+            "FDL"
+        } else {
+            image.r#type().as_str()
+        }
+    }
+
+    pub fn to_string(project: &super::Project) -> String {
+        let mut xml = String::new();
+        write_project(&mut xml, project).expect("writing to a String never fails");
+        xml
+    }
+
+    fn write_project(xml: &mut String, project: &super::Project) -> std::fmt::Result {
+        writeln!(xml, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+        writeln!(xml, "<Config>")?;
+        writeln!(
+            xml,
+            r#"  <Project alias="{}" name="{}" version="{}">"#,
+            escape(project.alias()),
+            escape(project.name()),
+            escape(project.version())
+        )?;
+        writeln!(xml, "    <FDLLevel>{}</FDLLevel>", project.fdl_level())?;
+
+        let partition_table = project.partition_table();
+        writeln!(
+            xml,
+            r#"    <Partitions strategy="{}" unit="{}">"#,
+            partition_table.strategy(),
+            partition_table.unit()
+        )?;
+        for partition in partition_table.partitions() {
+            writeln!(
+                xml,
+                r#"      <Partition gap="{}" id="{}" size="{}" />"#,
+                partition.gap(),
+                escape(partition.name()),
+                partition.size()
+            )?;
+        }
+        writeln!(xml, "    </Partitions>")?;
+
+        writeln!(xml, "    <ImgList>")?;
+        for image in project.images() {
+            writeln!(
+                xml,
+                r#"      <Img flag="{}" name="{}" select="{}">"#,
+                image.flag(),
+                escape(image.name()),
+                image.select
+            )?;
+            writeln!(xml, "        <ID>{}</ID>", escape(image.id()))?;
+            writeln!(
+                xml,
+                "        <Type>{}</Type>",
+                image_type_name(project, image)
+            )?;
+            let base = match image.block() {
+                super::Block::Absolute(address) => {
+                    writeln!(xml, "        <Block>")?;
+                    *address
+                }
+                super::Block::Partition(id) => {
+                    writeln!(xml, r#"        <Block id="{}">"#, escape(id))?;
+                    0
+                }
+            };
+            writeln!(xml, "          <Base>0x{:X}</Base>", base)?;
+            writeln!(xml, "          <Size>0x{:X}</Size>", image.block_size())?;
+            writeln!(xml, "        </Block>")?;
+            if image.file().is_none() {
+                writeln!(xml, "        <File />")?;
+            }
+            for file in image.files() {
+                writeln!(xml, "        <File>{}</File>", escape(file))?;
+            }
+            writeln!(xml, r#"        <Auth algo="{}" />"#, image.auth_algo())?;
+            writeln!(
+                xml,
+                "        <Description>{}</Description>",
+                escape(image.description())
+            )?;
+            writeln!(xml, "      </Img>")?;
+        }
+        writeln!(xml, "    </ImgList>")?;
+        writeln!(xml, "  </Project>")?;
+        writeln!(xml, "</Config>")?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::super::{Block, Image, ImageType, Partition, PartitionTable, Project};
+
+        fn roundtrip(project: &Project) -> Project {
+            let xml = super::to_string(project);
+            let config: super::super::deserialize::Config = serde_xml_rs::from_str(&xml).unwrap();
+            Project::from(config.project)
+        }
+
+        #[test]
+        fn test_serialize_roundtrip() {
+            let mut partition_table = PartitionTable::new(1, 2);
+            partition_table.add_partition(Partition::new("spl".into(), 0, 768));
+            partition_table.add_partition(Partition::new("rootfs".into(), 0, 0x100000));
+            let mut project = Project::new(
+                "AX620E".into(),
+                "AX630C".into(),
+                "V2.0.0 <test> & \"quoted\"".into(),
+                2,
+                partition_table,
+            );
+            project.add_image(Image::new(
+                "INIT".into(),
+                ImageType::Init,
+                Block::Absolute(0),
+                None,
+            ));
+            project.add_image(Image::new(
+                "FDL1".into(),
+                ImageType::Fdl1,
+                Block::Absolute(0x3000),
+                Some("fdl1.bin".into()),
+            ));
+            let mut rootfs = Image::new(
+                "ROOTFS".into(),
+                ImageType::Code,
+                Block::Partition("rootfs".into()),
+                Some("rootfs.ext4".into()),
+            );
+            rootfs.set_description("Root filesystem".into());
+            project.add_image(rootfs);
+
+            assert_eq!(roundtrip(&project), project);
+        }
+
+        #[test]
+        fn test_serialize_single_level_fdl() {
+            let mut partition_table = PartitionTable::new(1, 2);
+            partition_table.add_partition(Partition::new("spl".into(), 0, 768));
+            let mut project = Project::new(
+                "AX650".into(),
+                "AX650N".into(),
+                "V1".into(),
+                1,
+                partition_table,
+            );
+            project.add_image(Image::new(
+                "FDL".into(),
+                ImageType::Fdl2,
+                Block::Absolute(0x100000),
+                Some("fdl.bin".into()),
+            ));
+            let xml = super::to_string(&project);
+            assert!(xml.contains("<Type>FDL</Type>"));
+            assert_eq!(roundtrip(&project), project);
+        }
+
+        #[test]
+        fn test_serialize_split_image() {
+            let mut partition_table = PartitionTable::new(1, 2);
+            partition_table.add_partition(Partition::new("rootfs".into(), 0, 0x100000));
+            let mut project = Project::new(
+                "AX620E".into(),
+                "AX630C".into(),
+                "V1".into(),
+                2,
+                partition_table,
+            );
+            let mut rootfs = Image::new(
+                "ROOTFS".into(),
+                ImageType::Code,
+                Block::Partition("rootfs".into()),
+                Some("rootfs.ext4.a".into()),
+            );
+            rootfs.add_file("rootfs.ext4.b".into());
+            project.add_image(rootfs);
+
+            let project = roundtrip(&project);
+            let files: Vec<_> = project.images()[0].files().collect();
+            assert_eq!(files, ["rootfs.ext4.a", "rootfs.ext4.b"]);
+        }
+    }
+}
+
 pub mod deserialize {
     use serde::Deserialize;
 
@@ -221,9 +662,12 @@ pub mod deserialize {
                 images.push(img.into());
             }
             super::Project {
+                alias: project.alias,
+                name: project.name,
+                version: project.version,
                 partition_table,
                 images,
-                fdl_level: project.fdl_level
+                fdl_level: project.fdl_level,
             }
         }
     }
@@ -256,25 +700,13 @@ pub mod deserialize {
         gap: u64,
         #[serde(rename = "id")]
         id: String,
-        #[serde(rename = "size")]
-        size: String,
-    }
-    
-    fn hex_to_u64(hex_string: &str) -> Option<u64> {
-        match u64::from_str_radix(hex_string, 16) {
-            Ok(parsed_int) => Some(parsed_int),
-            Err(_) => None,
-        }
+        #[serde(rename = "size", deserialize_with = "from_dec_or_hex")]
+        size: u64,
     }
 
     impl From<Partition> for super::Partition {
         fn from(partition: Partition) -> Self {
-            if partition.size.starts_with("0x") {
-                
-                super::Partition::new(partition.id, partition.gap, hex_to_u64(&partition.size[2..]).unwrap())
-            } else {
-                super::Partition::new(partition.id, partition.gap, partition.size.parse::<u64>().unwrap())
-            }
+            super::Partition::new(partition.id, partition.gap, partition.size)
         }
     }
 
@@ -295,14 +727,15 @@ pub mod deserialize {
 
         #[serde(rename = "ID")]
         id: String,
-        #[serde(rename = "Type")]
-        img_type: String,
+        #[serde(rename = "Type", deserialize_with = "image_type")]
+        img_type: super::ImageType,
 
         #[serde(rename = "Block")]
         block: Block,
 
-        #[serde(rename = "File", deserialize_with = "empty_string_to_none")]
-        file: Option<String>,
+        /// Several `File` elements list the parts of an image split into several files.
+        #[serde(rename = "File", default)]
+        files: Vec<String>,
 
         #[serde(rename = "Auth")]
         auth: Auth,
@@ -313,12 +746,18 @@ pub mod deserialize {
 
     impl From<Img> for super::Image {
         fn from(img: Img) -> super::Image {
+            let mut files = img.files.into_iter().filter(|file| !file.is_empty());
             super::Image {
                 flag: img.flag,
                 name: img.name,
-                r#type: img.img_type.parse().unwrap(),
+                select: img.select,
+                id: img.id,
+                r#type: img.img_type,
+                block_size: img.block.size,
                 block: img.block.into(),
-                file: img.file,
+                file: files.next(),
+                extra_files: files.collect(),
+                auth_algo: img.auth.algo,
                 description: img.description,
             }
         }
@@ -332,15 +771,26 @@ pub mod deserialize {
         u64::from_str_radix(s.trim_start_matches("0x"), 16).map_err(serde::de::Error::custom)
     }
 
-    fn empty_string_to_none<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+    /// Parses a decimal number, or a hexadecimal one with a `0x` prefix.
+    fn from_dec_or_hex<'de, D>(deserializer: D) -> Result<u64, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        let s: Option<String> = Option::deserialize(deserializer)?;
-        match s {
-            Some(ref value) if value.is_empty() => Ok(None),
-            other => Ok(other),
+        let s: String = Deserialize::deserialize(deserializer)?;
+        match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => s.parse(),
         }
+        .map_err(serde::de::Error::custom)
+    }
+
+    fn image_type<'de, D>(deserializer: D) -> Result<super::ImageType, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| serde::de::Error::custom(format!("unknown image type: {}", s)))
     }
 
     #[derive(Debug, Deserialize)]
@@ -424,5 +874,193 @@ pub mod deserialize {
             assert_eq!(project.images()[0].file, None);
             assert_eq!(project.images()[0].description, "Handshake with romcode");
         }
+
+        #[test]
+        fn test_project_queries() {
+            let xml_data = r#"
+        <Config>
+        <Project alias="AX620E" name="AX630C" version="V2.0.0">
+            <FDLLevel>2</FDLLevel>
+            <Partitions strategy="1" unit="2">
+            <Partition gap="0" id="spl" size="768" />
+            </Partitions>
+            <ImgList>
+            <Img flag="2" name="INIT" select="1">
+                <ID>INIT</ID>
+                <Type>INIT</Type>
+                <Block>
+                <Base>0x0</Base>
+                <Size>0x0</Size>
+                </Block>
+                <File />
+                <Auth algo="0" />
+                <Description>Handshake with romcode</Description>
+            </Img>
+            <Img flag="2" name="FDL1" select="1">
+                <ID>FDL1</ID>
+                <Type>FDL1</Type>
+                <Block>
+                <Base>0x3000</Base>
+                <Size>0x0</Size>
+                </Block>
+                <File>fdl1.bin</File>
+                <Auth algo="0" />
+                <Description>FDL1</Description>
+            </Img>
+            <Img flag="2" name="FDL2" select="1">
+                <ID>FDL2</ID>
+                <Type>FDL2</Type>
+                <Block>
+                <Base>0x5C000000</Base>
+                <Size>0x0</Size>
+                </Block>
+                <File>fdl2.bin</File>
+                <Auth algo="0" />
+                <Description>FDL2</Description>
+            </Img>
+            <Img flag="0" name="SPL" select="0">
+                <ID>SPL</ID>
+                <Type>CODE</Type>
+                <Block id="spl">
+                <Base>0x0</Base>
+                <Size>0x300</Size>
+                </Block>
+                <File>spl.bin</File>
+                <Auth algo="1" />
+                <Description>SPL</Description>
+            </Img>
+            </ImgList>
+        </Project>
+        </Config>
+        "#;
+
+            let config: Config = serde_xml_rs::from_str(xml_data).unwrap();
+            let project = super::super::Project::from(config.project);
+            assert_eq!(project.alias(), "AX620E");
+            assert_eq!(project.name(), "AX630C");
+            assert_eq!(project.version(), "V2.0.0");
+
+            let fdl1 = project.image("FDL1").unwrap();
+            assert_eq!(fdl1.file(), Some("fdl1.bin"));
+            assert_eq!(fdl1.block(), &super::super::Block::Absolute(0x3000));
+            assert!(project.image("UBOOT").is_none());
+
+            let fdls = project
+                .fdl_images()
+                .map(|image| image.name())
+                .collect::<Vec<_>>();
+            assert_eq!(fdls, ["FDL1", "FDL2"]);
+
+            let code = project
+                .images_of_type(super::super::ImageType::Code)
+                .collect::<Vec<_>>();
+            assert_eq!(code.len(), 1);
+            assert_eq!(code[0].id(), "SPL");
+            assert!(!code[0].is_selected());
+            assert_eq!(code[0].block_size(), 0x300);
+            assert_eq!(code[0].auth_algo(), 1);
+            assert_eq!(
+                code[0].block(),
+                &super::super::Block::Partition("spl".into())
+            );
+        }
+
+        #[test]
+        fn test_deserialize_malformed() {
+            let xml = |size: &str, img_type: &str| {
+                format!(
+                    r#"
+        <Config>
+        <Project alias="AX620E" name="AX630C" version="V2.0.0">
+            <FDLLevel>2</FDLLevel>
+            <Partitions strategy="1" unit="2">
+            <Partition gap="0" id="spl" size="{}" />
+            </Partitions>
+            <ImgList>
+            <Img flag="2" name="INIT" select="1">
+                <ID>INIT</ID>
+                <Type>{}</Type>
+                <Block>
+                <Base>0x0</Base>
+                <Size>0x0</Size>
+                </Block>
+                <File />
+                <Auth algo="0" />
+                <Description>Handshake with romcode</Description>
+            </Img>
+            </ImgList>
+        </Project>
+        </Config>
+        "#,
+                    size, img_type
+                )
+            };
+
+            let config: Config = serde_xml_rs::from_str(&xml("0x300", "INIT")).unwrap();
+            let project = super::super::Project::from(config.project);
+            assert_eq!(project.partition_table().partitions()[0].size(), 0x300);
+
+            assert!(serde_xml_rs::from_str::<Config>(&xml("0xZZ", "INIT")).is_err());
+            assert!(serde_xml_rs::from_str::<Config>(&xml("large", "INIT")).is_err());
+            assert!(serde_xml_rs::from_str::<Config>(&xml("768", "BOOT")).is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_partition_name_too_long() {
+        let mut table = PartitionTable::new(1, 2);
+        table.add_partition(Partition::new("a".repeat(32), 0, 768));
+        assert_eq!(table.to_bytes().unwrap().len(), 8 + 0x58);
+
+        table.add_partition(Partition::new("a".repeat(33), 0, 768));
+        assert!(matches!(
+            table.to_bytes(),
+            Err(AxdlError::PartitionNameTooLong(_))
+        ));
+    }
+
+    #[test]
+    fn test_image_parts() {
+        let image = Image::new(
+            "ROOTFS".into(),
+            ImageType::Code,
+            Block::Partition("rootfs".into()),
+            Some("rootfs.img".into()),
+        );
+        assert_eq!(image.parts(["a.xml", "rootfs.img"]), ["rootfs.img"]);
+        assert_eq!(
+            image.parts([
+                "rootfs.img.010",
+                "rootfs.img.002",
+                "rootfs.img.x",
+                "rootfs.img.001"
+            ]),
+            ["rootfs.img.001", "rootfs.img.002", "rootfs.img.010"]
+        );
+        assert!(image.parts(["spl.img"]).is_empty());
+    }
+
+    #[test]
+    fn test_partition_table_roundtrip() {
+        let mut table = PartitionTable::new(1, 2);
+        table.add_partition(Partition::new("spl".into(), 0, 768));
+        table.add_partition(Partition::new("rootfs".into(), 0x1000, 0x400000));
+        let bytes = table.to_bytes().unwrap();
+        assert_eq!(PartitionTable::from_bytes(&bytes).unwrap(), table);
+        assert!(PartitionTable::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_required_size() {
+        let mut table = PartitionTable::new(1, 2);
+        table.add_partition(Partition::new("spl".into(), 0, 768));
+        table.add_partition(Partition::new("rootfs".into(), 256, 1024));
+        assert_eq!(table.required_size(), Some(2048 * 1024));
+        assert_eq!(PartitionTable::new(1, 7).required_size(), None);
     }
 }
@@ -0,0 +1,230 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Structured progress events, so that consumers can tell the phases of a download apart and
+//! time them without parsing the descriptions.
+
+use std::time::Duration;
+
+/// Phase of a download. The identifiers returned by [`Phase::id`] are stable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Loading the configuration from the AXP image.
+    LoadImage,
+    /// Opening the session with the device.
+    Start,
+    Handshake,
+    /// Downloading the flash downloaders (FDL1 and FDL2).
+    FlashDownloader,
+    PartitionTable,
+    /// Reading back a partition to see whether it must be written, for `skip_same`.
+    Compare,
+    /// Sending an image to the device.
+    Write,
+    /// Waiting for the device to finish writing an image it has received.
+    Flush,
+    Verify,
+    /// Writing the per-device provisioning data.
+    Provision,
+    /// Reading a partition from the device, e.g. to back it up.
+    Read,
+}
+
+impl Phase {
+    /// Returns the stable identifier of the phase.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::LoadImage => "load_image",
+            Self::Start => "start",
+            Self::Handshake => "handshake",
+            Self::FlashDownloader => "flash_downloader",
+            Self::PartitionTable => "partition_table",
+            Self::Compare => "compare",
+            Self::Write => "write",
+            Self::Flush => "flush",
+            Self::Verify => "verify",
+            Self::Provision => "provision",
+            Self::Read => "read",
+        }
+    }
+
+    /// Returns the human readable description of the phase working on `target`.
+    pub fn describe(&self, target: Option<&str>) -> String {
+        let target = target.unwrap_or_default();
+        match self {
+            Self::LoadImage => "Loading the AXP image configuration".to_string(),
+            Self::Start => "Start download".to_string(),
+            Self::Handshake => "Handshaking with the device".to_string(),
+            Self::FlashDownloader => "Downloading the flash downloaders".to_string(),
+            Self::PartitionTable => "Downloading the partition table".to_string(),
+            Self::Compare => format!("Comparing partition {}", target),
+            Self::Write => format!("Downloading image {}", target),
+            Self::Flush => format!("Waiting for the device to finish writing {}", target),
+            Self::Verify => format!("Verifying partition {}", target),
+            Self::Provision => format!("Writing provisioning data to {}", target),
+            Self::Read => format!("Reading partition {}", target),
+        }
+    }
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+/// Boot stage of the device. The identifiers returned by [`BootStage::id`] are stable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BootStage {
+    Romcode,
+    /// First flash downloader of a two-level image, which loads FDL2.
+    Fdl1,
+    /// Flash downloader which writes the partitions, the only one of a single-level image.
+    Fdl2,
+}
+
+impl BootStage {
+    /// Returns the stable identifier of the stage.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::Romcode => "romcode",
+            Self::Fdl1 => "fdl1",
+            Self::Fdl2 => "fdl2",
+        }
+    }
+}
+
+impl std::fmt::Display for BootStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+/// Transition of the device to a boot stage, passed to
+/// [`crate::DownloadProgress::report_stage`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageEvent<'a> {
+    pub stage: BootStage,
+    /// Handshake the stage answered with, including its version. FDL2 of a two-level image is
+    /// not asked for one, as it takes over from FDL1 right away.
+    pub handshake: Option<&'a crate::communication::HandshakeInfo>,
+    /// Time on the clock of [`ProgressEvent::timestamp`].
+    pub timestamp: Duration,
+}
+
+/// Progress of a download, passed to [`crate::DownloadProgress::report_event`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressEvent<'a> {
+    pub phase: Phase,
+    /// Image or partition the phase works on, if any.
+    pub target: Option<&'a str>,
+    pub description: &'a str,
+    /// Fraction of the phase done, if known.
+    pub progress: Option<f32>,
+    /// Bytes of the target transferred so far, for the steps which move image data, e.g. to
+    /// show the speed.
+    pub bytes: Option<u64>,
+    /// Time on a monotonic clock, counted from an arbitrary point fixed for the process.
+    pub timestamp: Duration,
+}
+
+/// Returns the current time on the clock of [`ProgressEvent::timestamp`].
+pub fn timestamp() -> Duration {
+    crate::time::monotonic()
+}
+
+/// Ignores the progress, for callers which do not show it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl crate::DownloadProgress for NoProgress {
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+
+    fn report_progress(&mut self, _description: &str, _progress: Option<f32>) {}
+}
+
+/// Passes every [`ProgressEvent`] to a closure, e.g. to forward it to a channel.
+///
+/// Descriptions reported without a phase, i.e. through
+/// [`crate::DownloadProgress::report_progress`] directly, are ignored.
+pub struct FnProgress<F>(pub F);
+
+impl<F: FnMut(ProgressEvent<'_>)> crate::DownloadProgress for FnProgress<F> {
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+
+    fn report_progress(&mut self, _description: &str, _progress: Option<f32>) {}
+
+    fn report_event(&mut self, event: &ProgressEvent<'_>) {
+        (self.0)(event.clone());
+    }
+}
+
+/// Builds [`ProgressEvent`]s for any [`crate::DownloadProgress`].
+pub(crate) trait ReportPhase {
+    fn report_phase(&mut self, phase: Phase, target: Option<&str>, progress: Option<f32>);
+
+    /// Reports that `bytes` out of `total` bytes of `target` were transferred.
+    fn report_transfer(&mut self, phase: Phase, target: &str, bytes: u64, total: u64);
+
+    /// Reports that the device entered `stage`, answering with `handshake` if it was asked.
+    fn enter_stage(
+        &mut self,
+        stage: BootStage,
+        handshake: Option<&crate::communication::HandshakeInfo>,
+    );
+}
+
+impl<P: crate::DownloadProgress + ?Sized> ReportPhase for P {
+    fn report_phase(&mut self, phase: Phase, target: Option<&str>, progress: Option<f32>) {
+        let description = phase.describe(target);
+        self.report_event(&ProgressEvent {
+            phase,
+            target,
+            description: &description,
+            progress,
+            bytes: None,
+            timestamp: crate::time::monotonic(),
+        });
+    }
+
+    fn report_transfer(&mut self, phase: Phase, target: &str, bytes: u64, total: u64) {
+        let description = phase.describe(Some(target));
+        self.report_event(&ProgressEvent {
+            phase,
+            target: Some(target),
+            description: &description,
+            progress: Some(bytes as f32 / total as f32),
+            bytes: Some(bytes),
+            timestamp: crate::time::monotonic(),
+        });
+    }
+
+    fn enter_stage(
+        &mut self,
+        stage: BootStage,
+        handshake: Option<&crate::communication::HandshakeInfo>,
+    ) {
+        tracing::debug!("device entered {}", stage);
+        self.report_stage(&StageEvent {
+            stage,
+            handshake,
+            timestamp: crate::time::monotonic(),
+        });
+    }
+}
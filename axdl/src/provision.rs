@@ -0,0 +1,286 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-device data such as serial numbers and MAC addresses, rendered from a template and
+//! written to a small partition (e.g. ENV or a vendor data partition) in the same session.
+
+use std::{collections::BTreeMap, str::FromStr};
+
+use crate::AxdlError;
+
+/// Name under which the provisioning data appears in reports and errors.
+pub const IMAGE_NAME: &str = "provisioning";
+
+/// Data written to `partition` after all images in the AXP file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvisionData {
+    pub partition: String,
+    pub data: Vec<u8>,
+}
+
+/// Text with `${name}` placeholders. `$$` stands for a literal `$`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    text: String,
+}
+
+impl Template {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self { text: text.into() }
+    }
+
+    /// Replaces every placeholder with its value.
+    ///
+    /// ```
+    /// use std::collections::BTreeMap;
+    /// use axdl::provision::Template;
+    ///
+    /// let values = BTreeMap::from([("serial".to_string(), "AX0001".to_string())]);
+    /// let text = Template::new("serial=${serial} cost=$$5").render(&values).unwrap();
+    /// assert_eq!(text, "serial=AX0001 cost=$5");
+    /// ```
+    pub fn render(&self, values: &BTreeMap<String, String>) -> Result<String, AxdlError> {
+        let mut output = String::with_capacity(self.text.len());
+        let mut rest = self.text.as_str();
+        while let Some(position) = rest.find('$') {
+            output.push_str(&rest[..position]);
+            rest = &rest[position + 1..];
+            if let Some(after) = rest.strip_prefix('$') {
+                output.push('$');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('{') {
+                let end = after.find('}').ok_or_else(|| {
+                    AxdlError::InvalidConfig("unterminated placeholder in template".into())
+                })?;
+                let name = &after[..end];
+                let value = values.get(name).ok_or_else(|| {
+                    AxdlError::InvalidConfig(format!("no value for placeholder ${{{}}}", name))
+                })?;
+                output.push_str(value);
+                rest = &after[end + 1..];
+            } else {
+                output.push('$');
+            }
+        }
+        output.push_str(rest);
+        Ok(output)
+    }
+}
+
+/// Serial number made of a prefix and a zero padded decimal counter, e.g. `AX000123`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerialNumber {
+    prefix: String,
+    number: u64,
+    width: usize,
+}
+
+impl SerialNumber {
+    /// Returns the serial number `n` units after this one, keeping the prefix and padding.
+    pub fn checked_add(&self, n: u64) -> Option<Self> {
+        Some(Self {
+            prefix: self.prefix.clone(),
+            number: self.number.checked_add(n)?,
+            width: self.width,
+        })
+    }
+}
+
+impl FromStr for SerialNumber {
+    type Err = AxdlError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let prefix = s.trim_end_matches(|c: char| c.is_ascii_digit());
+        let digits = &s[prefix.len()..];
+        let number = digits.parse().map_err(|_| {
+            AxdlError::InvalidConfig(format!("serial number {} does not end with a number", s))
+        })?;
+        Ok(Self {
+            prefix: prefix.to_string(),
+            number,
+            width: digits.len(),
+        })
+    }
+}
+
+impl std::fmt::Display for SerialNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{:0width$}",
+            self.prefix,
+            self.number,
+            width = self.width
+        )
+    }
+}
+
+/// 48-bit MAC address, written as `00:11:22:33:44:55`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacAddress([u8; 6]);
+
+impl MacAddress {
+    pub fn new(octets: [u8; 6]) -> Self {
+        Self(octets)
+    }
+
+    pub fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+
+    /// Returns the address `n` after this one, or `None` if it does not fit in 48 bits.
+    pub fn checked_add(&self, n: u64) -> Option<Self> {
+        let mut value = [0u8; 8];
+        value[2..].copy_from_slice(&self.0);
+        let value = u64::from_be_bytes(value).checked_add(n)?;
+        if value >> 48 != 0 {
+            return None;
+        }
+        let mut octets = [0u8; 6];
+        octets.copy_from_slice(&value.to_be_bytes()[2..]);
+        Some(Self(octets))
+    }
+}
+
+impl FromStr for MacAddress {
+    type Err = AxdlError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AxdlError::InvalidConfig(format!("invalid MAC address: {}", s));
+        let mut octets = [0u8; 6];
+        let mut parts = s.split([':', '-']);
+        for octet in octets.iter_mut() {
+            let part = parts.next().ok_or_else(invalid)?;
+            if part.len() != 2 {
+                return Err(invalid());
+            }
+            *octet = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self(octets))
+    }
+}
+
+impl std::fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+/// Values generated for the `index`-th device of a batch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sequence {
+    /// Serial number of the first device, available as `${serial}`.
+    pub serial: Option<SerialNumber>,
+    /// MAC address of the first device, available as `${mac}`.
+    pub mac: Option<MacAddress>,
+}
+
+impl Sequence {
+    /// Returns the template values for the device at `index`, including `${index}` itself.
+    pub fn values(&self, index: u64) -> Result<BTreeMap<String, String>, AxdlError> {
+        let mut values = BTreeMap::from([("index".to_string(), index.to_string())]);
+        if let Some(serial) = &self.serial {
+            let serial = serial.checked_add(index).ok_or_else(|| {
+                AxdlError::InvalidConfig(format!("serial number {} overflows", serial))
+            })?;
+            values.insert("serial".into(), serial.to_string());
+        }
+        if let Some(mac) = &self.mac {
+            let mac = mac.checked_add(index).ok_or_else(|| {
+                AxdlError::InvalidConfig(format!("MAC address {} overflows", mac))
+            })?;
+            values.insert("mac".into(), mac.to_string());
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_template_render() {
+        let values = BTreeMap::from([
+            ("serial".to_string(), "AX0001".to_string()),
+            ("mac".to_string(), "00:11:22:33:44:55".to_string()),
+        ]);
+        let template = Template::new("serial=${serial}\nethaddr=${mac}\nprice=$9 $$\n");
+        assert_eq!(
+            template.render(&values).unwrap(),
+            "serial=AX0001\nethaddr=00:11:22:33:44:55\nprice=$9 $\n"
+        );
+    }
+
+    #[test]
+    fn test_template_errors() {
+        let values = BTreeMap::new();
+        assert!(matches!(
+            Template::new("${serial}").render(&values),
+            Err(AxdlError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            Template::new("${serial").render(&values),
+            Err(AxdlError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_serial_number() {
+        let serial: SerialNumber = "AX000998".parse().unwrap();
+        assert_eq!(serial.checked_add(3).unwrap().to_string(), "AX001001");
+        assert_eq!(
+            "7".parse::<SerialNumber>()
+                .unwrap()
+                .checked_add(5)
+                .unwrap()
+                .to_string(),
+            "12"
+        );
+        assert!("AX".parse::<SerialNumber>().is_err());
+    }
+
+    #[test]
+    fn test_mac_address() {
+        let mac: MacAddress = "00-11-22-33-44-FF".parse().unwrap();
+        assert_eq!(mac.to_string(), "00:11:22:33:44:ff");
+        assert_eq!(mac.checked_add(1).unwrap().to_string(), "00:11:22:33:45:00");
+        assert!("ff:ff:ff:ff:ff:ff"
+            .parse::<MacAddress>()
+            .unwrap()
+            .checked_add(1)
+            .is_none());
+        assert!("00:11:22:33:44".parse::<MacAddress>().is_err());
+        assert!("00:11:22:33:44:55:66".parse::<MacAddress>().is_err());
+    }
+
+    #[test]
+    fn test_sequence_values() {
+        let sequence = Sequence {
+            serial: Some("SN0100".parse().unwrap()),
+            mac: Some("02:00:00:00:00:fe".parse().unwrap()),
+        };
+        let values = sequence.values(2).unwrap();
+        assert_eq!(values["index"], "2");
+        assert_eq!(values["serial"], "SN0102");
+        assert_eq!(values["mac"], "02:00:00:00:01:00");
+    }
+}
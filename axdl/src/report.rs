@@ -0,0 +1,203 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Summary of a download, one entry per written partition.
+
+use std::time::Duration;
+
+/// Result of reading back a partition after writing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyResult {
+    /// Verification was not requested.
+    Skipped,
+    Passed,
+    /// The data read back differs from the image, starting at `offset`.
+    Failed {
+        offset: u64,
+    },
+}
+
+impl std::fmt::Display for VerifyResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Skipped => write!(f, "skipped"),
+            Self::Passed => write!(f, "passed"),
+            Self::Failed { offset } => write!(f, "FAILED at {:#X}", offset),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionReport {
+    pub image: String,
+    pub partition: String,
+    pub bytes_written: u64,
+    pub verify: VerifyResult,
+    /// The partition already held the image, so it was not written.
+    pub skipped: bool,
+    pub duration: Duration,
+}
+
+/// Result of watching the console of the device boot after the download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootCheck {
+    /// Text the console was expected to print, e.g. a login prompt.
+    pub pattern: String,
+    /// Time until the pattern appeared, or `None` if it did not appear in time.
+    pub found_after: Option<Duration>,
+    pub timeout: Duration,
+}
+
+impl BootCheck {
+    pub fn is_success(&self) -> bool {
+        self.found_after.is_some()
+    }
+}
+
+impl std::fmt::Display for BootCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.found_after {
+            Some(after) => write!(
+                f,
+                "{:?} seen after {:.1} s",
+                self.pattern,
+                after.as_secs_f64()
+            ),
+            None => write!(
+                f,
+                "FAILED, {:?} not seen within {} s",
+                self.pattern,
+                self.timeout.as_secs()
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DownloadReport {
+    pub project: String,
+    pub partitions: Vec<PartitionReport>,
+    /// Images which do not look like the content expected in their partition.
+    pub warnings: Vec<String>,
+    /// Handshakes of the boot stages, in the order they answered.
+    pub handshakes: Vec<crate::communication::HandshakeInfo>,
+    /// Boot check after the download, filled in by the caller which watched the console.
+    pub boot: Option<BootCheck>,
+}
+
+impl DownloadReport {
+    /// Returns true unless a partition failed verification or the device did not boot.
+    pub fn is_success(&self) -> bool {
+        self.partitions
+            .iter()
+            .all(|partition| !matches!(partition.verify, VerifyResult::Failed { .. }))
+            && self.boot.as_ref().is_none_or(BootCheck::is_success)
+    }
+
+    /// Returns what went wrong if the download was not a success.
+    pub fn failure(&self) -> Option<&'static str> {
+        if self
+            .partitions
+            .iter()
+            .any(|partition| matches!(partition.verify, VerifyResult::Failed { .. }))
+        {
+            Some("Verification failed")
+        } else if !self.is_success() {
+            Some("Boot check failed")
+        } else {
+            None
+        }
+    }
+}
+
+/// Formats the report as plain text suitable for attaching to a ticket.
+impl std::fmt::Display for DownloadReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Project: {}", self.project)?;
+        writeln!(
+            f,
+            "Result: {}",
+            if self.is_success() { "OK" } else { "FAILED" }
+        )?;
+        if !self.handshakes.is_empty() {
+            let handshakes = self
+                .handshakes
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            writeln!(f, "Device: {}", handshakes.join(", "))?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "Warning: {}", warning)?;
+        }
+        for partition in &self.partitions {
+            if partition.skipped {
+                writeln!(
+                    f,
+                    "{} ({}): already up to date, {:.1} s",
+                    partition.partition,
+                    partition.image,
+                    partition.duration.as_secs_f64()
+                )?;
+                continue;
+            }
+            writeln!(
+                f,
+                "{} ({}): {} bytes written, verify {}, {:.1} s",
+                partition.partition,
+                partition.image,
+                partition.bytes_written,
+                partition.verify,
+                partition.duration.as_secs_f64()
+            )?;
+        }
+        if let Some(boot) = &self.boot {
+            writeln!(f, "Boot: {}", boot)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_boot_check_in_report() {
+        let mut report = DownloadReport {
+            project: "AX630C".into(),
+            ..Default::default()
+        };
+        report.boot = Some(BootCheck {
+            pattern: "login:".into(),
+            found_after: Some(Duration::from_millis(12_340)),
+            timeout: Duration::from_secs(120),
+        });
+        assert_eq!(report.failure(), None);
+        assert!(report
+            .to_string()
+            .ends_with("Boot: \"login:\" seen after 12.3 s\n"));
+
+        report.boot = Some(BootCheck {
+            found_after: None,
+            ..report.boot.unwrap()
+        });
+        assert!(!report.is_success());
+        assert_eq!(report.failure(), Some("Boot check failed"));
+        assert!(report
+            .to_string()
+            .ends_with("Boot: FAILED, \"login:\" not seen within 120 s\n"));
+    }
+}
@@ -0,0 +1,128 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Partition data provided by the caller instead of the image in the archive, e.g. an image
+//! generated with per-device data and streamed into the writer without storing it first.
+
+use crate::AxdlError;
+
+type OpenRead = dyn Fn() -> std::io::Result<Box<dyn std::io::Read>> + Send + Sync;
+#[cfg(feature = "async")]
+type OpenAsyncRead =
+    dyn Fn() -> std::io::Result<std::pin::Pin<Box<dyn futures_io::AsyncRead>>> + Send + Sync;
+
+enum Opener {
+    Read(Box<OpenRead>),
+    #[cfg(feature = "async")]
+    AsyncRead(Box<OpenAsyncRead>),
+}
+
+/// Data of a partition, read from the reader a closure returns.
+///
+/// The closure is called each time the data is needed: to check its content, to write it and
+/// to read it back for [`crate::DownloadConfig::skip_same`], so it must return the same data
+/// every time. Filters still apply to the data.
+pub struct PartitionSource {
+    size: u64,
+    open: Opener,
+}
+
+impl PartitionSource {
+    /// Source of `size` bytes read from the reader returned by `open`.
+    ///
+    /// It can be used with both [`crate::download_image`] and the async download.
+    pub fn from_read<F, R>(size: u64, open: F) -> Self
+    where
+        F: Fn() -> std::io::Result<R> + Send + Sync + 'static,
+        R: std::io::Read + 'static,
+    {
+        Self {
+            size,
+            open: Opener::Read(Box::new(move || {
+                open().map(|reader| Box::new(reader) as Box<dyn std::io::Read>)
+            })),
+        }
+    }
+
+    /// Source of `size` bytes read from the async reader returned by `open`.
+    ///
+    /// It can only be used with the async download.
+    #[cfg(feature = "async")]
+    pub fn from_async_read<F, R>(size: u64, open: F) -> Self
+    where
+        F: Fn() -> std::io::Result<R> + Send + Sync + 'static,
+        R: futures_io::AsyncRead + 'static,
+    {
+        Self {
+            size,
+            open: Opener::AsyncRead(Box::new(move || {
+                open()
+                    .map(|reader| Box::pin(reader) as std::pin::Pin<Box<dyn futures_io::AsyncRead>>)
+            })),
+        }
+    }
+
+    /// Size of the data in bytes, before filtering.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn open_error(image: &str, e: std::io::Error) -> AxdlError {
+        AxdlError::IoError(format!("failed to open the source of image {}", image), e)
+    }
+
+    /// Opens the data of `image` for the blocking download.
+    pub(crate) fn open(&self, image: &str) -> Result<Box<dyn std::io::Read>, AxdlError> {
+        match &self.open {
+            Opener::Read(open) => open().map_err(|e| Self::open_error(image, e)),
+            #[cfg(feature = "async")]
+            Opener::AsyncRead(_) => Err(AxdlError::InvalidConfig(format!(
+                "the source of image {} can only be used with the async download",
+                image
+            ))),
+        }
+    }
+
+    /// Opens the data of `image` for the async download.
+    #[cfg(feature = "async")]
+    pub(crate) fn open_async(
+        &self,
+        image: &str,
+    ) -> Result<std::pin::Pin<Box<dyn futures_io::AsyncRead>>, AxdlError> {
+        match &self.open {
+            Opener::Read(open) => open()
+                .map(|reader| {
+                    Box::pin(futures_util::io::AllowStdIo::new(reader))
+                        as std::pin::Pin<Box<dyn futures_io::AsyncRead>>
+                })
+                .map_err(|e| Self::open_error(image, e)),
+            Opener::AsyncRead(open) => open().map_err(|e| Self::open_error(image, e)),
+        }
+    }
+}
+
+impl std::fmt::Debug for PartitionSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.open {
+            Opener::Read(_) => "read",
+            #[cfg(feature = "async")]
+            Opener::AsyncRead(_) => "async read",
+        };
+        f.debug_struct("PartitionSource")
+            .field("size", &self.size)
+            .field("kind", &kind)
+            .finish()
+    }
+}
@@ -0,0 +1,379 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Clock, sleep and timeout which behave the same on native hosts and in the browser.
+//!
+//! `std::time::Instant` panics on `wasm32-unknown-unknown`, so the web build measures time with
+//! `performance.now()` and waits with the browser timer. Native async code has no runtime to
+//! provide a timer, so its sleeps are expired by a single timer thread instead of blocking the
+//! executor.
+
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Stopwatch {
+    #[cfg(not(all(target_arch = "wasm32", feature = "web")))]
+    start: std::time::Instant,
+    #[cfg(all(target_arch = "wasm32", feature = "web"))]
+    start: f64,
+}
+
+/// Milliseconds from a monotonic clock, falling back to `Date.now()` where the page has no
+/// `performance` object.
+#[cfg(all(target_arch = "wasm32", feature = "web"))]
+fn now_ms() -> f64 {
+    web_sys::window()
+        .and_then(|window| window.performance())
+        .map(|performance| performance.now())
+        .unwrap_or_else(js_sys::Date::now)
+}
+
+impl Stopwatch {
+    #[cfg(not(all(target_arch = "wasm32", feature = "web")))]
+    pub(crate) fn start() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+        }
+    }
+
+    #[cfg(all(target_arch = "wasm32", feature = "web"))]
+    pub(crate) fn start() -> Self {
+        Self { start: now_ms() }
+    }
+
+    #[cfg(not(all(target_arch = "wasm32", feature = "web")))]
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    #[cfg(all(target_arch = "wasm32", feature = "web"))]
+    pub(crate) fn elapsed(&self) -> Duration {
+        Duration::from_secs_f64(((now_ms() - self.start) / 1000.0).max(0.0))
+    }
+}
+
+/// Returns the time on a monotonic clock since its first use in the process.
+pub(crate) fn monotonic() -> Duration {
+    static EPOCH: std::sync::OnceLock<Stopwatch> = std::sync::OnceLock::new();
+    EPOCH.get_or_init(Stopwatch::start).elapsed()
+}
+
+/// Point in time after which an operation has timed out.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline {
+    stopwatch: Stopwatch,
+    timeout: Duration,
+}
+
+impl Deadline {
+    pub(crate) fn after(timeout: Duration) -> Self {
+        Self {
+            stopwatch: Stopwatch::start(),
+            timeout,
+        }
+    }
+
+    /// Time left until the deadline, zero once it has passed.
+    pub(crate) fn remaining(&self) -> Duration {
+        self.timeout.saturating_sub(self.stopwatch.elapsed())
+    }
+}
+
+/// Waits for `duration` using the browser timer.
+#[cfg(all(target_arch = "wasm32", feature = "web"))]
+pub(crate) async fn sleep(duration: Duration) {
+    let milliseconds = duration.as_millis().min(i32::MAX as u128) as i32;
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        if let Some(window) = web_sys::window() {
+            let _ = window
+                .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, milliseconds);
+        }
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+#[cfg(all(feature = "async", not(all(target_arch = "wasm32", feature = "web"))))]
+mod thread_timer {
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+    use std::task::{Context, Poll, Waker};
+    use std::time::{Duration, Instant};
+
+    #[derive(Default)]
+    struct State {
+        expired: bool,
+        waker: Option<Waker>,
+    }
+
+    #[derive(Default)]
+    struct Shared {
+        state: Mutex<State>,
+    }
+
+    impl Shared {
+        fn state(&self) -> MutexGuard<'_, State> {
+            self.state.lock().unwrap_or_else(|e| e.into_inner())
+        }
+
+        fn expire(&self) {
+            let waker = {
+                let mut state = self.state();
+                state.expired = true;
+                state.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Pending sleep in the queue of the timer thread, ordered by its deadline.
+    struct Entry {
+        deadline: Instant,
+        id: u64,
+        shared: Arc<Shared>,
+    }
+
+    impl PartialEq for Entry {
+        fn eq(&self, other: &Self) -> bool {
+            (self.deadline, self.id) == (other.deadline, other.id)
+        }
+    }
+
+    impl Eq for Entry {}
+
+    impl PartialOrd for Entry {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Entry {
+        fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+            (self.deadline, self.id).cmp(&(other.deadline, other.id))
+        }
+    }
+
+    #[derive(Default)]
+    struct Queue {
+        entries: BinaryHeap<Reverse<Entry>>,
+        next_id: u64,
+    }
+
+    /// Single thread which expires the sleeps of the whole process in the order of their
+    /// deadlines.
+    #[derive(Default)]
+    struct Timer {
+        queue: Mutex<Queue>,
+        condvar: Condvar,
+    }
+
+    impl Timer {
+        /// Returns the timer, starting its thread on first use.
+        fn get() -> &'static Timer {
+            static TIMER: OnceLock<&'static Timer> = OnceLock::new();
+            TIMER.get_or_init(|| {
+                let timer: &'static Timer = Box::leak(Box::default());
+                std::thread::spawn(|| timer.run());
+                timer
+            })
+        }
+
+        fn queue(&self) -> MutexGuard<'_, Queue> {
+            self.queue.lock().unwrap_or_else(|e| e.into_inner())
+        }
+
+        fn run(&self) {
+            let mut queue = self.queue();
+            loop {
+                let now = Instant::now();
+                match queue.entries.peek() {
+                    Some(Reverse(entry)) if entry.deadline <= now => {
+                        if let Some(Reverse(entry)) = queue.entries.pop() {
+                            entry.shared.expire();
+                        }
+                    }
+                    Some(Reverse(entry)) => {
+                        let wait = entry.deadline - now;
+                        queue = self
+                            .condvar
+                            .wait_timeout(queue, wait)
+                            .unwrap_or_else(|e| e.into_inner())
+                            .0;
+                    }
+                    None => {
+                        queue = self.condvar.wait(queue).unwrap_or_else(|e| e.into_inner());
+                    }
+                }
+            }
+        }
+
+        fn insert(&self, deadline: Instant, shared: Arc<Shared>) -> u64 {
+            let mut queue = self.queue();
+            let id = queue.next_id;
+            queue.next_id += 1;
+            queue.entries.push(Reverse(Entry {
+                deadline,
+                id,
+                shared,
+            }));
+            self.condvar.notify_one();
+            id
+        }
+
+        fn remove(&self, id: u64) {
+            self.queue().entries.retain(|Reverse(entry)| entry.id != id);
+        }
+
+        #[cfg(test)]
+        fn contains(&self, id: u64) -> bool {
+            self.queue()
+                .entries
+                .iter()
+                .any(|Reverse(entry)| entry.id == id)
+        }
+    }
+
+    /// Future which completes after a duration, timed by the timer thread.
+    ///
+    /// Dropping it removes it from the queue, so that abandoned timeouts do not pile up.
+    pub(crate) struct Sleep {
+        shared: Arc<Shared>,
+        id: u64,
+    }
+
+    impl Sleep {
+        pub(crate) fn new(duration: Duration) -> Self {
+            let shared = Arc::new(Shared::default());
+            // A duration too long to represent never expires in practice.
+            let deadline = Instant::now()
+                .checked_add(duration)
+                .unwrap_or_else(|| Instant::now() + Duration::from_secs(365 * 24 * 60 * 60));
+            let id = Timer::get().insert(deadline, shared.clone());
+            Self { shared, id }
+        }
+    }
+
+    impl Future for Sleep {
+        type Output = ();
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let mut state = self.shared.state();
+            if state.expired {
+                Poll::Ready(())
+            } else {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    impl Drop for Sleep {
+        fn drop(&mut self) {
+            if !self.shared.state().expired {
+                Timer::get().remove(self.id);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn test_sleeps() {
+            let sleeps: Vec<_> = (0..100)
+                .map(|i| Sleep::new(Duration::from_millis(i % 10)))
+                .collect();
+            for sleep in sleeps {
+                crate::time::block_on(sleep);
+            }
+            let abandoned = Sleep::new(Duration::from_secs(60));
+            let id = abandoned.id;
+            assert!(Timer::get().contains(id));
+            drop(abandoned);
+            assert!(!Timer::get().contains(id));
+        }
+    }
+}
+
+/// Waits for `duration` on the timer thread, so that the executor is not blocked.
+#[cfg(all(feature = "async", not(all(target_arch = "wasm32", feature = "web"))))]
+pub(crate) async fn sleep(duration: Duration) {
+    thread_timer::Sleep::new(duration).await
+}
+
+/// Fails with [`crate::AxdlError::DeviceTimeout`] if `future` does not complete within `duration`.
+#[cfg(feature = "async")]
+pub(crate) async fn timeout<T>(
+    duration: Duration,
+    future: impl std::future::Future<Output = Result<T, crate::AxdlError>>,
+) -> Result<T, crate::AxdlError> {
+    use futures_util::future::{select, Either};
+    let future = std::pin::pin!(future);
+    let sleep = std::pin::pin!(sleep(duration));
+    match select(future, sleep).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(crate::AxdlError::DeviceTimeout),
+    }
+}
+
+/// Minimal executor for tests which parks the thread until the future is woken.
+#[cfg(all(test, feature = "async"))]
+pub(crate) fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    struct Unpark(std::thread::Thread);
+    impl std::task::Wake for Unpark {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+    let waker = std::sync::Arc::new(Unpark(std::thread::current())).into();
+    let mut cx = std::task::Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            std::task::Poll::Ready(output) => return output,
+            std::task::Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_deadline() {
+        let deadline = Deadline::after(Duration::from_millis(20));
+        assert!(deadline.remaining() <= Duration::from_millis(20));
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(deadline.remaining(), Duration::ZERO);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_timeout() {
+        let pending = std::future::pending::<Result<(), crate::AxdlError>>();
+        let result = block_on(timeout(Duration::from_millis(10), pending));
+        assert!(matches!(result, Err(crate::AxdlError::DeviceTimeout)));
+        let ready = std::future::ready(Ok(1));
+        assert_eq!(
+            block_on(timeout(Duration::from_secs(60), ready)).unwrap(),
+            1
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! USB ids and endpoints of Axera devices in download mode, shared by all transports.
+//!
+//! The boot ROM enumerates as [`DEFAULT`]. Boards with other ids, e.g. a custom romcode, can
+//! [`register`] them at runtime so that every transport lists and opens them too.
+
+use std::sync::{PoisonError, RwLock};
+
+pub const VENDOR_ID: u16 = 0x32c9;
+pub const PRODUCT_ID: u16 = 0x1000;
+/// Address of the bulk OUT endpoint of the boot ROM.
+pub const ENDPOINT_OUT: u8 = 0x01;
+/// Address of the bulk IN endpoint of the boot ROM, with the direction bit set.
+pub const ENDPOINT_IN: u8 = 0x81;
+
+/// Vendor and product id of a device in download mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize)]
+pub struct UsbId {
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+/// Ids of the boot ROM.
+pub const DEFAULT: UsbId = UsbId::new(VENDOR_ID, PRODUCT_ID);
+
+impl UsbId {
+    pub const fn new(vendor_id: u16, product_id: u16) -> Self {
+        Self {
+            vendor_id,
+            product_id,
+        }
+    }
+}
+
+impl std::fmt::Display for UsbId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vendor_id, self.product_id)
+    }
+}
+
+impl std::str::FromStr for UsbId {
+    type Err = String;
+
+    /// Parses hexadecimal ids as printed by `lsusb`, e.g. `32c9:1000`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid USB id, expected VID:PID in hexadecimal: {}", s);
+        let (vendor_id, product_id) = s.split_once(':').ok_or_else(invalid)?;
+        let parse = |id: &str| u16::from_str_radix(id, 16).map_err(|_| invalid());
+        Ok(Self::new(parse(vendor_id)?, parse(product_id)?))
+    }
+}
+
+/// Ids registered in addition to [`DEFAULT`].
+static REGISTERED: RwLock<Vec<UsbId>> = RwLock::new(Vec::new());
+
+/// Adds `id` to the ids the transports look for. Registering an id twice has no effect.
+pub fn register(id: UsbId) {
+    let mut registered = REGISTERED.write().unwrap_or_else(PoisonError::into_inner);
+    if id != DEFAULT && !registered.contains(&id) {
+        registered.push(id);
+    }
+}
+
+/// Ids the transports look for, [`DEFAULT`] first.
+pub fn known() -> Vec<UsbId> {
+    let registered = REGISTERED.read().unwrap_or_else(PoisonError::into_inner);
+    std::iter::once(DEFAULT)
+        .chain(registered.iter().copied())
+        .collect()
+}
+
+/// Returns whether a device with these ids is one the transports look for.
+pub fn is_known(vendor_id: u16, product_id: u16) -> bool {
+    let id = UsbId::new(vendor_id, product_id);
+    id == DEFAULT
+        || REGISTERED
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains(&id)
+}
+
+/// Endpoint number of an endpoint address, which is what WebUSB transfers take.
+pub const fn endpoint_number(address: u8) -> u8 {
+    address & 0x0f
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        assert!(is_known(VENDOR_ID, PRODUCT_ID));
+        assert!(!is_known(0x1234, 0x5678));
+        let id: UsbId = "1234:5678".parse().unwrap();
+        assert_eq!(id, UsbId::new(0x1234, 0x5678));
+        assert_eq!(id.to_string(), "1234:5678");
+        assert!("1234".parse::<UsbId>().is_err());
+        assert!("1234:xyz".parse::<UsbId>().is_err());
+
+        register(id);
+        register(id);
+        register(DEFAULT);
+        assert!(is_known(0x1234, 0x5678));
+        assert_eq!(
+            known().iter().filter(|known| **known == id).count(),
+            1,
+            "registered once"
+        );
+        assert_eq!(known()[0], DEFAULT);
+    }
+
+    #[test]
+    fn test_endpoint_number() {
+        assert_eq!(endpoint_number(ENDPOINT_OUT), 1);
+        assert_eq!(endpoint_number(ENDPOINT_IN), 1);
+    }
+}
@@ -0,0 +1,201 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Random faults injected into the responses of a real device, to reproduce intermittent
+//! failures seen in the field, such as an occasional invalid frame, on the bench.
+//!
+//! The faults are drawn from a pseudo random sequence, so a run with the same seed injects the
+//! same faults into the same responses.
+
+use std::time::Duration;
+
+use crate::AxdlError;
+
+use super::{Device, DeviceInfo, DynDevice, TransportKind};
+
+/// Fault applied to a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InjectedFault {
+    /// The response is discarded and the read times out.
+    DropResponse,
+    /// The checksum of the response is inverted.
+    CorruptChecksum,
+}
+
+/// Fault injected into each response with the given probability, e.g. `drop-response:0.01`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Injection {
+    pub fault: InjectedFault,
+    pub probability: f64,
+}
+
+impl std::str::FromStr for Injection {
+    type Err = AxdlError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AxdlError::InvalidConfig(format!("invalid fault injection: {}", s));
+        let (fault, probability) = s.split_once(':').ok_or_else(invalid)?;
+        let fault = match fault {
+            "drop-response" => InjectedFault::DropResponse,
+            "corrupt-checksum" => InjectedFault::CorruptChecksum,
+            _ => return Err(invalid()),
+        };
+        let probability = probability.parse::<f64>().map_err(|_| invalid())?;
+        if !(0.0..=1.0).contains(&probability) {
+            return Err(invalid());
+        }
+        Ok(Self { fault, probability })
+    }
+}
+
+/// SplitMix64, which is enough to decide on faults and needs no dependency.
+#[derive(Debug, Clone)]
+struct Random(u64);
+
+impl Random {
+    fn next_f64(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Device which injects faults into the responses of `inner`.
+pub struct FaultInjectingDevice {
+    inner: DynDevice,
+    injections: Vec<Injection>,
+    random: Random,
+    responses: usize,
+}
+
+impl FaultInjectingDevice {
+    pub fn new(inner: DynDevice, injections: Vec<Injection>, seed: u64) -> Self {
+        Self {
+            inner,
+            injections,
+            random: Random(seed),
+            responses: 0,
+        }
+    }
+
+    /// Draws the fault for the next response, if any. One number is drawn per injection and
+    /// response, so the sequence of faults only depends on the seed.
+    fn draw(&mut self) -> Option<InjectedFault> {
+        let mut fault = None;
+        for injection in &self.injections {
+            if self.random.next_f64() < injection.probability && fault.is_none() {
+                fault = Some(injection.fault);
+            }
+        }
+        fault
+    }
+}
+
+impl DeviceInfo for FaultInjectingDevice {
+    fn transport_kind(&self) -> TransportKind {
+        self.inner.transport_kind()
+    }
+    fn display_name(&self) -> String {
+        self.inner.display_name()
+    }
+    fn unique_id(&self) -> String {
+        self.inner.unique_id()
+    }
+}
+
+impl Device for FaultInjectingDevice {
+    fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, AxdlError> {
+        let length = self.inner.read_timeout(buf, timeout)?;
+        self.responses += 1;
+        match self.draw() {
+            None => Ok(length),
+            Some(fault) => {
+                tracing::warn!("injecting {:?} into response {}", fault, self.responses);
+                match fault {
+                    InjectedFault::DropResponse => Err(AxdlError::DeviceTimeout),
+                    InjectedFault::CorruptChecksum => {
+                        if let Some(last) = buf[..length].last_mut() {
+                            *last ^= 0xff;
+                        }
+                        Ok(length)
+                    }
+                }
+            }
+        }
+    }
+    fn write_timeout(&mut self, buf: &[u8], timeout: Duration) -> Result<usize, AxdlError> {
+        self.inner.write_timeout(buf, timeout)
+    }
+    fn max_packet_size(&self) -> Option<usize> {
+        self.inner.max_packet_size()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transport::mock::MockDevice;
+
+    fn faults(seed: u64) -> Vec<Result<Vec<u8>, AxdlError>> {
+        let inner = MockDevice::new(|packet| vec![packet.to_vec()]);
+        let injections = vec![
+            "drop-response:0.3".parse().unwrap(),
+            "corrupt-checksum:0.3".parse().unwrap(),
+        ];
+        let mut device = FaultInjectingDevice::new(Box::new(inner), injections, seed);
+        (0..32u8)
+            .map(|i| {
+                device.write_timeout(&[i, i], Duration::ZERO).unwrap();
+                let mut buf = [0u8; 2];
+                device
+                    .read_timeout(&mut buf, Duration::ZERO)
+                    .map(|length| buf[..length].to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_faults_repeat_with_the_seed() {
+        let first = faults(7);
+        assert_eq!(format!("{:?}", first), format!("{:?}", faults(7)));
+        assert_ne!(format!("{:?}", first), format!("{:?}", faults(8)));
+        let dropped = first
+            .iter()
+            .filter(|r| matches!(r, Err(AxdlError::DeviceTimeout)))
+            .count();
+        let corrupted = first
+            .iter()
+            .enumerate()
+            .filter(|(i, r)| matches!(r, Ok(data) if data[1] != *i as u8))
+            .count();
+        assert!(dropped > 0 && corrupted > 0 && dropped + corrupted < 32);
+    }
+
+    #[test]
+    fn test_parse_injection() {
+        assert_eq!(
+            "corrupt-checksum:0.001".parse::<Injection>().unwrap(),
+            Injection {
+                fault: InjectedFault::CorruptChecksum,
+                probability: 0.001
+            }
+        );
+        assert!("drop-response".parse::<Injection>().is_err());
+        assert!("drop-response:1.5".parse::<Injection>().is_err());
+        assert!("delay:0.1".parse::<Injection>().is_err());
+    }
+}
@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Advisory per-device locks, so that two processes do not download to the same device.
+//!
+//! Each device has a lock file in `axdl-locks` under the temporary directory, locked for as long
+//! as the device is open. The OS releases the lock when the process exits, even if it crashed.
+
+use std::{fs::File, path::PathBuf};
+
+use crate::AxdlError;
+
+/// Exclusive lock on a device, released when dropped.
+#[derive(Debug)]
+pub struct DeviceLock {
+    _file: File,
+}
+
+impl DeviceLock {
+    fn path(key: &str) -> PathBuf {
+        let name: String = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        std::env::temp_dir()
+            .join("axdl-locks")
+            .join(format!("{}.lock", name))
+    }
+
+    /// Locks the device identified by `key`, e.g. its bus and port numbers.
+    ///
+    /// Fails with [`AxdlError::DeviceBusy`] if another process or handle holds the lock.
+    pub fn acquire(key: &str) -> Result<Self, AxdlError> {
+        let path = Self::path(key);
+        let io_error = |e| AxdlError::IoError(format!("failed to lock {}", path.display()), e);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(io_error)?;
+        }
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(io_error)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(std::fs::TryLockError::WouldBlock) => Err(AxdlError::DeviceBusy(key.to_string())),
+            Err(std::fs::TryLockError::Error(e)) => Err(io_error(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_device_lock() {
+        let key = format!("test-{}", std::process::id());
+        let lock = DeviceLock::acquire(&key).unwrap();
+        assert!(matches!(
+            DeviceLock::acquire(&key),
+            Err(AxdlError::DeviceBusy(_))
+        ));
+        drop(lock);
+        DeviceLock::acquire(&key).unwrap();
+    }
+}
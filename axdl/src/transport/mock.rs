@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::AxdlError;
+
+use super::{Device, DeviceInfo, TransportKind};
+
+/// Responder invoked for every packet written to a [`MockDevice`].
+///
+/// It returns the packets which the device sends back, in order.
+pub type MockResponder = Box<dyn FnMut(&[u8]) -> Vec<Vec<u8>> + Send>;
+
+/// In-memory device for tests and benchmarks.
+///
+/// Every write is passed to the responder and the packets it returns are queued
+/// for the following reads. A read with no queued packet fails with [`AxdlError::DeviceTimeout`].
+pub struct MockDevice {
+    responder: MockResponder,
+    pending: VecDeque<Vec<u8>>,
+    record: bool,
+    written: Vec<Vec<u8>>,
+    transfer_size: Option<usize>,
+    transport_kind: TransportKind,
+    max_packet_size: Option<usize>,
+    index: usize,
+}
+
+static DEVICES_CREATED: AtomicUsize = AtomicUsize::new(0);
+
+impl MockDevice {
+    pub fn new(responder: impl FnMut(&[u8]) -> Vec<Vec<u8>> + Send + 'static) -> Self {
+        Self {
+            responder: Box::new(responder),
+            pending: VecDeque::new(),
+            record: false,
+            written: Vec::new(),
+            transfer_size: None,
+            transport_kind: TransportKind::Mock,
+            max_packet_size: None,
+            index: DEVICES_CREATED.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Keeps a copy of every written packet, retrievable with [`MockDevice::written`].
+    pub fn with_recording(mut self) -> Self {
+        self.record = true;
+        self
+    }
+
+    /// Splits the packets from the responder into reads of at most `size` bytes, like a device
+    /// which sends a long response in several bulk transfers.
+    pub fn with_transfer_size(mut self, size: usize) -> Self {
+        self.transfer_size = Some(size.max(1));
+        self
+    }
+
+    /// Reports `kind` as its transport, to exercise what the host does differently for it.
+    pub fn with_transport_kind(mut self, kind: TransportKind) -> Self {
+        self.transport_kind = kind;
+        self
+    }
+
+    /// Reports `size` as the max packet size of its OUT endpoint, like a USB device.
+    pub fn with_max_packet_size(mut self, size: usize) -> Self {
+        self.max_packet_size = Some(size);
+        self
+    }
+
+    /// Queues a packet to be returned by a following read.
+    pub fn push_response(&mut self, packet: Vec<u8>) {
+        self.pending.push_back(packet);
+    }
+
+    /// Returns the recorded packets.
+    pub fn written(&self) -> &[Vec<u8>] {
+        &self.written
+    }
+}
+
+impl std::fmt::Debug for MockDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockDevice")
+            .field("pending", &self.pending.len())
+            .field("written", &self.written.len())
+            .finish()
+    }
+}
+
+impl DeviceInfo for MockDevice {
+    fn transport_kind(&self) -> TransportKind {
+        self.transport_kind
+    }
+    fn display_name(&self) -> String {
+        format!("Mock device #{}", self.index)
+    }
+    fn unique_id(&self) -> String {
+        format!("mock:{}", self.index)
+    }
+}
+
+impl Device for MockDevice {
+    fn read_timeout(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize, AxdlError> {
+        let packet = self.pending.pop_front().ok_or(AxdlError::DeviceTimeout)?;
+        let length = packet.len().min(buf.len());
+        buf[..length].copy_from_slice(&packet[..length]);
+        Ok(length)
+    }
+    fn write_timeout(&mut self, buf: &[u8], _timeout: Duration) -> Result<usize, AxdlError> {
+        if self.record {
+            self.written.push(buf.to_vec());
+        }
+        let responses = (self.responder)(buf);
+        match self.transfer_size {
+            Some(size) => self.pending.extend(
+                responses
+                    .iter()
+                    .flat_map(|response| response.chunks(size).map(<[u8]>::to_vec)),
+            ),
+            None => self.pending.extend(responses),
+        }
+        Ok(buf.len())
+    }
+    fn max_packet_size(&self) -> Option<usize> {
+        self.max_packet_size
+    }
+}
+
+/// Answers immediately, so the futures are always ready.
+#[cfg(feature = "async")]
+impl super::AsyncDevice for MockDevice {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, AxdlError> {
+        self.read_timeout(buf, Duration::ZERO)
+    }
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, AxdlError> {
+        self.write_timeout(buf, Duration::ZERO)
+    }
+    fn max_packet_size(&self) -> Option<usize> {
+        self.max_packet_size
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_device_info() {
+        let first = MockDevice::new(|_| Vec::new());
+        let second = MockDevice::new(|_| Vec::new());
+        assert_eq!(first.transport_kind(), TransportKind::Mock);
+        assert_eq!(first.transport_kind().to_string(), "mock");
+        assert_ne!(first.unique_id(), second.unique_id());
+    }
+}
@@ -2,6 +2,12 @@ use std::time::Duration;
 
 use crate::AxdlError;
 
+pub mod ids;
+pub mod inject;
+#[cfg(any(feature = "usb", feature = "serial"))]
+pub mod lock;
+pub mod mock;
+pub mod record;
 #[cfg(feature = "serial")]
 pub mod serial;
 #[cfg(feature = "usb")]
@@ -11,10 +17,47 @@ pub mod webserial;
 #[cfg(feature = "webusb")]
 pub mod webusb;
 
+/// Kind of transport a device is connected through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    Usb,
+    Serial,
+    WebUsb,
+    WebSerial,
+    Mock,
+}
+
+impl std::fmt::Display for TransportKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TransportKind::Usb => "USB",
+            TransportKind::Serial => "serial",
+            TransportKind::WebUsb => "WebUSB",
+            TransportKind::WebSerial => "WebSerial",
+            TransportKind::Mock => "mock",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Identifies a device in messages, logs and APIs when more than one device is in use.
+pub trait DeviceInfo {
+    fn transport_kind(&self) -> TransportKind;
+    /// Human readable name, e.g. the product name and the port.
+    fn display_name(&self) -> String;
+    /// Identifier which tells the device apart from the others open at the same time.
+    fn unique_id(&self) -> String;
+}
+
 /// Device trait for reading and writing data.
-pub trait Device {
+pub trait Device: DeviceInfo {
     fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, AxdlError>;
     fn write_timeout(&mut self, buf: &[u8], timeout: Duration) -> Result<usize, AxdlError>;
+    /// Max packet size of the OUT endpoint, for transports which have one.
+    fn max_packet_size(&self) -> Option<usize> {
+        None
+    }
 }
 
 /// Transport trait for listing devices and opening devices.
@@ -27,11 +70,127 @@ pub trait Transport {
 
 pub type DynDevice = Box<dyn Device>;
 
-#[cfg(feature = "webusb")]
+/// Returns whether a bulk transfer of `length` bytes must be terminated by a zero-length packet.
+///
+/// A transfer ending exactly on a packet boundary is otherwise indistinguishable from one that continues.
+pub fn needs_zero_length_packet(length: usize, max_packet_size: usize) -> bool {
+    max_packet_size > 0 && length > 0 && length.is_multiple_of(max_packet_size)
+}
+
+/// Opens the first of `paths` which is not in use by another process.
+///
+/// Fails with [`AxdlError::DeviceBusy`] if every device is in use.
+#[cfg(any(feature = "usb", feature = "serial"))]
+fn open_any<T: Transport>(paths: Vec<T::DeviceId>) -> Result<Option<T::DeviceType>, AxdlError> {
+    let mut busy = None;
+    for path in paths {
+        match T::open_device(&path) {
+            Ok(device) => return Ok(Some(device)),
+            Err(e @ AxdlError::DeviceBusy(_)) => busy = Some(e),
+            Err(e) => tracing::debug!("failed to open the device: {}", e),
+        }
+    }
+    busy.map_or(Ok(None), Err)
+}
+
+/// Calls `open` until it returns a device.
+///
+/// Fails with [`AxdlError::DeviceTimeout`] after `timeout`, or with [`AxdlError::UserCancelled`]
+/// once `is_cancelled` returns true.
+#[cfg(any(feature = "usb", feature = "serial"))]
+fn poll_for_device<D>(
+    timeout: Option<Duration>,
+    is_cancelled: impl Fn() -> bool,
+    mut open: impl FnMut() -> Result<Option<D>, AxdlError>,
+) -> Result<D, AxdlError> {
+    let start = std::time::Instant::now();
+    loop {
+        if let Some(device) = open()? {
+            return Ok(device);
+        }
+        if is_cancelled() {
+            return Err(AxdlError::UserCancelled);
+        }
+        if timeout.is_some_and(|timeout| start.elapsed() > timeout) {
+            return Err(AxdlError::DeviceTimeout);
+        }
+        std::thread::sleep(NativeTransport::POLL_INTERVAL);
+    }
+}
+
+/// Transports which can be opened without user interaction, i.e. on native builds.
+#[cfg(any(feature = "usb", feature = "serial"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NativeTransport {
+    #[cfg(feature = "usb")]
+    Usb,
+    #[cfg(feature = "serial")]
+    Serial,
+}
+
+#[cfg(any(feature = "usb", feature = "serial"))]
+impl NativeTransport {
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    /// Opens the first device found which is not in use by another process, or returns `None` if
+    /// no device is connected.
+    ///
+    /// Fails with [`AxdlError::DeviceBusy`] if every connected device is in use.
+    pub fn open_first(self) -> Result<Option<DynDevice>, AxdlError> {
+        fn open<T: Transport>() -> Result<Option<DynDevice>, AxdlError>
+        where
+            T::DeviceType: 'static,
+        {
+            Ok(open_any::<T>(T::list_devices()?)?.map(|device| Box::new(device) as DynDevice))
+        }
+        match self {
+            #[cfg(feature = "usb")]
+            Self::Usb => open::<usb::UsbTransport>(),
+            #[cfg(feature = "serial")]
+            Self::Serial => open::<serial::SerialTransport>(),
+        }
+    }
+
+    /// Polls until a device is connected.
+    ///
+    /// Fails with [`AxdlError::DeviceTimeout`] after `timeout`, or with [`AxdlError::UserCancelled`]
+    /// once `is_cancelled` returns true.
+    pub fn wait_for_device(
+        self,
+        timeout: Option<Duration>,
+        is_cancelled: impl Fn() -> bool,
+    ) -> Result<DynDevice, AxdlError> {
+        poll_for_device(timeout, is_cancelled, || self.open_first())
+    }
+
+    /// Returns true if a device is connected, without opening it.
+    pub fn is_connected(self) -> Result<bool, AxdlError> {
+        match self {
+            #[cfg(feature = "usb")]
+            Self::Usb => Ok(!usb::UsbTransport::list_devices()?.is_empty()),
+            #[cfg(feature = "serial")]
+            Self::Serial => Ok(!serial::SerialTransport::list_devices()?.is_empty()),
+        }
+    }
+
+    /// Polls until no device is connected, e.g. after the operator unplugged a flashed board.
+    pub fn wait_for_removal(self, is_cancelled: impl Fn() -> bool) -> Result<(), AxdlError> {
+        while self.is_connected()? {
+            if is_cancelled() {
+                return Err(AxdlError::UserCancelled);
+            }
+            std::thread::sleep(Self::POLL_INTERVAL);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
 mod async_transport {
+    use super::DeviceInfo;
     use crate::AxdlError;
 
-    pub trait AsyncDevice {
+    pub trait AsyncDevice: DeviceInfo {
         fn read(
             &mut self,
             buf: &mut [u8],
@@ -40,6 +199,10 @@ mod async_transport {
             &mut self,
             buf: &[u8],
         ) -> impl std::future::Future<Output = Result<usize, AxdlError>>;
+        /// Max packet size of the OUT endpoint, for transports which have one.
+        fn max_packet_size(&self) -> Option<usize> {
+            None
+        }
     }
 
     pub trait AsyncTransport {
@@ -53,5 +216,5 @@ mod async_transport {
     }
 }
 
-#[cfg(feature = "webusb")]
+#[cfg(feature = "async")]
 pub use async_transport::*;
@@ -0,0 +1,389 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 Kenta Ida
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording of the packets exchanged with a device, and replay of a recording.
+//!
+//! A session recorded from real hardware with [`RecordingDevice`] can be replayed with
+//! [`ReplayDevice`], which answers with the recorded responses and reports every request
+//! which differs from the recorded one. This catches protocol regressions without hardware.
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::AxdlError;
+
+use super::{Device, DeviceInfo, DynDevice, TransportKind};
+
+const MAGIC: &[u8; 8] = b"AXDLSES1";
+
+/// Direction of a recorded packet, seen from the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Written to the device.
+    Out,
+    /// Read from the device.
+    In,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+/// Packets exchanged with a device, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    /// Max packet size of the recorded device, which decides where zero-length packets are sent.
+    pub max_packet_size: Option<usize>,
+    pub packets: Vec<Packet>,
+}
+
+fn invalid_data(message: &str) -> AxdlError {
+    AxdlError::IoError(
+        "failed to read the recording".into(),
+        std::io::Error::new(std::io::ErrorKind::InvalidData, message),
+    )
+}
+
+impl Recording {
+    /// Writes the recording as `AXDLSES1`, the max packet size (u32, 0 if none) and then every
+    /// packet as a direction byte (0 out, 1 in), a u32 length and the data, little endian.
+    pub fn write_to(&self, mut writer: impl Write) -> Result<(), AxdlError> {
+        let io_error = |e| AxdlError::IoError("failed to write the recording".into(), e);
+        let max_packet_size = self.max_packet_size.unwrap_or(0) as u32;
+        writer.write_all(MAGIC).map_err(io_error)?;
+        writer
+            .write_all(&max_packet_size.to_le_bytes())
+            .map_err(io_error)?;
+        for packet in &self.packets {
+            let direction = match packet.direction {
+                Direction::Out => 0u8,
+                Direction::In => 1u8,
+            };
+            writer.write_all(&[direction]).map_err(io_error)?;
+            writer
+                .write_all(&(packet.data.len() as u32).to_le_bytes())
+                .map_err(io_error)?;
+            writer.write_all(&packet.data).map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a recording written by [`Recording::write_to`].
+    pub fn read_from(mut reader: impl Read) -> Result<Self, AxdlError> {
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .map_err(|e| AxdlError::IoError("failed to read the recording".into(), e))?;
+        let rest = data
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| invalid_data("not an axdl session recording"))?;
+        let (max_packet_size, mut rest) = rest
+            .split_first_chunk::<4>()
+            .ok_or_else(|| invalid_data("truncated header"))?;
+        let max_packet_size = u32::from_le_bytes(*max_packet_size) as usize;
+        let mut packets = Vec::new();
+        while let Some((&direction, after)) = rest.split_first() {
+            let direction = match direction {
+                0 => Direction::Out,
+                1 => Direction::In,
+                _ => return Err(invalid_data("invalid packet direction")),
+            };
+            let (length, after) = after
+                .split_first_chunk::<4>()
+                .ok_or_else(|| invalid_data("truncated packet"))?;
+            let length = u32::from_le_bytes(*length) as usize;
+            if after.len() < length {
+                return Err(invalid_data("truncated packet"));
+            }
+            let (packet, after) = after.split_at(length);
+            packets.push(Packet {
+                direction,
+                data: packet.to_vec(),
+            });
+            rest = after;
+        }
+        Ok(Self {
+            max_packet_size: (max_packet_size != 0).then_some(max_packet_size),
+            packets,
+        })
+    }
+}
+
+/// Device which records every packet passed to and from the inner device.
+///
+/// The recording is shared, so that it can be taken after the device has been boxed into a
+/// [`DynDevice`] and passed to [`crate::download_image`].
+pub struct RecordingDevice {
+    inner: DynDevice,
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl RecordingDevice {
+    pub fn new(inner: DynDevice) -> Self {
+        let recording = Recording {
+            max_packet_size: inner.max_packet_size(),
+            packets: Vec::new(),
+        };
+        Self {
+            inner,
+            recording: Arc::new(Mutex::new(recording)),
+        }
+    }
+
+    /// Returns a handle to the recording, which keeps growing while the device is used.
+    pub fn recording(&self) -> Arc<Mutex<Recording>> {
+        self.recording.clone()
+    }
+
+    fn push(&self, direction: Direction, data: &[u8]) {
+        self.recording
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .packets
+            .push(Packet {
+                direction,
+                data: data.to_vec(),
+            });
+    }
+}
+
+impl DeviceInfo for RecordingDevice {
+    fn transport_kind(&self) -> TransportKind {
+        self.inner.transport_kind()
+    }
+    fn display_name(&self) -> String {
+        self.inner.display_name()
+    }
+    fn unique_id(&self) -> String {
+        self.inner.unique_id()
+    }
+}
+
+impl Device for RecordingDevice {
+    fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, AxdlError> {
+        let length = self.inner.read_timeout(buf, timeout)?;
+        self.push(Direction::In, &buf[..length]);
+        Ok(length)
+    }
+    fn write_timeout(&mut self, buf: &[u8], timeout: Duration) -> Result<usize, AxdlError> {
+        let length = self.inner.write_timeout(buf, timeout)?;
+        self.push(Direction::Out, &buf[..length]);
+        Ok(length)
+    }
+    fn max_packet_size(&self) -> Option<usize> {
+        self.inner.max_packet_size()
+    }
+}
+
+/// Request which differs from the recorded one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Index of the request among the written packets.
+    pub index: usize,
+    /// Recorded request, or `None` if the recording had already ended.
+    pub expected: Option<Vec<u8>>,
+    pub actual: Vec<u8>,
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.expected {
+            None => write!(
+                f,
+                "request #{}: {} bytes written after the end of the recording",
+                self.index,
+                self.actual.len()
+            ),
+            Some(expected) => {
+                let offset = expected
+                    .iter()
+                    .zip(&self.actual)
+                    .position(|(a, b)| a != b)
+                    .unwrap_or(expected.len().min(self.actual.len()));
+                write!(
+                    f,
+                    "request #{}: expected {} bytes, got {} bytes, first difference at offset {}",
+                    self.index,
+                    expected.len(),
+                    self.actual.len(),
+                    offset
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ReplayState {
+    packets: VecDeque<Packet>,
+    pending: VecDeque<Vec<u8>>,
+    requests: usize,
+    mismatches: Vec<Mismatch>,
+}
+
+impl ReplayState {
+    fn queue_responses(&mut self) {
+        while self
+            .packets
+            .front()
+            .is_some_and(|p| p.direction == Direction::In)
+        {
+            if let Some(packet) = self.packets.pop_front() {
+                self.pending.push_back(packet.data);
+            }
+        }
+    }
+}
+
+/// Device which plays back a [`Recording`].
+///
+/// Every write is compared with the next recorded request and answered with the responses
+/// recorded after it, whether it matched or not. Clones share the playback, so that the result
+/// can be checked after a clone has been passed to [`crate::download_image`].
+#[derive(Debug, Clone)]
+pub struct ReplayDevice {
+    state: Arc<Mutex<ReplayState>>,
+    max_packet_size: Option<usize>,
+}
+
+impl ReplayDevice {
+    pub fn new(recording: Recording) -> Self {
+        let mut state = ReplayState {
+            packets: recording.packets.into(),
+            ..Default::default()
+        };
+        // The handshake is read before anything is written.
+        state.queue_responses();
+        Self {
+            state: Arc::new(Mutex::new(state)),
+            max_packet_size: recording.max_packet_size,
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, ReplayState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Requests which differed from the recording so far.
+    pub fn mismatches(&self) -> Vec<Mismatch> {
+        self.state().mismatches.clone()
+    }
+
+    /// Returns whether every recorded packet has been played back.
+    pub fn is_complete(&self) -> bool {
+        let state = self.state();
+        state.packets.is_empty() && state.pending.is_empty()
+    }
+}
+
+impl DeviceInfo for ReplayDevice {
+    fn transport_kind(&self) -> TransportKind {
+        TransportKind::Mock
+    }
+    fn display_name(&self) -> String {
+        "Replayed session".into()
+    }
+    fn unique_id(&self) -> String {
+        "replay".into()
+    }
+}
+
+impl Device for ReplayDevice {
+    fn read_timeout(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize, AxdlError> {
+        let packet = self
+            .state()
+            .pending
+            .pop_front()
+            .ok_or(AxdlError::DeviceTimeout)?;
+        let length = packet.len().min(buf.len());
+        buf[..length].copy_from_slice(&packet[..length]);
+        Ok(length)
+    }
+    fn write_timeout(&mut self, buf: &[u8], _timeout: Duration) -> Result<usize, AxdlError> {
+        let mut state = self.state();
+        // Responses which were not read before this request belong to the previous one.
+        state.pending.clear();
+        let expected = state.packets.pop_front().map(|p| p.data);
+        if expected.as_deref() != Some(buf) {
+            let index = state.requests;
+            state.mismatches.push(Mismatch {
+                index,
+                expected,
+                actual: buf.to_vec(),
+            });
+        }
+        state.requests += 1;
+        state.queue_responses();
+        Ok(buf.len())
+    }
+    fn max_packet_size(&self) -> Option<usize> {
+        self.max_packet_size
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transport::mock::MockDevice;
+
+    fn echo_device() -> MockDevice {
+        MockDevice::new(|packet| vec![packet.iter().rev().copied().collect()])
+    }
+
+    fn exchange(device: &mut impl Device, request: &[u8]) -> Vec<u8> {
+        device.write_timeout(request, Duration::ZERO).unwrap();
+        let mut buf = [0u8; 16];
+        let length = device.read_timeout(&mut buf, Duration::ZERO).unwrap();
+        buf[..length].to_vec()
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut device = RecordingDevice::new(Box::new(echo_device()));
+        exchange(&mut device, &[1, 2, 3]);
+        exchange(&mut device, &[4, 5]);
+        let recording = device.recording().lock().unwrap().clone();
+        assert_eq!(recording.packets.len(), 4);
+
+        let mut bytes = Vec::new();
+        recording.write_to(&mut bytes).unwrap();
+        let recording = Recording::read_from(bytes.as_slice()).unwrap();
+
+        let mut replay = ReplayDevice::new(recording.clone());
+        assert_eq!(exchange(&mut replay, &[1, 2, 3]), [3, 2, 1]);
+        assert!(!replay.is_complete());
+        assert_eq!(exchange(&mut replay, &[4, 5]), [5, 4]);
+        assert!(replay.is_complete());
+        assert!(replay.mismatches().is_empty());
+
+        let mut replay = ReplayDevice::new(recording);
+        assert_eq!(exchange(&mut replay, &[1, 2, 4]), [3, 2, 1]);
+        assert_eq!(replay.mismatches().len(), 1);
+        assert_eq!(
+            replay.mismatches()[0].to_string(),
+            "request #0: expected 3 bytes, got 3 bytes, first difference at offset 2"
+        );
+    }
+
+    #[test]
+    fn test_read_invalid_recording() {
+        assert!(Recording::read_from(&b"AXDLSES0"[..]).is_err());
+        assert!(Recording::read_from(&b"AXDLSES1\0\0\0\0\0\x05\0\0\0ab"[..]).is_err());
+    }
+}
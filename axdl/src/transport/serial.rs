@@ -1,24 +1,114 @@
-use crate::AxdlError;
+use crate::{communication::DEFAULT_MAX_FRAME_SIZE, frame::FrameAccumulator, AxdlError};
+
+use super::lock::DeviceLock;
 use std::time::Duration;
 
-use super::{Device, Transport};
+use super::{ids, Device, DeviceInfo, Transport, TransportKind};
 
-pub const VENDOR_ID: u16 = 0x32c9;
-pub const PRODUCT_ID: u16 = 0x1000;
+pub use super::ids::{PRODUCT_ID, VENDOR_ID};
 
 /// Transport implementation for serial ports
 pub struct SerialTransport;
 
-/// Device path for serial ports.
-#[derive(Debug, Clone, PartialEq)]
+/// Device path for serial ports, with the USB descriptors reported by the OS.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
 pub struct SerialDevicePath {
     port_name: String,
+    product: Option<String>,
+    serial_number: Option<String>,
+    /// Number of the USB interface of the port, which tells apart the ports of a composite device.
+    interface: Option<u8>,
+    /// Name of the USB interface of the port; only known on Linux.
+    interface_name: Option<String>,
 }
 
 impl SerialDevicePath {
     pub fn is_match(&self, port_name: &str) -> bool {
         self.port_name == port_name
     }
+
+    pub fn port_name(&self) -> &str {
+        &self.port_name
+    }
+
+    pub fn product(&self) -> Option<&str> {
+        self.product.as_deref()
+    }
+
+    pub fn serial_number(&self) -> Option<&str> {
+        self.serial_number.as_deref()
+    }
+
+    pub fn interface(&self) -> Option<u8> {
+        self.interface
+    }
+
+    pub fn interface_name(&self) -> Option<&str> {
+        self.interface_name.as_deref()
+    }
+}
+
+/// Selects one of the ports of a board which exposes several with the same ids, by the number
+/// or the name of their USB interface.
+///
+/// The textual form is the interface number, e.g. `0`, or a part of its name, e.g. `download`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterfaceFilter {
+    Number(u8),
+    /// Matches interface names containing this, ignoring case.
+    Name(String),
+}
+
+impl InterfaceFilter {
+    pub fn matches(&self, path: &SerialDevicePath) -> bool {
+        match self {
+            Self::Number(number) => path.interface == Some(*number),
+            Self::Name(name) => path
+                .interface_name
+                .as_deref()
+                .is_some_and(|interface| interface.to_lowercase().contains(&name.to_lowercase())),
+        }
+    }
+}
+
+impl std::str::FromStr for InterfaceFilter {
+    type Err = AxdlError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(AxdlError::InvalidConfig(
+                "empty serial interface filter".to_string(),
+            ));
+        }
+        Ok(s.parse()
+            .map(Self::Number)
+            .unwrap_or_else(|_| Self::Name(s.to_string())))
+    }
+}
+
+impl std::fmt::Display for InterfaceFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Number(number) => write!(f, "{}", number),
+            Self::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Reads the name of the USB interface of a tty from sysfs.
+#[cfg(target_os = "linux")]
+fn interface_name(port_name: &str) -> Option<String> {
+    let tty = std::path::Path::new(port_name).file_name()?;
+    let path = std::path::Path::new("/sys/class/tty")
+        .join(tty)
+        .join("device/interface");
+    let name = std::fs::read_to_string(path).ok()?;
+    Some(name.trim().to_string()).filter(|name| !name.is_empty())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn interface_name(_port_name: &str) -> Option<String> {
+    None
 }
 
 impl std::fmt::Display for SerialDevicePath {
@@ -37,9 +127,13 @@ impl Transport for SerialTransport {
             .iter()
             .filter_map(|port_info| match &port_info.port_type {
                 serialport::SerialPortType::UsbPort(usb) => {
-                    if usb.vid == VENDOR_ID && usb.pid == PRODUCT_ID {
+                    if ids::is_known(usb.vid, usb.pid) {
                         Some(SerialDevicePath {
                             port_name: port_info.port_name.clone(),
+                            product: usb.product.clone(),
+                            serial_number: usb.serial_number.clone(),
+                            interface: usb.interface,
+                            interface_name: interface_name(&port_info.port_name),
                         })
                     } else {
                         None
@@ -51,33 +145,368 @@ impl Transport for SerialTransport {
         Ok(list)
     }
     fn open_device(path: &Self::DeviceId) -> Result<Self::DeviceType, AxdlError> {
+        let lock = DeviceLock::acquire(&format!("serial-{}", path.port_name))?;
         let port = serialport::new(&path.port_name, 115200)
             .open()
             .map_err(AxdlError::SerialError)?;
-        Ok(SerialDevice { port })
+        Ok(SerialDevice::new(port, path.clone(), lock))
+    }
+}
+
+impl SerialTransport {
+    /// Lists the ports whose USB interface matches `filter`.
+    pub fn list_devices_matching(
+        filter: &InterfaceFilter,
+    ) -> Result<Vec<SerialDevicePath>, AxdlError> {
+        let mut list = Self::list_devices()?;
+        list.retain(|path| filter.matches(path));
+        Ok(list)
+    }
+
+    /// Opens the first port matching `filter` which is not in use by another process, or returns
+    /// `None` if no such port is connected.
+    pub fn open_first_matching(
+        filter: &InterfaceFilter,
+    ) -> Result<Option<SerialDevice>, AxdlError> {
+        super::open_any::<Self>(Self::list_devices_matching(filter)?)
+    }
+
+    /// Polls until a port matching `filter` is connected, see [`super::NativeTransport::wait_for_device`].
+    pub fn wait_for_device_matching(
+        filter: &InterfaceFilter,
+        timeout: Option<Duration>,
+        is_cancelled: impl Fn() -> bool,
+    ) -> Result<SerialDevice, AxdlError> {
+        super::poll_for_device(timeout, is_cancelled, || Self::open_first_matching(filter))
+    }
+
+    /// Opens the device and runs `sequence` on its modem control lines.
+    pub fn open_device_with_boot_sequence(
+        path: &SerialDevicePath,
+        sequence: &BootSequence,
+    ) -> Result<SerialDevice, AxdlError> {
+        let mut device = Self::open_device(path)?;
+        device.apply_boot_sequence(sequence)?;
+        Ok(device)
+    }
+}
+
+/// A step of a [`BootSequence`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineStep {
+    /// Sets RTS; `true` asserts the line.
+    Rts(bool),
+    /// Sets DTR; `true` asserts the line.
+    Dtr(bool),
+    Wait(Duration),
+}
+
+/// RTS/DTR sequence run after opening the port, for boards which wire BOOT and RESET to these lines.
+///
+/// The textual form is a comma separated list of `rts=0|1`, `dtr=0|1` and `wait=<milliseconds>`,
+/// e.g. `dtr=1,rts=1,wait=100,rts=0,wait=500,dtr=0` holds BOOT on DTR while pulsing RESET on RTS.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BootSequence {
+    steps: Vec<LineStep>,
+}
+
+impl BootSequence {
+    pub fn new(steps: Vec<LineStep>) -> Self {
+        Self { steps }
+    }
+
+    pub fn steps(&self) -> &[LineStep] {
+        &self.steps
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+impl std::str::FromStr for BootSequence {
+    type Err = AxdlError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            |step: &str| AxdlError::InvalidConfig(format!("invalid boot sequence step: {}", step));
+        let steps = s
+            .split(',')
+            .map(str::trim)
+            .filter(|step| !step.is_empty())
+            .map(|step| {
+                let (line, value) = step.split_once('=').ok_or_else(|| invalid(step))?;
+                let level = || match value.trim() {
+                    "0" => Ok(false),
+                    "1" => Ok(true),
+                    _ => Err(invalid(step)),
+                };
+                match line.trim() {
+                    "rts" => Ok(LineStep::Rts(level()?)),
+                    "dtr" => Ok(LineStep::Dtr(level()?)),
+                    "wait" => value
+                        .trim()
+                        .parse()
+                        .map(|ms| LineStep::Wait(Duration::from_millis(ms)))
+                        .map_err(|_| invalid(step)),
+                    _ => Err(invalid(step)),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { steps })
     }
 }
 
+impl std::fmt::Display for BootSequence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            match step {
+                LineStep::Rts(level) => write!(f, "rts={}", *level as u8)?,
+                LineStep::Dtr(level) => write!(f, "dtr={}", *level as u8)?,
+                LineStep::Wait(duration) => write!(f, "wait={}", duration.as_millis())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Longest gap allowed between the bytes of a frame before the partial frame is dropped.
+pub const DEFAULT_INTER_BYTE_TIMEOUT: Duration = Duration::from_millis(500);
+/// Size of the buffer the port is read into, large enough for a whole readback block so that
+/// slow hosts need few system calls per frame.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Serial port device which returns one complete frame per read.
 #[derive(Debug)]
 pub struct SerialDevice {
     port: Box<dyn serialport::SerialPort>,
+    accumulator: FrameAccumulator,
+    inter_byte_timeout: Duration,
+    read_buffer: Vec<u8>,
+    /// Timeout last set on the port, as setting it costs a system call.
+    port_timeout: Option<Duration>,
+    path: SerialDevicePath,
+    _lock: DeviceLock,
+}
+
+impl SerialDevice {
+    fn new(
+        port: Box<dyn serialport::SerialPort>,
+        path: SerialDevicePath,
+        lock: DeviceLock,
+    ) -> Self {
+        Self {
+            port,
+            accumulator: FrameAccumulator::new(DEFAULT_MAX_FRAME_SIZE),
+            inter_byte_timeout: DEFAULT_INTER_BYTE_TIMEOUT,
+            read_buffer: vec![0u8; READ_BUFFER_SIZE],
+            port_timeout: None,
+            path,
+            _lock: lock,
+        }
+    }
+
+    pub fn with_inter_byte_timeout(mut self, timeout: Duration) -> Self {
+        self.inter_byte_timeout = timeout;
+        self
+    }
+
+    /// Sets the length above which a frame is rejected; it should match the session's max frame size.
+    pub fn with_max_frame_length(mut self, length: usize) -> Self {
+        self.accumulator = FrameAccumulator::new(length);
+        self
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), AxdlError> {
+        if self.port_timeout != Some(timeout) {
+            self.port.set_timeout(timeout).map_err(port_error)?;
+            self.port_timeout = Some(timeout);
+        }
+        Ok(())
+    }
+
+    /// Runs `sequence` on the modem control lines and discards anything received meanwhile.
+    pub fn apply_boot_sequence(&mut self, sequence: &BootSequence) -> Result<(), AxdlError> {
+        for step in sequence.steps() {
+            tracing::debug!("boot sequence: {:?}", step);
+            match *step {
+                LineStep::Rts(level) => self.port.write_request_to_send(level),
+                LineStep::Dtr(level) => self.port.write_data_terminal_ready(level),
+                LineStep::Wait(duration) => {
+                    std::thread::sleep(duration);
+                    Ok(())
+                }
+            }
+            .map_err(AxdlError::SerialError)?;
+        }
+        if !sequence.is_empty() {
+            self.port
+                .clear(serialport::ClearBuffer::Input)
+                .map_err(AxdlError::SerialError)?;
+        }
+        Ok(())
+    }
+}
+
+impl DeviceInfo for SerialDevice {
+    fn transport_kind(&self) -> TransportKind {
+        TransportKind::Serial
+    }
+    fn display_name(&self) -> String {
+        match self.path.product() {
+            Some(product) => format!("{} ({})", product, self.path.port_name),
+            None => self.path.port_name.clone(),
+        }
+    }
+    fn unique_id(&self) -> String {
+        format!("serial:{}", self.path.port_name)
+    }
+}
+
+/// Returns whether an I/O error on the port means that the adapter has been unplugged.
+fn is_disconnected(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    if matches!(
+        error.kind(),
+        ErrorKind::BrokenPipe | ErrorKind::NotConnected | ErrorKind::ConnectionAborted
+    ) {
+        return true;
+    }
+    // EIO, ENXIO and ENODEV on Linux and macOS; ERROR_DEVICE_NOT_CONNECTED on Windows.
+    #[cfg(unix)]
+    const CODES: &[i32] = &[5, 6, 19];
+    #[cfg(windows)]
+    const CODES: &[i32] = &[1167];
+    #[cfg(not(any(unix, windows)))]
+    const CODES: &[i32] = &[];
+    error
+        .raw_os_error()
+        .is_some_and(|code| CODES.contains(&code))
+}
+
+fn io_error(context: &str, error: std::io::Error) -> AxdlError {
+    if is_disconnected(&error) {
+        AxdlError::DeviceDisconnected
+    } else {
+        AxdlError::IoError(context.into(), error)
+    }
+}
+
+fn port_error(error: serialport::Error) -> AxdlError {
+    match error.kind() {
+        serialport::ErrorKind::NoDevice => AxdlError::DeviceDisconnected,
+        _ => AxdlError::SerialError(error),
+    }
 }
 
 impl Device for SerialDevice {
     fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, AxdlError> {
-        self.port
-            .set_timeout(timeout)
-            .map_err(AxdlError::SerialError)?;
-        self.port
-            .read(buf)
-            .map_err(|e| AxdlError::IoError("read error".into(), e))
+        let deadline = crate::time::Deadline::after(timeout);
+        loop {
+            match self.accumulator.pop_frame(buf) {
+                Ok(Some(length)) => return Ok(length),
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::warn!("serial: dropped a frame: {}", e);
+                    return Err(AxdlError::InvalidFrame);
+                }
+            }
+            let remaining = deadline.remaining();
+            let wait = if self.accumulator.is_empty() {
+                remaining
+            } else {
+                remaining.min(self.inter_byte_timeout)
+            };
+            if wait.is_zero() {
+                return Err(AxdlError::DeviceTimeout);
+            }
+            self.set_timeout(wait)?;
+            match self.port.read(&mut self.read_buffer) {
+                Ok(length) => self.accumulator.push(&self.read_buffer[..length]),
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                    if !self.accumulator.is_empty() {
+                        tracing::warn!("serial: inter-byte timeout, dropping a partial frame");
+                        self.accumulator.clear();
+                        return Err(AxdlError::InvalidFrame);
+                    }
+                }
+                Err(e) => return Err(io_error("read error", e)),
+            }
+        }
     }
     fn write_timeout(&mut self, buf: &[u8], timeout: Duration) -> Result<usize, AxdlError> {
+        self.set_timeout(timeout)?;
+        // A large block may not fit in the driver buffer at once.
         self.port
-            .set_timeout(timeout)
-            .map_err(AxdlError::SerialError)?;
-        self.port
-            .write(buf)
-            .map_err(|e| AxdlError::IoError("write error".into(), e))
+            .write_all(buf)
+            .map_err(|e| io_error("write error", e))?;
+        Ok(buf.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_disconnected_errors() {
+        let broken_pipe = std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+        assert!(matches!(
+            io_error("write error", broken_pipe),
+            AxdlError::DeviceDisconnected
+        ));
+        let other = std::io::Error::from(std::io::ErrorKind::InvalidInput);
+        assert!(matches!(
+            io_error("write error", other),
+            AxdlError::IoError(_, _)
+        ));
+    }
+
+    #[test]
+    fn test_interface_filter() {
+        let path = |interface, interface_name: Option<&str>| SerialDevicePath {
+            port_name: "/dev/ttyACM0".into(),
+            product: None,
+            serial_number: None,
+            interface,
+            interface_name: interface_name.map(Into::into),
+        };
+        let filter: InterfaceFilter = "2".parse().unwrap();
+        assert_eq!(filter, InterfaceFilter::Number(2));
+        assert!(filter.matches(&path(Some(2), None)));
+        assert!(!filter.matches(&path(Some(0), None)));
+        assert!(!filter.matches(&path(None, None)));
+
+        let filter: InterfaceFilter = "Download".parse().unwrap();
+        assert_eq!(filter.to_string(), "Download");
+        assert!(filter.matches(&path(Some(0), Some("AXDL download port"))));
+        assert!(!filter.matches(&path(Some(2), Some("Console"))));
+        assert!(!filter.matches(&path(Some(0), None)));
+        assert!(" ".parse::<InterfaceFilter>().is_err());
+    }
+
+    #[test]
+    fn test_boot_sequence_parse() {
+        let sequence: BootSequence = "dtr=1, rts=1,wait=100,rts=0".parse().unwrap();
+        assert_eq!(
+            sequence.steps(),
+            &[
+                LineStep::Dtr(true),
+                LineStep::Rts(true),
+                LineStep::Wait(Duration::from_millis(100)),
+                LineStep::Rts(false),
+            ]
+        );
+        assert_eq!(sequence.to_string(), "dtr=1,rts=1,wait=100,rts=0");
+        assert!("".parse::<BootSequence>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_boot_sequence_invalid() {
+        assert!("rts=2".parse::<BootSequence>().is_err());
+        assert!("cts=1".parse::<BootSequence>().is_err());
+        assert!("wait".parse::<BootSequence>().is_err());
     }
 }
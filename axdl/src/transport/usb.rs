@@ -4,20 +4,47 @@ use rusb::DeviceHandle;
 
 use crate::AxdlError;
 
-use super::{Device, Transport};
+use super::{ids, lock::DeviceLock, Device, DeviceInfo, Transport, TransportKind};
 
-pub const VENDOR_ID: u16 = 0x32c9;
-pub const PRODUCT_ID: u16 = 0x1000;
-pub const ENDPOINT_OUT: u8 = 0x01;
-pub const ENDPOINT_IN: u8 = 0x81;
+pub use super::ids::{ENDPOINT_IN, ENDPOINT_OUT, PRODUCT_ID, VENDOR_ID};
+/// Bulk max packet size of high-speed devices, used when the descriptor cannot be read.
+pub const DEFAULT_MAX_PACKET_SIZE: u16 = 512;
 
 /// Transport implementation to use the USB device directly via libusb.
 pub struct UsbTransport;
 
-/// Device path for USB devices.
-#[derive(Debug, Clone, PartialEq)]
+/// Device path for USB devices, with descriptors captured when the device was listed.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
 pub struct UsbDevicePath {
     port_numbers: Vec<u8>,
+    bus_number: u8,
+    address: u8,
+    /// Product string; `None` if the device could not be opened to read it.
+    product: Option<String>,
+    /// Serial number string; `None` if the device has none or could not be opened to read it.
+    serial_number: Option<String>,
+}
+
+impl UsbDevicePath {
+    pub fn port_numbers(&self) -> &[u8] {
+        &self.port_numbers
+    }
+
+    pub fn bus_number(&self) -> u8 {
+        self.bus_number
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn product(&self) -> Option<&str> {
+        self.product.as_deref()
+    }
+
+    pub fn serial_number(&self) -> Option<&str> {
+        self.serial_number.as_deref()
+    }
 }
 
 impl std::fmt::Display for UsbDevicePath {
@@ -43,13 +70,21 @@ impl Transport for UsbTransport {
             .iter()
             .filter_map(|device| {
                 if let Ok(device_desc) = device.device_descriptor() {
-                    if device_desc.vendor_id() == VENDOR_ID
-                        && device_desc.product_id() == PRODUCT_ID
-                    {
-                        device
-                            .port_numbers()
-                            .ok()
-                            .map(|port_numbers| UsbDevicePath { port_numbers })
+                    if ids::is_known(device_desc.vendor_id(), device_desc.product_id()) {
+                        let port_numbers = device.port_numbers().ok()?;
+                        // Reading string descriptors needs access to the device, which may be denied.
+                        let handle = device.open().ok();
+                        let read_string = |index: Option<u8>| {
+                            let handle = handle.as_ref()?;
+                            handle.read_string_descriptor_ascii(index?).ok()
+                        };
+                        Some(UsbDevicePath {
+                            port_numbers,
+                            bus_number: device.bus_number(),
+                            address: device.address(),
+                            product: read_string(device_desc.product_string_index()),
+                            serial_number: read_string(device_desc.serial_number_string_index()),
+                        })
                     } else {
                         None
                     }
@@ -61,43 +96,197 @@ impl Transport for UsbTransport {
         Ok(list)
     }
     fn open_device(path: &Self::DeviceId) -> Result<Self::DeviceType, AxdlError> {
-        let device = rusb::devices()
-            .map_err(AxdlError::UsbError)?
+        let lock = DeviceLock::acquire(&format!("usb-{}-{}", path.bus_number, path))?;
+        let (handle, endpoints) = open_at(path)?;
+        Ok(UsbDevice {
+            handle,
+            endpoints,
+            path: path.clone(),
+            _lock: lock,
+        })
+    }
+}
+
+/// Interface and bulk endpoints used to talk to the device.
+///
+/// Some boards change their interface or endpoint numbers when they re-enumerate after FDL1, so
+/// this is chosen from the active configuration every time the device is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndpointSelection {
+    pub configuration: u8,
+    pub interface: u8,
+    pub alternate_setting: u8,
+    pub endpoint_out: u8,
+    pub endpoint_in: u8,
+    /// Max packet size of the OUT endpoint.
+    pub max_packet_size: u16,
+}
+
+impl EndpointSelection {
+    /// Interface 0 with the boot ROM endpoints, used when the descriptors cannot be read.
+    const FALLBACK: Self = Self {
+        configuration: 1,
+        interface: 0,
+        alternate_setting: 0,
+        endpoint_out: ENDPOINT_OUT,
+        endpoint_in: ENDPOINT_IN,
+        max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+    };
+
+    /// Picks an interface with a bulk OUT and a bulk IN endpoint, preferring the boot ROM endpoints.
+    fn from_config(config: &rusb::ConfigDescriptor) -> Option<Self> {
+        let candidates = config
+            .interfaces()
+            .flat_map(|interface| interface.descriptors())
+            .filter_map(|descriptor| {
+                let find = |direction, preferred| {
+                    let bulk = || {
+                        descriptor.endpoint_descriptors().filter(|endpoint| {
+                            endpoint.transfer_type() == rusb::TransferType::Bulk
+                                && endpoint.direction() == direction
+                        })
+                    };
+                    bulk()
+                        .find(|endpoint| endpoint.address() == preferred)
+                        .or_else(|| bulk().next())
+                };
+                let out = find(rusb::Direction::Out, ENDPOINT_OUT)?;
+                let r#in = find(rusb::Direction::In, ENDPOINT_IN)?;
+                Some(Self {
+                    configuration: config.number(),
+                    interface: descriptor.interface_number(),
+                    alternate_setting: descriptor.setting_number(),
+                    endpoint_out: out.address(),
+                    endpoint_in: r#in.address(),
+                    max_packet_size: out.max_packet_size(),
+                })
+            })
+            .collect::<Vec<_>>();
+        candidates
             .iter()
-            .find(|device| {
-                if let Ok(device_desc) = device.device_descriptor() {
-                    if device_desc.vendor_id() == VENDOR_ID
-                        && device_desc.product_id() == PRODUCT_ID
-                    {
-                        if let Ok(port_numbers) = device.port_numbers() {
-                            return port_numbers == path.port_numbers;
-                        }
+            .find(|selection| {
+                selection.endpoint_out == ENDPOINT_OUT && selection.endpoint_in == ENDPOINT_IN
+            })
+            .or(candidates.first())
+            .copied()
+    }
+}
+
+/// Finds the device at `path`, opens it and claims the interface selected from its descriptors.
+fn open_at(
+    path: &UsbDevicePath,
+) -> Result<(DeviceHandle<rusb::GlobalContext>, EndpointSelection), AxdlError> {
+    let device = rusb::devices()
+        .map_err(AxdlError::UsbError)?
+        .iter()
+        .find(|device| {
+            if let Ok(device_desc) = device.device_descriptor() {
+                if ids::is_known(device_desc.vendor_id(), device_desc.product_id()) {
+                    if let Ok(port_numbers) = device.port_numbers() {
+                        return port_numbers == path.port_numbers;
                     }
                 }
-                false
-            })
-            .ok_or(AxdlError::DeviceNotFound)?;
+            }
+            false
+        })
+        .ok_or(AxdlError::DeviceNotFound)?;
+
+    let endpoints = device
+        .active_config_descriptor()
+        .ok()
+        .and_then(|config| EndpointSelection::from_config(&config))
+        .unwrap_or_else(|| {
+            tracing::warn!(
+                "Bulk endpoints not found in the descriptors, assuming interface 0 with max packet size {}",
+                DEFAULT_MAX_PACKET_SIZE
+            );
+            EndpointSelection::FALLBACK
+        });
+    tracing::debug!(
+        "USB configuration {}, interface {} (alternate setting {}), OUT {:#04x}, IN {:#04x}, max packet size {}",
+        endpoints.configuration,
+        endpoints.interface,
+        endpoints.alternate_setting,
+        endpoints.endpoint_out,
+        endpoints.endpoint_in,
+        endpoints.max_packet_size
+    );
 
-        let handle = device.open().map_err(AxdlError::UsbError)?;
-        handle.claim_interface(0).map_err(AxdlError::UsbError)?;
-        Ok(UsbDevice { handle })
+    let handle = device.open().map_err(AxdlError::UsbError)?;
+    handle
+        .claim_interface(endpoints.interface)
+        .map_err(AxdlError::UsbError)?;
+    if endpoints.alternate_setting != 0 {
+        handle
+            .set_alternate_setting(endpoints.interface, endpoints.alternate_setting)
+            .map_err(AxdlError::UsbError)?;
     }
+    Ok((handle, endpoints))
 }
 
 #[derive(Debug)]
 pub struct UsbDevice {
     handle: DeviceHandle<rusb::GlobalContext>,
+    endpoints: EndpointSelection,
+    path: UsbDevicePath,
+    _lock: DeviceLock,
+}
+
+impl UsbDevice {
+    /// Interface and endpoints chosen when the device was last opened.
+    pub fn endpoints(&self) -> &EndpointSelection {
+        &self.endpoints
+    }
+
+    /// Opens the device at the same port again, e.g. after it re-enumerated, and repeats the
+    /// interface selection. The device lock is kept.
+    pub fn reopen(&mut self) -> Result<(), AxdlError> {
+        // Fails if the device has already gone away, which is fine.
+        let _ = self.handle.release_interface(self.endpoints.interface);
+        let (handle, endpoints) = open_at(&self.path)?;
+        self.handle = handle;
+        self.endpoints = endpoints;
+        Ok(())
+    }
+}
+
+impl DeviceInfo for UsbDevice {
+    fn transport_kind(&self) -> TransportKind {
+        TransportKind::Usb
+    }
+    fn display_name(&self) -> String {
+        format!(
+            "{} (USB {}-{})",
+            self.path.product().unwrap_or("Axera device"),
+            self.path.bus_number,
+            self.path
+        )
+    }
+    fn unique_id(&self) -> String {
+        format!("usb:{}-{}", self.path.bus_number, self.path)
+    }
+}
+
+/// Maps a transfer error, telling an unplugged device apart from other USB errors.
+fn transfer_error(error: rusb::Error) -> AxdlError {
+    match error {
+        rusb::Error::NoDevice => AxdlError::DeviceDisconnected,
+        error => AxdlError::UsbError(error),
+    }
 }
 
 impl Device for UsbDevice {
     fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, AxdlError> {
         self.handle
-            .read_bulk(ENDPOINT_IN, buf, timeout)
-            .map_err(AxdlError::UsbError)
+            .read_bulk(self.endpoints.endpoint_in, buf, timeout)
+            .map_err(transfer_error)
     }
     fn write_timeout(&mut self, buf: &[u8], timeout: Duration) -> Result<usize, AxdlError> {
         self.handle
-            .write_bulk(ENDPOINT_OUT, buf, timeout)
-            .map_err(AxdlError::UsbError)
+            .write_bulk(self.endpoints.endpoint_out, buf, timeout)
+            .map_err(transfer_error)
+    }
+    fn max_packet_size(&self) -> Option<usize> {
+        Some(self.endpoints.max_packet_size as usize)
     }
 }
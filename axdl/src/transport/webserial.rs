@@ -1,16 +1,16 @@
-use std::time::Duration;
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
 
 use wasm_streams::{ReadableStream, WritableStream};
 use webusb_web;
 
 use crate::AxdlError;
 
-use super::AsyncDevice;
+use super::{ids, AsyncDevice, AsyncTransport, DeviceInfo, TransportKind};
 
-pub const VENDOR_ID: u16 = 0x32c9;
-pub const PRODUCT_ID: u16 = 0x1000;
-pub const ENDPOINT_OUT: u8 = 0x01;
-pub const ENDPOINT_IN: u8 = 0x81;
+pub use super::ids::{ENDPOINT_IN, ENDPOINT_OUT, PRODUCT_ID, VENDOR_ID};
 
 pub fn new_serial() -> Result<web_sys::Serial, AxdlError> {
     web_sys::window()
@@ -18,18 +18,85 @@ pub fn new_serial() -> Result<web_sys::Serial, AxdlError> {
         .ok_or(AxdlError::Unsupported("WebSerial".to_string()))
 }
 
-/// Returns a device filter for Axera devices.
-pub fn axdl_device_filter() -> web_sys::SerialPortFilter {
+/// Returns a port filter for devices with `id`.
+pub fn device_filter(id: ids::UsbId) -> web_sys::SerialPortFilter {
     let mut filter = web_sys::SerialPortFilter::new();
-    filter.set_usb_vendor_id(VENDOR_ID);
-    filter.set_usb_product_id(PRODUCT_ID);
+    filter.set_usb_vendor_id(id.vendor_id);
+    filter.set_usb_product_id(id.product_id);
     filter
 }
 
+/// Returns a port filter for Axera devices with the default ids.
+pub fn axdl_device_filter() -> web_sys::SerialPortFilter {
+    device_filter(ids::DEFAULT)
+}
+
+/// Returns port filters for all known ids, see [`ids::register`].
+pub fn axdl_device_filters() -> Vec<web_sys::SerialPortFilter> {
+    ids::known().into_iter().map(device_filter).collect()
+}
+
+pub const BAUD_RATE: u32 = 115200;
+/// Size of the browser's receive buffer, large enough for a whole response frame.
+pub const BUFFER_SIZE: u32 = 48000;
+
+/// Transport for Axera serial ports the page has been granted access to through WebSerial.
+pub struct WebSerialTransport;
+
+impl WebSerialTransport {
+    /// Asks the user to pick an Axera serial port, which also grants the page access to it.
+    pub async fn request_port() -> Result<web_sys::SerialPort, AxdlError> {
+        let options = web_sys::SerialPortRequestOptions::new();
+        options.set_filters(&axdl_device_filters());
+        let promise = new_serial()?.request_port_with_options(&options);
+        let port = wasm_bindgen_futures::JsFuture::from(promise)
+            .await
+            .map_err(AxdlError::WebSerialError)?;
+        Ok(web_sys::SerialPort::from(port))
+    }
+}
+
+impl AsyncTransport for WebSerialTransport {
+    type DeviceId = web_sys::SerialPort;
+    type DeviceType = WebSerialDevice;
+
+    /// Lists the Axera serial ports the page is already allowed to access, without prompting the user.
+    async fn list_devices() -> Result<Vec<Self::DeviceId>, AxdlError> {
+        let ports = wasm_bindgen_futures::JsFuture::from(new_serial()?.get_ports())
+            .await
+            .map_err(AxdlError::WebSerialError)?;
+        Ok(js_sys::Array::from(&ports)
+            .iter()
+            .map(web_sys::SerialPort::from)
+            .filter(|port| {
+                let info = port.get_info();
+                info.get_usb_vendor_id()
+                    .zip(info.get_usb_product_id())
+                    .is_some_and(|(vendor_id, product_id)| ids::is_known(vendor_id, product_id))
+            })
+            .collect())
+    }
+
+    async fn open_device(path: &Self::DeviceId) -> Result<Self::DeviceType, AxdlError> {
+        tracing::info!("Device selected: {:?}", path);
+        let options = web_sys::SerialOptions::new(BAUD_RATE);
+        options.set_buffer_size(BUFFER_SIZE);
+        wasm_bindgen_futures::JsFuture::from(path.open(&options))
+            .await
+            .map_err(AxdlError::WebSerialError)?;
+        tracing::info!("Device opened: {:?}", path);
+        Ok(WebSerialDevice::new(path.clone()))
+    }
+}
+
+/// Number of ports opened so far, used to tell ports apart as Web Serial has no port names.
+static PORTS_OPENED: AtomicUsize = AtomicUsize::new(0);
+
 pub struct WebSerialDevice {
     port: web_sys::SerialPort,
     read_buffer: Vec<u8>,
     read_position: usize,
+    index: usize,
 }
 
 impl WebSerialDevice {
@@ -40,10 +107,31 @@ impl WebSerialDevice {
             port,
             read_buffer,
             read_position,
+            index: PORTS_OPENED.fetch_add(1, Ordering::Relaxed),
         }
     }
 }
 
+impl DeviceInfo for WebSerialDevice {
+    fn transport_kind(&self) -> TransportKind {
+        TransportKind::WebSerial
+    }
+    fn display_name(&self) -> String {
+        format!("Serial port #{}", self.index)
+    }
+    fn unique_id(&self) -> String {
+        format!("webserial:{}", self.index)
+    }
+}
+
+/// Returns whether `error` is the `NetworkError` raised when the port has been unplugged.
+fn is_network_error(error: &js_sys::wasm_bindgen::JsValue) -> bool {
+    js_sys::Reflect::get(error, &js_sys::wasm_bindgen::JsValue::from_str("name"))
+        .ok()
+        .and_then(|name| name.as_string())
+        .is_some_and(|name| name == "NetworkError")
+}
+
 impl AsyncDevice for WebSerialDevice {
     async fn read(&mut self, buf: &mut [u8]) -> Result<usize, AxdlError> {
         if buf.len() == 0 {
@@ -55,6 +143,12 @@ impl AsyncDevice for WebSerialDevice {
             let mut reader = stream.get_reader();
             pin_utils::pin_mut!(reader);
             let result = reader.read().await;
+            match &result {
+                // The stream ends when the port is closed, e.g. because it was unplugged.
+                Ok(None) => return Err(AxdlError::DeviceDisconnected),
+                Err(e) if is_network_error(e) => return Err(AxdlError::DeviceDisconnected),
+                _ => {}
+            }
             if let Ok(Some(chunk)) = result {
                 if let Ok(buffer) = js_sys::Uint8Array::try_from(chunk) {
                     let length = buffer.length() as usize;
@@ -88,10 +182,13 @@ impl AsyncDevice for WebSerialDevice {
         let writer = stream.get_writer();
         pin_utils::pin_mut!(writer);
         tracing::debug!("webserial: write {} bytes", buffer.byte_length());
-        writer
-            .write(buffer.into())
-            .await
-            .map_err(AxdlError::WebSerialError)?;
+        writer.write(buffer.into()).await.map_err(|e| {
+            if is_network_error(&e) {
+                AxdlError::DeviceDisconnected
+            } else {
+                AxdlError::WebSerialError(e)
+            }
+        })?;
         Ok(buf.len())
     }
 }
@@ -4,18 +4,119 @@ use webusb_web;
 
 use crate::AxdlError;
 
-use super::AsyncDevice;
+use super::{ids, AsyncDevice, AsyncTransport, DeviceInfo, TransportKind};
 
-pub const VENDOR_ID: u16 = 0x32c9;
-pub const PRODUCT_ID: u16 = 0x1000;
-pub const ENDPOINT_OUT: u8 = 0x01;
-pub const ENDPOINT_IN: u8 = 0x01;
+pub use super::ids::{PRODUCT_ID, VENDOR_ID};
+/// Endpoint numbers, which WebUSB transfers take instead of endpoint addresses.
+pub const ENDPOINT_OUT: u8 = ids::endpoint_number(ids::ENDPOINT_OUT);
+pub const ENDPOINT_IN: u8 = ids::endpoint_number(ids::ENDPOINT_IN);
 
-/// Returns a device filter for Axera devices.
-pub fn axdl_device_filter() -> webusb_web::UsbDeviceFilter {
+/// Returns a device filter for devices with `id`.
+pub fn device_filter(id: ids::UsbId) -> webusb_web::UsbDeviceFilter {
     webusb_web::UsbDeviceFilter::new()
-        .with_vendor_id(VENDOR_ID)
-        .with_product_id(PRODUCT_ID)
+        .with_vendor_id(id.vendor_id)
+        .with_product_id(id.product_id)
+}
+
+/// Returns a device filter for Axera devices with the default ids.
+pub fn axdl_device_filter() -> webusb_web::UsbDeviceFilter {
+    device_filter(ids::DEFAULT)
+}
+
+/// Returns device filters for all known ids, see [`ids::register`].
+pub fn axdl_device_filters() -> Vec<webusb_web::UsbDeviceFilter> {
+    ids::known().into_iter().map(device_filter).collect()
+}
+
+/// Transport for Axera devices the page has been granted access to through WebUSB.
+pub struct WebUsbTransport;
+
+impl WebUsbTransport {
+    /// Asks the user to pick an Axera device, which also grants the page access to it.
+    pub async fn request_device() -> Result<webusb_web::UsbDevice, AxdlError> {
+        let usb = webusb_web::Usb::new().map_err(AxdlError::WebUsbError)?;
+        usb.request_device(axdl_device_filters())
+            .await
+            .map_err(AxdlError::WebUsbError)
+    }
+}
+
+impl AsyncTransport for WebUsbTransport {
+    type DeviceId = webusb_web::UsbDevice;
+    type DeviceType = webusb_web::OpenUsbDevice;
+
+    /// Lists the Axera devices the page is already allowed to access, without prompting the user.
+    async fn list_devices() -> Result<Vec<Self::DeviceId>, AxdlError> {
+        let usb = webusb_web::Usb::new().map_err(AxdlError::WebUsbError)?;
+        Ok(usb
+            .devices()
+            .await
+            .into_iter()
+            .filter(|device| ids::is_known(device.vendor_id(), device.product_id()))
+            .collect())
+    }
+
+    async fn open_device(path: &Self::DeviceId) -> Result<Self::DeviceType, AxdlError> {
+        tracing::info!("Device selected: {:?}", path);
+        let device = path.open().await.map_err(AxdlError::WebUsbError)?;
+        tracing::info!("Device opened: {:?}", device);
+        device
+            .claim_interface(0)
+            .await
+            .map_err(|error| match error.kind() {
+                webusb_web::ErrorKind::Disconnected => AxdlError::DeviceDisconnected,
+                _ => AxdlError::InterfaceClaimFailed(error),
+            })?;
+        Ok(device)
+    }
+}
+
+/// Returns the max packet size of the bulk OUT endpoint in the active configuration.
+pub fn max_packet_size(device: &webusb_web::UsbDevice) -> Option<u32> {
+    device
+        .configuration()?
+        .interfaces
+        .iter()
+        .flat_map(|interface| interface.alternate.endpoints.iter())
+        .find(|endpoint| {
+            endpoint.endpoint_number == ENDPOINT_OUT
+                && matches!(endpoint.direction, webusb_web::UsbDirection::Out)
+        })
+        .map(|endpoint| endpoint.packet_size)
+}
+
+impl DeviceInfo for webusb_web::OpenUsbDevice {
+    fn transport_kind(&self) -> TransportKind {
+        TransportKind::WebUsb
+    }
+    fn display_name(&self) -> String {
+        let device = self.device();
+        match (device.product_name(), device.serial_number()) {
+            (Some(product), Some(serial)) => format!("{} ({})", product, serial),
+            (Some(product), None) => product,
+            (None, _) => "Axera device (WebUSB)".into(),
+        }
+    }
+    fn unique_id(&self) -> String {
+        // WebUSB does not expose the bus topology, so the serial number is the only stable key.
+        let device = self.device();
+        match device.serial_number() {
+            Some(serial) => format!("webusb:{}", serial),
+            None => format!(
+                "webusb:{:04x}:{:04x}",
+                device.vendor_id(),
+                device.product_id()
+            ),
+        }
+    }
+}
+
+/// Maps a transfer error, telling an unplugged device apart from other WebUSB errors.
+fn transfer_error(error: webusb_web::Error) -> AxdlError {
+    match error.kind() {
+        webusb_web::ErrorKind::Disconnected => AxdlError::DeviceDisconnected,
+        _ => AxdlError::WebUsbError(error),
+    }
 }
 
 impl AsyncDevice for webusb_web::OpenUsbDevice {
@@ -23,7 +124,7 @@ impl AsyncDevice for webusb_web::OpenUsbDevice {
         let result = self
             .transfer_in(ENDPOINT_IN, buf.len() as u32)
             .await
-            .map_err(AxdlError::WebUsbError)?;
+            .map_err(transfer_error)?;
         let bytes_to_copy = result.len().min(buf.len());
 
         buf[..bytes_to_copy].copy_from_slice(&result[..bytes_to_copy]);
@@ -34,7 +135,11 @@ impl AsyncDevice for webusb_web::OpenUsbDevice {
         let bytes_written = self
             .transfer_out(ENDPOINT_OUT, buf)
             .await
-            .map_err(AxdlError::WebUsbError)?;
+            .map_err(transfer_error)?;
         Ok(bytes_written as usize)
     }
+
+    fn max_packet_size(&self) -> Option<usize> {
+        max_packet_size(self.device()).map(|size| size as usize)
+    }
 }